@@ -0,0 +1,73 @@
+//! Times reading and writing object fields through string keys, where the key constant and the
+//! key stored in the table are usually the same interned string. Run with
+//! `cargo bench --bench field_lookup`.
+
+use std::time::{Duration, Instant};
+
+use piccolo::{Closure, Lua, StaticError, Thread};
+
+const ITERATIONS: u32 = 20;
+
+const SETUP: &str = r#"
+    object = {
+        position_x = 0, position_y = 0, velocity_x = 1, velocity_y = 2,
+        health = 100, armor = 10, name = "object", level = 1,
+    }
+"#;
+
+const READ_FIELDS: &str = r#"
+    local object = object
+    local sum = 0
+    for i = 1, 100000 do
+        sum = sum + object.position_x + object.position_y + object.velocity_x + object.velocity_y
+            + object.health + object.armor + object.level
+    end
+    return sum
+"#;
+
+const WRITE_FIELDS: &str = r#"
+    local object = object
+    for i = 1, 100000 do
+        object.position_x = object.position_x + object.velocity_x
+        object.position_y = object.position_y + object.velocity_y
+    end
+    object.position_x = 0
+    object.position_y = 0
+    return object.health
+"#;
+
+fn run(lua: &mut Lua, source: &'static str) -> Result<i64, StaticError> {
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, source.as_bytes())?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(thread))
+    })?;
+    lua.run_thread::<Option<i64>>(&thread)
+        .map(|result| result.unwrap_or(0))
+}
+
+fn time(lua: &mut Lua, source: &'static str, result: i64) -> Result<Duration, StaticError> {
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        assert_eq!(run(lua, source)?, result);
+        elapsed += start.elapsed();
+    }
+    Ok(elapsed / ITERATIONS)
+}
+
+fn main() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    run(&mut lua, SETUP)?;
+
+    println!(
+        "700k field reads: {:?}",
+        time(&mut lua, READ_FIELDS, 11_400_000)?
+    );
+    println!(
+        "400k field reads and 200k field writes: {:?}",
+        time(&mut lua, WRITE_FIELDS, 100)?
+    );
+    Ok(())
+}
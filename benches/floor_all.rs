@@ -0,0 +1,55 @@
+//! Compares flooring a 10k element array with `math.floor` per element against a single call to
+//! `math.floor_all`. Run with `cargo bench --bench floor_all`.
+
+use std::time::{Duration, Instant};
+
+use piccolo::{Closure, Lua, StaticError, Thread};
+
+const ITERATIONS: u32 = 100;
+
+const SETUP: &str = r#"
+    values = {}
+    for i = 1, 10000 do
+        values[i] = i / 7
+    end
+"#;
+
+const PER_ELEMENT: &str = r#"
+    local floor, result = math.floor, {}
+    for i = 1, #values do
+        result[i] = floor(values[i])
+    end
+    return #result
+"#;
+
+const VECTORIZED: &str = r#"
+    return #math.floor_all(values)
+"#;
+
+fn run(lua: &mut Lua, source: &'static str) -> Result<i64, StaticError> {
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, source.as_bytes())?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(thread))
+    })?;
+    lua.run_thread::<Option<i64>>(&thread)
+        .map(|len| len.unwrap_or(0))
+}
+
+fn time(lua: &mut Lua, source: &'static str) -> Result<Duration, StaticError> {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert_eq!(run(lua, source)?, 10000);
+    }
+    Ok(start.elapsed() / ITERATIONS)
+}
+
+fn main() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    run(&mut lua, SETUP)?;
+
+    println!("math.floor per element: {:?}", time(&mut lua, PER_ELEMENT)?);
+    println!("math.floor_all:         {:?}", time(&mut lua, VECTORIZED)?);
+    Ok(())
+}
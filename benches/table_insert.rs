@@ -0,0 +1,74 @@
+//! Times inserting at and removing from the front of a 10k element sequence with `table.insert` and
+//! `table.remove`, which shift every other element. Run with `cargo bench --bench table_insert`.
+
+use std::time::{Duration, Instant};
+
+use piccolo::{Closure, Lua, StaticError, Thread};
+
+const ITERATIONS: u32 = 100;
+
+const SETUP: &str = r#"
+    values = {}
+    for i = 1, 10000 do
+        values[i] = i
+    end
+"#;
+
+const INSERT_FRONT: &str = r#"
+    local insert = table.insert
+    for i = 1, 100 do
+        insert(values, 1, i)
+    end
+    return #values
+"#;
+
+const REMOVE_FRONT: &str = r#"
+    local remove = table.remove
+    for i = 1, 100 do
+        remove(values, 1)
+    end
+    return #values
+"#;
+
+fn run(lua: &mut Lua, source: &'static str) -> Result<i64, StaticError> {
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, source.as_bytes())?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(thread))
+    })?;
+    lua.run_thread::<Option<i64>>(&thread)
+        .map(|len| len.unwrap_or(0))
+}
+
+fn time(lua: &mut Lua, source: &'static str, len: i64) -> Result<Duration, StaticError> {
+    // Undo each change afterwards, so that every iteration starts from the same sequence.
+    let undo = if len > 10000 {
+        REMOVE_FRONT
+    } else {
+        INSERT_FRONT
+    };
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        assert_eq!(run(lua, source)?, len);
+        elapsed += start.elapsed();
+        run(lua, undo)?;
+    }
+    Ok(elapsed / ITERATIONS)
+}
+
+fn main() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    run(&mut lua, SETUP)?;
+
+    println!(
+        "100 table.insert at the front: {:?}",
+        time(&mut lua, INSERT_FRONT, 10100)?
+    );
+    println!(
+        "100 table.remove at the front: {:?}",
+        time(&mut lua, REMOVE_FRONT, 9900)?
+    );
+    Ok(())
+}
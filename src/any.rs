@@ -1,6 +1,6 @@
 use std::{any::TypeId, fmt};
 
-use gc_arena::{barrier::Write, Collect, Gc, Mutation, Root, Rootable};
+use gc_arena::{barrier::Write, Collect, Finalization, Gc, GcWeak, Mutation, Root, Rootable};
 
 /// Garbage collected `Any` type that can be downcast.
 //
@@ -141,6 +141,41 @@ impl<'gc, M> AnyValue<'gc, M> {
         // SAFETY: We have just called the write barrier for the containing `Gc`.
         Some(unsafe { Write::assume(root) })
     }
+
+    pub fn downgrade(self) -> AnyValueWeak<'gc, M> {
+        AnyValueWeak(Gc::downgrade(self.0))
+    }
+}
+
+/// A weak reference to an `AnyValue`, which does not keep it alive.
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct AnyValueWeak<'gc, M: 'gc>(GcWeak<'gc, Header<M>>);
+
+impl<'gc, M> Copy for AnyValueWeak<'gc, M> {}
+
+impl<'gc, M> Clone for AnyValueWeak<'gc, M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'gc, M> AnyValueWeak<'gc, M> {
+    pub fn upgrade(self, mc: &Mutation<'gc>) -> Option<AnyValue<'gc, M>> {
+        self.0.upgrade(mc).map(AnyValue)
+    }
+
+    pub fn is_dead(self, fc: &Finalization<'gc>) -> bool {
+        self.0.is_dead(fc)
+    }
+
+    pub fn resurrect(self, fc: &Finalization<'gc>) -> Option<AnyValue<'gc, M>> {
+        self.0.resurrect(fc).map(AnyValue)
+    }
+
+    pub fn as_ptr(self) -> *const () {
+        GcWeak::as_ptr(self.0) as *const ()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,575 @@
+//! Saving compiled function prototypes as bytes and loading them again, as done by `string.dump`
+//! and by `load` for binary chunks.
+//!
+//! A dumped prototype starts with a header recording the format version and byte order, and
+//! loading fails with an [`UndumpError`] if either does not match. All values are stored little
+//! endian.
+//!
+//! Like PUC-Rio Lua, loading does not verify the bytecode itself. Bytecode which was not produced
+//! by [`dump`] may fail in arbitrary ways when run, including panics (though never undefined
+//! behavior), so it should not be loaded from untrusted sources. For this reason the `load`
+//! function only accepts binary chunks when its mode argument contains 'b', its default mode is
+//! "t".
+
+use allocator_api2::{boxed, vec};
+use gc_arena::{allocator_api::MetricsAlloc, Gc};
+use thiserror::Error;
+
+use crate::{
+    compiler::{FunctionRef, LineNumber, LocalVariable},
+    opcode::{OpCode, Operation, RCIndex},
+    types::{
+        ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueDescriptor,
+        UpValueIndex, VarCount,
+    },
+    Constant, Context, FunctionProto, String,
+};
+
+/// Every binary chunk starts with this signature, and `load` treats any chunk starting with its
+/// first byte as binary.
+pub const SIGNATURE: &[u8] = b"\x1bpiccolo";
+
+/// Changed whenever the encoding of prototypes or opcodes changes.
+const FORMAT_VERSION: u8 = 1;
+const LITTLE_ENDIAN: u8 = 0;
+
+// Written after the header to catch chunks which were corrupted in ways the header cannot detect,
+// such as line ending conversion.
+const CHECK_INTEGER: i64 = 0x5678;
+const CHECK_NUMBER: f64 = 370.5;
+
+// Prototypes are read recursively, so limit the nesting of binary chunks to protect the Rust
+// stack. This is far more nesting than the compiler allows.
+const MAX_PROTOTYPE_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum UndumpError {
+    #[error("not a binary chunk")]
+    NotBinary,
+    #[error("bad binary format (version mismatch)")]
+    VersionMismatch,
+    #[error("bad binary format (byte order mismatch)")]
+    ByteOrderMismatch,
+    #[error("bad binary format (corrupted chunk)")]
+    Corrupted,
+    #[error("truncated precompiled chunk")]
+    Truncated,
+}
+
+/// Serializes a prototype along with all of its nested prototypes.
+///
+/// If `strip` is true, debug information (line numbers, local variable names, and upvalue names)
+/// is left out.
+pub fn dump(proto: &FunctionProto<'_>, strip: bool) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.bytes(SIGNATURE);
+    w.u8(FORMAT_VERSION);
+    w.u8(LITTLE_ENDIAN);
+    w.i64(CHECK_INTEGER);
+    w.f64(CHECK_NUMBER);
+    w.string(proto.chunk_name.as_bytes());
+    w.proto(proto, strip);
+    w.0
+}
+
+/// Loads a prototype that was serialized with [`dump`].
+pub fn undump<'gc>(ctx: Context<'gc>, bytes: &[u8]) -> Result<FunctionProto<'gc>, UndumpError> {
+    let mut r = Reader { ctx, bytes };
+
+    if r.bytes.first() != SIGNATURE.first() {
+        return Err(UndumpError::NotBinary);
+    }
+    if r.take(SIGNATURE.len())? != SIGNATURE {
+        return Err(UndumpError::Corrupted);
+    }
+    if r.u8()? != FORMAT_VERSION {
+        return Err(UndumpError::VersionMismatch);
+    }
+    if r.u8()? != LITTLE_ENDIAN {
+        return Err(UndumpError::ByteOrderMismatch);
+    }
+    if r.i64()? != CHECK_INTEGER || r.f64()? != CHECK_NUMBER {
+        return Err(UndumpError::Corrupted);
+    }
+
+    let chunk_name = r.string()?;
+    let proto = r.proto(chunk_name, 0)?;
+    if !r.bytes.is_empty() {
+        return Err(UndumpError::Corrupted);
+    }
+    Ok(proto)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.0.extend_from_slice(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn i64(&mut self, v: i64) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn f64(&mut self, v: f64) {
+        self.u64(v.to_bits());
+    }
+
+    fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+
+    fn len(&mut self, v: usize) {
+        self.usize(v);
+    }
+
+    fn string(&mut self, v: &[u8]) {
+        self.len(v.len());
+        self.bytes(v);
+    }
+
+    fn proto(&mut self, proto: &FunctionProto<'_>, strip: bool) {
+        match &proto.reference {
+            FunctionRef::Named(name, line) => {
+                self.u8(0);
+                self.string(name.as_bytes());
+                self.u64(line.0);
+            }
+            FunctionRef::Expression(line) => {
+                self.u8(1);
+                self.u64(line.0);
+            }
+            FunctionRef::Chunk => self.u8(2),
+        }
+        self.u8(proto.fixed_params);
+        self.u16(proto.stack_size);
+
+        self.len(proto.constants.len());
+        for constant in proto.constants.iter() {
+            match constant {
+                Constant::Nil => self.u8(0),
+                Constant::Boolean(false) => self.u8(1),
+                Constant::Boolean(true) => self.u8(2),
+                Constant::Integer(i) => {
+                    self.u8(3);
+                    self.i64(*i);
+                }
+                Constant::Number(n) => {
+                    self.u8(4);
+                    self.f64(*n);
+                }
+                Constant::String(s) => {
+                    self.u8(5);
+                    self.string(s.as_bytes());
+                }
+            }
+        }
+
+        self.len(proto.opcodes.len());
+        for opcode in proto.opcodes.iter() {
+            write_operation(self, opcode.decode());
+        }
+
+        self.len(proto.upvalues.len());
+        for upvalue in proto.upvalues.iter() {
+            match *upvalue {
+                UpValueDescriptor::Environment => self.u8(0),
+                UpValueDescriptor::ParentLocal(r) => {
+                    self.u8(1);
+                    self.u8(r.0);
+                }
+                UpValueDescriptor::Outer(u) => {
+                    self.u8(2);
+                    self.u8(u.0);
+                }
+            }
+        }
+
+        self.len(proto.prototypes.len());
+        for p in proto.prototypes.iter() {
+            self.proto(p, strip);
+        }
+
+        if strip {
+            self.len(0);
+            self.len(0);
+            self.len(0);
+        } else {
+            self.len(proto.opcode_line_numbers.len());
+            for &(pc, line) in proto.opcode_line_numbers.iter() {
+                self.usize(pc);
+                self.u64(line.0);
+            }
+
+            self.len(proto.local_variables.len());
+            for local in proto.local_variables.iter() {
+                self.string(local.name.as_bytes());
+                self.u8(local.register.0);
+                self.usize(local.start_pc);
+                self.usize(local.end_pc);
+            }
+
+            self.len(proto.upvalue_names.len());
+            for name in proto.upvalue_names.iter() {
+                self.string(name.as_bytes());
+            }
+        }
+    }
+}
+
+struct Reader<'gc, 'a> {
+    ctx: Context<'gc>,
+    bytes: &'a [u8],
+}
+
+impl<'gc, 'a> Reader<'gc, 'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], UndumpError> {
+        if self.bytes.len() < len {
+            return Err(UndumpError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, UndumpError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, UndumpError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(UndumpError::Corrupted),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, UndumpError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, UndumpError> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, UndumpError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, UndumpError> {
+        Ok(self.u64()? as i64)
+    }
+
+    fn f64(&mut self) -> Result<f64, UndumpError> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, UndumpError> {
+        usize::try_from(self.u64()?).map_err(|_| UndumpError::Corrupted)
+    }
+
+    fn len(&mut self) -> Result<usize, UndumpError> {
+        let len = self.usize()?;
+        // Every element of every list takes at least one byte, so a longer length can only come
+        // from a truncated or corrupted chunk. Checking this up front keeps us from reserving huge
+        // amounts of memory.
+        if len > self.bytes.len() {
+            return Err(UndumpError::Truncated);
+        }
+        Ok(len)
+    }
+
+    fn string(&mut self) -> Result<String<'gc>, UndumpError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        Ok(self.ctx.state.strings.intern(&self.ctx, bytes))
+    }
+
+    fn list<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T, UndumpError>,
+    ) -> Result<boxed::Box<[T], MetricsAlloc<'gc>>, UndumpError> {
+        let len = self.len()?;
+        let mut list = vec::Vec::with_capacity_in(len, MetricsAlloc::new(&self.ctx));
+        for _ in 0..len {
+            list.push(read(self)?);
+        }
+        Ok(list.into_boxed_slice())
+    }
+
+    fn proto(
+        &mut self,
+        chunk_name: String<'gc>,
+        depth: usize,
+    ) -> Result<FunctionProto<'gc>, UndumpError> {
+        if depth > MAX_PROTOTYPE_DEPTH {
+            return Err(UndumpError::Corrupted);
+        }
+
+        let reference = match self.u8()? {
+            0 => FunctionRef::Named(self.string()?, LineNumber(self.u64()?)),
+            1 => FunctionRef::Expression(LineNumber(self.u64()?)),
+            2 => FunctionRef::Chunk,
+            _ => return Err(UndumpError::Corrupted),
+        };
+        let fixed_params = self.u8()?;
+        let stack_size = self.u16()?;
+
+        let constants = self.list(|r| {
+            Ok(match r.u8()? {
+                0 => Constant::Nil,
+                1 => Constant::Boolean(false),
+                2 => Constant::Boolean(true),
+                3 => Constant::Integer(r.i64()?),
+                4 => Constant::Number(r.f64()?),
+                5 => Constant::String(r.string()?),
+                _ => return Err(UndumpError::Corrupted),
+            })
+        })?;
+
+        let opcodes = self.list(|r| Ok(OpCode::encode(read_operation(r)?)))?;
+
+        let upvalues = self.list(|r| {
+            Ok(match r.u8()? {
+                0 => UpValueDescriptor::Environment,
+                1 => UpValueDescriptor::ParentLocal(RegisterIndex(r.u8()?)),
+                2 => UpValueDescriptor::Outer(UpValueIndex(r.u8()?)),
+                _ => return Err(UndumpError::Corrupted),
+            })
+        })?;
+
+        let prototypes = self.list(|r| {
+            let proto = r.proto(chunk_name, depth + 1)?;
+            Ok(Gc::new(&r.ctx, proto))
+        })?;
+
+        let opcode_line_numbers = self.list(|r| Ok((r.usize()?, LineNumber(r.u64()?))))?;
+
+        let local_variables = self.list(|r| {
+            Ok(LocalVariable {
+                name: r.string()?,
+                register: RegisterIndex(r.u8()?),
+                start_pc: r.usize()?,
+                end_pc: r.usize()?,
+            })
+        })?;
+
+        let upvalue_names = self.list(|r| r.string())?;
+
+        Ok(FunctionProto {
+            chunk_name,
+            reference,
+            fixed_params,
+            stack_size,
+            constants,
+            opcodes,
+            upvalues,
+            prototypes,
+            opcode_line_numbers,
+            local_variables,
+            upvalue_names,
+        })
+    }
+}
+
+trait Field: Sized {
+    fn write(self, w: &mut Writer);
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError>;
+}
+
+impl Field for u8 {
+    fn write(self, w: &mut Writer) {
+        w.u8(self);
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        r.u8()
+    }
+}
+
+impl Field for bool {
+    fn write(self, w: &mut Writer) {
+        w.u8(self as u8);
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        r.bool()
+    }
+}
+
+impl Field for i16 {
+    fn write(self, w: &mut Writer) {
+        w.bytes(&self.to_le_bytes());
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        r.i16()
+    }
+}
+
+impl Field for RegisterIndex {
+    fn write(self, w: &mut Writer) {
+        w.u8(self.0);
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        Ok(RegisterIndex(r.u8()?))
+    }
+}
+
+impl Field for ConstantIndex16 {
+    fn write(self, w: &mut Writer) {
+        w.u16(self.0);
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        Ok(ConstantIndex16(r.u16()?))
+    }
+}
+
+impl Field for UpValueIndex {
+    fn write(self, w: &mut Writer) {
+        w.u8(self.0);
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        Ok(UpValueIndex(r.u8()?))
+    }
+}
+
+impl Field for PrototypeIndex {
+    fn write(self, w: &mut Writer) {
+        w.u8(self.0);
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        Ok(PrototypeIndex(r.u8()?))
+    }
+}
+
+// Both `Opt254` and `VarCount` use 255 to mean "none" or "variable".
+impl Field for Opt254 {
+    fn write(self, w: &mut Writer) {
+        w.u8(self.to_u8().unwrap_or(255));
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        Ok(Opt254::try_some(r.u8()?).unwrap_or(Opt254::none()))
+    }
+}
+
+impl Field for VarCount {
+    fn write(self, w: &mut Writer) {
+        w.u8(self.to_constant().unwrap_or(255));
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        Ok(VarCount::try_constant(r.u8()?).unwrap_or(VarCount::variable()))
+    }
+}
+
+impl Field for RCIndex {
+    fn write(self, w: &mut Writer) {
+        match self {
+            RCIndex::Register(i) => {
+                w.u8(0);
+                w.u8(i.0);
+            }
+            RCIndex::Constant(i) => {
+                w.u8(1);
+                w.u8(i.0);
+            }
+        }
+    }
+
+    fn read(r: &mut Reader<'_, '_>) -> Result<Self, UndumpError> {
+        match r.u8()? {
+            0 => Ok(RCIndex::Register(RegisterIndex(r.u8()?))),
+            1 => Ok(RCIndex::Constant(ConstantIndex8(r.u8()?))),
+            _ => Err(UndumpError::Corrupted),
+        }
+    }
+}
+
+// Operations are stored as a one byte tag followed by each of their fields in order. Tags must
+// never be reused for a different operation without bumping `FORMAT_VERSION`.
+macro_rules! operations {
+    ($($tag:literal => $name:ident { $($field:ident),* $(,)? }),* $(,)?) => {
+        fn write_operation(w: &mut Writer, operation: Operation) {
+            match operation {
+                $(Operation::$name { $($field),* } => {
+                    w.u8($tag);
+                    $(Field::write($field, w);)*
+                })*
+            }
+        }
+
+        fn read_operation(r: &mut Reader<'_, '_>) -> Result<Operation, UndumpError> {
+            Ok(match r.u8()? {
+                $($tag => Operation::$name { $($field: Field::read(r)?),* },)*
+                _ => return Err(UndumpError::Corrupted),
+            })
+        }
+    };
+}
+
+operations! {
+    0 => Move { dest, source },
+    1 => LoadConstant { dest, constant },
+    2 => LoadBool { dest, value, skip_next },
+    3 => LoadNil { dest, count },
+    4 => NewTable { dest, array_size, map_size },
+    5 => GetTable { dest, table, key },
+    6 => SetTable { table, key, value },
+    7 => GetUpTable { dest, table, key },
+    8 => SetUpTable { table, key, value },
+    9 => SetList { base, count },
+    10 => Call { func, args, returns },
+    11 => TailCall { func, args },
+    12 => Return { start, count },
+    13 => VarArgs { dest, count },
+    14 => Jump { offset, close_upvalues },
+    15 => ToBeClosed { value },
+    16 => Test { value, is_true },
+    17 => TestSet { dest, value, is_true },
+    18 => Closure { dest, proto },
+    19 => NumericForPrep { base, jump },
+    20 => NumericForLoop { base, jump },
+    21 => GenericForCall { base, var_count },
+    22 => GenericForLoop { base, jump },
+    23 => Method { base, table, key },
+    24 => Concat { dest, source, count },
+    25 => GetUpValue { dest, source },
+    26 => SetUpValue { dest, source },
+    27 => Length { dest, source },
+    28 => Eq { skip_if, left, right },
+    29 => Less { skip_if, left, right },
+    30 => LessEq { skip_if, left, right },
+    31 => Not { dest, source },
+    32 => Minus { dest, source },
+    33 => Add { dest, left, right },
+    34 => Sub { dest, left, right },
+    35 => Mul { dest, left, right },
+    36 => Div { dest, left, right },
+    37 => IDiv { dest, left, right },
+    38 => Mod { dest, left, right },
+    39 => Pow { dest, left, right },
+    40 => BitAnd { dest, left, right },
+    41 => BitOr { dest, left, right },
+    42 => BitXor { dest, left, right },
+    43 => ShiftLeft { dest, left, right },
+    44 => ShiftRight { dest, left, right },
+    45 => BitNot { dest, source },
+}
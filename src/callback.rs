@@ -4,16 +4,35 @@ use std::{
 };
 
 use allocator_api2::boxed;
-use gc_arena::{allocator_api::MetricsAlloc, Collect, Gc, Mutation};
+use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Finalization, Gc, Mutation};
 
 use crate::{Context, Error, Fuel, Function, Stack};
 
+/// What a `Callback` does once it has finished running.
 #[derive(Collect)]
 #[collect(no_drop)]
 pub enum CallbackReturn<'gc> {
+    /// Return the values in the stack to the caller.
     Return,
+    /// Continue by running the given `Sequence`, starting with the stack unchanged.
     Sequence(AnySequence<'gc>),
+    /// Yield the values in the stack from the containing coroutine, as `coroutine.yield` does.
+    ///
+    /// When the coroutine is resumed, the resume arguments are placed in the stack and passed to
+    /// the given `Sequence` if there is one, otherwise they are returned to the caller of this
+    /// callback. If the coroutine is resumed with an error, `Sequence::error` is called instead.
+    ///
+    /// Callbacks never run on a native stack of their own, so by the time the coroutine is
+    /// suspended the callback has already returned, and there is no Rust stack frame to come back
+    /// to. Any state needed after resuming must be moved into the `Sequence`, and any borrows or
+    /// other non-`'static` Rust state held by the callback cannot live across the yield.
     Yield(Option<AnySequence<'gc>>),
+    /// Call the given function with the arguments in the stack, in place of this callback.
+    ///
+    /// The function replaces the callback's frame rather than being called from it, so callbacks
+    /// that tail call each other, or Lua functions, do not grow the call stack. If a `Sequence` is
+    /// given, it receives the function's results (or error) before they are returned to the
+    /// caller.
     TailCall(Function<'gc>, Option<AnySequence<'gc>>),
 }
 
@@ -80,6 +99,32 @@ impl<'gc> AnyCallback<'gc> {
         Self(unsafe { Gc::cast::<Header>(hc) })
     }
 
+    /// Create a callback from a Rust function.
+    ///
+    /// The callback is given direct access to the `Stack`, which holds the arguments it was called
+    /// with and is where it should place its return values. Parsing arguments with
+    /// `Stack::consume` is convenient, but callbacks which only need to look at their arguments
+    /// can read the stack slots directly and avoid any intermediate allocation:
+    ///
+    /// ```
+    /// # use piccolo::{AnyCallback, CallbackReturn, Lua, TypeError, Value};
+    /// # let mut lua = Lua::core();
+    /// # lua.run(|ctx| {
+    /// let sum = AnyCallback::from_fn(&ctx, |_, _, stack| {
+    ///     let mut total: i64 = 0;
+    ///     for value in &*stack {
+    ///         total += value.to_integer().ok_or(TypeError {
+    ///             expected: "integer",
+    ///             found: value.type_name(),
+    ///         })?;
+    ///     }
+    ///     stack.clear();
+    ///     stack.push_back(Value::Integer(total));
+    ///     Ok(CallbackReturn::Return)
+    /// });
+    /// # ctx.state.globals.set(ctx, "sum", sum).unwrap();
+    /// # });
+    /// ```
     pub fn from_fn<F>(mc: &Mutation<'gc>, call: F) -> AnyCallback<'gc>
     where
         F: 'static
@@ -131,10 +176,54 @@ impl<'gc> AnyCallback<'gc> {
         AnyCallback::new(mc, RootCallback { root, call })
     }
 
+    /// Create a callback that owns a mutable, garbage collected state value.
+    ///
+    /// The state is allocated in a `Gc<RefLock<S>>`, so unlike state shared through something like
+    /// `Rc<RefCell<_>>`, it may hold `Gc` pointers, is traced by the collector, and is dropped along
+    /// with the callback.
+    ///
+    /// On every call, the state is mutably borrowed through `Gc::write`, which applies the write
+    /// barrier, so `call` may freely store new `Gc` pointers in it. The borrow lasts for the whole
+    /// of `call`, so the callback must not be called again while it is running. This can only
+    /// happen if `call` steps some other `Thread` which calls this callback, and doing so panics.
+    ///
+    /// ```
+    /// # use piccolo::{AnyCallback, CallbackReturn, Lua, Value};
+    /// # let mut lua = Lua::core();
+    /// # lua.run(|ctx| {
+    /// let counter = AnyCallback::from_fn_with_state(&ctx, 0i64, |count, ctx, _, stack| {
+    ///     *count += 1;
+    ///     stack.replace(ctx, *count);
+    ///     Ok(CallbackReturn::Return)
+    /// });
+    /// # ctx.state.globals.set(ctx, "counter", counter).unwrap();
+    /// # });
+    /// ```
+    pub fn from_fn_with_state<S, F>(mc: &Mutation<'gc>, state: S, call: F) -> AnyCallback<'gc>
+    where
+        S: 'gc + Collect,
+        F: 'static
+            + Fn(
+                &mut S,
+                Context<'gc>,
+                &mut Fuel,
+                &mut Stack<'gc>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    {
+        let state = Gc::new(mc, RefLock::new(state));
+        Self::from_fn_with(mc, state, move |state, ctx, fuel, stack| {
+            call(&mut state.borrow_mut(&ctx), ctx, fuel, stack)
+        })
+    }
+
     pub fn as_ptr(self) -> *const () {
         Gc::as_ptr(self.0) as *const ()
     }
 
+    pub(crate) fn is_dead(self, fc: &Finalization<'gc>) -> bool {
+        Gc::downgrade(self.0).is_dead(fc)
+    }
+
     pub fn call(
         self,
         ctx: Context<'gc>,
@@ -8,7 +8,8 @@ use gc_arena::{allocator_api::MetricsAlloc, lock::Lock, Collect, Gc, Mutation};
 use thiserror::Error;
 
 use crate::{
-    compiler::{self, CompiledPrototype},
+    bytecode::{self, UndumpError},
+    compiler::{self, CompiledPrototype, FunctionRef, LineNumber, LocalVariable},
     opcode::OpCode,
     types::UpValueDescriptor,
     Constant, Context, String, Table, Thread, Value,
@@ -25,29 +26,39 @@ pub enum ProtoCompileError {
 #[derive(Debug, Collect)]
 #[collect(no_drop)]
 pub struct FunctionProto<'gc> {
+    /// The name of the chunk this prototype was compiled from, used in error messages and stack
+    /// traces.
+    pub chunk_name: String<'gc>,
+    pub reference: FunctionRef<String<'gc>>,
     pub fixed_params: u8,
     pub stack_size: u16,
     pub constants: boxed::Box<[Constant<String<'gc>>], MetricsAlloc<'gc>>,
     pub opcodes: boxed::Box<[OpCode], MetricsAlloc<'gc>>,
     pub upvalues: boxed::Box<[UpValueDescriptor], MetricsAlloc<'gc>>,
     pub prototypes: boxed::Box<[Gc<'gc, FunctionProto<'gc>>], MetricsAlloc<'gc>>,
+    pub opcode_line_numbers: boxed::Box<[(usize, LineNumber)], MetricsAlloc<'gc>>,
+    pub local_variables: boxed::Box<[LocalVariable<String<'gc>>], MetricsAlloc<'gc>>,
+    pub upvalue_names: boxed::Box<[String<'gc>], MetricsAlloc<'gc>>,
 }
 
 impl<'gc> FunctionProto<'gc> {
     pub fn from_compiled(
         mc: &Mutation<'gc>,
+        chunk_name: String<'gc>,
         compiled_function: &CompiledPrototype<String<'gc>>,
     ) -> Self {
-        Self::from_compiled_map_strings(mc, compiled_function, |s| *s)
+        Self::from_compiled_map_strings(mc, chunk_name, compiled_function, |s| *s)
     }
 
     pub fn from_compiled_map_strings<S>(
         mc: &Mutation<'gc>,
+        chunk_name: String<'gc>,
         compiled_function: &CompiledPrototype<S>,
         map_string: impl Fn(&S) -> String<'gc> + Copy,
     ) -> Self {
         fn new<'gc, S>(
             mc: &Mutation<'gc>,
+            chunk_name: String<'gc>,
             compiled_function: &CompiledPrototype<S>,
             map_string: impl Fn(&S) -> String<'gc> + Copy,
         ) -> FunctionProto<'gc> {
@@ -64,31 +75,68 @@ impl<'gc> FunctionProto<'gc> {
             let opcodes = SliceExt::to_vec_in(compiled_function.opcodes.as_slice(), alloc.clone());
             let upvalues =
                 SliceExt::to_vec_in(compiled_function.upvalues.as_slice(), alloc.clone());
+            let opcode_line_numbers = SliceExt::to_vec_in(
+                compiled_function.opcode_line_numbers.as_slice(),
+                alloc.clone(),
+            );
+
+            let mut local_variables = vec::Vec::new_in(alloc.clone());
+            local_variables.extend(compiled_function.local_variables.iter().map(|v| {
+                LocalVariable {
+                    name: map_string(&v.name),
+                    register: v.register,
+                    start_pc: v.start_pc,
+                    end_pc: v.end_pc,
+                }
+            }));
+
+            let mut upvalue_names = vec::Vec::new_in(alloc.clone());
+            upvalue_names.extend(compiled_function.upvalue_names.iter().map(map_string));
 
             let mut prototypes = vec::Vec::new_in(alloc);
             prototypes.extend(
                 compiled_function
                     .prototypes
                     .iter()
-                    .map(|cf| Gc::new(mc, new(mc, cf, map_string))),
+                    .map(|cf| Gc::new(mc, new(mc, chunk_name, cf, map_string))),
             );
 
             FunctionProto {
+                chunk_name,
+                reference: match &compiled_function.reference {
+                    FunctionRef::Named(name, line) => FunctionRef::Named(map_string(name), *line),
+                    FunctionRef::Expression(line) => FunctionRef::Expression(*line),
+                    FunctionRef::Chunk => FunctionRef::Chunk,
+                },
                 fixed_params: compiled_function.fixed_params,
                 stack_size: compiled_function.stack_size,
                 constants: constants.into_boxed_slice(),
                 opcodes: opcodes.into_boxed_slice(),
                 upvalues: upvalues.into_boxed_slice(),
                 prototypes: prototypes.into_boxed_slice(),
+                opcode_line_numbers: opcode_line_numbers.into_boxed_slice(),
+                local_variables: local_variables.into_boxed_slice(),
+                upvalue_names: upvalue_names.into_boxed_slice(),
             }
         }
 
-        new(mc, compiled_function, map_string)
+        new(mc, chunk_name, compiled_function, map_string)
     }
 
+    /// Compile a prototype from source with the default chunk name of `[string]`.
     pub fn compile(
         ctx: Context<'gc>,
         source: impl Read,
+    ) -> Result<FunctionProto<'gc>, ProtoCompileError> {
+        Self::compile_with_name(ctx, "[string]", source)
+    }
+
+    /// Compile a prototype from source, using `chunk_name` as the source name in error messages
+    /// and stack traces.
+    pub fn compile_with_name(
+        ctx: Context<'gc>,
+        chunk_name: &str,
+        source: impl Read,
     ) -> Result<FunctionProto<'gc>, ProtoCompileError> {
         #[derive(Copy, Clone)]
         struct Interner<'gc>(Context<'gc>);
@@ -106,7 +154,27 @@ impl<'gc> FunctionProto<'gc> {
         let chunk = compiler::parse_chunk(source, interner)?;
         let compiled_function = compiler::compile_chunk(&chunk, interner)?;
 
-        Ok(FunctionProto::from_compiled(&ctx, &compiled_function))
+        Ok(FunctionProto::from_compiled(
+            &ctx,
+            ctx.state.strings.intern(&ctx, chunk_name.as_bytes()),
+            &compiled_function,
+        ))
+    }
+
+    /// Returns the source line of the opcode at index `pc`, if this prototype has any line
+    /// information.
+    pub fn line_number(&self, pc: usize) -> Option<LineNumber> {
+        let i = self
+            .opcode_line_numbers
+            .partition_point(|&(index, _)| index <= pc);
+        Some(self.opcode_line_numbers[i.checked_sub(1)?].1)
+    }
+
+    /// Returns the local variables that are in scope at opcode index `pc`, in declaration order.
+    pub fn active_locals(&self, pc: usize) -> impl Iterator<Item = &LocalVariable<String<'gc>>> {
+        self.local_variables
+            .iter()
+            .filter(move |v| v.start_pc <= pc && pc < v.end_pc)
     }
 }
 
@@ -121,6 +189,27 @@ pub enum UpValueState<'gc> {
 #[collect(no_drop)]
 pub struct UpValue<'gc>(pub Gc<'gc, Lock<UpValueState<'gc>>>);
 
+impl<'gc> UpValue<'gc> {
+    /// Returns the current value of this upvalue.
+    ///
+    /// If the upvalue is still open, this reads from the owning thread's stack, so it must not be
+    /// called while that thread is borrowed (such as from inside of the VM).
+    pub fn get(self) -> Value<'gc> {
+        match self.0.get() {
+            UpValueState::Open(thread, ind) => thread.stack_value(ind),
+            UpValueState::Closed(v) => v,
+        }
+    }
+
+    /// Sets the value of this upvalue, with the same restrictions as [`UpValue::get`].
+    pub fn set(self, mc: &Mutation<'gc>, value: Value<'gc>) {
+        match self.0.get() {
+            UpValueState::Open(thread, ind) => thread.set_stack_value(mc, ind, value),
+            UpValueState::Closed(_) => self.0.set(mc, UpValueState::Closed(value)),
+        }
+    }
+}
+
 #[derive(Debug, Collect)]
 #[collect(no_drop)]
 pub struct ClosureState<'gc> {
@@ -152,6 +241,8 @@ pub enum ClosureError {
     HasUpValues,
     #[error("closure requires _ENV upvalue but no environment was provided")]
     RequiresEnv,
+    #[error("closure has no _ENV upvalue")]
+    NoEnv,
 }
 
 impl<'gc> Closure<'gc> {
@@ -194,4 +285,74 @@ impl<'gc> Closure<'gc> {
         let proto = FunctionProto::compile(ctx, source)?;
         Ok(Closure::new(&ctx, proto, Some(env)).unwrap())
     }
+
+    /// Compile a top-level closure from source like [`Closure::load`], using `chunk_name` as the
+    /// source name in error messages and stack traces.
+    pub fn load_with_name(
+        ctx: Context<'gc>,
+        chunk_name: &str,
+        source: impl Read,
+    ) -> Result<Closure<'gc>, ProtoCompileError> {
+        let proto = FunctionProto::compile_with_name(ctx, chunk_name, source)?;
+        Ok(Closure::new(&ctx, proto, Some(ctx.state.globals)).unwrap())
+    }
+
+    /// Returns the current value of this closure's `_ENV` upvalue, or `None` if the closure does
+    /// not access `_ENV`.
+    ///
+    /// Like [`UpValue::get`], this must not be called while the thread owning an open `_ENV`
+    /// upvalue is borrowed.
+    pub fn env(self) -> Option<Value<'gc>> {
+        self.env_upvalue().map(|u| u.get())
+    }
+
+    /// Sets the value of this closure's `_ENV` upvalue, so that global accesses in the closure
+    /// read and write `env` instead.
+    ///
+    /// The `_ENV` upvalue is shared with any closures that captured it, so for a top-level chunk
+    /// this changes the environment of every function defined inside of it. Returns
+    /// [`ClosureError::NoEnv`] if the closure never accesses `_ENV`.
+    pub fn set_env(self, mc: &Mutation<'gc>, env: Table<'gc>) -> Result<(), ClosureError> {
+        self.env_upvalue()
+            .ok_or(ClosureError::NoEnv)?
+            .set(mc, Value::Table(env));
+        Ok(())
+    }
+
+    fn env_upvalue(self) -> Option<UpValue<'gc>> {
+        let proto = &self.0.proto;
+        let index = proto
+            .upvalues
+            .iter()
+            .position(|&u| u == UpValueDescriptor::Environment)
+            .or_else(|| {
+                proto
+                    .upvalue_names
+                    .iter()
+                    .position(|name| name.as_bytes() == b"_ENV")
+            })?;
+        self.0.upvalues.get(index).copied()
+    }
+
+    /// Load a closure from a binary chunk produced by [`bytecode::dump`].
+    ///
+    /// The loaded prototype need not be a top-level chunk. As in PUC-Rio Lua, the closure's first
+    /// upvalue, if it has any, is set to `env` and any other upvalues are set to nil.
+    pub fn load_binary(
+        ctx: Context<'gc>,
+        bytes: &[u8],
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, UndumpError> {
+        let proto = Gc::new(&ctx, bytecode::undump(ctx, bytes)?);
+        let mut upvalues = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        upvalues.extend((0..proto.upvalues.len()).map(|i| {
+            let value = if i == 0 {
+                Value::Table(env)
+            } else {
+                Value::Nil
+            };
+            UpValue(Gc::new(&ctx, Lock::new(UpValueState::Closed(value))))
+        }));
+        Ok(Closure(Gc::new(&ctx, ClosureState { proto, upvalues })))
+    }
 }
@@ -26,16 +26,16 @@ use super::{
     parser::{
         AssignmentStatement, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk,
         ConstructorField, Expression, FieldSuffix, ForStatement, FunctionCallStatement,
-        FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalFunctionStatement,
-        LocalStatement, PrimaryExpression, RecordKey, RepeatStatement, ReturnStatement,
-        SimpleExpression, Statement, SuffixPart, SuffixedExpression, TableConstructor,
-        UnaryOperator, WhileStatement,
+        FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LineAnnotated,
+        LineNumber, LocalAttribute, LocalFunctionStatement, LocalStatement, PrimaryExpression,
+        RecordKey, RepeatStatement, ReturnStatement, SimpleExpression, Statement, SuffixPart,
+        SuffixedExpression, TableConstructor, UnaryOperator, WhileStatement,
     },
     register_allocator::RegisterAllocator,
     StringInterner,
 };
 
-#[derive(Debug, Copy, Clone, Error)]
+#[derive(Debug, Clone, Error)]
 pub enum CompilerError {
     #[error("insufficient available registers")]
     Registers,
@@ -47,14 +47,20 @@ pub enum CompilerError {
     Functions,
     #[error("too many constants")]
     Constants,
-    #[error("label defined multiple times")]
-    DuplicateLabel,
-    #[error("goto target label not found")]
-    GotoInvalid,
-    #[error("jump into scope of new local variable")]
-    JumpLocal,
+    #[error("label '{0}' already defined")]
+    DuplicateLabel(String),
+    #[error("no visible label '{0}' for goto")]
+    GotoInvalid(String),
+    #[error("break outside a loop")]
+    BreakOutsideLoop,
+    #[error("continue outside a loop")]
+    ContinueOutsideLoop,
+    #[error("jumps into the scope of local '{0}'")]
+    JumpLocal(String),
     #[error("jump offset overflow")]
     JumpOverflow,
+    #[error("attempt to assign to const variable '{0}'")]
+    AssignToConst(String),
 }
 
 #[derive(Debug, Clone, Collect)]
@@ -67,6 +73,49 @@ pub struct CompiledPrototype<S> {
     pub opcodes: Vec<OpCode>,
     pub upvalues: Vec<UpValueDescriptor>,
     pub prototypes: Vec<Box<CompiledPrototype<S>>>,
+    pub reference: FunctionRef<S>,
+    /// Pairs of an opcode index and the source line of every opcode from that index up to the
+    /// next entry, sorted by opcode index.
+    pub opcode_line_numbers: Vec<(usize, LineNumber)>,
+    /// Every local variable declared in this function, in declaration order.
+    pub local_variables: Vec<LocalVariable<S>>,
+    /// The name of each upvalue, in the same order as `upvalues`.
+    pub upvalue_names: Vec<S>,
+}
+
+/// Debug information for a local variable, which lives in `register` while the pc of the function
+/// is within `start_pc..end_pc`.
+#[derive(Debug, Clone, Collect)]
+#[collect(no_drop)]
+pub struct LocalVariable<S> {
+    pub name: S,
+    pub register: RegisterIndex,
+    pub start_pc: usize,
+    pub end_pc: usize,
+}
+
+/// Describes how a function prototype was defined in its source, for use in error messages and
+/// stack traces.
+#[derive(Debug, Clone, Copy, Collect)]
+#[collect(no_drop)]
+pub enum FunctionRef<S> {
+    /// A function defined by a `function` or `local function` statement, along with its full name
+    /// (such as `a.b:c`) and the line of its definition.
+    Named(S, LineNumber),
+    /// An anonymous function expression defined on the given line.
+    Expression(LineNumber),
+    /// The top-level function of a chunk.
+    Chunk,
+}
+
+impl<S> FunctionRef<S> {
+    pub fn map_strings<S2>(self, f: impl Fn(S) -> S2) -> FunctionRef<S2> {
+        match self {
+            FunctionRef::Named(name, line) => FunctionRef::Named(f(name), line),
+            FunctionRef::Expression(line) => FunctionRef::Expression(line),
+            FunctionRef::Chunk => FunctionRef::Chunk,
+        }
+    }
 }
 
 impl<S> CompiledPrototype<S> {
@@ -87,6 +136,19 @@ impl<S> CompiledPrototype<S> {
                 .into_iter()
                 .map(|p| Box::new(p.map_strings(f)))
                 .collect(),
+            reference: self.reference.map_strings(f),
+            opcode_line_numbers: self.opcode_line_numbers,
+            local_variables: self
+                .local_variables
+                .into_iter()
+                .map(|v| LocalVariable {
+                    name: f(v.name),
+                    register: v.register,
+                    start_pc: v.start_pc,
+                    end_pc: v.end_pc,
+                })
+                .collect(),
+            upvalue_names: self.upvalue_names.into_iter().map(f).collect(),
         }
     }
 }
@@ -97,7 +159,7 @@ pub fn compile_chunk<S: StringInterner>(
 ) -> Result<CompiledPrototype<S::String>, CompilerError> {
     let mut compiler = Compiler {
         string_interner: create_string,
-        current_function: CompilerFunction::start(&[], true)?,
+        current_function: CompilerFunction::start(FunctionRef::Chunk, &[], true)?,
         upper_functions: Vec::new(),
     };
     compiler.block(&chunk.block)?;
@@ -111,6 +173,8 @@ struct Compiler<S: StringInterner> {
 }
 
 struct CompilerFunction<S> {
+    reference: FunctionRef<S>,
+
     constants: Vec<Constant<S>>,
     constant_table: FxHashMap<IdenticalConstant<S>, ConstantIndex16>,
 
@@ -121,7 +185,12 @@ struct CompilerFunction<S> {
 
     has_varargs: bool,
     fixed_params: u8,
-    locals: Vec<(S, RegisterIndex)>,
+    // Every local variable in scope along with its register and attribute, if it has one.
+    locals: Vec<(S, RegisterIndex, Option<LocalAttribute>)>,
+    // Debug information for every local ever declared, and the index in `local_variables` of each
+    // entry in `locals`.
+    local_variables: Vec<LocalVariable<S>>,
+    active_local_variables: Vec<usize>,
 
     blocks: Vec<BlockDescriptor>,
     unique_jump_id: u64,
@@ -129,11 +198,13 @@ struct CompilerFunction<S> {
     pending_jumps: Vec<PendingJump<S>>,
 
     operations: Vec<Operation>,
+    operation_lines: Vec<(usize, LineNumber)>,
 }
 
 impl<S> Default for CompilerFunction<S> {
     fn default() -> Self {
         Self {
+            reference: FunctionRef::Chunk,
             constants: Vec::new(),
             constant_table: FxHashMap::default(),
             upvalues: Vec::new(),
@@ -142,11 +213,14 @@ impl<S> Default for CompilerFunction<S> {
             has_varargs: false,
             fixed_params: 0,
             locals: Vec::new(),
+            local_variables: Vec::new(),
+            active_local_variables: Vec::new(),
             blocks: Vec::new(),
             unique_jump_id: 0,
             jump_targets: Vec::new(),
             pending_jumps: Vec::new(),
             operations: Vec::new(),
+            operation_lines: Vec::new(),
         }
     }
 }
@@ -218,6 +292,8 @@ enum JumpLabel<S> {
     Unique(u64),
     Named(S),
     Break,
+    // The end of the body of the innermost loop, where the next iteration begins.
+    Continue,
 }
 
 impl<S> PartialEq for JumpLabel<S>
@@ -229,6 +305,7 @@ where
             (JumpLabel::Unique(a), JumpLabel::Unique(b)) => a == b,
             (JumpLabel::Named(a), JumpLabel::Named(b)) => a.as_ref() == b.as_ref(),
             (JumpLabel::Break, JumpLabel::Break) => true,
+            (JumpLabel::Continue, JumpLabel::Continue) => true,
             _ => false,
         }
     }
@@ -236,6 +313,18 @@ where
 
 impl<S> Eq for JumpLabel<S> where S: AsRef<[u8]> {}
 
+impl<S: AsRef<[u8]>> JumpLabel<S> {
+    // The name of this label for use in error messages.
+    fn name(&self) -> String {
+        match self {
+            JumpLabel::Unique(id) => format!("<unique {id}>"),
+            JumpLabel::Named(name) => String::from_utf8_lossy(name.as_ref()).into_owned(),
+            JumpLabel::Break => "break".to_owned(),
+            JumpLabel::Continue => "continue".to_owned(),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct BlockDescriptor {
     // The index of the first local variable in this block. All locals above this will be freed when
@@ -246,6 +335,16 @@ struct BlockDescriptor {
     bottom_jump_target: usize,
     // True if any lower function has an upvalue reference to variables in this block
     owns_upvalues: bool,
+    // True if this block declares any to-be-closed variables
+    has_to_be_closed: bool,
+}
+
+impl BlockDescriptor {
+    // Whether leaving this block must close the upvalues and to-be-closed variables above
+    // `stack_bottom`.
+    fn needs_close(&self) -> bool {
+        self.owns_upvalues || self.has_to_be_closed
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -285,16 +384,17 @@ impl<S: StringInterner> Compiler<S> {
             stack_bottom: self.current_function.register_allocator.stack_top(),
             bottom_jump_target: self.current_function.jump_targets.len(),
             owns_upvalues: false,
+            has_to_be_closed: false,
         });
     }
 
     fn exit_block(&mut self) -> Result<(), CompilerError> {
         let last_block = self.current_function.blocks.pop().unwrap();
 
-        while let Some((_, last)) = self.current_function.locals.last() {
+        while let Some((_, last, _)) = self.current_function.locals.last() {
             if last.0 as u16 >= last_block.stack_bottom {
                 self.current_function.register_allocator.free(*last);
-                self.current_function.locals.pop();
+                self.current_function.pop_local();
             } else {
                 break;
             }
@@ -303,7 +403,7 @@ impl<S: StringInterner> Compiler<S> {
             .jump_targets
             .drain(last_block.bottom_jump_target..);
 
-        if last_block.owns_upvalues && !self.current_function.blocks.is_empty() {
+        if last_block.needs_close() && !self.current_function.blocks.is_empty() {
             self.current_function.operations.push(Operation::Jump {
                 offset: 0,
                 close_upvalues: u8::try_from(last_block.stack_bottom)
@@ -325,7 +425,7 @@ impl<S: StringInterner> Compiler<S> {
                     pending_jump.stack_top >= self.current_function.register_allocator.stack_top()
                 );
                 pending_jump.stack_top = self.current_function.register_allocator.stack_top();
-                pending_jump.close_upvalues |= last_block.owns_upvalues;
+                pending_jump.close_upvalues |= last_block.needs_close();
             }
         }
 
@@ -340,13 +440,13 @@ impl<S: StringInterner> Compiler<S> {
     fn block_statements(&mut self, block: &Block<S::String>) -> Result<(), CompilerError> {
         if let Some(return_statement) = &block.return_statement {
             for statement in &block.statements {
-                self.statement(statement)?;
+                self.annotated_statement(statement)?;
             }
-            self.return_statement(return_statement)?;
+            self.annotated_return_statement(return_statement)?;
         } else {
             let mut last = block.statements.len();
             for i in (0..block.statements.len()).rev() {
-                match &block.statements[i] {
+                match &block.statements[i].inner {
                     Statement::Label(_) => {}
                     _ => break,
                 }
@@ -356,17 +456,33 @@ impl<S: StringInterner> Compiler<S> {
 
             self.enter_block();
             for i in 0..block.statements.len() - trailing_labels.len() {
-                self.statement(&block.statements[i])?;
+                self.annotated_statement(&block.statements[i])?;
             }
             self.exit_block()?;
 
             for label_statement in trailing_labels {
-                self.statement(&label_statement)?;
+                self.annotated_statement(&label_statement)?;
             }
         }
         Ok(())
     }
 
+    fn annotated_statement(
+        &mut self,
+        statement: &LineAnnotated<Statement<S::String>>,
+    ) -> Result<(), CompilerError> {
+        self.current_function.set_line(statement.line);
+        self.statement(&statement.inner)
+    }
+
+    fn annotated_return_statement(
+        &mut self,
+        return_statement: &LineAnnotated<ReturnStatement<S::String>>,
+    ) -> Result<(), CompilerError> {
+        self.current_function.set_line(return_statement.line);
+        self.return_statement(&return_statement.inner)
+    }
+
     fn statement(&mut self, statement: &Statement<S::String>) -> Result<(), CompilerError> {
         match statement {
             Statement::If(if_statement) => self.if_statement(if_statement),
@@ -383,6 +499,7 @@ impl<S: StringInterner> Compiler<S> {
                 self.jump_target(JumpLabel::Named(label_statement.name.clone()))
             }
             Statement::Break => self.jump(JumpLabel::Break),
+            Statement::Continue => self.jump(JumpLabel::Continue),
             Statement::Goto(goto_statement) => {
                 self.jump(JumpLabel::Named(goto_statement.name.clone()))
             }
@@ -402,8 +519,15 @@ impl<S: StringInterner> Compiler<S> {
             .collect::<Result<Vec<_>, CompilerError>>()?;
 
         // A return of a single function call is a tail call, and this is the only thing
-        // in Lua that is considered a tail call
-        if returns.len() == 1 {
+        // in Lua that is considered a tail call. Tail calls would discard the current frame
+        // before any to-be-closed variables could be closed, so they are not made while any are in
+        // scope.
+        let has_to_be_closed = self
+            .current_function
+            .blocks
+            .iter()
+            .any(|b| b.has_to_be_closed);
+        if returns.len() == 1 && !has_to_be_closed {
             match returns.pop().unwrap() {
                 ExprDescriptor::FunctionCall { func, args } => {
                     let func = self.expr_discharge(*func, ExprDestination::PushNew)?;
@@ -507,11 +631,12 @@ impl<S: StringInterner> Compiler<S> {
                     .register_allocator
                     .push(1)
                     .ok_or(CompilerError::Registers)?;
-                self.current_function.locals.push((name.clone(), loop_var));
+                self.current_function.declare_local(name.clone(), loop_var);
 
                 self.block_statements(body)?;
                 self.exit_block()?;
 
+                self.jump_target(JumpLabel::Continue)?;
                 let for_loop_index = self.current_function.operations.len();
                 self.current_function
                     .operations
@@ -529,7 +654,7 @@ impl<S: StringInterner> Compiler<S> {
                             *prep_base == base && *jump == 0,
                             "instruction is not placeholder NumericForPrep"
                         );
-                        *jump = jump_offset(for_prep_index, for_loop_index)
+                        *jump = jump_offset(for_prep_index, for_loop_index + 1)
                             .ok_or(CompilerError::JumpOverflow)?;
                     }
                     _ => panic!("instruction is not placeholder NumericForPrep"),
@@ -550,10 +675,14 @@ impl<S: StringInterner> Compiler<S> {
             } => {
                 let loop_label = self.unique_jump_label();
 
+                // The loop's closing value is a to-be-closed variable, so the whole loop is in a
+                // block which closes it when the loop is left.
+                self.enter_block();
+
                 assert!(arguments.len() >= 1);
                 let base = if arguments.len() == 1 {
                     let args = self.expression(&arguments[0])?;
-                    self.expr_push_count(args, 3)?
+                    self.expr_push_count(args, 4)?
                 } else {
                     let iterator = self.expression(&arguments[0])?;
                     let top = self.expr_discharge(iterator, ExprDestination::PushNew)?;
@@ -572,9 +701,27 @@ impl<S: StringInterner> Compiler<S> {
                     };
                     self.expr_discharge(control, ExprDestination::PushNew)?;
 
+                    let closing = if let Some(closing) = arguments.get(3) {
+                        self.expression(closing)?
+                    } else {
+                        ExprDescriptor::Constant(Constant::Nil)
+                    };
+                    self.expr_discharge(closing, ExprDestination::PushNew)?;
+
                     top
                 };
 
+                self.current_function
+                    .operations
+                    .push(Operation::ToBeClosed {
+                        value: RegisterIndex(base.0 + 3),
+                    });
+                self.current_function
+                    .blocks
+                    .last_mut()
+                    .unwrap()
+                    .has_to_be_closed = true;
+
                 self.enter_block();
                 self.enter_block();
 
@@ -589,8 +736,7 @@ impl<S: StringInterner> Compiler<S> {
                     .ok_or(CompilerError::Registers)?;
                 for i in 0..name_count {
                     self.current_function
-                        .locals
-                        .push((names[i as usize].clone(), RegisterIndex(names_reg.0 + i)));
+                        .declare_local(names[i as usize].clone(), RegisterIndex(names_reg.0 + i));
                 }
 
                 self.jump(loop_label.clone())?;
@@ -599,6 +745,7 @@ impl<S: StringInterner> Compiler<S> {
                 self.block_statements(body)?;
                 self.exit_block()?;
 
+                self.jump_target(JumpLabel::Continue)?;
                 self.jump_target(loop_label)?;
                 self.current_function
                     .operations
@@ -624,6 +771,7 @@ impl<S: StringInterner> Compiler<S> {
                 self.current_function
                     .register_allocator
                     .pop_to(base.0 as u16);
+                self.exit_block()?;
             }
         }
         Ok(())
@@ -644,6 +792,7 @@ impl<S: StringInterner> Compiler<S> {
         self.enter_block();
 
         self.block_statements(&while_statement.block)?;
+        self.jump_target(JumpLabel::Continue)?;
         self.jump(start_label)?;
 
         self.jump_target(JumpLabel::Break)?;
@@ -667,12 +816,15 @@ impl<S: StringInterner> Compiler<S> {
         // `repeat` statements do not follow the trailing label rule, because the variables inside
         // the block are in scope for the `until` condition at the end.
         for statement in &repeat_statement.body.statements {
-            self.statement(statement)?;
+            self.annotated_statement(statement)?;
         }
         if let Some(return_statement) = &repeat_statement.body.return_statement {
-            self.return_statement(return_statement)?;
+            self.annotated_return_statement(return_statement)?;
         }
 
+        // Like a `goto`, a `continue` may not jump into the scope of a local which the condition
+        // could see.
+        self.jump_target(JumpLabel::Continue)?;
         let condition = self.expression(&repeat_statement.until)?;
         self.expr_test(condition, true)?;
         self.jump(start_label)?;
@@ -713,17 +865,33 @@ impl<S: StringInterner> Compiler<S> {
             self.get_environment()?
         };
 
+        let mut full_name = function_statement.name.as_ref().to_vec();
+        for field in &function_statement.fields {
+            full_name.push(b'.');
+            full_name.extend_from_slice(field.as_ref());
+        }
+        if let Some(method) = &function_statement.method {
+            full_name.push(b':');
+            full_name.extend_from_slice(method.as_ref());
+        }
+        let reference = FunctionRef::Named(
+            self.string_interner.intern(&full_name),
+            function_statement.definition.line_defined,
+        );
+
         let proto = if function_statement.method.is_some() {
             let mut parameters = vec![self.string_interner.intern(b"self")];
             parameters.extend_from_slice(&function_statement.definition.parameters);
 
             self.new_prototype(
+                reference,
                 &parameters,
                 function_statement.definition.has_varargs,
                 &function_statement.definition.body,
             )?
         } else {
             self.new_prototype(
+                reference,
                 &function_statement.definition.parameters,
                 function_statement.definition.has_varargs,
                 &function_statement.definition.body,
@@ -757,10 +925,10 @@ impl<S: StringInterner> Compiler<S> {
                 .operations
                 .push(Operation::LoadNil { dest, count });
             for i in 0..name_len {
-                self.current_function.locals.push((
+                self.current_function.declare_local(
                     local_statement.names[i].clone(),
                     RegisterIndex(dest.0 + i as u8),
-                ));
+                );
             }
         } else {
             for i in 0..val_len {
@@ -776,20 +944,35 @@ impl<S: StringInterner> Compiler<S> {
                     let dest = self.expr_push_count(expr, names_left)?;
 
                     for j in 0..names_left {
-                        self.current_function.locals.push((
+                        self.current_function.declare_local(
                             local_statement.names[val_len - 1 + j as usize].clone(),
                             RegisterIndex(dest.0 + j),
-                        ));
+                        );
                     }
                 } else {
                     let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
                     self.current_function
-                        .locals
-                        .push((local_statement.names[i].clone(), reg));
+                        .declare_local(local_statement.names[i].clone(), reg);
                 }
             }
         }
 
+        let first_local = self.current_function.locals.len() - name_len;
+        for (i, &attribute) in local_statement.attributes.iter().enumerate() {
+            self.current_function.locals[first_local + i].2 = attribute;
+            if attribute == Some(LocalAttribute::Close) {
+                let value = self.current_function.locals[first_local + i].1;
+                self.current_function
+                    .operations
+                    .push(Operation::ToBeClosed { value });
+                self.current_function
+                    .blocks
+                    .last_mut()
+                    .unwrap()
+                    .has_to_be_closed = true;
+            }
+        }
+
         Ok(())
     }
 
@@ -836,25 +1019,27 @@ impl<S: StringInterner> Compiler<S> {
             expr: ExprDescriptor<S::String>,
         ) -> Result<(), CompilerError> {
             match target {
-                AssignmentTarget::Name(name) => match this.find_variable(name.clone())? {
-                    VariableDescriptor::Local(dest) => {
-                        this.expr_discharge(expr, ExprDestination::Register(dest))?;
-                    }
-                    VariableDescriptor::UpValue(dest) => {
-                        let (source, source_is_temp) = this.expr_any_register(expr)?;
-                        this.current_function
-                            .operations
-                            .push(Operation::SetUpValue { source, dest });
-                        if source_is_temp {
-                            this.current_function.register_allocator.free(source);
+                AssignmentTarget::Name(name) => {
+                    match this.find_assignable_variable(name.clone())? {
+                        VariableDescriptor::Local(dest) => {
+                            this.expr_discharge(expr, ExprDestination::Register(dest))?;
+                        }
+                        VariableDescriptor::UpValue(dest) => {
+                            let (source, source_is_temp) = this.expr_any_register(expr)?;
+                            this.current_function
+                                .operations
+                                .push(Operation::SetUpValue { source, dest });
+                            if source_is_temp {
+                                this.current_function.register_allocator.free(source);
+                            }
+                        }
+                        VariableDescriptor::Global(name) => {
+                            let env = this.get_environment()?;
+                            let key = ExprDescriptor::Constant(Constant::String(name));
+                            this.set_table(env, key, expr)?;
                         }
                     }
-                    VariableDescriptor::Global(name) => {
-                        let env = this.get_environment()?;
-                        let key = ExprDescriptor::Constant(Constant::String(name));
-                        this.set_table(env, key, expr)?;
-                    }
-                },
+                }
 
                 AssignmentTarget::Field(table, field) => {
                     let table = this.suffixed_expression(table)?;
@@ -912,10 +1097,13 @@ impl<S: StringInterner> Compiler<S> {
             .push(1)
             .ok_or(CompilerError::Registers)?;
         self.current_function
-            .locals
-            .push((local_function.name.clone(), dest));
+            .declare_local(local_function.name.clone(), dest);
 
         let proto = self.new_prototype(
+            FunctionRef::Named(
+                local_function.name.clone(),
+                local_function.definition.line_defined,
+            ),
             &local_function.definition.parameters,
             local_function.definition.has_varargs,
             &local_function.definition.body,
@@ -1007,8 +1195,12 @@ impl<S: StringInterner> Compiler<S> {
         &mut self,
         function: &FunctionDefinition<S::String>,
     ) -> Result<ExprDescriptor<S::String>, CompilerError> {
-        let proto =
-            self.new_prototype(&function.parameters, function.has_varargs, &function.body)?;
+        let proto = self.new_prototype(
+            FunctionRef::Expression(function.line_defined),
+            &function.parameters,
+            function.has_varargs,
+            &function.body,
+        )?;
         Ok(ExprDescriptor::Closure(proto))
     }
 
@@ -1156,13 +1348,14 @@ impl<S: StringInterner> Compiler<S> {
 
     fn new_prototype(
         &mut self,
+        reference: FunctionRef<S::String>,
         parameters: &[S::String],
         has_varargs: bool,
         body: &Block<S::String>,
     ) -> Result<PrototypeIndex, CompilerError> {
         let old_current = mem::replace(
             &mut self.current_function,
-            CompilerFunction::start(parameters, has_varargs)?,
+            CompilerFunction::start(reference, parameters, has_varargs)?,
         );
         self.upper_functions.push(old_current);
         self.block(body)?;
@@ -1179,6 +1372,34 @@ impl<S: StringInterner> Compiler<S> {
         ))
     }
 
+    // Finds a variable like `find_variable`, returning an error if it is a `<const>` or `<close>`
+    // local.
+    fn find_assignable_variable(
+        &mut self,
+        name: S::String,
+    ) -> Result<VariableDescriptor<S::String>, CompilerError> {
+        // Upvalues always refer to the nearest local of the same name in an enclosing function, so
+        // the innermost local with this name across all functions is the one being assigned.
+        let is_const = iter::once(&self.current_function)
+            .chain(self.upper_functions.iter().rev())
+            .find_map(|function| {
+                function
+                    .locals
+                    .iter()
+                    .rev()
+                    .find(|(local_name, _, _)| local_name.as_ref() == name.as_ref())
+            })
+            .is_some_and(|(_, _, attribute)| attribute.is_some());
+
+        if is_const {
+            return Err(CompilerError::AssignToConst(
+                String::from_utf8_lossy(name.as_ref()).into_owned(),
+            ));
+        }
+
+        self.find_variable(name)
+    }
+
     fn find_variable(
         &mut self,
         name: S::String,
@@ -1199,7 +1420,7 @@ impl<S: StringInterner> Compiler<S> {
 
         for i in (0..=current_function).rev() {
             for j in (0..get_function(self, i).locals.len()).rev() {
-                let (local_name, register) = get_function(self, i).locals[j].clone();
+                let (local_name, register, _) = get_function(self, i).locals[j].clone();
                 if name.as_ref() == local_name.as_ref() {
                     if i == current_function {
                         return Ok(VariableDescriptor::Local(register));
@@ -1295,12 +1516,12 @@ impl<S: StringInterner> Compiler<S> {
         for jump_target in self.current_function.jump_targets.iter().rev() {
             if jump_target.label == target {
                 // We need to close upvalues only if any of the blocks we're jumping over own
-                // upvalues
+                // upvalues or to-be-closed variables
                 assert!(jump_target.stack_top <= current_stack_top);
                 assert!(jump_target.block_index <= current_block_index);
                 let needs_close_upvalues = jump_target.stack_top < current_stack_top
                     && (jump_target.block_index..=current_block_index)
-                        .any(|i| self.current_function.blocks[i].owns_upvalues);
+                        .any(|i| self.current_function.blocks[i].needs_close());
 
                 self.current_function.operations.push(Operation::Jump {
                     offset: jump_offset(jmp_inst, jump_target.instruction)
@@ -1347,7 +1568,7 @@ impl<S: StringInterner> Compiler<S> {
             if jump_target.block_index < current_block_index {
                 break;
             } else if jump_target.label == jump_label {
-                return Err(CompilerError::DuplicateLabel);
+                return Err(CompilerError::DuplicateLabel(jump_label.name()));
             }
         }
 
@@ -1375,7 +1596,16 @@ impl<S: StringInterner> Compiler<S> {
         for pending_jump in resolving_jumps {
             assert!(pending_jump.stack_top <= current_stack_top);
             if pending_jump.stack_top < current_stack_top {
-                return Err(CompilerError::JumpLocal);
+                // The first local declared after the jump is the one it would jump into the scope
+                // of.
+                let local = self
+                    .current_function
+                    .locals
+                    .iter()
+                    .find(|(_, register, _)| register.0 as u16 == pending_jump.stack_top)
+                    .map(|(name, _, _)| String::from_utf8_lossy(name.as_ref()).into_owned())
+                    .unwrap_or_default();
+                return Err(CompilerError::JumpLocal(local));
             }
 
             match &mut self.current_function.operations[pending_jump.instruction] {
@@ -2203,9 +2433,14 @@ impl<S: StringInterner> Compiler<S> {
     }
 }
 
-impl<S: Clone> CompilerFunction<S> {
-    fn start(parameters: &[S], has_varargs: bool) -> Result<CompilerFunction<S>, CompilerError> {
+impl<S: AsRef<[u8]> + Clone> CompilerFunction<S> {
+    fn start(
+        reference: FunctionRef<S>,
+        parameters: &[S],
+        has_varargs: bool,
+    ) -> Result<CompilerFunction<S>, CompilerError> {
         let mut function = CompilerFunction::default();
+        function.reference = reference;
         let fixed_params: u8 = parameters
             .len()
             .try_into()
@@ -2216,20 +2451,48 @@ impl<S: Clone> CompilerFunction<S> {
         function.has_varargs = has_varargs;
         function.fixed_params = fixed_params;
         for i in 0..fixed_params {
-            function
-                .locals
-                .push((parameters[i as usize].clone(), RegisterIndex(i)));
+            function.declare_local(parameters[i as usize].clone(), RegisterIndex(i));
         }
         Ok(function)
     }
 
+    // Brings a new local variable into scope, starting from the next operation.
+    fn declare_local(&mut self, name: S, register: RegisterIndex) {
+        self.active_local_variables.push(self.local_variables.len());
+        self.local_variables.push(LocalVariable {
+            name: name.clone(),
+            register,
+            start_pc: self.operations.len(),
+            end_pc: self.operations.len(),
+        });
+        self.locals.push((name, register, None));
+    }
+
+    // Takes the most recently declared local variable out of scope, returning its register.
+    fn pop_local(&mut self) -> Option<RegisterIndex> {
+        let (_, register, _) = self.locals.pop()?;
+        let index = self.active_local_variables.pop().unwrap();
+        self.local_variables[index].end_pc = self.operations.len();
+        Some(register)
+    }
+
+    // Marks every operation pushed after this point as belonging to the given source line.
+    fn set_line(&mut self, line: LineNumber) {
+        let next = self.operations.len();
+        match self.operation_lines.last_mut() {
+            Some((index, last_line)) if *index == next => *last_line = line,
+            Some((_, last_line)) if *last_line == line => {}
+            _ => self.operation_lines.push((next, line)),
+        }
+    }
+
     fn finish(mut self) -> Result<CompiledPrototype<S>, CompilerError> {
         self.operations.push(Operation::Return {
             start: RegisterIndex(0),
             count: VarCount::constant(0),
         });
         assert!(self.locals.len() == self.fixed_params as usize);
-        for (_, r) in self.locals.drain(..) {
+        while let Some(r) = self.pop_local() {
             self.register_allocator.free(r);
         }
         assert_eq!(
@@ -2238,8 +2501,12 @@ impl<S: Clone> CompilerFunction<S> {
             "register leak detected"
         );
 
-        if !self.pending_jumps.is_empty() {
-            return Err(CompilerError::GotoInvalid);
+        if let Some(pending_jump) = self.pending_jumps.first() {
+            return Err(match pending_jump.target {
+                JumpLabel::Break => CompilerError::BreakOutsideLoop,
+                JumpLabel::Continue => CompilerError::ContinueOutsideLoop,
+                ref target => CompilerError::GotoInvalid(target.name()),
+            });
         }
 
         Ok(CompiledPrototype {
@@ -2254,7 +2521,11 @@ impl<S: Clone> CompilerFunction<S> {
                 .map(OpCode::encode)
                 .collect(),
             upvalues: self.upvalues.iter().map(|(_, d)| *d).collect(),
+            upvalue_names: self.upvalues.into_iter().map(|(n, _)| n).collect(),
             prototypes: self.functions.into_iter().map(|f| Box::new(f)).collect(),
+            reference: self.reference,
+            opcode_line_numbers: self.operation_lines,
+            local_variables: self.local_variables,
         })
     }
 }
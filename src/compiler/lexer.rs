@@ -11,6 +11,8 @@ use super::StringInterner;
 #[derive(Clone)]
 pub enum Token<S> {
     Break,
+    // Only produced with the `continue` feature enabled, otherwise `continue` is a plain name.
+    Continue,
     Do,
     Else,
     ElseIf,
@@ -77,6 +79,7 @@ impl<S: AsRef<[u8]>> PartialEq for Token<S> {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Token::Break, Token::Break) => true,
+            (Token::Continue, Token::Continue) => true,
             (Token::Do, Token::Do) => true,
             (Token::Else, Token::Else) => true,
             (Token::ElseIf, Token::ElseIf) => true,
@@ -144,6 +147,7 @@ impl<S: AsRef<[u8]>> fmt::Debug for Token<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::Break => write!(f, "Break"),
+            Token::Continue => write!(f, "Continue"),
             Token::Do => write!(f, "Do"),
             Token::Else => write!(f, "Else"),
             Token::ElseIf => write!(f, "ElseIf"),
@@ -754,6 +758,9 @@ where
     // Reads a hex or decimal integer or floating point identifier. Allows decimal integers (123),
     // hex integers (0xdeadbeef), decimal floating point with optional exponent and exponent sign
     // (3.21e+1), and hex floats with optional exponent and exponent sign (0xe.2fp-1c).
+    //
+    // With the `digit-separators` feature enabled, digits may also be separated by single
+    // underscores (1_000, 0xDEAD_BEEF).
     fn read_numeral(&mut self) -> Result<Token<S::String>, LexerError> {
         let p1 = self.peek(0).unwrap().unwrap();
         assert!(p1 == b'.' || is_digit(p1));
@@ -769,14 +776,19 @@ where
         }
 
         let mut has_radix = false;
+        let mut after_digit = false;
         while let Some(c) = self.peek(0)? {
             if c == b'.' && !has_radix {
                 self.string_buffer.push(b'.');
                 has_radix = true;
+                after_digit = false;
                 self.advance(1);
             } else if (!is_hex && is_digit(c)) || (is_hex && is_hex_digit(c)) {
                 self.string_buffer.push(c);
+                after_digit = true;
                 self.advance(1);
+            } else if cfg!(feature = "digit-separators") && c == b'_' {
+                self.skip_digit_separator(after_digit, is_hex)?;
             } else {
                 break;
             }
@@ -798,10 +810,14 @@ where
                     }
                 }
 
+                let mut after_digit = false;
                 while let Some(c) = self.peek(0)? {
                     if is_digit(c) {
                         self.string_buffer.push(c);
+                        after_digit = true;
                         self.advance(1);
+                    } else if cfg!(feature = "digit-separators") && c == b'_' {
+                        self.skip_digit_separator(after_digit, false)?;
                     } else {
                         break;
                     }
@@ -830,6 +846,21 @@ where
         ))
     }
 
+    // Skips a `_` digit separator in a numeral. A separator must appear directly between two
+    // digits, so leading, trailing, and doubled underscores, as well as underscores next to the
+    // radix point, exponent, or hex prefix, are malformed numbers.
+    fn skip_digit_separator(&mut self, after_digit: bool, is_hex: bool) -> Result<(), LexerError> {
+        let before_digit = match self.peek(1)? {
+            Some(c) => (!is_hex && is_digit(c)) || (is_hex && is_hex_digit(c)),
+            None => false,
+        };
+        if !after_digit || !before_digit {
+            return Err(LexerError::BadNumber);
+        }
+        self.advance(1);
+        Ok(())
+    }
+
     fn peek(&mut self, n: usize) -> Result<Option<u8>, LexerError> {
         if let Some(source) = self.source.as_mut() {
             while self.peek_buffer.len() <= n {
@@ -893,7 +924,7 @@ pub fn read_integer(s: &[u8]) -> Option<i64> {
 pub fn read_hex_integer(s: &[u8]) -> Option<i64> {
     let (is_neg, s) = read_neg(s);
 
-    if s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
+    if s.len() < 3 || s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
         return None;
     }
 
@@ -1035,6 +1066,7 @@ fn get_char_token<S>(c: u8) -> Option<Token<S>> {
 fn get_reserved_word_token<S>(word: &[u8]) -> Option<Token<S>> {
     match word {
         b"break" => Some(Token::Break),
+        b"continue" if cfg!(feature = "continue") => Some(Token::Continue),
         b"do" => Some(Token::Do),
         b"else" => Some(Token::Else),
         b"elseif" => Some(Token::ElseIf),
@@ -1239,6 +1271,55 @@ mod tests {
         );
     }
 
+    #[cfg(not(feature = "digit-separators"))]
+    #[test]
+    fn no_digit_separators() {
+        test_tokens("1_000", &[Token::Integer(1), name_token("_000")]);
+    }
+
+    #[cfg(feature = "digit-separators")]
+    #[test]
+    fn digit_separators() {
+        test_tokens(
+            r#"
+            1_000
+            0xDEAD_BEEF
+            1_000.000_1
+            1e1_0
+            0x1_0p1_0
+        "#,
+            &[
+                Token::Integer(1000),
+                Token::Integer(0xdeadbeef),
+                Token::Float(1000.0001),
+                Token::Float(1e10),
+                Token::Float(16384.0),
+            ],
+        );
+
+        for source in [
+            "1_", "1__000", "0x_FF", "1_.5", "1._5", "1_e5", "1e_5", "0xFF_", "1e5_",
+        ] {
+            let mut lexer = Lexer::new(source.as_bytes(), BasicInterner::default());
+            assert!(
+                matches!(lexer.read_token(), Err(LexerError::BadNumber)),
+                "{source} should be a malformed number"
+            );
+        }
+    }
+
+    #[cfg(not(feature = "continue"))]
+    #[test]
+    fn continue_is_a_name() {
+        test_tokens("continue", &[name_token("continue")]);
+    }
+
+    #[cfg(feature = "continue")]
+    #[test]
+    fn continue_keyword() {
+        test_tokens("continue", &[Token::Continue]);
+    }
+
     #[test]
     fn words() {
         test_tokens(
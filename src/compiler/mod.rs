@@ -6,8 +6,8 @@ pub mod parser;
 mod register_allocator;
 
 pub use self::{
-    compiler::{compile_chunk, CompiledPrototype, CompilerError},
+    compiler::{compile_chunk, CompiledPrototype, CompilerError, FunctionRef, LocalVariable},
     interning::StringInterner,
-    parser::parse_chunk,
     parser::ParserError,
+    parser::{parse_chunk, LineNumber},
 };
@@ -105,11 +105,20 @@ pub fn simple_binop_const_fold<S: AsRef<[u8]>>(
     right: &Constant<S>,
 ) -> Option<Constant<S>> {
     match simple_binop {
-        SimpleBinOp::Add => left.add(right),
-        SimpleBinOp::Sub => left.subtract(right),
-        SimpleBinOp::Mul => left.multiply(right),
-        SimpleBinOp::Mod => left.modulo(right),
+        // Integer operations that overflow are left for the VM, which may be configured to raise
+        // an error rather than wrap.
+        SimpleBinOp::Add => left.checked_add(right),
+        SimpleBinOp::Sub => left.checked_subtract(right),
+        SimpleBinOp::Mul => left.checked_multiply(right),
         SimpleBinOp::Pow => left.exponentiate(right),
+        // Likewise, division by zero is left for the VM, which may be configured to raise an error
+        // even for floats.
+        SimpleBinOp::Mod | SimpleBinOp::Div | SimpleBinOp::IDiv
+            if right.to_number() == Some(0.0) =>
+        {
+            None
+        }
+        SimpleBinOp::Mod => left.modulo(right),
         SimpleBinOp::Div => left.float_divide(right),
         SimpleBinOp::IDiv => left.floor_divide(right),
         _ => None,
@@ -1,5 +1,6 @@
-use std::{io::Read, rc::Rc};
+use std::{fmt, io::Read, rc::Rc};
 
+use gc_arena::Collect;
 use thiserror::Error;
 
 use super::{
@@ -7,6 +8,25 @@ use super::{
     StringInterner,
 };
 
+/// A 0-indexed line number in the parsed source.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Collect)]
+#[collect(require_static)]
+pub struct LineNumber(pub u64);
+
+impl fmt::Display for LineNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Lua line numbers are 1-indexed.
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
+/// An AST node along with the line in the source on which it starts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineAnnotated<T> {
+    pub line: LineNumber,
+    pub inner: T,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Chunk<S> {
     pub block: Block<S>,
@@ -14,8 +34,8 @@ pub struct Chunk<S> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Block<S> {
-    pub statements: Vec<Statement<S>>,
-    pub return_statement: Option<ReturnStatement<S>>,
+    pub statements: Vec<LineAnnotated<Statement<S>>>,
+    pub return_statement: Option<LineAnnotated<ReturnStatement<S>>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -30,6 +50,9 @@ pub enum Statement<S> {
     LocalStatement(LocalStatement<S>),
     Label(LabelStatement<S>),
     Break,
+    // A piccolo extension enabled by the `continue` feature, which skips to the next iteration of
+    // the innermost loop.
+    Continue,
     Goto(GotoStatement<S>),
     FunctionCall(FunctionCallStatement<S>),
     Assignment(AssignmentStatement<S>),
@@ -102,9 +125,20 @@ pub struct LocalFunctionStatement<S> {
 #[derive(Debug, PartialEq, Clone)]
 pub struct LocalStatement<S> {
     pub names: Vec<S>,
+    // The attribute given to each name in `names`, if any.
+    pub attributes: Vec<Option<LocalAttribute>>,
     pub values: Vec<Expression<S>>,
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LocalAttribute {
+    // A variable which may not be assigned to after it is declared.
+    Const,
+    // A to-be-closed variable, whose `__close` metamethod is called when it goes out of scope.
+    // To-be-closed variables are also constant.
+    Close,
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum BinaryOperator {
     Add,
@@ -199,6 +233,7 @@ pub struct FunctionDefinition<S> {
     pub parameters: Vec<S>,
     pub has_varargs: bool,
     pub body: Block<S>,
+    pub line_defined: LineNumber,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -254,6 +289,10 @@ pub enum ParserError {
     ExpressionNotStatement,
     #[error("recursion limit reached")]
     RecursionLimit,
+    #[error("unknown attribute {0:?}")]
+    UnknownAttribute(String),
+    #[error("multiple to-be-closed variables in local list")]
+    MultipleToBeClosed,
     #[error(transparent)]
     LexerError(#[from] LexerError),
 }
@@ -273,7 +312,7 @@ where
 
 struct Parser<R, S: StringInterner> {
     lexer: Lexer<R, S>,
-    read_buffer: Vec<Token<S::String>>,
+    read_buffer: Vec<(Token<S::String>, LineNumber)>,
     recursion_guard: Rc<()>,
 }
 
@@ -302,12 +341,20 @@ where
                     self.take_next()?;
                 }
                 Some(&Token::Return) => {
-                    return_statement = Some(self.parse_return_statement()?);
+                    let line = self.line_number()?;
+                    return_statement = Some(LineAnnotated {
+                        line,
+                        inner: self.parse_return_statement()?,
+                    });
                     break;
                 }
                 None => break,
                 _ => {
-                    statements.push(self.parse_statement()?);
+                    let line = self.line_number()?;
+                    statements.push(LineAnnotated {
+                        line,
+                        inner: self.parse_statement()?,
+                    });
                 }
             }
         }
@@ -346,6 +393,10 @@ where
                 self.take_next()?;
                 Statement::Break
             }
+            Token::Continue => {
+                self.take_next()?;
+                Statement::Continue
+            }
             Token::Goto => Statement::Goto(self.parse_goto_statement()?),
             _ => self.parse_expression_statement()?,
         })
@@ -520,10 +571,22 @@ where
     fn parse_local_statement(&mut self) -> Result<LocalStatement<S::String>, ParserError> {
         self.expect_next(Token::Local)?;
         let mut names = Vec::new();
+        let mut attributes = Vec::new();
         names.push(self.expect_name()?);
+        attributes.push(self.parse_local_attribute()?);
         while self.check_ahead(0, Token::Comma)? {
             self.take_next()?;
             names.push(self.expect_name()?);
+            attributes.push(self.parse_local_attribute()?);
+        }
+
+        if attributes
+            .iter()
+            .filter(|&&a| a == Some(LocalAttribute::Close))
+            .count()
+            > 1
+        {
+            return Err(ParserError::MultipleToBeClosed);
         }
 
         let values = if self.check_ahead(0, Token::Assign)? {
@@ -533,7 +596,28 @@ where
             Vec::new()
         };
 
-        Ok(LocalStatement { names, values })
+        Ok(LocalStatement {
+            names,
+            attributes,
+            values,
+        })
+    }
+
+    fn parse_local_attribute(&mut self) -> Result<Option<LocalAttribute>, ParserError> {
+        if !self.check_ahead(0, Token::LessThan)? {
+            return Ok(None);
+        }
+        self.take_next()?;
+        let name = self.expect_name()?;
+        self.expect_next(Token::GreaterThan)?;
+
+        match name.as_ref() {
+            b"const" => Ok(Some(LocalAttribute::Const)),
+            b"close" => Ok(Some(LocalAttribute::Close)),
+            other => Err(ParserError::UnknownAttribute(
+                String::from_utf8_lossy(other).into_owned(),
+            )),
+        }
     }
 
     fn parse_label_statement(&mut self) -> Result<LabelStatement<S::String>, ParserError> {
@@ -797,6 +881,7 @@ where
     }
 
     fn parse_function_definition(&mut self) -> Result<FunctionDefinition<S::String>, ParserError> {
+        let line_defined = self.line_number()?;
         self.expect_next(Token::LeftParen)?;
 
         let mut parameters = Vec::new();
@@ -832,6 +917,7 @@ where
             parameters,
             has_varargs,
             body,
+            line_defined,
         })
     }
 
@@ -891,7 +977,7 @@ where
     // Return a reference to the next token in the stream, erroring if we are at the end.
     fn get_next(&mut self) -> Result<&Token<S::String>, ParserError> {
         self.read_ahead(1)?;
-        if let Some(token) = self.read_buffer.get(0) {
+        if let Some((token, _)) = self.read_buffer.get(0) {
             Ok(token)
         } else {
             Err(ParserError::EndOfStream { expected: None })
@@ -906,7 +992,7 @@ where
                 expected: Some(format!("{:?}", token)),
             })
         } else {
-            let next_token = self.read_buffer.remove(0);
+            let (next_token, _) = self.read_buffer.remove(0);
             if next_token == token {
                 Ok(())
             } else {
@@ -926,7 +1012,7 @@ where
                 expected: Some("name".to_owned()),
             })
         } else {
-            match self.read_buffer.remove(0) {
+            match self.read_buffer.remove(0).0 {
                 Token::Name(name) => Ok(name),
                 token => Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -944,7 +1030,7 @@ where
                 expected: Some("string".to_owned()),
             })
         } else {
-            match self.read_buffer.remove(0) {
+            match self.read_buffer.remove(0).0 {
                 Token::String(string) => Ok(string),
                 token => Err(ParserError::Unexpected {
                     unexpected: format!("{:?}", token),
@@ -960,21 +1046,21 @@ where
         if self.read_buffer.is_empty() {
             Err(ParserError::EndOfStream { expected: None })
         } else {
-            Ok(self.read_buffer.remove(0))
+            Ok(self.read_buffer.remove(0).0)
         }
     }
 
     // Return the nth token ahead in the stream, if it is not past the end.
     fn look_ahead(&mut self, n: usize) -> Result<Option<&Token<S::String>>, ParserError> {
         self.read_ahead(n + 1)?;
-        Ok(self.read_buffer.get(n))
+        Ok(self.read_buffer.get(n).map(|(t, _)| t))
     }
 
     // Return true if the nth token ahead in the stream matches the given token. If this would read
     // past the end of the stream, this will simply return false.
     fn check_ahead(&mut self, n: usize, token: Token<S::String>) -> Result<bool, ParserError> {
         self.read_ahead(n)?;
-        Ok(if let Some(t) = self.read_buffer.get(n) {
+        Ok(if let Some((t, _)) = self.read_buffer.get(n) {
             *t == token
         } else {
             false
@@ -985,14 +1071,28 @@ where
     // possible).
     fn read_ahead(&mut self, n: usize) -> Result<(), ParserError> {
         while self.read_buffer.len() <= n {
+            // Skip whitespace first so that the line number is the line the token starts on.
+            self.lexer.skip_whitespace()?;
+            let line = LineNumber(self.lexer.line_number());
             if let Some(token) = self.lexer.read_token()? {
-                self.read_buffer.push(token);
+                self.read_buffer.push((token, line));
             } else {
                 break;
             }
         }
         Ok(())
     }
+
+    // Returns the line number of the next token in the stream, or the current line if we are at
+    // the end.
+    fn line_number(&mut self) -> Result<LineNumber, ParserError> {
+        self.read_ahead(0)?;
+        Ok(if let Some((_, line)) = self.read_buffer.get(0) {
+            *line
+        } else {
+            LineNumber(self.lexer.line_number())
+        })
+    }
 }
 
 const MAX_RECURSION: usize = 200;
@@ -1087,57 +1187,66 @@ mod tests {
             Chunk {
                 block: Block {
                     statements: vec![
-                        Statement::FunctionCall(FunctionCallStatement {
-                            head: SuffixedExpression {
-                                primary: PrimaryExpression::Name(interner.intern(b"print"),),
-                                suffixes: vec![],
-                            },
-                            call: CallSuffix::Function(vec![
-                                Expression {
+                        LineAnnotated {
+                            line: LineNumber(0),
+                            inner: Statement::FunctionCall(FunctionCallStatement {
+                                head: SuffixedExpression {
+                                    primary: PrimaryExpression::Name(interner.intern(b"print"),),
+                                    suffixes: vec![],
+                                },
+                                call: CallSuffix::Function(vec![
+                                    Expression {
+                                        head: Box::new(HeadExpression::Simple(
+                                            SimpleExpression::Integer(10,)
+                                        )),
+                                        tail: vec![],
+                                    },
+                                    Expression {
+                                        head: Box::new(HeadExpression::Simple(
+                                            SimpleExpression::Integer(20,)
+                                        )),
+                                        tail: vec![],
+                                    },
+                                ]),
+                            }),
+                        },
+                        LineAnnotated {
+                            line: LineNumber(0),
+                            inner: Statement::FunctionCall(FunctionCallStatement {
+                                head: SuffixedExpression {
+                                    primary: PrimaryExpression::Name(interner.intern(b"print"),),
+                                    suffixes: vec![],
+                                },
+                                call: CallSuffix::Function(vec![Expression {
                                     head: Box::new(HeadExpression::Simple(
-                                        SimpleExpression::Integer(10,)
+                                        SimpleExpression::String(interner.intern(b"foo"),)
                                     )),
                                     tail: vec![],
+                                },]),
+                            }),
+                        },
+                        LineAnnotated {
+                            line: LineNumber(0),
+                            inner: Statement::FunctionCall(FunctionCallStatement {
+                                head: SuffixedExpression {
+                                    primary: PrimaryExpression::Name(interner.intern(b"print"),),
+                                    suffixes: vec![],
                                 },
-                                Expression {
+                                call: CallSuffix::Function(vec![Expression {
                                     head: Box::new(HeadExpression::Simple(
-                                        SimpleExpression::Integer(20,)
+                                        SimpleExpression::TableConstructor(TableConstructor {
+                                            fields: vec![ConstructorField::Array(Expression {
+                                                head: Box::new(HeadExpression::Simple(
+                                                    SimpleExpression::Float(30.0),
+                                                )),
+                                                tail: vec![],
+                                            }),],
+                                        }),
                                     )),
                                     tail: vec![],
-                                },
-                            ]),
-                        }),
-                        Statement::FunctionCall(FunctionCallStatement {
-                            head: SuffixedExpression {
-                                primary: PrimaryExpression::Name(interner.intern(b"print"),),
-                                suffixes: vec![],
-                            },
-                            call: CallSuffix::Function(vec![Expression {
-                                head: Box::new(HeadExpression::Simple(SimpleExpression::String(
-                                    interner.intern(b"foo"),
-                                ))),
-                                tail: vec![],
-                            },]),
-                        }),
-                        Statement::FunctionCall(FunctionCallStatement {
-                            head: SuffixedExpression {
-                                primary: PrimaryExpression::Name(interner.intern(b"print"),),
-                                suffixes: vec![],
-                            },
-                            call: CallSuffix::Function(vec![Expression {
-                                head: Box::new(HeadExpression::Simple(
-                                    SimpleExpression::TableConstructor(TableConstructor {
-                                        fields: vec![ConstructorField::Array(Expression {
-                                            head: Box::new(HeadExpression::Simple(
-                                                SimpleExpression::Float(30.0),
-                                            )),
-                                            tail: vec![],
-                                        }),],
-                                    }),
-                                )),
-                                tail: vec![],
-                            },]),
-                        }),
+                                },]),
+                            }),
+                        },
                     ],
                     return_statement: None,
                 },
@@ -0,0 +1,104 @@
+use std::cell::{Cell, RefCell};
+
+use gc_arena::{metrics::Metrics, Collect, Mutation};
+
+use crate::{
+    ArithmeticMode, CallDepthLimit, FloatDivideByZero, KeyHashing, MemoryLimit, MetaChainLimit,
+    SizeLimits,
+};
+
+/// Settings of a Lua instance which the VM consults on its hot paths, such as on every entry into
+/// a Lua frame.
+///
+/// Unlike a [`Setting`](crate::Setting), which is looked up in the registry each time it is read,
+/// these are held directly by the [`State`](crate::State) and reading one is a plain load. They
+/// are changed through the `Lua::set_*` methods, and take effect the next time the VM reads them.
+#[derive(Collect)]
+#[collect(require_static)]
+pub struct VmConfig {
+    metrics: Metrics,
+    memory_limit: Cell<MemoryLimit>,
+    arithmetic_mode: Cell<ArithmeticMode>,
+    float_divide_by_zero: Cell<FloatDivideByZero>,
+    key_hashing: RefCell<KeyHashing>,
+    size_limits: Cell<SizeLimits>,
+    meta_chain_limit: Cell<MetaChainLimit>,
+    call_depth_limit: Cell<CallDepthLimit>,
+}
+
+impl VmConfig {
+    pub fn new(mc: &Mutation<'_>) -> VmConfig {
+        VmConfig {
+            metrics: mc.metrics().clone(),
+            memory_limit: Default::default(),
+            arithmetic_mode: Default::default(),
+            float_divide_by_zero: Default::default(),
+            key_hashing: Default::default(),
+            size_limits: Default::default(),
+            meta_chain_limit: Default::default(),
+            call_depth_limit: Default::default(),
+        }
+    }
+
+    /// The metrics of the arena that holds this Lua instance, which the memory limit is checked
+    /// against.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn memory_limit(&self) -> MemoryLimit {
+        self.memory_limit.get()
+    }
+
+    pub fn set_memory_limit(&self, limit: MemoryLimit) {
+        self.memory_limit.set(limit);
+    }
+
+    pub fn arithmetic_mode(&self) -> ArithmeticMode {
+        self.arithmetic_mode.get()
+    }
+
+    pub fn set_arithmetic_mode(&self, mode: ArithmeticMode) {
+        self.arithmetic_mode.set(mode);
+    }
+
+    pub fn float_divide_by_zero(&self) -> FloatDivideByZero {
+        self.float_divide_by_zero.get()
+    }
+
+    pub fn set_float_divide_by_zero(&self, mode: FloatDivideByZero) {
+        self.float_divide_by_zero.set(mode);
+    }
+
+    pub fn key_hashing(&self) -> KeyHashing {
+        self.key_hashing.borrow().clone()
+    }
+
+    pub fn set_key_hashing(&self, hashing: KeyHashing) {
+        *self.key_hashing.borrow_mut() = hashing;
+    }
+
+    pub fn size_limits(&self) -> SizeLimits {
+        self.size_limits.get()
+    }
+
+    pub fn set_size_limits(&self, limits: SizeLimits) {
+        self.size_limits.set(limits);
+    }
+
+    pub fn meta_chain_limit(&self) -> MetaChainLimit {
+        self.meta_chain_limit.get()
+    }
+
+    pub fn set_meta_chain_limit(&self, limit: MetaChainLimit) {
+        self.meta_chain_limit.set(limit);
+    }
+
+    pub fn call_depth_limit(&self) -> CallDepthLimit {
+        self.call_depth_limit.get()
+    }
+
+    pub fn set_call_depth_limit(&self, limit: CallDepthLimit) {
+        self.call_depth_limit.set(limit);
+    }
+}
@@ -2,7 +2,7 @@ use std::hash::{Hash, Hasher};
 
 use gc_arena::Collect;
 
-use crate::compiler::lexer::{read_float, read_hex_float};
+use crate::compiler::lexer::{read_float, read_hex_float, read_hex_integer, read_integer};
 
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -51,45 +51,32 @@ impl<S> Constant<S> {
 impl<S: AsRef<[u8]>> Constant<S> {
     /// Interprets Numbers, Integers, and Strings as a Number, if possible.
     pub fn to_number(&self) -> Option<f64> {
-        match self {
-            &Self::Integer(a) => Some(a as f64),
-            &Self::Number(a) => Some(a),
-            Self::String(a) => {
-                if let Some(f) = read_hex_float(a.as_ref()) {
-                    Some(f)
-                } else {
-                    read_float(a.as_ref())
-                }
-            }
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(a as f64),
+            Self::Number(a) => Some(a),
             _ => None,
         }
     }
 
     /// Interprets Numbers, Integers, and Strings as an Integer, if possible.
     pub fn to_integer(&self) -> Option<i64> {
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(a),
+            Self::Number(a) => float_to_int(a),
+            _ => None,
+        }
+    }
+
+    /// Interprets Numbers, Integers, and Strings as either an Integer or a Number, if possible.
+    ///
+    /// Strings are converted the same way that numerals in Lua source are, so a string written as
+    /// an integer (like "10" or "0x10") becomes an Integer, and any other numeric string becomes a
+    /// Number. Leading and trailing whitespace is ignored.
+    pub fn to_numeric(&self) -> Option<Self> {
         match self {
-            &Self::Integer(a) => Some(a),
-            &Self::Number(a) => {
-                if ((a as i64) as f64) == a {
-                    Some(a as i64)
-                } else {
-                    None
-                }
-            }
-            Self::String(a) => match if let Some(f) = read_hex_float(a.as_ref()) {
-                Some(f)
-            } else {
-                read_float(a.as_ref())
-            } {
-                Some(f) => {
-                    if ((f as i64) as f64) == f {
-                        Some(f as i64)
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
-            },
+            &Self::Integer(a) => Some(Self::Integer(a)),
+            &Self::Number(a) => Some(Self::Number(a)),
+            Self::String(a) => read_numeric(a.as_ref()),
             _ => None,
         }
     }
@@ -97,22 +84,49 @@ impl<S: AsRef<[u8]>> Constant<S> {
     // Mathematical operators
 
     pub fn add(&self, rhs: &Self) -> Option<Self> {
-        Some(match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => Self::Integer(a.wrapping_add(b)),
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.wrapping_add(b)),
             (a, b) => Self::Number(a.to_number()? + b.to_number()?),
         })
     }
 
     pub fn subtract(&self, rhs: &Self) -> Option<Self> {
-        Some(match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => Self::Integer(a.wrapping_sub(b)),
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.wrapping_sub(b)),
             (a, b) => Self::Number(a.to_number()? - b.to_number()?),
         })
     }
 
     pub fn multiply(&self, rhs: &Self) -> Option<Self> {
-        Some(match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => Self::Integer(a.wrapping_mul(b)),
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.wrapping_mul(b)),
+            (a, b) => Self::Number(a.to_number()? * b.to_number()?),
+        })
+    }
+
+    /// Like [`Constant::add`], but returns `None` rather than wrapping if adding two Integers
+    /// overflows.
+    pub fn checked_add(&self, rhs: &Self) -> Option<Self> {
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.checked_add(b)?),
+            (a, b) => Self::Number(a.to_number()? + b.to_number()?),
+        })
+    }
+
+    /// Like [`Constant::subtract`], but returns `None` rather than wrapping if subtracting two
+    /// Integers overflows.
+    pub fn checked_subtract(&self, rhs: &Self) -> Option<Self> {
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.checked_sub(b)?),
+            (a, b) => Self::Number(a.to_number()? - b.to_number()?),
+        })
+    }
+
+    /// Like [`Constant::multiply`], but returns `None` rather than wrapping if multiplying two
+    /// Integers overflows.
+    pub fn checked_multiply(&self, rhs: &Self) -> Option<Self> {
+        Some(match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => Self::Integer(a.checked_mul(b)?),
             (a, b) => Self::Number(a.to_number()? * b.to_number()?),
         })
     }
@@ -124,13 +138,22 @@ impl<S: AsRef<[u8]>> Constant<S> {
 
     /// This operation returns an Integer only if both arguments are Integers. Rounding is towards
     /// negative infinity.
+    ///
+    /// Integer division by zero has no result, float division by zero produces `inf` or `nan`.
     pub fn floor_divide(&self, rhs: &Self) -> Option<Self> {
-        match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => {
+        match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => {
                 if b == 0 {
                     None
                 } else {
-                    Some(Self::Integer(a.wrapping_div(b)))
+                    let q = a.wrapping_div(b);
+                    // Rust division truncates, so correct the quotient when the signs differ and
+                    // there is a remainder.
+                    if a.wrapping_rem(b) != 0 && (a ^ b) < 0 {
+                        Some(Self::Integer(q - 1))
+                    } else {
+                        Some(Self::Integer(q))
+                    }
                 }
             }
             (a, b) => Some(Self::Number((a.to_number()? / b.to_number()?).floor())),
@@ -138,33 +161,106 @@ impl<S: AsRef<[u8]>> Constant<S> {
     }
 
     /// Computes the Lua modulus (`%`) operator. This is unlike Rust's `%` operator which computes
-    /// the remainder.
+    /// the remainder: the result of the Lua modulus always has the sign of the divisor.
+    ///
+    /// Integer modulus by zero has no result, float modulus by zero produces `nan`.
     pub fn modulo(&self, rhs: &Self) -> Option<Self> {
-        match (self, rhs) {
-            (&Self::Integer(a), &Self::Integer(b)) => {
+        match (self.to_numeric()?, rhs.to_numeric()?) {
+            (Self::Integer(a), Self::Integer(b)) => {
                 if b == 0 {
                     None
                 } else {
-                    Some(Self::Integer(((a % b) + b) % b))
+                    let r = a.wrapping_rem(b);
+                    if r != 0 && (r ^ b) < 0 {
+                        Some(Self::Integer(r + b))
+                    } else {
+                        Some(Self::Integer(r))
+                    }
                 }
             }
             (a, b) => {
                 let (a, b) = (a.to_number()?, b.to_number()?);
-                Some(Self::Number(((a % b) + b) % b))
+                let r = a % b;
+                let signs_differ = if r > 0.0 { b < 0.0 } else { r < 0.0 && b != r };
+                if signs_differ {
+                    Some(Self::Number(r + b))
+                } else {
+                    Some(Self::Number(r))
+                }
             }
         }
     }
 
     /// This operation always returns a Number, even when called with Integer arguments.
+    ///
+    /// When both operands are integral, the exponent is small and non-negative, and the result is
+    /// exactly representable as a float, the result is computed exactly with integer
+    /// multiplication, so that `10 ^ 3` is exactly `1000.0`. Everything else uses `powf`.
     pub fn exponentiate(&self, rhs: &Self) -> Option<Self> {
-        Some(Self::Number(self.to_number()?.powf(rhs.to_number()?)))
+        const MAX_INTEGER_EXPONENT: f64 = 64.0;
+
+        let (a, b) = (self.to_number()?, rhs.to_number()?);
+        // Zero is left to `powf`, which keeps the sign of `-0.0`.
+        let exact = if a != 0.0
+            && a.fract() == 0.0
+            && a.abs() < i64::MAX as f64
+            && (0.0..=MAX_INTEGER_EXPONENT).contains(&b)
+            && b.fract() == 0.0
+        {
+            (a as i128)
+                .checked_pow(b as u32)
+                .filter(|&r| (r as f64) as i128 == r)
+        } else {
+            None
+        };
+        Some(Self::Number(match exact {
+            Some(r) => r as f64,
+            None => a.powf(b),
+        }))
     }
 
     pub fn negate(&self) -> Option<Self> {
-        match self {
-            &Self::Integer(a) => Some(Self::Integer(a.wrapping_neg())),
-            &Self::Number(a) => Some(Self::Number(-a)),
-            s => s.to_number().map(|x| Self::Number(-x)),
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(Self::Integer(a.wrapping_neg())),
+            Self::Number(a) => Some(Self::Number(-a)),
+            _ => None,
+        }
+    }
+
+    // Math library functions which, like the operators, keep integers as integers.
+
+    pub fn abs(&self) -> Option<Self> {
+        match self.to_numeric()? {
+            // As in Lua, the absolute value of the minimum integer wraps around to itself.
+            Self::Integer(a) => Some(Self::Integer(a.wrapping_abs())),
+            Self::Number(a) => Some(Self::Number(a.abs())),
+            _ => None,
+        }
+    }
+
+    pub fn floor(&self) -> Option<Self> {
+        self.round_with(f64::floor)
+    }
+
+    pub fn ceil(&self) -> Option<Self> {
+        self.round_with(f64::ceil)
+    }
+
+    // Rounds a number with the given float rounding function, leaving integers unchanged so that
+    // they do not lose precision by passing through a float. A rounded float that fits in an integer
+    // is returned as one, so a result of -0.0 (from `floor(-0.0)` or `ceil(-0.5)`) becomes the
+    // integer 0, as in PUC-Rio Lua.
+    fn round_with(&self, round: fn(f64) -> f64) -> Option<Self> {
+        match self.to_numeric()? {
+            Self::Integer(a) => Some(Self::Integer(a)),
+            Self::Number(a) => {
+                let rounded = round(a);
+                Some(match float_to_int(rounded) {
+                    Some(i) => Self::Integer(i),
+                    None => Self::Number(rounded),
+                })
+            }
+            _ => None,
         }
     }
 
@@ -207,11 +303,11 @@ impl<S: AsRef<[u8]>> Constant<S> {
             (Self::Boolean(_), _) => false,
 
             (Self::Integer(a), Self::Integer(b)) => a == b,
-            (Self::Integer(a), Self::Number(b)) => *a as f64 == *b,
+            (Self::Integer(a), Self::Number(b)) => float_to_int(*b) == Some(*a),
             (Self::Integer(_), _) => false,
 
             (Self::Number(a), Self::Number(b)) => a == b,
-            (Self::Number(a), Self::Integer(b)) => *b as f64 == *a,
+            (Self::Number(a), Self::Integer(b)) => float_to_int(*a) == Some(*b),
             (Self::Number(_), _) => false,
 
             (Self::String(a), Self::String(b)) => a.as_ref() == b.as_ref(),
@@ -219,9 +315,14 @@ impl<S: AsRef<[u8]>> Constant<S> {
         }
     }
 
+    /// Integers and Numbers are compared by their exact mathematical value, without first
+    /// converting the Integer to a Number, so that mixed comparisons stay consistent with
+    /// comparisons between two Integers.
     pub fn less_than(&self, rhs: &Self) -> Option<bool> {
         Some(match (self, rhs) {
             (Self::Integer(a), Self::Integer(b)) => a < b,
+            (Self::Integer(a), Self::Number(b)) => int_less_than_float(*a, *b),
+            (Self::Number(a), Self::Integer(b)) => float_less_than_int(*a, *b),
             (Self::String(a), Self::String(b)) => a.as_ref() < b.as_ref(),
             (a, b) => a.to_number()? < b.to_number()?,
         })
@@ -230,12 +331,94 @@ impl<S: AsRef<[u8]>> Constant<S> {
     pub fn less_equal(&self, rhs: &Self) -> Option<bool> {
         Some(match (self, rhs) {
             (Self::Integer(a), Self::Integer(b)) => a <= b,
+            (Self::Integer(a), Self::Number(b)) => int_less_equal_float(*a, *b),
+            (Self::Number(a), Self::Integer(b)) => float_less_equal_int(*a, *b),
             (Self::String(a), Self::String(b)) => a.as_ref() <= b.as_ref(),
             (a, b) => a.to_number()? <= b.to_number()?,
         })
     }
 }
 
+// 2^63, the first float past the end of the i64 range. Every float in `[-2^63, 2^63)` has a floor
+// and ceiling representable as an i64 (floats this large are all integral), and every float outside
+// of it is larger or smaller than every i64.
+const TWO_POW_63: f64 = 9223372036854775808.0;
+
+/// Converts a float to the integer with the same mathematical value, if there is one.
+///
+/// This is exact, so unlike checking that the float survives a round trip through `as i64`, it
+/// does not mistake `2^63` (which saturates to `i64::MAX`) for an integer.
+pub(crate) fn float_to_int(f: f64) -> Option<i64> {
+    if f >= -TWO_POW_63 && f < TWO_POW_63 && f.floor() == f {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
+
+fn int_less_than_float(i: i64, f: f64) -> bool {
+    if f >= TWO_POW_63 {
+        true
+    } else if f > -TWO_POW_63 {
+        i < f.ceil() as i64
+    } else {
+        // Also reached for NaN.
+        false
+    }
+}
+
+fn int_less_equal_float(i: i64, f: f64) -> bool {
+    if f >= TWO_POW_63 {
+        true
+    } else if f >= -TWO_POW_63 {
+        i <= f.floor() as i64
+    } else {
+        false
+    }
+}
+
+fn float_less_than_int(f: f64, i: i64) -> bool {
+    if f >= -TWO_POW_63 && f < TWO_POW_63 {
+        (f.floor() as i64) < i
+    } else {
+        f < 0.0
+    }
+}
+
+fn float_less_equal_int(f: f64, i: i64) -> bool {
+    if f > -TWO_POW_63 && f < TWO_POW_63 {
+        f.ceil() as i64 <= i
+    } else {
+        f < 0.0
+    }
+}
+
+// Parses a string as a Lua numeral, producing an Integer if the string is written as one and a
+// Number otherwise.
+fn read_numeric<S>(s: &[u8]) -> Option<Constant<S>> {
+    let s = s.trim_ascii();
+
+    // Rust's float parser accepts "inf" and "nan", which are not Lua numerals.
+    let digits = match s.first()? {
+        b'-' | b'+' => &s[1..],
+        _ => s,
+    };
+    if !digits
+        .first()
+        .is_some_and(|&c| c == b'.' || c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    if let Some(i) = read_hex_integer(s).or_else(|| read_integer(s)) {
+        Some(Constant::Integer(i))
+    } else {
+        Some(Constant::Number(
+            read_hex_float(s).or_else(|| read_float(s))?,
+        ))
+    }
+}
+
 impl<S: AsRef<[u8]>> PartialEq for Constant<S> {
     fn eq(&self, other: &Self) -> bool {
         self.is_equal(other)
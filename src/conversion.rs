@@ -119,7 +119,7 @@ where
 
 impl<'gc, T: IntoValue<'gc>> IntoValue<'gc> for Vec<T> {
     fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
-        let table = Table::new(&ctx);
+        let table = Table::with_instance_hashing(ctx);
         for (i, v) in self.into_iter().enumerate() {
             table.set(ctx, i64::try_from(i).unwrap() + 1, v).unwrap();
         }
@@ -132,7 +132,7 @@ where
     &'a T: IntoValue<'gc>,
 {
     fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
-        let table = Table::new(&ctx);
+        let table = Table::with_instance_hashing(ctx);
         for (i, v) in self.iter().enumerate() {
             table.set(ctx, i64::try_from(i).unwrap() + 1, v).unwrap();
         }
@@ -366,6 +366,52 @@ impl<I, T: FromIterator<I>> FromIterator<I> for Variadic<T> {
     }
 }
 
+/// An optional argument which is replaced with `T::default()` when it is `nil` or missing.
+///
+/// This is meant for trailing optional arguments of callbacks, and avoids unwrapping an `Option`
+/// when the default is the `Default` of the argument type. Arguments with any other default can
+/// use `Option<T>` and `Option::unwrap_or`:
+///
+/// ```
+/// # use piccolo::{AnyCallback, CallbackReturn, DefaultArg, Lua};
+/// # let mut lua = Lua::core();
+/// # lua.run(|ctx| {
+/// // Called as `f(a [, b [, c]])`, where `b` defaults to 0 and `c` defaults to 1.
+/// let f = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+///     let (a, b, c): (i64, DefaultArg<i64>, Option<i64>) = stack.consume(ctx)?;
+///     stack.replace(ctx, (a + *b) * c.unwrap_or(1));
+///     Ok(CallbackReturn::Return)
+/// });
+/// # ctx.state.globals.set(ctx, "f", f).unwrap();
+/// # });
+/// ```
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DefaultArg<T>(pub T);
+
+impl<T> ops::Deref for DefaultArg<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> ops::DerefMut for DefaultArg<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'gc, T: FromValue<'gc> + Default> FromValue<'gc> for DefaultArg<T> {
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        if value.is_nil() {
+            Ok(DefaultArg(T::default()))
+        } else {
+            Ok(DefaultArg(T::from_value(ctx, value)?))
+        }
+    }
+}
+
 pub struct IterIntoValue<'gc, I> {
     ctx: Context<'gc>,
     iter: I,
@@ -4,7 +4,8 @@ use gc_arena::{Collect, Rootable};
 use thiserror::Error;
 
 use crate::{
-    AnyCallback, AnyUserData, CallbackReturn, Context, MetaMethod, Singleton, Table, Value,
+    AnyCallback, AnyUserData, ArrayShiftError, CallbackReturn, Context, InvalidTableKey,
+    MetaMethod, ReadOnlyTable, Singleton, StackOverflow, Table, Value,
 };
 
 #[derive(Debug, Clone, Copy, Error)]
@@ -145,6 +146,16 @@ impl<'gc> Error<'gc> {
     pub fn to_value(&self, ctx: Context<'gc>) -> Value<'gc> {
         match self {
             Error::Lua(err) => err.0,
+            // Errors that PUC-Rio Lua raises as plain strings are given to scripts as strings
+            // rather than wrapped as userdata, so that scripts can inspect them.
+            Error::Runtime(err)
+                if err.is::<InvalidTableKey>()
+                    || err.is::<ReadOnlyTable>()
+                    || err.is::<ArrayShiftError>()
+                    || err.is::<StackOverflow>() =>
+            {
+                ctx.intern(&err.to_string())
+            }
             Error::Runtime(err) => {
                 #[derive(Copy, Clone, Collect)]
                 #[collect(no_drop)]
@@ -152,7 +163,7 @@ impl<'gc> Error<'gc> {
 
                 impl<'gc> Singleton<'gc> for UDMeta<'gc> {
                     fn create(ctx: Context<'gc>) -> Self {
-                        let table = Table::new(&ctx);
+                        let table = Table::with_instance_hashing(ctx);
                         table
                             .set(
                                 ctx,
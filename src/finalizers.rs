@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+
+use gc_arena::{lock::RefLock, Collect, Finalization, Gc, GcWeak, Mutation};
+
+use crate::{
+    table::{TableState, Weakness},
+    userdata::WeakUserData,
+    Context, MetaMethod, Table, Value,
+};
+
+/// Tracks the tables and userdata which have a `__gc` metamethod, so that the metamethod can be
+/// called once they become unreachable.
+///
+/// As in PUC-Rio Lua, a value is only finalized if it was registered with [`Finalizers::register`]
+/// while its metatable had a `__gc` field. The `setmetatable` function does this for tables, while
+/// userdata created from Rust must be registered explicitly after setting their metatable.
+///
+/// When the garbage collector finds that a registered value is unreachable, the value is
+/// resurrected and queued, and `Lua` later calls its `__gc` metamethod with the value as the only
+/// argument (see [`Lua::run_finalizers`](crate::Lua::run_finalizers)). The usual Lua caveats apply:
+///
+/// - Each value is finalized at most once. If a finalizer stores the value somewhere reachable, it
+///   is resurrected and will not be finalized again when it becomes unreachable a second time.
+/// - Values that become unreachable in the same collection are finalized in the reverse order in
+///   which they were registered.
+/// - Finalizers run on their own thread, and any error they raise is discarded.
+/// - Any value which a finalizable value refers to is kept alive until its finalizer has run.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Finalizers<'gc>(Gc<'gc, RefLock<FinalizersState<'gc>>>);
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+enum WeakObject<'gc> {
+    Table(GcWeak<'gc, RefLock<TableState<'gc>>>),
+    UserData(WeakUserData<'gc>),
+}
+
+impl<'gc> WeakObject<'gc> {
+    fn address(self) -> usize {
+        match self {
+            WeakObject::Table(t) => GcWeak::as_ptr(t) as *const () as usize,
+            WeakObject::UserData(u) => u.as_ptr() as usize,
+        }
+    }
+
+    fn is_dead(self, fc: &Finalization<'gc>) -> bool {
+        match self {
+            WeakObject::Table(t) => t.is_dead(fc),
+            WeakObject::UserData(u) => u.is_dead(fc),
+        }
+    }
+
+    fn resurrect(self, fc: &Finalization<'gc>) -> Option<Value<'gc>> {
+        match self {
+            WeakObject::Table(t) => t.resurrect(fc).map(|t| Value::Table(Table(t))),
+            WeakObject::UserData(u) => u.resurrect(fc).map(Value::UserData),
+        }
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct FinalizersState<'gc> {
+    // Registered values which are still alive, in the order they were registered.
+    registered: Vec<WeakObject<'gc>>,
+    // The addresses of every value in `registered`, so that values are only registered once.
+    addresses: HashSet<usize>,
+    // Values which were found to be unreachable and are waiting for their finalizer to be called.
+    pending: Vec<Value<'gc>>,
+}
+
+impl<'gc> Finalizers<'gc> {
+    pub fn new(mc: &Mutation<'gc>) -> Self {
+        Finalizers(Gc::new(
+            mc,
+            RefLock::new(FinalizersState {
+                registered: Vec::new(),
+                addresses: HashSet::new(),
+                pending: Vec::new(),
+            }),
+        ))
+    }
+
+    /// Marks a table or userdata for finalization, if its metatable currently has a `__gc` field.
+    ///
+    /// Does nothing for other values, or if the value is already registered.
+    pub fn register(&self, ctx: Context<'gc>, value: Value<'gc>) {
+        let (object, metatable) = match value {
+            Value::Table(t) => (WeakObject::Table(Gc::downgrade(t.0)), t.metatable()),
+            Value::UserData(u) => (WeakObject::UserData(u.downgrade()), u.metatable()),
+            _ => return,
+        };
+
+        if !metatable.is_some_and(|mt| !mt.get(ctx, MetaMethod::Gc).is_nil()) {
+            return;
+        }
+
+        let mut state = self.0.borrow_mut(&ctx);
+        if state.addresses.insert(object.address()) {
+            state.registered.push(object);
+        }
+    }
+
+    /// Removes and returns the next unreachable value waiting to be finalized.
+    pub fn pop_pending(&self, mc: &Mutation<'gc>) -> Option<Value<'gc>> {
+        self.0.borrow_mut(mc).pending.pop()
+    }
+
+    // Called once the arena is fully marked, before it is swept. Every registered value which is
+    // now unreachable is resurrected and moved to the pending list.
+    pub(crate) fn prepare(&self, fc: &Finalization<'gc>) {
+        let mut state = self.0.borrow_mut(fc);
+        let state = &mut *state;
+
+        let mut dead = Vec::new();
+        state.registered.retain(|&object| {
+            if object.is_dead(fc) {
+                dead.push(object);
+                false
+            } else {
+                true
+            }
+        });
+
+        // `pending` is popped from the back, so pushing in registration order finalizes the most
+        // recently registered value first.
+        for object in dead {
+            state.addresses.remove(&object.address());
+            if let Some(value) = object.resurrect(fc) {
+                state.pending.push(value);
+            }
+        }
+    }
+}
+
+/// Tracks the tables which hold their keys or values weakly, so that entries referring to
+/// unreachable objects can be removed before those objects are freed.
+///
+/// A table's [`Weakness`] comes from the `__mode` field of its metatable, and is updated whenever
+/// the table is passed to [`WeakTables::register`], which [`Table::set_metatable`] does. As in
+/// PUC-Rio Lua, the mode should be set in the metatable before it is assigned.
+///
+/// Once the garbage collector has found every reachable object, and before any unreachable one is
+/// freed, each entry which weakly refers to an unreachable table, function, thread, or userdata is
+/// removed. This differs from PUC-Rio Lua in a few ways:
+///
+/// - Tables with weak keys are not "ephemeron" tables, their values are held strongly. An entry
+///   whose value refers to its own key is never removed.
+/// - Entries are removed before `__gc` finalizers are run, so a value being finalized has already
+///   been removed from the keys of weak tables as well as from their values.
+/// - A weak table which is itself unreachable is kept for one more collection, holding its keys and
+///   values strongly from then on.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct WeakTables<'gc>(Gc<'gc, RefLock<WeakTablesState<'gc>>>);
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct WeakTablesState<'gc> {
+    // Registered tables which are still alive and may be weak.
+    registered: Vec<GcWeak<'gc, RefLock<TableState<'gc>>>>,
+    // The addresses of every table in `registered`, so that tables are only registered once.
+    addresses: HashSet<usize>,
+}
+
+impl<'gc> WeakTables<'gc> {
+    pub fn new(mc: &Mutation<'gc>) -> Self {
+        WeakTables(Gc::new(
+            mc,
+            RefLock::new(WeakTablesState {
+                registered: Vec::new(),
+                addresses: HashSet::new(),
+            }),
+        ))
+    }
+
+    /// Sets the weakness of a table from the `__mode` field of its current metatable.
+    pub fn register(&self, ctx: Context<'gc>, table: Table<'gc>) {
+        let weakness = match table.metatable().map(|mt| mt.get(ctx, MetaMethod::Mode)) {
+            Some(Value::String(mode)) => Weakness::from_mode(mode.as_bytes()),
+            _ => Weakness::default(),
+        };
+
+        if weakness.is_weak() {
+            let mut state = self.0.borrow_mut(&ctx);
+            if state.addresses.insert(table.as_ptr() as usize) {
+                state.registered.push(Gc::downgrade(table.0));
+            }
+        }
+        // A table which stops being weak is left registered until the next collection, which
+        // is harmless since it has no weak entries to clear.
+        if table.weakness() != weakness {
+            table.set_weakness(&ctx, weakness);
+        }
+    }
+
+    // Called once the arena is fully marked, before it is swept and before any finalizable values
+    // are resurrected. Removes every entry of a weak table which refers to an unreachable object.
+    pub(crate) fn clear_dead(&self, fc: &Finalization<'gc>) {
+        let mut state = self.0.borrow_mut(fc);
+        let state = &mut *state;
+
+        state.registered.retain(|&weak| {
+            let is_dead = weak.is_dead(fc);
+            let table = weak.resurrect(fc).map(Table);
+            let keep = match table {
+                // An unreachable weak table is not traced, but it could still be resurrected by a
+                // finalizer, and then the dead objects it refers to would be reachable again. So
+                // it is resurrected here to clear it, and made strong so that it is collected
+                // normally in the next cycle.
+                Some(table) => {
+                    table.clear_dead(fc);
+                    if is_dead {
+                        table.set_weakness(fc, Weakness::default());
+                    }
+                    !is_dead && table.weakness().is_weak()
+                }
+                None => false,
+            };
+
+            if !keep {
+                state
+                    .addresses
+                    .remove(&(GcWeak::as_ptr(weak) as *const () as usize));
+            }
+            keep
+        });
+    }
+}
@@ -0,0 +1,390 @@
+//! Conversion between Lua tables and JSON text, enabled by the `json` feature.
+//!
+//! JSON has separate array and object types while Lua only has tables, so tables are converted
+//! with the following rule:
+//!
+//! - A non-empty table whose keys are exactly the integers `1..=n` is written as an array.
+//! - Any other table, including the empty table, is written as an object, and every key must be a
+//!   string.
+//!
+//! Since Lua tables cannot hold `nil`, a `null` in a JSON object leaves the key unset, and a `null`
+//! in a JSON array leaves a hole. Such tables will not convert back to the same JSON. An empty JSON
+//! array becomes an empty table, which is written back as `{}`.
+//!
+//! Integers and floats are kept distinct: floats are always written with a fractional part or an
+//! exponent, and JSON numbers without one are read as integers when they fit in an `i64`.
+
+use std::{fmt::Write as _, string::String as StdString};
+
+use thiserror::Error;
+
+use crate::{Context, InvalidTableKey, String, Table, Value};
+
+#[derive(Debug, Clone, Error)]
+pub enum JsonError {
+    #[error("cannot convert a {0} value to JSON")]
+    Unsupported(&'static str),
+    #[error("cannot convert a table with a {0} key to a JSON object")]
+    BadKey(&'static str),
+    #[error("cannot convert {0} to JSON")]
+    NonFinite(f64),
+    #[error("cannot convert a string that is not valid UTF-8 to JSON")]
+    InvalidUtf8,
+    #[error("cannot convert a table that contains itself to JSON")]
+    Cycle,
+    #[error("JSON is nested more than {MAX_DEPTH} levels deep")]
+    TooDeep,
+    #[error("invalid JSON at byte {0}")]
+    Syntax(usize),
+    #[error("JSON must be an array or object to be converted to a table")]
+    NotATable,
+    #[error(transparent)]
+    InvalidKey(#[from] InvalidTableKey),
+}
+
+/// The deepest nesting of arrays and objects that is converted in either direction, which keeps
+/// the recursion in both conversions bounded.
+pub const MAX_DEPTH: usize = 256;
+
+/// Writes `table` and everything nested in it as JSON text.
+///
+/// Fails if any value is not a table, string, number, or boolean, if a float is infinite or NaN,
+/// or if a table contains itself.
+pub fn table_to_json<'gc>(table: Table<'gc>) -> Result<StdString, JsonError> {
+    let mut encoder = Encoder {
+        out: StdString::new(),
+        parents: Vec::new(),
+    };
+    encoder.table(table)?;
+    Ok(encoder.out)
+}
+
+/// Reads JSON text, which must be an array or an object, into a new table.
+pub fn json_to_table<'gc>(ctx: Context<'gc>, json: &str) -> Result<Table<'gc>, JsonError> {
+    let mut decoder = Decoder {
+        ctx,
+        input: json.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    decoder.skip_whitespace();
+    let value = match decoder.peek() {
+        Some(b'[' | b'{') => decoder.value()?,
+        _ => return Err(JsonError::NotATable),
+    };
+    decoder.skip_whitespace();
+    if decoder.pos != decoder.input.len() {
+        return Err(JsonError::Syntax(decoder.pos));
+    }
+    match value {
+        Value::Table(t) => Ok(t),
+        _ => unreachable!(),
+    }
+}
+
+struct Encoder<'gc> {
+    out: StdString,
+    // The tables currently being written, to detect cycles.
+    parents: Vec<Table<'gc>>,
+}
+
+impl<'gc> Encoder<'gc> {
+    fn value(&mut self, value: Value<'gc>) -> Result<(), JsonError> {
+        match value {
+            Value::Boolean(b) => self.out.push_str(if b { "true" } else { "false" }),
+            Value::Integer(i) => write!(self.out, "{i}").unwrap(),
+            Value::Number(n) => {
+                if !n.is_finite() {
+                    return Err(JsonError::NonFinite(n));
+                }
+                // `Debug` always includes a `.` or exponent, so the number is read back as a float.
+                write!(self.out, "{n:?}").unwrap();
+            }
+            Value::String(s) => self.string(s)?,
+            Value::Table(t) => self.table(t)?,
+            v => return Err(JsonError::Unsupported(v.type_name())),
+        }
+        Ok(())
+    }
+
+    fn table(&mut self, table: Table<'gc>) -> Result<(), JsonError> {
+        if self.parents.contains(&table) {
+            return Err(JsonError::Cycle);
+        }
+        if self.parents.len() >= MAX_DEPTH {
+            return Err(JsonError::TooDeep);
+        }
+        self.parents.push(table);
+
+        let entries = table.iter_sorted();
+        let len = entries.len();
+        let is_array = len > 0
+            && entries
+                .as_slice()
+                .iter()
+                .all(|(k, _)| matches!(*k, Value::Integer(i) if i >= 1 && i as usize <= len));
+
+        // Integer keys sort first and in order, so the entries of an array are already in order.
+        if is_array {
+            self.out.push('[');
+            for (i, (_, value)) in entries.enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                self.value(value)?;
+            }
+            self.out.push(']');
+        } else {
+            self.out.push('{');
+            for (i, (key, value)) in entries.enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                match key {
+                    Value::String(s) => self.string(s)?,
+                    k => return Err(JsonError::BadKey(k.type_name())),
+                }
+                self.out.push(':');
+                self.value(value)?;
+            }
+            self.out.push('}');
+        }
+
+        self.parents.pop();
+        Ok(())
+    }
+
+    fn string(&mut self, s: String<'gc>) -> Result<(), JsonError> {
+        let s = s.to_str().map_err(|_| JsonError::InvalidUtf8)?;
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if c < ' ' => write!(self.out, "\\u{:04x}", c as u32).unwrap(),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+        Ok(())
+    }
+}
+
+struct Decoder<'gc, 'a> {
+    ctx: Context<'gc>,
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'gc, 'a> Decoder<'gc, 'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(JsonError::Syntax(self.pos))
+        }
+    }
+
+    fn literal(&mut self, word: &[u8], value: Value<'gc>) -> Result<Value<'gc>, JsonError> {
+        if self.input[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(JsonError::Syntax(self.pos))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value<'gc>, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Value::String(String::from_slice(&self.ctx, self.string()?))),
+            Some(b't') => self.literal(b"true", Value::Boolean(true)),
+            Some(b'f') => self.literal(b"false", Value::Boolean(false)),
+            Some(b'n') => self.literal(b"null", Value::Nil),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(JsonError::Syntax(self.pos)),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        f: fn(&mut Self) -> Result<Table<'gc>, JsonError>,
+    ) -> Result<Value<'gc>, JsonError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(JsonError::TooDeep);
+        }
+        self.depth += 1;
+        let table = f(self)?;
+        self.depth -= 1;
+        Ok(Value::Table(table))
+    }
+
+    fn object(&mut self) -> Result<Table<'gc>, JsonError> {
+        let table = Table::with_instance_hashing(self.ctx);
+        self.expect(b'{')?;
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(table);
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(JsonError::Syntax(self.pos));
+            }
+            let key = String::from_slice(&self.ctx, self.string()?);
+            self.expect(b':')?;
+            let value = self.value()?;
+            table.set_value(&self.ctx, key.into(), value)?;
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(table);
+                }
+                _ => return Err(JsonError::Syntax(self.pos)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Table<'gc>, JsonError> {
+        let table = Table::with_instance_hashing(self.ctx);
+        self.expect(b'[')?;
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(table);
+        }
+        for i in 1.. {
+            let value = self.value()?;
+            table.set_value(&self.ctx, Value::Integer(i), value)?;
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(JsonError::Syntax(self.pos)),
+            }
+        }
+        Ok(table)
+    }
+
+    fn number(&mut self) -> Result<Value<'gc>, JsonError> {
+        let start = self.pos;
+        let mut is_float = false;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        while let Some(c) = self.peek() {
+            match c {
+                b'0'..=b'9' | b'+' | b'-' => {}
+                b'.' | b'e' | b'E' => is_float = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        // The characters were checked above, so this is ASCII.
+        let text = std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        if !is_float {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(Value::Integer(i));
+            }
+        }
+        // Rust accepts a few forms that JSON does not, like `1.` and `.5`, which is harmless.
+        text.parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| JsonError::Syntax(start))
+    }
+
+    // Reads a string literal, starting at its opening quote, and returns its UTF-8 contents.
+    fn string(&mut self) -> Result<Vec<u8>, JsonError> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let c = self.peek().ok_or(JsonError::Syntax(self.pos))?;
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(bytes),
+                b'\\' => {
+                    let escape = self.peek().ok_or(JsonError::Syntax(self.pos))?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' | b'\\' | b'/' => bytes.push(escape),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'u' => {
+                            let c = self.unicode_escape()?;
+                            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => return Err(JsonError::Syntax(self.pos - 1)),
+                    }
+                }
+                c if c < 0x20 => return Err(JsonError::Syntax(self.pos - 1)),
+                c => bytes.push(c),
+            }
+        }
+    }
+
+    // Reads the digits of a `\u` escape, along with a second escape if the first is the high half
+    // of a UTF-16 surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let start = self.pos;
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.input[self.pos..].starts_with(b"\\u") {
+                return Err(JsonError::Syntax(start));
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(JsonError::Syntax(start));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or(JsonError::Syntax(start))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .ok_or(JsonError::Syntax(self.pos))?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(JsonError::Syntax(self.pos));
+        }
+        let code = digits
+            .iter()
+            .fold(0, |code, &d| code * 16 + (d as char).to_digit(16).unwrap());
+        self.pos += 4;
+        Ok(code)
+    }
+}
@@ -1,18 +1,26 @@
 pub mod any;
+pub mod bytecode;
 pub mod callback;
 pub mod closure;
 pub mod compiler;
+pub mod config;
 pub mod constant;
 pub mod conversion;
 pub mod error;
+pub mod finalizers;
 pub mod fuel;
 pub mod function;
 pub mod io;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod lua;
+pub mod memory;
 pub mod meta_ops;
 pub mod opcode;
 pub mod raw_ops;
 pub mod registry;
+pub mod serialize;
+pub mod snapshot;
 pub mod stack;
 pub mod stdlib;
 pub mod string;
@@ -25,21 +33,31 @@ pub mod value;
 pub use self::{
     callback::{AnyCallback, AnySequence, Callback, CallbackReturn, Sequence, SequencePoll},
     closure::{Closure, ClosureError, FunctionProto, ProtoCompileError},
+    config::VmConfig,
     constant::Constant,
-    conversion::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, Variadic},
+    conversion::{DefaultArg, FromMultiValue, FromValue, IntoMultiValue, IntoValue, Variadic},
     error::{Error, RuntimeError, StaticError, TypeError},
+    finalizers::{Finalizers, WeakTables},
     fuel::Fuel,
     function::Function,
-    lua::{Context, Lua, State},
-    meta_ops::MetaMethod,
+    lua::{Context, Lua, State, LUA_VERSION},
+    memory::{MemoryLimit, MemoryLimitExceeded, SizeLimits, StringLengthOverflow},
+    meta_ops::{MetaChainLimit, MetaMethod},
+    raw_ops::{ArithmeticMode, FloatDivideByZero},
     registry::{
-        Registry, Singleton, StaticCallback, StaticClosure, StaticFunction, StaticTable,
+        Registry, Setting, Singleton, StaticCallback, StaticClosure, StaticFunction, StaticTable,
         StaticThread, StaticUserData, StaticValue,
     },
     stack::Stack,
     string::{String, StringError},
-    table::{InvalidTableKey, Table},
-    thread::{BadThreadMode, Thread, ThreadMode, VMError},
+    table::{
+        ArrayShiftError, InvalidTableKey, IterationMode, KeyHashing, ModifiedDuringIteration,
+        ReadOnlyTable, SetPathError, Table, TableGrowth, Weakness,
+    },
+    thread::{
+        BacktraceFrame, BadThreadMode, CallDepthLimit, Hook, HookMask, StackOverflow, Thread,
+        ThreadMode, VMError,
+    },
     userdata::{AnyUserData, BadUserDataType},
     value::Value,
 };
@@ -1,20 +1,38 @@
-use std::ops;
+use std::{collections::HashSet, ops};
 
-use gc_arena::{metrics::Metrics, Arena, Collect, Mutation, Rootable};
+use gc_arena::{metrics::Metrics, Arena, Collect, Finalization, Gc, Mutation, Rootable};
 
 use crate::{
     error::RuntimeError,
-    stdlib::{load_base, load_coroutine, load_io, load_math, load_string, load_table},
+    meta_ops::{self, MetaChainLimit},
+    raw_ops::{ArithmeticMode, FloatDivideByZero},
+    registry::{Fetchable, Stashable},
+    stdlib::{
+        load_base, load_coroutine, load_debug, load_io, load_io_with, load_math, load_package,
+        load_stdlib, load_string, load_table, PrintConfig, StdlibConfig, Warnings,
+    },
     string::InternedStringSet,
-    Error, FromMultiValue, Fuel, Registry, StaticError, StaticThread, Table, ThreadMode,
+    table::{IterationMode, KeyHashing, NextValue},
+    BacktraceFrame, CallDepthLimit, Error, Finalizers, FromMultiValue, Fuel, IntoMultiValue,
+    MemoryLimit, MetaMethod, Registry, Setting, SizeLimits, StaticError, StaticFunction,
+    StaticThread, StaticValue, Table, Thread, ThreadMode, Value, VmConfig, WeakTables,
 };
 
+/// The dialect of Lua that piccolo targets, which is also the value of the `_VERSION` global.
+///
+/// Behavior that differs between Lua versions (such as which metamethods are consulted) follows
+/// this version.
+pub const LUA_VERSION: &str = "Lua 5.4";
+
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct State<'gc> {
     pub globals: Table<'gc>,
     pub registry: Registry<'gc>,
     pub strings: InternedStringSet<'gc>,
+    pub finalizers: Finalizers<'gc>,
+    pub weak_tables: WeakTables<'gc>,
+    pub config: Gc<'gc, VmConfig>,
 }
 
 impl<'gc> State<'gc> {
@@ -23,6 +41,9 @@ impl<'gc> State<'gc> {
             globals: Table::new(mc),
             registry: Registry::new(mc),
             strings: InternedStringSet::new(mc),
+            finalizers: Finalizers::new(mc),
+            weak_tables: WeakTables::new(mc),
+            config: Gc::new(mc, VmConfig::new(mc)),
         }
     }
 
@@ -32,6 +53,14 @@ impl<'gc> State<'gc> {
             state: self,
         }
     }
+
+    // Called once marking is done and before anything is swept. Weak tables must drop their
+    // entries which are about to be freed, and finalizable values must be found while they can
+    // still be resurrected.
+    fn prepare_sweep(&self, fc: &Finalization<'gc>) {
+        self.weak_tables.clear_dead(fc);
+        self.finalizers.prepare(fc);
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -40,6 +69,54 @@ pub struct Context<'gc> {
     pub state: &'gc State<'gc>,
 }
 
+impl<'gc> Context<'gc> {
+    /// The table of global variables, which is the default `_ENV` of loaded chunks and is
+    /// available to scripts as `_G` once the base library is loaded.
+    pub fn globals(&self) -> Table<'gc> {
+        self.state.globals
+    }
+
+    /// Creates a string value from `s`.
+    ///
+    /// Strings are interned: while a string with the same contents is still alive, this returns
+    /// that same string rather than allocating a new one. Interning is weak, so strings are still
+    /// collected once nothing else references them.
+    pub fn intern(&self, s: &str) -> Value<'gc> {
+        self.intern_bytes(s.as_bytes())
+    }
+
+    /// Creates a string value from arbitrary bytes, which need not be UTF-8. Interned the same way
+    /// as [`Context::intern`].
+    pub fn intern_bytes(&self, s: &[u8]) -> Value<'gc> {
+        Value::String(self.state.strings.intern(self, s))
+    }
+
+    /// Roots a value so that it can be held outside of the current `Lua::run` call, such as by a
+    /// callback that wants to keep a value it was given for later use.
+    ///
+    /// Values like `Value<'gc>` and `Table<'gc>` borrow the arena for `'gc` and cannot escape the
+    /// closure they were obtained in, and a value which is only reachable from Rust would be freed
+    /// by the next collection anyway. The stashed handle returned here, such as a `StaticValue` or
+    /// `StaticTable`, has no lifetime and keeps its value alive across any number of collections
+    /// until the handle is dropped. It can be stored anywhere, including in the state of a
+    /// callback, and turned back into a value with [`Context::fetch`] inside a later call.
+    ///
+    /// A stashed handle is only valid for the `Lua` instance it was created in, and fetching it
+    /// from a different instance will panic. Since it is a root, a stashed value which refers back
+    /// to whatever holds the handle will never be collected, so handles should not be stored inside
+    /// of values owned by the same `Lua` instance.
+    ///
+    /// This is shorthand for `self.state.registry.stash(&self, value)`.
+    pub fn stash<S: Stashable<'gc>>(&self, value: S) -> S::Stashed {
+        self.state.registry.stash(self, value)
+    }
+
+    /// Returns the value held by a handle created with [`Context::stash`].
+    pub fn fetch<F: Fetchable<'gc>>(&self, stashed: &F) -> F::Fetched {
+        self.state.registry.fetch(stashed)
+    }
+}
+
 impl<'gc> ops::Deref for Context<'gc> {
     type Target = Mutation<'gc>;
 
@@ -56,6 +133,9 @@ impl Default for Lua {
     }
 }
 
+// The amount of fuel to run threads with between garbage collection steps.
+const FUEL_PER_GC: i32 = 4096;
+
 impl Lua {
     /// Create a new `Lua` instance with no parts of the stdlib loaded.
     pub fn empty() -> Self {
@@ -69,19 +149,36 @@ impl Lua {
         lua
     }
 
-    /// Create a new `Lua` instance with all of the stdlib loaded.
+    /// Create a new `Lua` instance with all of the stdlib loaded. The `os` library is only loaded
+    /// with the `std` feature.
     pub fn full() -> Self {
         let mut lua = Lua::core();
         lua.load_io();
+        #[cfg(feature = "std")]
+        lua.load_os();
+        lua.load_debug();
+        lua
+    }
+
+    /// Create a new `Lua` instance with only the parts of the stdlib enabled in `config` loaded.
+    pub fn with_stdlib(config: StdlibConfig) -> Self {
+        let mut lua = Self::empty();
+        lua.load_stdlib(config);
         lua
     }
 
+    /// Load the parts of the stdlib enabled in `config`.
+    pub fn load_stdlib(&mut self, config: StdlibConfig) {
+        self.run(|ctx| load_stdlib(ctx, config))
+    }
+
     /// Load the core parts of the stdlib that do not allow performing any I/O.
     ///
     /// Calls:
     ///   - `load_base`
     ///   - `load_coroutine`
     ///   - `load_math`
+    ///   - `load_package`
     ///   - `load_string`
     ///   - `load_table`
     pub fn load_core(&mut self) {
@@ -89,6 +186,7 @@ impl Lua {
             load_base(ctx);
             load_coroutine(ctx);
             load_math(ctx);
+            load_package(ctx);
             load_string(ctx);
             load_table(ctx);
         })
@@ -101,6 +199,28 @@ impl Lua {
         })
     }
 
+    /// Load the parts of the stdlib that allow I/O, with `print` configured by `config`.
+    pub fn load_io_with(&mut self, config: PrintConfig) {
+        self.run(|ctx| {
+            load_io_with(ctx, config);
+        })
+    }
+
+    /// Load the `os` library functions which create, rename, and remove files.
+    #[cfg(feature = "std")]
+    pub fn load_os(&mut self) {
+        self.run(|ctx| {
+            crate::stdlib::load_os(ctx);
+        })
+    }
+
+    /// Load the `debug` library, which allows inspecting the call stack of running threads.
+    pub fn load_debug(&mut self) {
+        self.run(|ctx| {
+            load_debug(ctx);
+        })
+    }
+
     /// Size of all memory used by this Lua context.
     ///
     /// This is equivalent to `self.gc_metrics().total_allocation()`. This counts all `Gc`
@@ -110,9 +230,136 @@ impl Lua {
         self.gc_metrics().total_allocation()
     }
 
-    /// Finish the current collection cycle completely, calls `gc_arena::Arena::collect_all()`.
+    /// Sets a limit on `Lua::total_memory` in bytes, past which running threads will raise a
+    /// "not enough memory" error, or removes the limit if `limit` is `None`.
+    ///
+    /// See [`MemoryLimit`] for details on how the limit is enforced.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.run(|ctx| MemoryLimit::set(ctx, limit))
+    }
+
+    /// Sets the largest strings and tables that scripts may create, see [`SizeLimits`].
+    pub fn set_size_limits(&mut self, limits: SizeLimits) {
+        self.run(|ctx| ctx.state.config.set_size_limits(limits))
+    }
+
+    /// Sets whether integer overflow in addition, subtraction, and multiplication wraps (the
+    /// default) or raises an error.
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.run(|ctx| ctx.state.config.set_arithmetic_mode(mode))
+    }
+
+    /// Sets whether float division by zero produces an infinity or NaN (the default) or raises an
+    /// error.
+    pub fn set_float_divide_by_zero(&mut self, mode: FloatDivideByZero) {
+        self.run(|ctx| ctx.state.config.set_float_divide_by_zero(mode))
+    }
+
+    /// Sets how tables created by Lua table constructors and by the stdlib hash their keys. Use
+    /// [`KeyHashing::random`] when running untrusted scripts, see [`KeyHashing`] for details.
+    ///
+    /// The globals table and every table reachable from it through keys and values, which includes
+    /// the tables of any stdlib that is already loaded, are rehashed with the new hashing.
+    pub fn set_key_hashing(&mut self, hashing: KeyHashing) {
+        self.run(|ctx| {
+            ctx.state.config.set_key_hashing(hashing.clone());
+
+            let mut visited = HashSet::new();
+            let mut pending = vec![ctx.state.globals];
+            while let Some(table) = pending.pop() {
+                if !visited.insert(table) {
+                    continue;
+                }
+                table.set_key_hashing(&ctx, hashing.clone());
+                let mut key = Value::Nil;
+                while let NextValue::Found { key: k, value } = table.next(key) {
+                    for v in [k, value] {
+                        if let Value::Table(t) = v {
+                            pending.push(t);
+                        }
+                    }
+                    key = k;
+                }
+            }
+        })
+    }
+
+    /// Sets the handler which receives the messages of the `warn` function, replacing the default
+    /// which writes them to stderr. Warnings start out disabled, see [`Warnings`].
+    pub fn set_warn_handler(&mut self, handler: impl FnMut(&[u8]) + 'static) {
+        self.run(|ctx| Warnings::get(ctx).set_handler(handler))
+    }
+
+    /// Sets whether `next` and `pairs` raise an error when the table being iterated over gains a
+    /// new key during the iteration, see [`IterationMode`].
+    pub fn set_iteration_mode(&mut self, mode: IterationMode) {
+        self.run(|ctx| Setting::set(ctx, mode))
+    }
+
+    /// Sets how many tables an `__index` or `__newindex` lookup may pass through before raising a
+    /// "chain too long" error, see [`MetaChainLimit`].
+    pub fn set_meta_chain_limit(&mut self, limit: usize) {
+        self.run(|ctx| ctx.state.config.set_meta_chain_limit(MetaChainLimit(limit)))
+    }
+
+    /// Sets how many frames the call stack of a thread may hold before a "stack overflow" error is
+    /// raised in it, see [`CallDepthLimit`].
+    pub fn set_call_depth_limit(&mut self, limit: usize) {
+        self.run(|ctx| ctx.state.config.set_call_depth_limit(CallDepthLimit(limit)))
+    }
+
+    /// Sets a handler which is called with every error that a top-level thread raises and nothing
+    /// catches, along with the thread's call stack. See [`Thread::set_uncaught_error_handler`].
+    pub fn set_uncaught_error_handler(
+        &mut self,
+        handler: impl for<'gc> FnMut(Context<'gc>, &Error<'gc>, &[BacktraceFrame<'gc>]) + 'static,
+    ) {
+        self.run(|ctx| Thread::set_uncaught_error_handler(ctx, handler))
+    }
+
+    /// Run a complete collection cycle, freeing everything that is unreachable, and then call the
+    /// `__gc` metamethod of any finalizable values that were found to be unreachable.
     pub fn gc_collect(&mut self) {
-        self.0.collect_all();
+        // A cycle that is already sweeping has finished marking, so finish it and then run a fresh
+        // cycle to find everything that is unreachable now.
+        if self.0.mark_all().is_none() {
+            self.collect_all();
+        }
+        self.collect_all();
+        self.run_finalizers();
+    }
+
+    /// Calls the `__gc` metamethod of every value that the collector has found to be unreachable
+    /// since the last call, each on its own thread. See [`Finalizers`] for the details.
+    ///
+    /// This is called automatically by `Lua::gc_collect` and each time `Lua::finish_thread`
+    /// returns. Embedders which only run threads with `Lua::finish_thread_with_fuel` must call it
+    /// themselves at a convenient point.
+    ///
+    /// Errors raised by finalizers are ignored, and a finalizer which yields or runs out of fuel
+    /// is abandoned.
+    pub fn run_finalizers(&mut self) {
+        while let Some(thread) = self.run(|ctx| {
+            while let Some(value) = ctx.state.finalizers.pop_pending(&ctx) {
+                // As in PUC-Rio Lua, the `__gc` field is looked up again when the value is
+                // finalized, and is skipped if it is no longer callable.
+                let Some(metatable) = meta_ops::metatable(ctx, value) else {
+                    continue;
+                };
+                let Ok(function) = meta_ops::call(ctx, metatable.get(ctx, MetaMethod::Gc)) else {
+                    continue;
+                };
+
+                let thread = Thread::new(&ctx);
+                thread
+                    .start(ctx, function, value)
+                    .expect("new threads are always stopped");
+                return Some(ctx.stash(thread));
+            }
+            None
+        }) {
+            self.step_thread(&thread);
+        }
     }
 
     pub fn gc_metrics(&self) -> &Metrics {
@@ -127,11 +374,28 @@ impl Lua {
 
         let r = self.0.mutate(move |mc, state| f(state.ctx(mc)));
         if self.0.metrics().allocation_debt() > COLLECTOR_GRANULARITY {
-            self.0.collect_debt();
+            self.collect_debt();
         }
         r
     }
 
+    // The arena must only ever be collected through `Lua::collect_debt` and `Lua::collect_all`,
+    // which run `State::prepare_sweep` once marking is done and before anything is swept. The
+    // `Collect` impl of weak tables is only sound if this happens in every cycle.
+    fn collect_debt(&mut self) {
+        if let Some(marked) = self.0.mark_debt() {
+            marked.finalize(|fc, root| root.prepare_sweep(fc));
+        }
+        self.0.collect_debt();
+    }
+
+    fn collect_all(&mut self) {
+        if let Some(marked) = self.0.mark_all() {
+            marked.finalize(|fc, root| root.prepare_sweep(fc));
+        }
+        self.0.collect_all();
+    }
+
     pub fn try_run<F, R>(&mut self, f: F) -> Result<R, StaticError>
     where
         F: for<'gc> FnOnce(Context<'gc>) -> Result<R, Error<'gc>>,
@@ -140,10 +404,14 @@ impl Lua {
     }
 
     /// Will run the thread until it is out fo the `ThreadMode::Normal` state *or* a callback
-    /// interrupts it (via `Fuel`).
+    /// interrupts it (via `Fuel`), and then calls any pending finalizers with
+    /// `Lua::run_finalizers`.
     pub fn finish_thread(&mut self, thread: &StaticThread) {
-        const FUEL_PER_GC: i32 = 4096;
+        self.step_thread(thread);
+        self.run_finalizers();
+    }
 
+    fn step_thread(&mut self, thread: &StaticThread) {
         loop {
             let mut fuel = Fuel::with_fuel(FUEL_PER_GC);
 
@@ -166,6 +434,101 @@ impl Lua {
         }
     }
 
+    /// Like `Lua::finish_thread`, but only runs the thread for as long as the given `Fuel` lasts.
+    ///
+    /// Returns true if the thread is out of the `ThreadMode::Normal` state. Returns false if the
+    /// thread ran out of fuel or was interrupted by a callback, in which case it is paused in a
+    /// resumable state and calling this again (after refilling `fuel`) continues where it left off.
+    ///
+    /// This allows embedders to time-slice untrusted scripts that may otherwise never finish.
+    pub fn finish_thread_with_fuel(&mut self, thread: &StaticThread, fuel: &mut Fuel) -> bool {
+        while fuel.should_continue() {
+            // Only hand out fuel a slice at a time, so that we can collect garbage in between.
+            let reserve = fuel.remaining_fuel().saturating_sub(FUEL_PER_GC).max(0);
+            fuel.consume_fuel(reserve);
+
+            let finished = self.run(|ctx| {
+                let thread = ctx.state.registry.fetch(thread);
+                match thread.mode() {
+                    ThreadMode::Normal => {
+                        thread.step(ctx, fuel).unwrap();
+                        false
+                    }
+                    _ => true,
+                }
+            });
+
+            fuel.adjust_fuel(reserve);
+            if finished {
+                return true;
+            }
+        }
+
+        self.run(|ctx| ctx.state.registry.fetch(thread).mode() != ThreadMode::Normal)
+    }
+
+    /// Calls a function with the given arguments on a new thread, runs it to completion, and
+    /// returns its results.
+    ///
+    /// The whole call runs synchronously, with garbage collected between steps the same way as
+    /// `Lua::finish_thread`. Since collection can happen during the call, no `'gc` values can be
+    /// held across it: the function must be stashed in the registry beforehand, and the arguments
+    /// and results must be types that do not borrow from the arena (like numbers and Rust strings).
+    /// This also means that it cannot be called from inside `Lua::run` or a callback, which must
+    /// instead return `CallbackReturn::TailCall` or use a `Sequence` to call back into Lua.
+    ///
+    /// Errors raised by the function are returned as a `StaticError`.
+    pub fn call_function<A, R>(
+        &mut self,
+        function: &StaticFunction,
+        args: A,
+    ) -> Result<R, StaticError>
+    where
+        A: for<'gc> IntoMultiValue<'gc>,
+        R: for<'gc> FromMultiValue<'gc>,
+    {
+        let thread = self.try_run(|ctx| {
+            let thread = Thread::new(&ctx);
+            thread.start(ctx, ctx.state.registry.fetch(function), args)?;
+            Ok(ctx.state.registry.stash(&ctx, thread))
+        })?;
+        self.run_thread(&thread)
+    }
+
+    /// Like `Lua::call_function`, but catches any error the way that `pcall` does.
+    ///
+    /// On error, returns the error value that `pcall` would have returned, stashed in the registry
+    /// so that it can be fetched again inside `Lua::run`. Lua errors are returned as the exact value
+    /// passed to `error`, and Rust errors (including failing to convert the results into `R`) are
+    /// converted to a value with `Error::to_value`.
+    pub fn protected_call<A, R>(
+        &mut self,
+        function: &StaticFunction,
+        args: A,
+    ) -> Result<R, StaticValue>
+    where
+        A: for<'gc> IntoMultiValue<'gc>,
+        R: for<'gc> FromMultiValue<'gc>,
+    {
+        let thread = self.run(|ctx| {
+            let thread = Thread::new(&ctx);
+            thread
+                .start(ctx, ctx.state.registry.fetch(function), args)
+                .expect("new threads are always stopped");
+            ctx.state.registry.stash(&ctx, thread)
+        });
+        self.finish_thread(&thread);
+        self.run(|ctx| {
+            ctx.state
+                .registry
+                .fetch(&thread)
+                .take_return::<R>(ctx)
+                .map_err(Error::from)
+                .and_then(|r| r)
+                .map_err(|e| ctx.state.registry.stash(&ctx, e.to_value(ctx)))
+        })
+    }
+
     pub fn run_thread<R: for<'gc> FromMultiValue<'gc>>(
         &mut self,
         thread: &StaticThread,
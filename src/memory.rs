@@ -0,0 +1,135 @@
+use gc_arena::Collect;
+use thiserror::Error;
+
+use crate::{Context, VmConfig};
+
+/// The error raised in a running thread once the memory limit set with [`MemoryLimit::set`] is
+/// exceeded.
+#[derive(Debug, Copy, Clone, Error)]
+#[error("not enough memory")]
+pub struct MemoryLimitExceeded;
+
+/// A configurable cap on the total memory used by a Lua instance.
+///
+/// The limit is compared against `Metrics::total_allocation()`, which accounts for every `Gc`
+/// allocation along with the storage of strings, tables, and other collections held inside of them.
+/// Allocations which may be arbitrarily large are checked before they are made: growing a table
+/// created by Lua or by the stdlib, and building the result of `string.rep`, fail with a "not
+/// enough memory" error if they would take memory use past the limit. Every other allocation is
+/// small, so instead `Thread::step` checks the limit after every VM step and callback, and raises a
+/// [`MemoryLimitExceeded`] error in the thread if it has been passed. Both errors can be caught
+/// with `pcall` like any other.
+///
+/// Memory is only reclaimed when the garbage collector runs between calls to `Lua::run`, so memory
+/// will often stay above the limit for some time after an error is raised. To give scripts room to
+/// handle the error, once `Thread::step` raises it, it is not raised again until memory drops back
+/// below the limit, unless memory grows past 125% of the limit, at which point it is raised on
+/// every check.
+///
+/// The limit is held in the [`VmConfig`] of the Lua instance.
+#[derive(Debug, Copy, Clone, Default, Collect)]
+#[collect(require_static)]
+pub struct MemoryLimit {
+    limit: Option<usize>,
+    // Whether an error has been raised since memory was last below the limit.
+    raised: bool,
+}
+
+impl MemoryLimit {
+    /// Sets the memory limit in bytes, or removes it if `limit` is `None`.
+    pub fn set(ctx: Context<'_>, limit: Option<usize>) {
+        ctx.state.config.set_memory_limit(MemoryLimit {
+            limit,
+            raised: false,
+        });
+    }
+
+    /// Returns the current memory limit in bytes, if there is one.
+    pub fn limit(ctx: Context<'_>) -> Option<usize> {
+        ctx.state.config.memory_limit().limit
+    }
+
+    /// Returns an error if memory use is over the limit and an error should be raised.
+    pub fn check(ctx: Context<'_>) -> Result<(), MemoryLimitExceeded> {
+        let config = &ctx.state.config;
+        let mut state = config.memory_limit();
+        let Some(limit) = state.limit else {
+            return Ok(());
+        };
+
+        let total = config.metrics().total_allocation();
+        let raise = total > limit && (!state.raised || total > limit.saturating_add(limit / 4));
+        if state.raised != (total > limit) {
+            state.raised = total > limit;
+            config.set_memory_limit(state);
+        }
+
+        if raise {
+            Err(MemoryLimitExceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error if allocating `bytes` more would take memory use over the limit.
+    pub fn check_allocation(ctx: Context<'_>, bytes: usize) -> Result<(), MemoryLimitExceeded> {
+        Self::check_allocation_in(&ctx.state.config, bytes)
+    }
+
+    pub(crate) fn check_allocation_in(
+        config: &VmConfig,
+        bytes: usize,
+    ) -> Result<(), MemoryLimitExceeded> {
+        match config.memory_limit().limit {
+            Some(limit) if config.metrics().total_allocation().saturating_add(bytes) > limit => {
+                Err(MemoryLimitExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The error raised by string functions such as `string.rep` when the string they would create is
+/// longer than [`SizeLimits::max_string_len`].
+#[derive(Debug, Copy, Clone, Error)]
+#[error("string length overflow")]
+pub struct StringLengthOverflow;
+
+/// Caps on the size of a single string or table, which make a script that asks for an enormous
+/// string or table raise a catchable error rather than attempting the allocation.
+///
+/// These guard against single huge requests, such as `string.rep("x", 1e15)`, which could abort the
+/// process before [`MemoryLimit`] is ever checked. They are not a substitute for a memory limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct SizeLimits {
+    /// The length in bytes of the longest string that string functions will create.
+    pub max_string_len: usize,
+    /// The number of entries past which tables created by Lua table constructors refuse to grow,
+    /// see [`Table::set_max_len`](crate::Table::set_max_len).
+    pub max_table_len: usize,
+}
+
+impl SizeLimits {
+    /// The default limits, strings of just under 2 GiB and tables of 2^31 entries, which no
+    /// reasonable script reaches.
+    pub const DEFAULT: SizeLimits = SizeLimits {
+        max_string_len: i32::MAX as usize,
+        max_table_len: 1 << 31,
+    };
+
+    /// Returns an error if a string of `len` bytes would be over the limit.
+    pub fn check_string_len(ctx: Context<'_>, len: usize) -> Result<(), StringLengthOverflow> {
+        if len > ctx.state.config.size_limits().max_string_len {
+            Err(StringLengthOverflow)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -1,7 +1,9 @@
-use gc_arena::Collect;
+use gc_arena::{lock::Lock, Collect, Gc, Rootable};
+use thiserror::Error;
 
 use crate::{
-    AnyCallback, CallbackReturn, Context, Function, IntoValue, RuntimeError, TypeError, Value,
+    raw_ops, AnyCallback, CallbackReturn, Context, Function, IntoValue, RuntimeError, Singleton,
+    Table, TypeError, Value,
 };
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Collect)]
@@ -13,6 +15,9 @@ pub enum MetaMethod {
     Call,
     Pairs,
     ToString,
+    Close,
+    Gc,
+    Mode,
 }
 
 impl MetaMethod {
@@ -24,6 +29,9 @@ impl MetaMethod {
             MetaMethod::Call => "__call",
             MetaMethod::Pairs => "__pairs",
             MetaMethod::ToString => "__tostring",
+            MetaMethod::Close => "__close",
+            MetaMethod::Gc => "__gc",
+            MetaMethod::Mode => "__mode",
         }
     }
 }
@@ -48,166 +56,316 @@ pub enum MetaResult<'gc, const N: usize> {
     Call(MetaCall<'gc, N>),
 }
 
+/// The default for [`MetaChainLimit`], the same limit that PUC-Rio Lua uses.
+pub const DEFAULT_META_CHAIN_LIMIT: usize = 2000;
+
+/// The error raised when following `__index` or `__newindex` tables passes through more than
+/// [`MetaChainLimit`] values, which usually means that the chain loops back on itself.
+#[derive(Debug, Copy, Clone, Error)]
+#[error("'{}' chain too long; possible loop", .0.name())]
+pub struct MetaChainTooLong(pub MetaMethod);
+
+/// The maximum number of values, including the original one, that [`index`] and [`new_index`] will
+/// visit while following `__index` and `__newindex` tables. It is held in the
+/// [`VmConfig`](crate::VmConfig) and defaults to [`DEFAULT_META_CHAIN_LIMIT`].
+///
+/// The original value is always looked up, so a limit of 0 or 1 only forbids following `__index`
+/// and `__newindex` tables, and never makes a plain table access fail.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct MetaChainLimit(pub usize);
+
+impl Default for MetaChainLimit {
+    fn default() -> Self {
+        MetaChainLimit(DEFAULT_META_CHAIN_LIMIT)
+    }
+}
+
+/// The types of value which share a single metatable between every value of the type, rather than
+/// each value having its own metatable like tables and userdata do.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PrimitiveType {
+    Nil,
+    Boolean,
+    /// Both integers and floats.
+    Number,
+    String,
+    Function,
+    Thread,
+    /// All light userdata share one metatable, as in PUC-Rio Lua.
+    LightUserData,
+}
+
+impl PrimitiveType {
+    const COUNT: usize = 7;
+
+    /// Returns the type of `value`, or `None` if `value` is a table or userdata.
+    pub fn of(value: Value<'_>) -> Option<PrimitiveType> {
+        Some(match value {
+            Value::Nil => PrimitiveType::Nil,
+            Value::Boolean(_) => PrimitiveType::Boolean,
+            Value::Integer(_) | Value::Number(_) => PrimitiveType::Number,
+            Value::String(_) => PrimitiveType::String,
+            Value::Function(_) => PrimitiveType::Function,
+            Value::Thread(_) => PrimitiveType::Thread,
+            Value::LightUserData(_) => PrimitiveType::LightUserData,
+            Value::Table(_) | Value::UserData(_) => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct TypeMetatables<'gc>(Gc<'gc, Lock<[Option<Table<'gc>>; PrimitiveType::COUNT]>>);
+
+impl<'gc> Singleton<'gc> for TypeMetatables<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        TypeMetatables(Gc::new(&ctx, Lock::new([None; PrimitiveType::COUNT])))
+    }
+}
+
+impl<'gc> TypeMetatables<'gc> {
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.state
+            .registry
+            .singleton::<Rootable![TypeMetatables<'_>]>(ctx)
+    }
+}
+
+/// Returns the metatable shared by every value of the type `ty`.
+pub fn type_metatable<'gc>(ctx: Context<'gc>, ty: PrimitiveType) -> Option<Table<'gc>> {
+    TypeMetatables::get(ctx).0.get()[ty as usize]
+}
+
+/// Sets the metatable shared by every value of the type `ty`, returning the previous one.
+///
+/// The string library uses this to give strings a metatable whose `__index` is the `string`
+/// table, so that methods can be called on strings like `s:sub(1, 2)`.
+pub fn set_type_metatable<'gc>(
+    ctx: Context<'gc>,
+    ty: PrimitiveType,
+    metatable: Option<Table<'gc>>,
+) -> Option<Table<'gc>> {
+    let metatables = TypeMetatables::get(ctx).0;
+    let mut all = metatables.get();
+    let previous = std::mem::replace(&mut all[ty as usize], metatable);
+    metatables.set(&ctx, all);
+    previous
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct NamedMetatables<'gc>(Table<'gc>);
+
+impl<'gc> Singleton<'gc> for NamedMetatables<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        NamedMetatables(Table::with_instance_hashing(ctx))
+    }
+}
+
+impl<'gc> NamedMetatables<'gc> {
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.state
+            .registry
+            .singleton::<Rootable![NamedMetatables<'_>]>(ctx)
+    }
+}
+
+/// Returns the metatable registered under `name` with [`set_named_metatable`] or
+/// [`named_metatable_or_init`], if there is one.
+pub fn named_metatable<'gc>(ctx: Context<'gc>, name: &str) -> Option<Table<'gc>> {
+    match NamedMetatables::get(ctx).0.get(ctx, ctx.intern(name)) {
+        Value::Table(t) => Some(t),
+        _ => None,
+    }
+}
+
+/// Registers `metatable` under `name` for this Lua instance, returning the previously registered
+/// metatable. Registering `None` removes the name.
+///
+/// Registered metatables are only reachable from Rust, so scripts cannot replace them.
+pub fn set_named_metatable<'gc>(
+    ctx: Context<'gc>,
+    name: &str,
+    metatable: Option<Table<'gc>>,
+) -> Option<Table<'gc>> {
+    let previous = named_metatable(ctx, name);
+    NamedMetatables::get(ctx)
+        .0
+        .set(ctx, ctx.intern(name), metatable)
+        .unwrap();
+    previous
+}
+
+/// Returns the metatable registered under `name`, first creating and registering an empty table
+/// filled in by `init` if there is none.
+///
+/// This is the equivalent of `luaL_newmetatable`, for libraries which create many objects of the
+/// same kind, such as a userdata type or tables used as instances of a class. Rather than building
+/// a metatable for every object, the library looks up the shared one by name each time it creates
+/// an object and attaches it with `set_metatable`, and `init` only runs once per Lua instance.
+pub fn named_metatable_or_init<'gc>(
+    ctx: Context<'gc>,
+    name: &str,
+    init: impl FnOnce(Table<'gc>),
+) -> Table<'gc> {
+    if let Some(metatable) = named_metatable(ctx, name) {
+        return metatable;
+    }
+
+    let metatable = Table::with_instance_hashing(ctx);
+    init(metatable);
+    set_named_metatable(ctx, name, Some(metatable));
+    metatable
+}
+
+/// Returns the metatable of any value, which is either the value's own metatable for tables and
+/// userdata, or the metatable set for its type with [`set_type_metatable`].
+pub fn metatable<'gc>(ctx: Context<'gc>, value: Value<'gc>) -> Option<Table<'gc>> {
+    match value {
+        Value::Table(t) => t.metatable(),
+        Value::UserData(u) => u.metatable(),
+        v => type_metatable(ctx, PrimitiveType::of(v).unwrap()),
+    }
+}
+
+/// Performs the Lua indexing operation `table[key]`, following `__index` metamethods.
+///
+/// `__index` tables are followed here. If an `__index` function is reached, this returns a call to
+/// it with the arguments `[table, key]`, where `table` is the value whose metatable holds the
+/// function, which is not the original value if the lookup went through `__index` tables first.
+/// The caller must make the call and use only its first result as the value of the index, or Nil
+/// if it returns nothing.
 pub fn index<'gc>(
     ctx: Context<'gc>,
-    table: Value<'gc>,
+    mut table: Value<'gc>,
     key: Value<'gc>,
-) -> Result<MetaResult<'gc, 2>, TypeError> {
-    let idx = match table {
-        Value::Table(table) => {
-            let v = table.get(ctx, key);
-            if !v.is_nil() {
-                return Ok(MetaResult::Value(v));
-            }
+) -> Result<MetaResult<'gc, 2>, RuntimeError> {
+    let mut visited = 1;
+    loop {
+        let idx = match table {
+            Value::Table(table) => {
+                let v = table.get(ctx, key);
+                if !v.is_nil() {
+                    return Ok(MetaResult::Value(v));
+                }
 
-            let idx = if let Some(mt) = table.metatable() {
-                mt.get(ctx, MetaMethod::Index)
-            } else {
-                Value::Nil
-            };
+                let idx = if let Some(mt) = table.metatable() {
+                    mt.get(ctx, MetaMethod::Index)
+                } else {
+                    Value::Nil
+                };
 
-            if idx.is_nil() {
-                return Ok(MetaResult::Value(Value::Nil));
-            }
+                if idx.is_nil() {
+                    return Ok(MetaResult::Value(table.default_value()));
+                }
 
-            idx
-        }
-        Value::UserData(u) if u.metatable().is_some() => {
-            let idx = if let Some(mt) = u.metatable() {
-                mt.get(ctx, MetaMethod::Index)
-            } else {
-                Value::Nil
-            };
-
-            if idx.is_nil() {
-                return Err(TypeError {
-                    expected: "table",
-                    found: table.type_name(),
-                });
+                idx
             }
+            _ => {
+                let idx = metatable(ctx, table)
+                    .map(|mt| mt.get(ctx, MetaMethod::Index))
+                    .unwrap_or_default();
 
-            idx
-        }
-        _ => {
-            return Err(TypeError {
-                expected: "table",
-                found: table.type_name(),
-            })
-        }
-    };
-
-    Ok(MetaResult::Call(match idx {
-        Value::Table(table) => MetaCall {
-            function: AnyCallback::from_fn(&ctx, |ctx, _, stack| {
-                let table = stack.get(0);
-                let key = stack.get(1);
-                stack.clear();
-                match index(ctx, table, key)? {
-                    MetaResult::Value(v) => {
-                        stack.push_back(v);
-                        Ok(CallbackReturn::Return.into())
-                    }
-                    MetaResult::Call(call) => {
-                        stack.extend(call.args);
-                        Ok(CallbackReturn::TailCall(call.function, None).into())
+                if idx.is_nil() {
+                    return Err(TypeError {
+                        expected: "table",
+                        found: table.type_name(),
                     }
+                    .into());
                 }
-            })
-            .into(),
-            args: [table.into(), key],
-        },
-        _ => MetaCall {
-            function: call(ctx, idx)?,
-            args: [table, key],
-        },
-    }))
+
+                idx
+            }
+        };
+
+        // `__index` tables are followed here rather than through a call, so that the length of
+        // the chain can be limited.
+        if let Value::Table(_) = idx {
+            if visited >= ctx.state.config.meta_chain_limit().0 {
+                return Err(MetaChainTooLong(MetaMethod::Index).into());
+            }
+            visited += 1;
+            table = idx;
+        } else {
+            return Ok(MetaResult::Call(MetaCall {
+                function: call(ctx, idx)?,
+                args: [table, key],
+            }));
+        }
+    }
 }
 
 pub fn new_index<'gc>(
     ctx: Context<'gc>,
-    table: Value<'gc>,
+    mut table: Value<'gc>,
     key: Value<'gc>,
     value: Value<'gc>,
 ) -> Result<Option<MetaCall<'gc, 3>>, RuntimeError> {
-    let idx = match table {
-        Value::Table(table) => {
-            let v = table.get(ctx, key);
-            if !v.is_nil() {
-                // If the value is present in the table, then we do not invoke the metamethod.
-                table.set_value(&ctx, key, value)?;
-                return Ok(None);
-            }
+    let mut visited = 1;
+    loop {
+        let idx = match table {
+            Value::Table(table) => {
+                let v = table.get(ctx, key);
+                if !v.is_nil() {
+                    // If the value is present in the table, then we do not invoke the metamethod.
+                    table.set_value(&ctx, key, value)?;
+                    return Ok(None);
+                }
+
+                let idx = if let Some(mt) = table.metatable() {
+                    mt.get(ctx, MetaMethod::NewIndex)
+                } else {
+                    Value::Nil
+                };
+
+                if idx.is_nil() {
+                    // If we do not have a __newindex metamethod, then just set the table value
+                    // directly.
+                    table.set_value(&ctx, key, value)?;
+                    return Ok(None);
+                }
 
-            let idx = if let Some(mt) = table.metatable() {
-                mt.get(ctx, MetaMethod::NewIndex)
-            } else {
-                Value::Nil
-            };
-
-            if idx.is_nil() {
-                // If we do not have a __newindex metamethod, then just set the table value
-                // directly.
-                table.set_value(&ctx, key, value)?;
-                return Ok(None);
+                idx
             }
+            _ => {
+                let idx = metatable(ctx, table)
+                    .map(|mt| mt.get(ctx, MetaMethod::NewIndex))
+                    .unwrap_or_default();
 
-            idx
-        }
-        Value::UserData(u) if u.metatable().is_some() => {
-            let idx = if let Some(mt) = u.metatable() {
-                mt.get(ctx, MetaMethod::NewIndex)
-            } else {
-                Value::Nil
-            };
-
-            if idx.is_nil() {
-                return Err(TypeError {
-                    expected: "table",
-                    found: table.type_name(),
+                if idx.is_nil() {
+                    return Err(TypeError {
+                        expected: "table",
+                        found: table.type_name(),
+                    }
+                    .into());
                 }
-                .into());
+
+                idx
             }
+        };
 
-            idx
-        }
-        _ => {
-            return Err(TypeError {
-                expected: "table",
-                found: table.type_name(),
+        if let Value::Table(_) = idx {
+            if visited >= ctx.state.config.meta_chain_limit().0 {
+                return Err(MetaChainTooLong(MetaMethod::NewIndex).into());
             }
-            .into())
+            visited += 1;
+            table = idx;
+        } else {
+            return Ok(Some(MetaCall {
+                function: call(ctx, idx)?,
+                args: [table, key, value],
+            }));
         }
-    };
-
-    Ok(Some(match idx {
-        Value::Table(table) => MetaCall {
-            function: AnyCallback::from_fn(&ctx, |ctx, _, stack| {
-                let (table, key, value): (Value, Value, Value) = stack.consume(ctx)?;
-                if let Some(call) = new_index(ctx, table, key, value)? {
-                    stack.extend(call.args);
-                    Ok(CallbackReturn::TailCall(call.function, None).into())
-                } else {
-                    Ok(CallbackReturn::Return)
-                }
-            })
-            .into(),
-            args: [table.into(), key, value],
-        },
-        _ => MetaCall {
-            function: call(ctx, idx)?,
-            args: [table, key, value],
-        },
-    }))
+    }
 }
 
 pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, TypeError> {
-    let metatable = match v {
-        Value::Function(f) => return Ok(f),
-        Value::Table(t) => t.metatable(),
-        Value::UserData(ud) => ud.metatable(),
-        _ => None,
+    if let Value::Function(f) = v {
+        return Ok(f);
     }
-    .ok_or(TypeError {
+
+    let metatable = metatable(ctx, v).ok_or(TypeError {
         expected: "function",
         found: v.type_name(),
     })?;
@@ -227,37 +385,50 @@ pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, Type
     }
 }
 
-pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, TypeError> {
-    if let Some(metatable) = match v {
-        Value::Table(t) => t.metatable(),
-        Value::UserData(u) => u.metatable(),
-        _ => None,
-    } {
-        let len = metatable.get(ctx, MetaMethod::Len);
-        if !len.is_nil() {
-            return Ok(MetaResult::Call(MetaCall {
-                function: call(ctx, len)?,
-                args: [v],
-            }));
-        }
+/// Returns the `__close` metamethod to call when a to-be-closed variable holding `v` goes out of
+/// scope.
+///
+/// `nil` and `false` may be assigned to to-be-closed variables and are ignored, so `None` is returned
+/// for them. Any other value without a `__close` metamethod is an error.
+pub fn close<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Option<Function<'gc>>, TypeError> {
+    if !v.to_bool() {
+        return Ok(None);
     }
 
-    match v {
-        Value::String(s) => Ok(MetaResult::Value(s.len().into())),
-        Value::Table(t) => Ok(MetaResult::Value(t.length().into())),
-        f => Err(TypeError {
-            expected: "string or table",
-            found: f.type_name(),
-        }),
+    let close = metatable(ctx, v)
+        .map(|metatable| metatable.get(ctx, MetaMethod::Close))
+        .unwrap_or(Value::Nil);
+
+    if close.is_nil() {
+        return Err(TypeError {
+            expected: "closable value",
+            found: v.type_name(),
+        });
     }
+
+    Ok(Some(call(ctx, close)?))
+}
+
+pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, RuntimeError> {
+    // As in PUC-Rio Lua, the length of a string is always its raw length, even if the string
+    // metatable has a `__len` field.
+    if !v.is_string() {
+        if let Some(metatable) = metatable(ctx, v) {
+            let len = metatable.get(ctx, MetaMethod::Len);
+            if !len.is_nil() {
+                return Ok(MetaResult::Call(MetaCall {
+                    function: call(ctx, len)?,
+                    args: [v],
+                }));
+            }
+        }
+    }
+
+    Ok(MetaResult::Value(raw_ops::len(v)?))
 }
 
 pub fn tostring<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, TypeError> {
-    if let Some(metatable) = match v {
-        Value::Table(t) => t.metatable(),
-        Value::UserData(u) => u.metatable(),
-        _ => None,
-    } {
+    if let Some(metatable) = metatable(ctx, v) {
         let tostring = metatable.get(ctx, MetaMethod::ToString);
         if !tostring.is_nil() {
             return Ok(MetaResult::Call(MetaCall {
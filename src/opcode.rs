@@ -110,9 +110,15 @@ pub enum Operation {
     },
     Jump {
         offset: i16,
-        // If set, close upvalues >= `close_upvalues`
+        // If set, close upvalues and to-be-closed variables >= `close_upvalues`
         close_upvalues: Opt254,
     },
+    /// Marks the value in the given register as a to-be-closed variable, whose `__close` metamethod
+    /// will be called when it goes out of scope. Raises an error if the value is not `nil`, `false`,
+    /// or a value with a `__close` metamethod.
+    ToBeClosed {
+        value: RegisterIndex,
+    },
     /// Test the register as a boolean, if its boolean value matches `is_true`, skip the next
     /// instruction.
     Test {
@@ -133,8 +139,16 @@ pub enum Operation {
     },
     /// Used to set up for a numeric for loop:
     ///
-    /// R(base) -= R(base + 2)
-    /// pc += jump
+    /// if R(base) <?= R(base + 1) then
+    ///     R(base + 3) = R(base)
+    /// else
+    ///     pc += jump
+    /// end
+    ///
+    /// If the initial value and step are integers, the loop counts over integers and R(base + 1) is
+    /// replaced with the number of iterations remaining after the first, so that the loop index can
+    /// never overflow. Otherwise, the control values are all converted to floats. In either case, a
+    /// step of zero is an error.
     NumericForPrep {
         base: RegisterIndex,
         jump: i16,
@@ -148,22 +162,27 @@ pub enum Operation {
     /// end
     ///
     /// The `<?=` operator here means "less than" if the step (aka R(base + 2)) is positive, and
-    /// "greater than" if the step is negative
+    /// "greater than" if the step is negative.
+    ///
+    /// For integer loops, the loop instead continues while the remaining iteration count in
+    /// R(base + 1) is positive, decrementing it each time.
     NumericForLoop {
         base: RegisterIndex,
         jump: i16,
     },
     /// Used to set up for a generic for loop:
     ///
-    /// R(base + 3), ..., R(base + 2 + var_count) = R(base)(R(base + 1), R(base + 2))
+    /// R(base + 4), ..., R(base + 3 + var_count) = R(base)(R(base + 1), R(base + 2))
+    ///
+    /// R(base + 3) holds the loop's closing value, and is left untouched.
     GenericForCall {
         base: RegisterIndex,
         var_count: u8,
     },
     /// Used to iterate a generic for loop:
     ///
-    /// if R(base + 1) ~= nil then
-    ///     R(base) = R(base + 1)
+    /// if R(base + 2) ~= nil then
+    ///     R(base) = R(base + 2)
     ///     pc += jump
     /// end
     GenericForLoop {
@@ -364,6 +383,7 @@ impl OpCode {
             Operation::TailCall { func, args } => OpCodeRepr::TailCall { func, args },
             Operation::Return { start, count } => OpCodeRepr::Return { start, count },
             Operation::VarArgs { dest, count } => OpCodeRepr::VarArgs { dest, count },
+            Operation::ToBeClosed { value } => OpCodeRepr::ToBeClosed { value },
             Operation::Jump {
                 offset,
                 close_upvalues,
@@ -754,6 +774,7 @@ impl OpCode {
             OpCodeRepr::TailCall { func, args } => Operation::TailCall { func, args },
             OpCodeRepr::Return { start, count } => Operation::Return { start, count },
             OpCodeRepr::VarArgs { dest, count } => Operation::VarArgs { dest, count },
+            OpCodeRepr::ToBeClosed { value } => Operation::ToBeClosed { value },
             OpCodeRepr::Jump {
                 offset,
                 close_upvalues,
@@ -1265,6 +1286,9 @@ enum OpCodeRepr {
         offset: i16,
         close_upvalues: Opt254,
     },
+    ToBeClosed {
+        value: RegisterIndex,
+    },
     Test {
         value: RegisterIndex,
         is_true: bool,
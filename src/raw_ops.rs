@@ -1,7 +1,59 @@
-use crate::Value;
+use gc_arena::Collect;
+use thiserror::Error;
+
+use crate::{constant::float_to_int, Value};
 
 // TODO: This module should be entirely replaced by `meta_ops` as they are added.
 
+/// How the VM handles integer overflow in addition, subtraction, and multiplication.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum ArithmeticMode {
+    /// Integers wrap around on overflow, as in PUC-Rio Lua, so `math.maxinteger + 1 ==
+    /// math.mininteger`.
+    #[default]
+    Wrapping,
+    /// Integer overflow raises an "integer overflow" error. Float arithmetic is unaffected.
+    Checked,
+}
+
+/// How the VM handles float division by zero in the `/`, `//`, and `%` operators.
+///
+/// Integer floor division and modulus by zero always raise an error, as in PUC-Rio Lua, since
+/// they have no integer result.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum FloatDivideByZero {
+    /// Division by zero produces an infinity or NaN, as in PUC-Rio Lua, so `1 / 0 == math.huge`.
+    #[default]
+    Ieee,
+    /// Division by zero raises an "attempt to divide by zero" error, for stricter numeric code.
+    /// This also applies to `/` with integer operands, which always performs float division.
+    Error,
+}
+
+/// The error from taking the length of a value which is neither a string nor a table.
+#[derive(Debug, Copy, Clone, Error)]
+#[error("attempt to get length of a {found} value")]
+pub struct LengthError {
+    pub found: &'static str,
+}
+
+/// The raw length operator `#`, without consulting `__len`.
+///
+/// Returns the length in bytes of a string, or a border of a table as in [`Table::length`].
+///
+/// [`Table::length`]: crate::Table::length
+pub fn len<'gc>(v: Value<'gc>) -> Result<Value<'gc>, LengthError> {
+    match v {
+        Value::String(s) => Ok(Value::Integer(s.len())),
+        Value::Table(t) => Ok(Value::Integer(t.length())),
+        v => Err(LengthError {
+            found: v.type_name(),
+        }),
+    }
+}
+
 pub fn add<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.add(&rhs.to_constant()?)?.into())
 }
@@ -14,14 +66,39 @@ pub fn multiply<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.multiply(&rhs.to_constant()?)?.into())
 }
 
+pub fn checked_add<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
+    Some(lhs.to_constant()?.checked_add(&rhs.to_constant()?)?.into())
+}
+
+pub fn checked_subtract<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
+    Some(
+        lhs.to_constant()?
+            .checked_subtract(&rhs.to_constant()?)?
+            .into(),
+    )
+}
+
+pub fn checked_multiply<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
+    Some(
+        lhs.to_constant()?
+            .checked_multiply(&rhs.to_constant()?)?
+            .into(),
+    )
+}
+
 pub fn float_divide<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.float_divide(&rhs.to_constant()?)?.into())
 }
 
-pub fn floor_divide<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
+pub fn idiv<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.floor_divide(&rhs.to_constant()?)?.into())
 }
 
+#[deprecated(note = "renamed to `idiv`")]
+pub fn floor_divide<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
+    idiv(lhs, rhs)
+}
+
 pub fn modulo<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.modulo(&rhs.to_constant()?)?.into())
 }
@@ -34,6 +111,21 @@ pub fn negate<'gc>(lhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.negate()?.into())
 }
 
+/// The absolute value used by `math.abs`, which keeps integers as integers.
+pub fn abs<'gc>(v: Value<'gc>) -> Option<Value<'gc>> {
+    Some(v.to_constant()?.abs()?.into())
+}
+
+/// The rounding used by `math.floor`, which returns an integer whenever the result fits in one.
+pub fn floor<'gc>(v: Value<'gc>) -> Option<Value<'gc>> {
+    Some(v.to_constant()?.floor()?.into())
+}
+
+/// The rounding used by `math.ceil`, which returns an integer whenever the result fits in one.
+pub fn ceil<'gc>(v: Value<'gc>) -> Option<Value<'gc>> {
+    Some(v.to_constant()?.ceil()?.into())
+}
+
 pub fn bitwise_not<'gc>(v: Value<'gc>) -> Option<Value<'gc>> {
     Some(v.to_constant()?.bitwise_not()?.into())
 }
@@ -66,6 +158,20 @@ pub fn less_equal<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<bool> {
     Some(lhs.to_constant()?.less_equal(&rhs.to_constant()?)?.into())
 }
 
+/// Like [`less_than`], but orders NaN after every other number and as equal to itself.
+///
+/// Every comparison with NaN is false under `less_than`, so it does not consistently order numbers
+/// that include NaN. This does, which makes it suitable for sorting. Values which `less_than`
+/// cannot compare are still not comparable.
+pub fn sort_less_than<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<bool> {
+    let less = less_than(lhs, rhs)?;
+    let is_nan = |v| matches!(v, Value::Number(n) if n.is_nan());
+    Some(match (is_nan(lhs), is_nan(rhs)) {
+        (false, false) => less,
+        (lhs_nan, _) => !lhs_nan,
+    })
+}
+
 pub fn equal<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> bool {
     match (lhs, rhs) {
         (Value::Nil, Value::Nil) => true,
@@ -75,11 +181,11 @@ pub fn equal<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> bool {
         (Value::Boolean(_), _) => false,
 
         (Value::Integer(a), Value::Integer(b)) => a == b,
-        (Value::Integer(a), Value::Number(b)) => a as f64 == b,
+        (Value::Integer(a), Value::Number(b)) => float_to_int(b) == Some(a),
         (Value::Integer(_), _) => false,
 
         (Value::Number(a), Value::Number(b)) => a == b,
-        (Value::Number(a), Value::Integer(b)) => b as f64 == a,
+        (Value::Number(a), Value::Integer(b)) => float_to_int(a) == Some(b),
         (Value::Number(_), _) => false,
 
         (Value::String(a), Value::String(b)) => a == b,
@@ -96,5 +202,8 @@ pub fn equal<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> bool {
 
         (Value::UserData(a), Value::UserData(b)) => a == b,
         (Value::UserData(_), _) => false,
+
+        (Value::LightUserData(a), Value::LightUserData(b)) => a == b,
+        (Value::LightUserData(_), _) => false,
     }
 }
@@ -107,6 +107,7 @@ pub enum StaticValue {
     Function(StaticFunction),
     Thread(StaticThread),
     UserData(StaticUserData),
+    LightUserData(usize),
 }
 
 impl StaticValue {
@@ -124,6 +125,7 @@ impl StaticValue {
             StaticValue::Boolean(b) => Some(Value::Boolean(*b)),
             StaticValue::Integer(i) => Some(Value::Integer(*i)),
             StaticValue::Number(n) => Some(Value::Number(*n)),
+            StaticValue::LightUserData(p) => Some(Value::LightUserData(*p)),
             _ => None,
         }
     }
@@ -193,6 +195,48 @@ impl<'gc, T: Default> Singleton<'gc> for T {
     }
 }
 
+/// A value shared by every thread of a Lua instance, such as a mode or a limit which the VM or the
+/// stdlib consults while running.
+///
+/// There is one setting per type `T`, stored in the registry as a [`Singleton`] and starting out as
+/// `T::default()`. Settings which share a representation, such as two different `usize` limits,
+/// each need a type of their own.
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct Setting<'gc, T: Collect + Clone + 'static>(Gc<'gc, RefLock<T>>);
+
+impl<'gc, T: Collect + Clone + 'static> Copy for Setting<'gc, T> {}
+
+impl<'gc, T: Collect + Clone + 'static> Clone for Setting<'gc, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'gc, T: Collect + Clone + Default + 'static> Singleton<'gc> for Setting<'gc, T> {
+    fn create(ctx: Context<'gc>) -> Self {
+        Setting(Gc::new(&ctx, RefLock::new(T::default())))
+    }
+}
+
+impl<'gc, T: Collect + Clone + Default + 'static> Setting<'gc, T> {
+    /// Returns the current value of the setting in this Lua instance.
+    pub fn get(ctx: Context<'gc>) -> T {
+        Self::fetch(ctx).0.borrow().clone()
+    }
+
+    /// Changes the setting for every thread in this Lua instance.
+    pub fn set(ctx: Context<'gc>, value: T) {
+        *Self::fetch(ctx).0.borrow_mut(&ctx) = value;
+    }
+
+    fn fetch(ctx: Context<'gc>) -> Self {
+        *ctx.state
+            .registry
+            .singleton::<Rootable![Setting<'_, T>]>(ctx)
+    }
+}
+
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct Registry<'gc> {
@@ -333,6 +377,7 @@ impl<'gc> Stashable<'gc> for Value<'gc> {
             Value::Function(f) => StaticValue::Function(f.stash(roots, mc)),
             Value::Thread(t) => StaticValue::Thread(t.stash(roots, mc)),
             Value::UserData(u) => StaticValue::UserData(u.stash(roots, mc)),
+            Value::LightUserData(p) => StaticValue::LightUserData(p),
         }
     }
 }
@@ -351,6 +396,7 @@ impl<'gc> Fetchable<'gc> for StaticValue {
             StaticValue::Function(f) => Value::Function(f.fetch(roots)),
             StaticValue::Thread(t) => Value::Thread(t.fetch(roots)),
             StaticValue::UserData(u) => Value::UserData(u.fetch(roots)),
+            StaticValue::LightUserData(p) => Value::LightUserData(*p),
         }
     }
 }
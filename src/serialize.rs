@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+
+use thiserror::Error;
+
+use crate::{stdlib::format::quote_value, Table, Value};
+
+#[derive(Debug, Clone, Error)]
+pub enum SerializeError {
+    #[error("cannot serialize a {0} value")]
+    Unsupported(&'static str),
+    #[error("cannot serialize a table that contains itself")]
+    Cycle,
+    #[error("cannot serialize tables nested more than {MAX_DEPTH} levels deep")]
+    TooDeep,
+}
+
+/// The deepest nesting of tables that [`serialize`] writes, which keeps its recursion bounded.
+pub const MAX_DEPTH: usize = 256;
+
+/// Writes `value` as Lua source for an expression that evaluates to an equal value, so that
+/// `load("return " .. source)` reads it back.
+///
+/// Only nil, booleans, numbers, strings, and tables of those can be serialized. Integers and floats
+/// are kept distinct, and every float (including infinities and NaN) reads back as the same float.
+///
+/// The output is deterministic: table entries are written in the order of [`Table::iter_sorted`],
+/// with the sequence `1..=n` written positionally and every other key written as `name = value`
+/// where the key is a valid identifier, or as `[key] = value` otherwise. Metatables are not
+/// written, and a table that is reachable along more than one path is written once per path. A
+/// table which contains itself cannot be written and fails with [`SerializeError::Cycle`], and
+/// tables nested more than [`MAX_DEPTH`] levels deep fail with [`SerializeError::TooDeep`].
+pub fn serialize<'gc>(value: Value<'gc>) -> Result<Vec<u8>, SerializeError> {
+    let mut out = Vec::new();
+    write_value(&mut out, value, &mut HashSet::new())?;
+    Ok(out)
+}
+
+fn write_value<'gc>(
+    out: &mut Vec<u8>,
+    value: Value<'gc>,
+    parents: &mut HashSet<Table<'gc>>,
+) -> Result<(), SerializeError> {
+    match value {
+        Value::Table(t) => write_table(out, t, parents),
+        v if quote_value(out, v) => Ok(()),
+        v => Err(SerializeError::Unsupported(v.type_name())),
+    }
+}
+
+fn write_table<'gc>(
+    out: &mut Vec<u8>,
+    table: Table<'gc>,
+    parents: &mut HashSet<Table<'gc>>,
+) -> Result<(), SerializeError> {
+    if parents.contains(&table) {
+        return Err(SerializeError::Cycle);
+    }
+    if parents.len() >= MAX_DEPTH {
+        return Err(SerializeError::TooDeep);
+    }
+    parents.insert(table);
+
+    out.push(b'{');
+    // Integer keys are sorted first, so any sequence starting at 1 comes before every other key
+    // and can be written as positional fields.
+    let mut next_index = 1;
+    for (i, (key, value)) in table.iter_sorted().enumerate() {
+        if i > 0 {
+            out.extend_from_slice(b", ");
+        }
+        match key {
+            Value::Integer(k) if k == next_index => next_index += 1,
+            Value::String(s) if is_identifier(s.as_bytes()) => {
+                out.extend_from_slice(s.as_bytes());
+                out.extend_from_slice(b" = ");
+            }
+            key => {
+                out.push(b'[');
+                write_value(out, key, parents)?;
+                out.extend_from_slice(b"] = ");
+            }
+        }
+        write_value(out, value, parents)?;
+    }
+    out.push(b'}');
+
+    parents.remove(&table);
+    Ok(())
+}
+
+// True if `s` can be written as a field name in a table constructor without brackets.
+fn is_identifier(s: &[u8]) -> bool {
+    const RESERVED: &[&[u8]] = &[
+        b"and",
+        b"break",
+        b"do",
+        b"else",
+        b"elseif",
+        b"end",
+        b"false",
+        b"for",
+        b"function",
+        b"goto",
+        b"if",
+        b"in",
+        b"local",
+        b"nil",
+        b"not",
+        b"or",
+        b"repeat",
+        b"return",
+        b"then",
+        b"true",
+        b"until",
+        b"while",
+    ];
+
+    match s.split_first() {
+        Some((&first, rest)) => {
+            (first.is_ascii_alphabetic() || first == b'_')
+                && rest.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+                && !RESERVED.contains(&s)
+                && !(cfg!(feature = "continue") && s == b"continue")
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+
+use hashbrown::HashMap;
+use thiserror::Error;
+
+use crate::{Context, InvalidTableKey, Table, Value};
+
+/// A copy of a graph of Lua values which does not borrow from any Lua instance, so it can be stored
+/// on disk and later restored into the same or a different [`crate::Lua`].
+///
+/// Tables are stored once each in `tables` and referred to by index, so tables which are reachable
+/// along more than one path, including through cycles, are still shared after the snapshot is
+/// restored. Metatables and whether a table is frozen are preserved.
+///
+/// Only primitive values, strings, and tables are supported for now. Taking a snapshot of a graph
+/// containing functions, threads, or userdata fails with [`SnapshotError::Unsupported`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub root: SnapshotValue,
+    pub tables: Vec<SnapshotTable>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Box<[u8]>),
+    /// An index into `Snapshot::tables`.
+    Table(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotTable {
+    pub entries: Vec<(SnapshotValue, SnapshotValue)>,
+    /// An index into `Snapshot::tables`.
+    pub metatable: Option<usize>,
+    pub frozen: bool,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum SnapshotError {
+    #[error("cannot snapshot a {0} value")]
+    Unsupported(&'static str),
+    #[error("snapshot refers to missing table {0}")]
+    MissingTable(usize),
+    #[error("snapshot data is malformed")]
+    Malformed,
+    #[error(transparent)]
+    InvalidKey(#[from] InvalidTableKey),
+}
+
+impl Snapshot {
+    /// Copies `value` and everything reachable from it through table entries and metatables.
+    pub fn capture<'gc>(value: Value<'gc>) -> Result<Snapshot, SnapshotError> {
+        let mut capture = Capture {
+            indexes: HashMap::new(),
+            queue: VecDeque::new(),
+        };
+
+        let root = capture.value(value)?;
+
+        let mut tables = Vec::new();
+        while let Some(table) = capture.queue.pop_front() {
+            let mut entries = Vec::new();
+            for (key, value) in table.entries_snapshot() {
+                entries.push((capture.value(key)?, capture.value(value)?));
+            }
+
+            let metatable = table.metatable().map(|mt| capture.table(mt));
+            tables.push(SnapshotTable {
+                entries,
+                metatable,
+                frozen: table.is_frozen(),
+            });
+        }
+
+        Ok(Snapshot { root, tables })
+    }
+
+    /// Creates new Lua values from this snapshot and returns the restored root value.
+    pub fn restore<'gc>(&self, ctx: Context<'gc>) -> Result<Value<'gc>, SnapshotError> {
+        let tables: Vec<Table<'gc>> = self
+            .tables
+            .iter()
+            .map(|_| Table::with_instance_hashing(ctx))
+            .collect();
+
+        let restore_value = |value: &SnapshotValue| -> Result<Value<'gc>, SnapshotError> {
+            Ok(match value {
+                SnapshotValue::Nil => Value::Nil,
+                SnapshotValue::Boolean(b) => Value::Boolean(*b),
+                SnapshotValue::Integer(i) => Value::Integer(*i),
+                SnapshotValue::Number(n) => Value::Number(*n),
+                SnapshotValue::String(s) => ctx.intern_bytes(s),
+                SnapshotValue::Table(i) => {
+                    Value::Table(*tables.get(*i).ok_or(SnapshotError::MissingTable(*i))?)
+                }
+            })
+        };
+
+        for (table, snapshot) in tables.iter().zip(&self.tables) {
+            for (key, value) in &snapshot.entries {
+                table.set_value(&ctx, restore_value(key)?, restore_value(value)?)?;
+            }
+
+            if let Some(i) = snapshot.metatable {
+                let metatable = *tables.get(i).ok_or(SnapshotError::MissingTable(i))?;
+                table.set_metatable(ctx, Some(metatable));
+            }
+        }
+
+        // Tables are only frozen once every table is filled in, since a frozen table may appear as
+        // a key or value of a table restored before it. Likewise, weakness and finalizers are only
+        // read once the `__mode` and `__gc` fields of every metatable are restored.
+        for (table, snapshot) in tables.iter().zip(&self.tables) {
+            ctx.state.weak_tables.register(ctx, *table);
+            ctx.state.finalizers.register(ctx, (*table).into());
+            if snapshot.frozen {
+                table.freeze(&ctx);
+            }
+        }
+
+        restore_value(&self.root)
+    }
+
+    /// Encodes this snapshot in a compact binary format which can be decoded with
+    /// [`Snapshot::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(SNAPSHOT_MAGIC);
+        write_len(&mut buf, self.tables.len());
+        for table in &self.tables {
+            match table.metatable {
+                Some(i) => {
+                    buf.push(1);
+                    write_len(&mut buf, i);
+                }
+                None => buf.push(0),
+            }
+            buf.push(table.frozen as u8);
+            write_len(&mut buf, table.entries.len());
+            for (key, value) in &table.entries {
+                write_value(&mut buf, key);
+                write_value(&mut buf, value);
+            }
+        }
+        write_value(&mut buf, &self.root);
+        buf
+    }
+
+    /// Decodes a snapshot previously encoded with [`Snapshot::to_bytes`].
+    ///
+    /// Table indexes are not checked here, a snapshot referring to a missing table is only
+    /// rejected by [`Snapshot::restore`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut reader = Reader(
+            bytes
+                .strip_prefix(SNAPSHOT_MAGIC)
+                .ok_or(SnapshotError::Malformed)?,
+        );
+
+        let table_count = reader.len()?;
+        let mut tables = Vec::new();
+        for _ in 0..table_count {
+            let metatable = match reader.byte()? {
+                0 => None,
+                1 => Some(reader.len()?),
+                _ => return Err(SnapshotError::Malformed),
+            };
+            let frozen = match reader.byte()? {
+                0 => false,
+                1 => true,
+                _ => return Err(SnapshotError::Malformed),
+            };
+            let entry_count = reader.len()?;
+            let mut entries = Vec::new();
+            for _ in 0..entry_count {
+                entries.push((reader.value()?, reader.value()?));
+            }
+            tables.push(SnapshotTable {
+                entries,
+                metatable,
+                frozen,
+            });
+        }
+        let root = reader.value()?;
+
+        if !reader.0.is_empty() {
+            return Err(SnapshotError::Malformed);
+        }
+
+        Ok(Snapshot { root, tables })
+    }
+}
+
+struct Capture<'gc> {
+    indexes: HashMap<Table<'gc>, usize>,
+    // Tables which have been given an index but whose contents have not been captured yet, in
+    // index order.
+    queue: VecDeque<Table<'gc>>,
+}
+
+impl<'gc> Capture<'gc> {
+    fn table(&mut self, table: Table<'gc>) -> usize {
+        let next = self.indexes.len();
+        *self.indexes.entry(table).or_insert_with(|| {
+            self.queue.push_back(table);
+            next
+        })
+    }
+
+    fn value(&mut self, value: Value<'gc>) -> Result<SnapshotValue, SnapshotError> {
+        Ok(match value {
+            Value::Nil => SnapshotValue::Nil,
+            Value::Boolean(b) => SnapshotValue::Boolean(b),
+            Value::Integer(i) => SnapshotValue::Integer(i),
+            Value::Number(n) => SnapshotValue::Number(n),
+            Value::String(s) => SnapshotValue::String(s.as_bytes().into()),
+            Value::Table(t) => SnapshotValue::Table(self.table(t)),
+            value @ (Value::Function(_)
+            | Value::Thread(_)
+            | Value::UserData(_)
+            | Value::LightUserData(_)) => {
+                return Err(SnapshotError::Unsupported(value.type_name()));
+            }
+        })
+    }
+}
+
+const SNAPSHOT_MAGIC: &[u8] = b"\x1bpiccolo-snapshot\x01";
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_NUMBER: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_TABLE: u8 = 6;
+
+fn write_len(buf: &mut Vec<u8>, len: usize) {
+    buf.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &SnapshotValue) {
+    match value {
+        SnapshotValue::Nil => buf.push(TAG_NIL),
+        SnapshotValue::Boolean(false) => buf.push(TAG_FALSE),
+        SnapshotValue::Boolean(true) => buf.push(TAG_TRUE),
+        SnapshotValue::Integer(i) => {
+            buf.push(TAG_INTEGER);
+            buf.extend_from_slice(&i.to_le_bytes());
+        }
+        SnapshotValue::Number(n) => {
+            buf.push(TAG_NUMBER);
+            buf.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        SnapshotValue::String(s) => {
+            buf.push(TAG_STRING);
+            write_len(buf, s.len());
+            buf.extend_from_slice(s);
+        }
+        SnapshotValue::Table(i) => {
+            buf.push(TAG_TABLE);
+            write_len(buf, *i);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Malformed);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, SnapshotError> {
+        usize::try_from(self.u64()?).map_err(|_| SnapshotError::Malformed)
+    }
+
+    fn value(&mut self) -> Result<SnapshotValue, SnapshotError> {
+        Ok(match self.byte()? {
+            TAG_NIL => SnapshotValue::Nil,
+            TAG_FALSE => SnapshotValue::Boolean(false),
+            TAG_TRUE => SnapshotValue::Boolean(true),
+            TAG_INTEGER => SnapshotValue::Integer(self.u64()? as i64),
+            TAG_NUMBER => SnapshotValue::Number(f64::from_bits(self.u64()?)),
+            TAG_STRING => {
+                let len = self.len()?;
+                SnapshotValue::String(self.bytes(len)?.into())
+            }
+            TAG_TABLE => SnapshotValue::Table(self.len()?),
+            _ => return Err(SnapshotError::Malformed),
+        })
+    }
+}
@@ -9,6 +9,35 @@ use gc_arena::{allocator_api::MetricsAlloc, Collect, Mutation};
 
 use crate::{Context, FromMultiValue, FromValue, IntoMultiValue, IntoValue, TypeError, Value};
 
+/// The values passed to and returned from a callback or sequence.
+///
+/// A callback is called with its arguments on the stack, and returns whatever values it leaves on
+/// the stack. A fixed number of values can be returned with `Stack::replace` and a tuple, and a
+/// number of values only known at runtime can be returned by replacing the stack with a `Variadic`
+/// collection, or by clearing it and extending it with an iterator:
+///
+/// ```
+/// # use piccolo::{AnyCallback, CallbackReturn, Lua, Value, Variadic};
+/// # let mut lua = Lua::core();
+/// # lua.run(|ctx| {
+/// // Called as `range(n)`, returns `1, 2, ..., n`.
+/// let range = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+///     let n: i64 = stack.consume(ctx)?;
+///     let values: Vec<i64> = (1..=n).collect();
+///     stack.replace(ctx, Variadic(values));
+///     Ok(CallbackReturn::Return)
+/// });
+///
+/// // Called as `repeat(v, n)`, returns `v` `n` times.
+/// let repeat = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+///     let (value, n): (Value, i64) = stack.consume(ctx)?;
+///     stack.extend(std::iter::repeat(value).take(n.max(0) as usize));
+///     Ok(CallbackReturn::Return)
+/// });
+/// # ctx.state.globals.set(ctx, "range", range).unwrap();
+/// # ctx.state.globals.set(ctx, "repeat", repeat).unwrap();
+/// # });
+/// ```
 #[derive(Clone, Collect)]
 #[collect(no_drop)]
 pub struct Stack<'gc>(vec::Vec<Value<'gc>, MetricsAlloc<'gc>>);
@@ -65,12 +94,14 @@ impl<'gc> Stack<'gc> {
         self.0.drain(range)
     }
 
+    /// Pushes every value of `v` onto the end of the stack.
     pub fn into_back(&mut self, ctx: Context<'gc>, v: impl IntoMultiValue<'gc>) {
         for v in v.into_multi_value(ctx) {
             self.0.push(v.into_value(ctx));
         }
     }
 
+    /// Inserts every value of `v` at the start of the stack, in order.
     pub fn into_front(&mut self, ctx: Context<'gc>, v: impl IntoMultiValue<'gc>) {
         let mut c = 0;
         for v in v.into_multi_value(ctx) {
@@ -92,11 +123,13 @@ impl<'gc> Stack<'gc> {
         }
     }
 
+    /// Replaces the entire contents of the stack with the values of `v`.
     pub fn replace(&mut self, ctx: Context<'gc>, v: impl IntoMultiValue<'gc>) {
         self.0.clear();
         self.0.extend(v.into_multi_value(ctx));
     }
 
+    /// Converts the entire contents of the stack into `V`, leaving the stack empty.
     pub fn consume<V: FromMultiValue<'gc>>(&mut self, ctx: Context<'gc>) -> Result<V, TypeError> {
         V::from_multi_value(ctx, self.0.drain(..))
     }
@@ -1,13 +1,25 @@
+use std::string::String as StdString;
+
 use gc_arena::Collect;
 
 use crate::{
+    bytecode,
     meta_ops::{self, MetaResult},
-    table::NextValue,
-    AnyCallback, AnySequence, CallbackReturn, Context, Error, Fuel, IntoValue, MetaMethod,
-    Sequence, SequencePoll, Stack, String, Table, Value, Variadic,
+    table::{IterationMode, NextValue},
+    AnyCallback, AnySequence, CallbackReturn, Closure, Context, Error, Fuel, FunctionProto,
+    IntoValue, MetaMethod, Sequence, SequencePoll, Setting, Stack, String, Table, Value, Variadic,
+    LUA_VERSION,
+};
+
+use super::{
+    util::{bad_argument, parse_args},
+    Warnings,
 };
 
 pub fn load_base<'gc>(ctx: Context<'gc>) {
+    ctx.state.globals.set(ctx, "_G", ctx.state.globals).unwrap();
+    ctx.state.globals.set(ctx, "_VERSION", LUA_VERSION).unwrap();
+
     ctx.state
         .globals
         .set(
@@ -42,6 +54,46 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    ctx.state
+        .globals
+        .set(
+            ctx,
+            "warn",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                if stack.is_empty() {
+                    return Err(bad_argument(ctx, 1, "warn", "string", "no value"));
+                }
+
+                let mut message = Vec::new();
+                for (i, value) in (&*stack).into_iter().enumerate() {
+                    match value {
+                        Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+                            value.display(&mut message)?
+                        }
+                        _ => {
+                            return Err(bad_argument(
+                                ctx,
+                                i + 1,
+                                "warn",
+                                "string",
+                                value.type_name(),
+                            ))
+                        }
+                    }
+                }
+
+                // Only a message made of a single argument can be a control message.
+                let warnings = Warnings::get(ctx);
+                match message.split_first() {
+                    Some((b'@', control)) if stack.len() == 1 => warnings.control(control),
+                    _ => warnings.warn(&message),
+                }
+                stack.clear();
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     ctx.state
         .globals
         .set(
@@ -177,14 +229,9 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
             ctx,
             "getmetatable",
             AnyCallback::from_fn(&ctx, |ctx, _, stack| {
-                if let Value::Table(t) = stack.get(0) {
-                    stack.replace(ctx, t.metatable());
-                    Ok(CallbackReturn::Return)
-                } else {
-                    Err("'getmetatable' can only be used on table types"
-                        .into_value(ctx)
-                        .into())
-                }
+                let v: Value = parse_args(ctx, "getmetatable", stack)?;
+                stack.replace(ctx, meta_ops::metatable(ctx, v));
+                Ok(CallbackReturn::Return)
             }),
         )
         .unwrap();
@@ -196,7 +243,8 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
             "setmetatable",
             AnyCallback::from_fn(&ctx, |ctx, _, stack| {
                 let (t, mt): (Table, Option<Table>) = stack.consume(ctx)?;
-                t.set_metatable(&ctx, mt);
+                t.try_set_metatable(ctx, mt)?;
+                ctx.state.finalizers.register(ctx, t.into());
                 stack.replace(ctx, t);
                 Ok(CallbackReturn::Return)
             }),
@@ -207,11 +255,15 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         ctx: Context<'gc>,
         table: Table<'gc>,
         index: Value<'gc>,
-    ) -> Result<(Value<'gc>, Value<'gc>), Value<'gc>> {
-        match table.next(index) {
+    ) -> Result<(Value<'gc>, Value<'gc>), Error<'gc>> {
+        let next = match Setting::<IterationMode>::get(ctx) {
+            IterationMode::Unchecked => table.next(index),
+            IterationMode::Checked => table.next_checked(&ctx, index)?,
+        };
+        match next {
             NextValue::Found { key, value } => Ok((key, value)),
             NextValue::Last => Ok((Value::Nil, Value::Nil)),
-            NextValue::NotFound => Err("invalid table key".into_value(ctx)),
+            NextValue::NotFound => Err("invalid table key".into_value(ctx).into()),
         }
     }
 
@@ -230,11 +282,7 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
             "pairs",
             AnyCallback::from_fn_with(&ctx, next, move |next, ctx, _, stack| {
                 let table = stack.get(0);
-                if let Some(mt) = match table {
-                    Value::Table(t) => t.metatable(),
-                    Value::UserData(u) => u.metatable(),
-                    _ => None,
-                } {
+                if let Some(mt) = meta_ops::metatable(ctx, table) {
                     let pairs = mt.get(ctx, MetaMethod::Pairs);
                     if !pairs.is_nil() {
                         let f = meta_ops::call(ctx, pairs)?;
@@ -299,6 +347,56 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    ctx.state
+        .globals
+        .set(
+            ctx,
+            "load",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (chunk, chunk_name, mode, env): (
+                    String,
+                    Option<String>,
+                    Option<String>,
+                    Option<Table>,
+                ) = parse_args(ctx, "load", stack)?;
+                // Binary chunks are not verified when loaded, so unlike PUC-Rio Lua they must be
+                // asked for explicitly with a mode containing 'b'.
+                let mode = mode.map(|m| m.as_bytes()).unwrap_or(&b"t"[..]);
+                let env = env.unwrap_or(ctx.state.globals);
+
+                let is_binary = chunk.as_bytes().first() == bytecode::SIGNATURE.first();
+                let result = if is_binary && !mode.contains(&b'b') {
+                    Err(format!(
+                        "attempt to load a binary chunk (mode is '{}')",
+                        StdString::from_utf8_lossy(mode)
+                    ))
+                } else if !is_binary && !mode.contains(&b't') {
+                    Err(format!(
+                        "attempt to load a text chunk (mode is '{}')",
+                        StdString::from_utf8_lossy(mode)
+                    ))
+                } else if is_binary {
+                    Closure::load_binary(ctx, chunk.as_bytes(), env).map_err(|e| e.to_string())
+                } else {
+                    let chunk_name = chunk_name.map(|n| n.to_str_lossy());
+                    FunctionProto::compile_with_name(
+                        ctx,
+                        chunk_name.as_deref().unwrap_or("[string]"),
+                        chunk.as_bytes(),
+                    )
+                    .map(|proto| Closure::new(&ctx, proto, Some(env)).unwrap())
+                    .map_err(|e| e.to_string())
+                };
+
+                match result {
+                    Ok(closure) => stack.replace(ctx, closure),
+                    Err(message) => stack.replace(ctx, (Value::Nil, message)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     ctx.state
         .globals
         .set(
@@ -0,0 +1,111 @@
+use crate::Context;
+
+#[cfg(feature = "std")]
+use super::load_os;
+use super::{
+    load_base, load_coroutine, load_debug, load_io, load_math, load_package, load_string,
+    load_table,
+};
+
+/// Which parts of the stdlib [`load_stdlib`] loads.
+///
+/// Sandboxes can start from [`StdlibConfig::none`] and enable only the libraries that scripts
+/// should have access to. The `io`, `os`, and `debug` libraries allow access to the host and to the
+/// internals of other code, so they are only enabled by [`StdlibConfig::full`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StdlibConfig {
+    /// Global functions like `pairs`, `pcall`, and `setmetatable`.
+    pub base: bool,
+    pub coroutine: bool,
+    pub math: bool,
+    /// The `package` table and `require`.
+    pub package: bool,
+    /// The `string` table, along with the metatable that allows calling its functions as methods
+    /// on strings.
+    pub string: bool,
+    pub table: bool,
+    /// The `print` function.
+    pub io: bool,
+    /// The `os` functions which work with files. These are only available with the `std` feature,
+    /// without it this has no effect.
+    pub os: bool,
+    pub debug: bool,
+}
+
+impl StdlibConfig {
+    /// Loads nothing.
+    pub const fn none() -> Self {
+        StdlibConfig {
+            base: false,
+            coroutine: false,
+            math: false,
+            package: false,
+            string: false,
+            table: false,
+            io: false,
+            os: false,
+            debug: false,
+        }
+    }
+
+    /// The libraries loaded by `Lua::load_core`, which do not allow performing any I/O.
+    pub const fn core() -> Self {
+        StdlibConfig {
+            base: true,
+            coroutine: true,
+            math: true,
+            package: true,
+            string: true,
+            table: true,
+            ..Self::none()
+        }
+    }
+
+    /// Every library, as loaded by `Lua::full`.
+    pub const fn full() -> Self {
+        StdlibConfig {
+            io: true,
+            os: true,
+            debug: true,
+            ..Self::core()
+        }
+    }
+}
+
+impl Default for StdlibConfig {
+    fn default() -> Self {
+        Self::core()
+    }
+}
+
+/// Loads each part of the stdlib that is enabled in `config`.
+pub fn load_stdlib<'gc>(ctx: Context<'gc>, config: StdlibConfig) {
+    if config.base {
+        load_base(ctx);
+    }
+    if config.coroutine {
+        load_coroutine(ctx);
+    }
+    if config.math {
+        load_math(ctx);
+    }
+    if config.package {
+        load_package(ctx);
+    }
+    if config.string {
+        load_string(ctx);
+    }
+    if config.table {
+        load_table(ctx);
+    }
+    if config.io {
+        load_io(ctx);
+    }
+    #[cfg(feature = "std")]
+    if config.os {
+        load_os(ctx);
+    }
+    if config.debug {
+        load_debug(ctx);
+    }
+}
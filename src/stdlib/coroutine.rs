@@ -6,7 +6,7 @@ use crate::{
 };
 
 pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
-    let coroutine = Table::new(&ctx);
+    let coroutine = Table::with_instance_hashing(ctx);
 
     coroutine
         .set(
@@ -0,0 +1,451 @@
+use std::fmt::Write;
+
+use crate::{
+    compiler::FunctionRef, AnyCallback, BacktraceFrame, CallbackReturn, Closure, Context, Error,
+    Function, Hook, HookMask, IntoValue, Table, Thread, Value,
+};
+
+use super::util::bad_argument;
+
+pub fn load_debug<'gc>(ctx: Context<'gc>) {
+    let debug = Table::with_instance_hashing(ctx);
+
+    debug
+        .set(
+            ctx,
+            "traceback",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (thread, arg_start) = match stack.get(0) {
+                    Value::Thread(thread) => (Some(thread), 1),
+                    _ => (Thread::current(ctx), 0),
+                };
+
+                let message = stack.get(arg_start);
+                let mut trace = match message {
+                    Value::Nil => String::new(),
+                    Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+                        format!("{message}\n")
+                    }
+                    // Non-string messages are returned untouched, as in PUC-Rio Lua.
+                    message => {
+                        stack.replace(ctx, message);
+                        return Ok(CallbackReturn::Return);
+                    }
+                };
+
+                // By default, skip `debug.traceback` itself when tracing the calling thread.
+                let level = match stack.get(arg_start + 1) {
+                    Value::Nil if arg_start == 0 => 1,
+                    Value::Nil => 0,
+                    level => level
+                        .to_integer()
+                        .and_then(|l| usize::try_from(l).ok())
+                        .ok_or_else(|| {
+                            bad_argument(
+                                ctx,
+                                arg_start + 2,
+                                "traceback",
+                                "number",
+                                level.type_name(),
+                            )
+                        })?,
+                };
+
+                let frames = thread.map(|t| t.backtrace()).unwrap_or_default();
+                trace.push_str(&format_traceback(frames.into_iter().skip(level)));
+
+                stack.replace(ctx, trace);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "getinfo",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (thread, arg_start) = match stack.get(0) {
+                    Value::Thread(thread) => (Some(thread), 1),
+                    _ => (Thread::current(ctx), 0),
+                };
+
+                let info = Table::with_instance_hashing(ctx);
+                match stack.get(arg_start) {
+                    Value::Function(Function::Closure(closure)) => {
+                        set_closure_info(ctx, info, closure, None);
+                    }
+                    Value::Function(Function::Callback(_)) => {
+                        set_callback_info(ctx, info);
+                    }
+                    level @ (Value::Integer(_) | Value::Number(_)) => {
+                        let level = level.to_integer().ok_or_else(|| {
+                            bad_argument(ctx, arg_start + 1, "getinfo", "integer", "number")
+                        })?;
+                        let frames = thread.map(|t| t.backtrace()).unwrap_or_default();
+                        // Levels outside of the call stack, including negative ones, have no info.
+                        let frame = usize::try_from(level).ok().and_then(|l| frames.get(l));
+                        match frame {
+                            Some(&BacktraceFrame::Lua { closure, pc }) => {
+                                set_closure_info(ctx, info, closure, Some(pc));
+                            }
+                            Some(BacktraceFrame::Callback) => set_callback_info(ctx, info),
+                            None => {
+                                stack.replace(ctx, Value::Nil);
+                                return Ok(CallbackReturn::Return);
+                            }
+                        }
+                    }
+                    arg => {
+                        return Err(bad_argument(
+                            ctx,
+                            arg_start + 1,
+                            "getinfo",
+                            "function or level",
+                            arg.type_name(),
+                        ));
+                    }
+                }
+
+                stack.replace(ctx, info);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "sethook",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (thread, arg_start) = match stack.get(0) {
+                    Value::Thread(thread) => (Some(thread), 1),
+                    _ => (Thread::current(ctx), 0),
+                };
+
+                let hook = match stack.get(arg_start) {
+                    Value::Nil => None,
+                    Value::Function(function) => {
+                        let mask = match stack.get(arg_start + 1) {
+                            Value::String(mask) => mask.as_bytes(),
+                            Value::Nil => b"",
+                            mask => {
+                                return Err(bad_argument(
+                                    ctx,
+                                    arg_start + 2,
+                                    "sethook",
+                                    "string",
+                                    mask.type_name(),
+                                ))
+                            }
+                        };
+                        let count = match stack.get(arg_start + 2) {
+                            Value::Nil => 0,
+                            count => count
+                                .to_integer()
+                                .map(|c| c.clamp(0, u32::MAX.into()) as u32)
+                                .ok_or_else(|| {
+                                    bad_argument(
+                                        ctx,
+                                        arg_start + 3,
+                                        "sethook",
+                                        "number",
+                                        count.type_name(),
+                                    )
+                                })?,
+                        };
+                        Some(Hook {
+                            function,
+                            mask: HookMask {
+                                call: mask.contains(&b'c'),
+                                ret: mask.contains(&b'r'),
+                                line: mask.contains(&b'l'),
+                            },
+                            count,
+                        })
+                    }
+                    hook => {
+                        return Err(bad_argument(
+                            ctx,
+                            arg_start + 1,
+                            "sethook",
+                            "function",
+                            hook.type_name(),
+                        ))
+                    }
+                };
+
+                if let Some(thread) = thread {
+                    thread.set_hook(&ctx, hook);
+                }
+                stack.clear();
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "gethook",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let thread = match stack.get(0) {
+                    Value::Thread(thread) => Some(thread),
+                    _ => Thread::current(ctx),
+                };
+
+                if let Some(hook) = thread.and_then(|t| t.hook()) {
+                    let mut mask = String::new();
+                    if hook.mask.call {
+                        mask.push('c');
+                    }
+                    if hook.mask.ret {
+                        mask.push('r');
+                    }
+                    if hook.mask.line {
+                        mask.push('l');
+                    }
+                    stack.replace(ctx, (hook.function, mask, hook.count as i64));
+                } else {
+                    stack.replace(ctx, Value::Nil);
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "getlocal",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (thread, arg_start) = match stack.get(0) {
+                    Value::Thread(thread) => (Some(thread), 1),
+                    _ => (Thread::current(ctx), 0),
+                };
+                let n = integer_arg(ctx, stack.get(arg_start + 1), arg_start + 2, "getlocal")?;
+
+                match stack.get(arg_start) {
+                    // For a function, only the names of its parameters are available, and not even
+                    // those if the function was loaded from a stripped binary chunk.
+                    Value::Function(Function::Closure(closure)) => {
+                        let proto = &closure.0.proto;
+                        let name = usize::try_from(n)
+                            .ok()
+                            .and_then(|n| n.checked_sub(1))
+                            .filter(|&i| i < proto.fixed_params as usize)
+                            .map(|i| match proto.local_variables.get(i) {
+                                Some(local) => Value::String(local.name),
+                                None => ctx.intern("?"),
+                            });
+                        stack.replace(ctx, name);
+                    }
+                    Value::Function(Function::Callback(_)) => stack.replace(ctx, Value::Nil),
+                    level => {
+                        let level = stack_level(ctx, thread, level, arg_start + 1, "getlocal")?;
+                        let local = thread
+                            .zip(usize::try_from(n).ok().and_then(|n| n.checked_sub(1)))
+                            .and_then(|(t, i)| t.get_local(level, i));
+                        match local {
+                            Some((name, value)) => stack.replace(ctx, (name, value)),
+                            None => stack.replace(ctx, Value::Nil),
+                        }
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "setlocal",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (thread, arg_start) = match stack.get(0) {
+                    Value::Thread(thread) => (Some(thread), 1),
+                    _ => (Thread::current(ctx), 0),
+                };
+                let level =
+                    stack_level(ctx, thread, stack.get(arg_start), arg_start + 1, "setlocal")?;
+                let n = integer_arg(ctx, stack.get(arg_start + 1), arg_start + 2, "setlocal")?;
+                let value = stack.get(arg_start + 2);
+
+                let name = thread
+                    .zip(usize::try_from(n).ok().and_then(|n| n.checked_sub(1)))
+                    .and_then(|(t, i)| t.set_local(&ctx, level, i, value));
+                stack.replace(ctx, name);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "getupvalue",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let n = integer_arg(ctx, stack.get(1), 2, "getupvalue")?;
+                match upvalue_arg(ctx, stack.get(0), n, "getupvalue")? {
+                    Some((closure, i)) => stack.replace(
+                        ctx,
+                        (upvalue_name(ctx, closure, i), closure.0.upvalues[i].get()),
+                    ),
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    debug
+        .set(
+            ctx,
+            "setupvalue",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let n = integer_arg(ctx, stack.get(1), 2, "setupvalue")?;
+                match upvalue_arg(ctx, stack.get(0), n, "setupvalue")? {
+                    Some((closure, i)) => {
+                        closure.0.upvalues[i].set(&ctx, stack.get(2));
+                        stack.replace(ctx, upvalue_name(ctx, closure, i));
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    ctx.state.globals.set(ctx, "debug", debug).unwrap();
+}
+
+fn integer_arg<'gc>(
+    ctx: Context<'gc>,
+    value: Value<'gc>,
+    position: usize,
+    function: &str,
+) -> Result<i64, Error<'gc>> {
+    value
+        .to_integer()
+        .ok_or_else(|| bad_argument(ctx, position, function, "number", value.type_name()))
+}
+
+// Converts a Lua stack level argument into an index into `Thread::backtrace`, erroring if there is
+// no frame at that level.
+fn stack_level<'gc>(
+    ctx: Context<'gc>,
+    thread: Option<Thread<'gc>>,
+    level: Value<'gc>,
+    position: usize,
+    function: &str,
+) -> Result<usize, Error<'gc>> {
+    let level = integer_arg(ctx, level, position, function)?;
+    let frame_count = thread.map(|t| t.backtrace().len()).unwrap_or(0);
+    match usize::try_from(level) {
+        Ok(level) if level < frame_count => Ok(level),
+        _ => Err(
+            format!("bad argument #{position} to '{function}' (level out of range)")
+                .into_value(ctx)
+                .into(),
+        ),
+    }
+}
+
+// Finds the closure and 0-based upvalue index for a `getupvalue` style function argument and
+// 1-based upvalue number. Callbacks have no visible upvalues.
+fn upvalue_arg<'gc>(
+    ctx: Context<'gc>,
+    function: Value<'gc>,
+    n: i64,
+    name: &str,
+) -> Result<Option<(Closure<'gc>, usize)>, Error<'gc>> {
+    match function {
+        Value::Function(Function::Closure(closure)) => Ok(usize::try_from(n)
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .filter(|&i| i < closure.0.upvalues.len())
+            .map(|i| (closure, i))),
+        Value::Function(Function::Callback(_)) => Ok(None),
+        function => Err(bad_argument(ctx, 1, name, "function", function.type_name())),
+    }
+}
+
+// Stripped binary chunks keep no upvalue names, like PUC-Rio Lua these are reported as "?".
+fn upvalue_name<'gc>(ctx: Context<'gc>, closure: Closure<'gc>, index: usize) -> Value<'gc> {
+    match closure.0.proto.upvalue_names.get(index) {
+        Some(&name) => Value::String(name),
+        None => ctx.intern("?"),
+    }
+}
+
+// Fills in the `getinfo` fields for a Lua function, `pc` is the opcode that the function is
+// currently executing if it is active.
+fn set_closure_info<'gc>(
+    ctx: Context<'gc>,
+    info: Table<'gc>,
+    closure: Closure<'gc>,
+    pc: Option<usize>,
+) {
+    let proto = &closure.0.proto;
+    info.set(ctx, "source", proto.chunk_name).unwrap();
+    info.set(ctx, "short_src", proto.chunk_name).unwrap();
+
+    let current_line = pc
+        .and_then(|pc| proto.line_number(pc))
+        .map(|line| line.0 as i64 + 1)
+        .unwrap_or(-1);
+    info.set(ctx, "currentline", current_line).unwrap();
+
+    let (what, line_defined) = match proto.reference {
+        FunctionRef::Named(name, line) => {
+            info.set(ctx, "name", name).unwrap();
+            ("Lua", line.0 as i64 + 1)
+        }
+        FunctionRef::Expression(line) => ("Lua", line.0 as i64 + 1),
+        FunctionRef::Chunk => ("main", 0),
+    };
+    info.set(ctx, "what", what).unwrap();
+    info.set(ctx, "linedefined", line_defined).unwrap();
+
+    info.set(ctx, "nups", closure.0.upvalues.len() as i64)
+        .unwrap();
+}
+
+fn set_callback_info<'gc>(ctx: Context<'gc>, info: Table<'gc>) {
+    info.set(ctx, "source", "=[C]").unwrap();
+    info.set(ctx, "short_src", "[C]").unwrap();
+    info.set(ctx, "currentline", -1).unwrap();
+    info.set(ctx, "what", "C").unwrap();
+    info.set(ctx, "linedefined", -1).unwrap();
+    info.set(ctx, "nups", 0).unwrap();
+}
+
+/// Formats a call stack, innermost frame first, the way `debug.traceback` does: a
+/// `stack traceback:` header followed by one tab-indented line per frame.
+///
+/// The frames usually come from [`Thread::backtrace`], or from an uncaught error handler, see
+/// [`Thread::set_uncaught_error_handler`].
+pub fn format_traceback<'gc>(frames: impl IntoIterator<Item = BacktraceFrame<'gc>>) -> String {
+    let mut trace = String::from("stack traceback:");
+    for frame in frames {
+        match frame {
+            BacktraceFrame::Lua { closure, pc } => {
+                let proto = &closure.0.proto;
+                write!(trace, "\n\t{}:", proto.chunk_name).unwrap();
+                match proto.line_number(pc) {
+                    Some(line) => write!(trace, "{line}: in ").unwrap(),
+                    None => trace.push_str("?: in "),
+                }
+                match proto.reference {
+                    FunctionRef::Named(name, _) => write!(trace, "function '{name}'").unwrap(),
+                    FunctionRef::Expression(line) => {
+                        write!(trace, "function <{}:{line}>", proto.chunk_name).unwrap()
+                    }
+                    FunctionRef::Chunk => trace.push_str("main chunk"),
+                }
+            }
+            BacktraceFrame::Callback => trace.push_str("\n\t[C]: in ?"),
+        }
+    }
+    trace
+}
@@ -0,0 +1,414 @@
+//! The conversions behind `string.format`, which follow C's `printf` in the "C" locale rather than
+//! Rust's formatting, so that the output matches PUC-Rio Lua.
+
+use crate::Value;
+
+/// A parsed conversion specification, such as `%-8.3f`.
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct Spec {
+    pub(crate) left_align: bool,
+    pub(crate) plus_sign: bool,
+    pub(crate) space_sign: bool,
+    pub(crate) alternate: bool,
+    pub(crate) zero_pad: bool,
+    pub(crate) width: usize,
+    pub(crate) precision: Option<usize>,
+    pub(crate) conversion: u8,
+}
+
+impl Spec {
+    /// Parses a conversion specification from the bytes following a `%`, returning the spec and
+    /// the number of bytes that it takes up.
+    ///
+    /// As in PUC-Rio Lua, the width and precision may have at most two digits. Returns `None` if
+    /// the specification is malformed, but does not check that the conversion character is one
+    /// that `string.format` supports.
+    pub(crate) fn parse(fmt: &[u8]) -> Option<(Spec, usize)> {
+        let mut spec = Spec::default();
+        let mut i = 0;
+
+        while let Some(&c) = fmt.get(i) {
+            match c {
+                b'-' => spec.left_align = true,
+                b'+' => spec.plus_sign = true,
+                b' ' => spec.space_sign = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero_pad = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let digits = |i: &mut usize| -> Option<usize> {
+            let start = *i;
+            while fmt.get(*i).is_some_and(u8::is_ascii_digit) {
+                *i += 1;
+            }
+            if *i - start > 2 {
+                return None;
+            }
+            Some(
+                fmt[start..*i]
+                    .iter()
+                    .fold(0, |n, &d| n * 10 + (d - b'0') as usize),
+            )
+        };
+
+        spec.width = digits(&mut i)?;
+        if fmt.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(digits(&mut i)?);
+        }
+
+        spec.conversion = *fmt.get(i)?;
+        Some((spec, i + 1))
+    }
+
+    /// Whether any flags, width, or precision were given.
+    pub(crate) fn has_modifiers(&self) -> bool {
+        self.left_align
+            || self.plus_sign
+            || self.space_sign
+            || self.alternate
+            || self.zero_pad
+            || self.width != 0
+            || self.precision.is_some()
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus_sign {
+            "+"
+        } else if self.space_sign {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    // Writes `prefix` (a sign or radix prefix) and `body` padded to the field width. Zero padding
+    // goes between the prefix and the body.
+    fn pad(&self, out: &mut Vec<u8>, prefix: &str, body: &[u8], zero_pad: bool) {
+        let len = prefix.len() + body.len();
+        let padding = self.width.saturating_sub(len);
+        if self.left_align {
+            out.extend_from_slice(prefix.as_bytes());
+            out.extend_from_slice(body);
+            out.extend(std::iter::repeat(b' ').take(padding));
+        } else if zero_pad {
+            out.extend_from_slice(prefix.as_bytes());
+            out.extend(std::iter::repeat(b'0').take(padding));
+            out.extend_from_slice(body);
+        } else {
+            out.extend(std::iter::repeat(b' ').take(padding));
+            out.extend_from_slice(prefix.as_bytes());
+            out.extend_from_slice(body);
+        }
+    }
+}
+
+/// Formats an integer with one of the `d`, `i`, `u`, `o`, `x`, or `X` conversions. As in Lua, the
+/// unsigned conversions format the two's complement bits of negative integers.
+pub(crate) fn format_integer(out: &mut Vec<u8>, spec: &Spec, i: i64) {
+    let (negative, mut digits) = match spec.conversion {
+        b'o' => (false, format!("{:o}", i as u64)),
+        b'x' => (false, format!("{:x}", i as u64)),
+        b'X' => (false, format!("{:X}", i as u64)),
+        b'u' => (false, (i as u64).to_string()),
+        _ => (i < 0, i.unsigned_abs().to_string()),
+    };
+
+    if let Some(precision) = spec.precision {
+        if precision == 0 && i == 0 {
+            digits.clear();
+        } else if digits.len() < precision {
+            digits.insert_str(0, &"0".repeat(precision - digits.len()));
+        }
+    }
+
+    let prefix = match spec.conversion {
+        b'o' if spec.alternate && !digits.starts_with('0') => "0",
+        b'x' if spec.alternate && i != 0 => "0x",
+        b'X' if spec.alternate && i != 0 => "0X",
+        b'd' | b'i' => spec.sign(negative),
+        _ => "",
+    };
+
+    // The `0` flag is ignored when a precision is given.
+    let zero_pad = spec.zero_pad && spec.precision.is_none();
+    spec.pad(out, prefix, digits.as_bytes(), zero_pad);
+}
+
+/// Formats a float with one of the `a`, `A`, `e`, `E`, `f`, `F`, `g`, or `G` conversions.
+pub(crate) fn format_float(out: &mut Vec<u8>, spec: &Spec, n: f64) {
+    let upper = spec.conversion.is_ascii_uppercase();
+    let sign = spec.sign(n.is_sign_negative());
+
+    if !n.is_finite() {
+        let body = match (n.is_nan(), upper) {
+            (true, false) => "nan",
+            (true, true) => "NAN",
+            (false, false) => "inf",
+            (false, true) => "INF",
+        };
+        // Infinity and NaN are never zero padded.
+        spec.pad(out, sign, body.as_bytes(), false);
+        return;
+    }
+
+    if spec.conversion.to_ascii_lowercase() == b'a' {
+        let mut body = format_hex(n.abs(), spec.precision, spec.alternate);
+        let mut prefix = format!("{sign}0x");
+        if upper {
+            body.make_ascii_uppercase();
+            prefix.make_ascii_uppercase();
+        }
+        spec.pad(out, &prefix, body.as_bytes(), spec.zero_pad);
+        return;
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    let n = n.abs();
+    let mut body = match spec.conversion.to_ascii_lowercase() {
+        b'e' => format_exponent(n, precision, spec.alternate),
+        b'f' => format_fixed(n, precision, spec.alternate),
+        _ => format_general(n, precision, spec.alternate),
+    };
+    if upper {
+        body.make_ascii_uppercase();
+    }
+
+    spec.pad(out, sign, body.as_bytes(), spec.zero_pad);
+}
+
+/// Formats a finite, non-negative float like C's `%.*g` would, with the `#` flag if `alternate`
+/// is set.
+///
+/// This picks whichever of the `%e` and `%f` styles is appropriate for the magnitude of `n`, and
+/// then (unless `alternate` is set) removes trailing zeros from the fraction.
+pub(crate) fn format_general(n: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+
+    // The choice of style depends on the exponent of `n` after rounding to `precision` significant
+    // digits.
+    let (_, exponent) = split_exponent(n, precision - 1);
+    let mut s = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(n, precision - 1, alternate)
+    } else {
+        format_fixed(n, (precision as i32 - 1 - exponent) as usize, alternate)
+    };
+
+    if !alternate {
+        let exponent_start = s.find('e').unwrap_or(s.len());
+        let (mantissa, exponent) = s.split_at(exponent_start);
+        if mantissa.contains('.') {
+            let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+            s = format!("{mantissa}{exponent}");
+        }
+    }
+
+    s
+}
+
+/// Formats a finite, non-negative float like C's `%a` would, without the leading `0x`.
+///
+/// Normal floats are written as `1.<fraction>p<exponent>` and subnormals as
+/// `0.<fraction>p-1022`, with the 52 bit fraction in hex. Without a precision, the fraction is
+/// written exactly with trailing zeros removed. With a precision, it is rounded to that many hex
+/// digits, half to even, which may carry into the leading digit (as in glibc, `1.f8p+0` at
+/// precision 0 is `2p+0`).
+fn format_hex(n: f64, precision: Option<usize>, alternate: bool) -> String {
+    const FRACTION_DIGITS: usize = 13;
+
+    let bits = n.to_bits();
+    let biased_exponent = (bits >> 52) as i32 & 0x7ff;
+    let mut fraction = bits & ((1 << 52) - 1);
+    let (mut lead, exponent) = match (biased_exponent, fraction) {
+        (0, 0) => (0, 0),
+        (0, _) => (0, -1022),
+        (e, _) => (1, e - 1023),
+    };
+
+    let digits = precision.unwrap_or(FRACTION_DIGITS);
+    if digits < FRACTION_DIGITS {
+        let shift = 4 * (FRACTION_DIGITS - digits);
+        let rest = fraction & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        fraction >>= shift;
+        if rest > half || (rest == half && fraction & 1 == 1) {
+            fraction += 1;
+            if fraction >> (4 * digits) != 0 {
+                fraction = 0;
+                lead += 1;
+            }
+        }
+    }
+
+    let mut hex = if digits == 0 {
+        String::new()
+    } else {
+        format!("{:0width$x}", fraction, width = digits.min(FRACTION_DIGITS))
+    };
+    match precision {
+        Some(precision) => hex.extend(std::iter::repeat('0').take(precision - hex.len())),
+        None => hex.truncate(hex.trim_end_matches('0').len()),
+    }
+
+    let mut body = lead.to_string();
+    if !hex.is_empty() || alternate {
+        body.push('.');
+    }
+    body.push_str(&hex);
+    body.push_str(&format!("p{exponent:+}"));
+    body
+}
+
+// Formats a finite, non-negative float like C's `%.*e`.
+fn format_exponent(n: f64, precision: usize, alternate: bool) -> String {
+    let (mut mantissa, exponent) = split_exponent(n, precision);
+    if alternate && precision == 0 {
+        mantissa.push('.');
+    }
+    let exponent_sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{exponent_sign}{:02}", exponent.unsigned_abs())
+}
+
+// Formats a finite, non-negative float like C's `%.*f`.
+fn format_fixed(n: f64, precision: usize, alternate: bool) -> String {
+    let mut s = format!("{n:.precision$}");
+    if alternate && precision == 0 {
+        s.push('.');
+    }
+    s
+}
+
+// Rounds `n` to `precision` digits after the decimal point in scientific notation, returning the
+// mantissa and the decimal exponent.
+fn split_exponent(n: f64, precision: usize) -> (String, i32) {
+    let s = format!("{n:.precision$e}");
+    let (mantissa, exponent) = s.split_once('e').unwrap();
+    (mantissa.to_owned(), exponent.parse().unwrap())
+}
+
+/// Writes `s` as a `%s` conversion, truncated to the precision and padded to the width.
+pub(crate) fn format_string(out: &mut Vec<u8>, spec: &Spec, s: &[u8]) {
+    let s = match spec.precision {
+        Some(precision) => &s[..precision.min(s.len())],
+        None => s,
+    };
+    spec.pad(out, "", s, false);
+}
+
+/// Writes `s` as a Lua string literal that reads back as the same string, as `%q` does.
+pub(crate) fn quote_string(out: &mut Vec<u8>, s: &[u8]) {
+    out.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' | b'\\' => out.extend_from_slice(&[b'\\', c]),
+            b'\n' => out.extend_from_slice(b"\\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            c if c.is_ascii_control() => {
+                // A shorter escape would run into a following digit.
+                if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    out.extend_from_slice(format!("\\{c:03}").as_bytes());
+                } else {
+                    out.extend_from_slice(format!("\\{c}").as_bytes());
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Writes a nil, boolean, number, or string as a Lua literal that reads back as the same value, as
+/// `%q` does. Returns false without writing anything for any other type of value.
+pub(crate) fn quote_value(out: &mut Vec<u8>, value: Value) -> bool {
+    match value {
+        Value::String(s) => quote_string(out, s.as_bytes()),
+        // The minimum integer has no literal, since its negation overflows and hex literals are not
+        // allowed to wrap around to it.
+        Value::Integer(i64::MIN) => out.extend_from_slice(b"(-9223372036854775807 - 1)"),
+        Value::Integer(i) => out.extend_from_slice(i.to_string().as_bytes()),
+        Value::Number(n) if n.is_nan() => out.extend_from_slice(b"(0/0)"),
+        Value::Number(n) if n == f64::INFINITY => out.extend_from_slice(b"1e9999"),
+        Value::Number(n) if n == f64::NEG_INFINITY => out.extend_from_slice(b"-1e9999"),
+        // Rust's debug formatting is the shortest representation that reads back as the same
+        // float, and always includes a `.` or exponent so it stays a float.
+        Value::Number(n) => out.extend_from_slice(format!("{n:?}").as_bytes()),
+        Value::Nil | Value::Boolean(_) => out.extend_from_slice(value.to_string().as_bytes()),
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(spec: &str, n: f64) -> String {
+        let (spec, _) = Spec::parse(spec.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        format_float(&mut out, &spec, n);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_general() {
+        assert_eq!(format("g", 0.1), "0.1");
+        assert_eq!(format("g", 3.14159265), "3.14159");
+        assert_eq!(format("g", 100000.0), "100000");
+        assert_eq!(format("g", 1e6), "1e+06");
+        assert_eq!(format("g", 1e20), "1e+20");
+        assert_eq!(format("g", 1e-4), "0.0001");
+        assert_eq!(format("g", 1e-5), "1e-05");
+        assert_eq!(format("g", 999999.5), "1e+06");
+        assert_eq!(format("g", 0.0), "0");
+        assert_eq!(format("g", -0.0), "-0");
+        assert_eq!(format("g", 1e300 * 10.0), "inf");
+        assert_eq!(format(".14g", 0.1), "0.1");
+        assert_eq!(format(".14g", 1.0 / 3.0), "0.33333333333333");
+        assert_eq!(format(".3g", 1234.5), "1.23e+03");
+        assert_eq!(format("#g", 1.5), "1.50000");
+        assert_eq!(format("G", 1e-10), "1E-10");
+        assert_eq!(format("10.3g", -1.5), "      -1.5");
+        assert_eq!(format("010g", -1.5), "-0000001.5");
+    }
+
+    #[test]
+    fn test_fixed_and_exponent() {
+        assert_eq!(format("f", 1.5), "1.500000");
+        assert_eq!(format(".0f", 2.5), "2");
+        assert_eq!(format("#.0f", 2.0), "2.");
+        assert_eq!(format("+.2f", 3.14159), "+3.14");
+        assert_eq!(format("e", 12345.678), "1.234568e+04");
+        assert_eq!(format(".2E", 0.000123), "1.23E-04");
+        assert_eq!(format("e", 1e100), "1.000000e+100");
+        assert_eq!(format("-8.1f|", 1.0), "1.0     ");
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(format("a", 1.0), "0x1p+0");
+        assert_eq!(format("a", 10.0), "0x1.4p+3");
+        assert_eq!(format("a", 0.1), "0x1.999999999999ap-4");
+        assert_eq!(format("a", 0.5), "0x1p-1");
+        assert_eq!(format("a", 0.0), "0x0p+0");
+        assert_eq!(format("a", -0.0), "-0x0p+0");
+        assert_eq!(format("a", 5e-324), "0x0.0000000000001p-1022");
+        assert_eq!(format("a", f64::MAX), "0x1.fffffffffffffp+1023");
+        assert_eq!(format("A", 10.0), "0X1.4P+3");
+        assert_eq!(format("A", f64::NAN), "NAN");
+        assert_eq!(format("a", -f64::INFINITY), "-inf");
+        assert_eq!(format(".1a", 1.03125), "0x1.0p+0");
+        assert_eq!(format(".1a", 1.09375), "0x1.2p+0");
+        assert_eq!(format(".0a", 1.96875), "0x2p+0");
+        assert_eq!(format(".3a", 10.0), "0x1.400p+3");
+        assert_eq!(format(".15a", 1.0), "0x1.000000000000000p+0");
+        assert_eq!(format("#a", 1.0), "0x1.p+0");
+        assert_eq!(format("+a", 2.0), "+0x1p+1");
+        assert_eq!(format("012a", 1.0), "0x0000001p+0");
+        assert_eq!(format("10a|", -1.0), "   -0x1p+0");
+    }
+}
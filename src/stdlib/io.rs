@@ -1,6 +1,9 @@
-use std::io::{self, Write};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
 
-use gc_arena::Collect;
+use gc_arena::{Collect, Gc};
 
 use crate::{
     meta_ops::{self, MetaResult},
@@ -8,13 +11,47 @@ use crate::{
     Value,
 };
 
+/// Configures where `print` writes its output and how it formats it.
+///
+/// The default writes to stdout, separating values with a tab and ending each call with a newline,
+/// as PUC-Rio Lua does.
+pub struct PrintConfig {
+    /// Where printed values are written. This is flushed at the end of every call to `print`.
+    pub output: Box<dyn Write>,
+    /// Written between each printed value.
+    pub separator: Vec<u8>,
+    /// Written after the last printed value.
+    pub terminator: Vec<u8>,
+}
+
+impl Default for PrintConfig {
+    fn default() -> Self {
+        Self {
+            output: Box::new(io::stdout()),
+            separator: b"\t".to_vec(),
+            terminator: b"\n".to_vec(),
+        }
+    }
+}
+
 pub fn load_io<'gc>(ctx: Context<'gc>) {
+    load_io_with(ctx, PrintConfig::default())
+}
+
+/// Loads the I/O library, with `print` configured by the given `PrintConfig`.
+pub fn load_io_with<'gc>(ctx: Context<'gc>, config: PrintConfig) {
+    #[derive(Collect)]
+    #[collect(require_static)]
+    struct PrintState(RefCell<PrintConfig>);
+
+    let state = Gc::new(&ctx, PrintState(RefCell::new(config)));
+
     ctx.state
         .globals
         .set(
             ctx,
             "print",
-            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            AnyCallback::from_fn_with(&ctx, state, |&state, ctx, _, stack| {
                 #[derive(Debug, Copy, Clone, Eq, PartialEq, Collect)]
                 #[collect(require_static)]
                 enum Mode {
@@ -26,6 +63,7 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
                 #[derive(Collect)]
                 #[collect(no_drop)]
                 struct PrintSeq<'gc> {
+                    state: Gc<'gc, PrintState>,
                     mode: Mode,
                     values: Vec<Value<'gc>>,
                 }
@@ -37,7 +75,8 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
                         _fuel: &mut Fuel,
                         stack: &mut Stack<'gc>,
                     ) -> Result<SequencePoll<'gc>, Error<'gc>> {
-                        let mut stdout = io::stdout();
+                        let mut config = self.state.0.borrow_mut();
+                        let config = &mut *config;
 
                         if self.mode == Mode::Init {
                             self.mode = Mode::First;
@@ -52,9 +91,9 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
                                     if self.mode == Mode::First {
                                         self.mode = Mode::Rest;
                                     } else {
-                                        stdout.write_all(&b"\t"[..])?;
+                                        config.output.write_all(&config.separator)?;
                                     }
-                                    v.display(&mut stdout)?
+                                    v.display(&mut config.output)?
                                 }
                                 MetaResult::Call(call) => {
                                     stack.extend(call.args);
@@ -66,8 +105,8 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
                             }
                         }
 
-                        stdout.write_all(&b"\n"[..])?;
-                        stdout.flush()?;
+                        config.output.write_all(&config.terminator)?;
+                        config.output.flush()?;
                         Ok(SequencePoll::Return)
                     }
                 }
@@ -75,6 +114,7 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
                 Ok(CallbackReturn::Sequence(AnySequence::new(
                     &ctx,
                     PrintSeq {
+                        state,
                         mode: Mode::Init,
                         values: stack.drain(..).rev().collect(),
                     },
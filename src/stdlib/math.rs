@@ -1,13 +1,51 @@
-use std::{cell::RefCell, f64, ops::DerefMut, rc::Rc};
+use std::{cell::RefCell, f64};
 
-use gc_arena::Mutation;
+use gc_arena::{Collect, Gc, Mutation, Rootable};
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 
 use crate::{
     raw_ops, AnyCallback, CallbackReturn, Context, FromMultiValue, IntoMultiValue, IntoValue,
-    Table, Value, Variadic,
+    Singleton, Table, Value, Variadic,
 };
 
+use super::util::parse_args;
+
+/// The random number generator used by `math.random` and `math.randomseed`.
+///
+/// This is stored as a registry singleton so that every `Lua` instance has its own independent
+/// generator whose lifetime is managed by the garbage collector. There is no state shared between
+/// instances, so hosts running many interpreters (on one OS thread or several) never contend over
+/// it, and the `RefCell` is only ever borrowed for the duration of a single draw.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct MathRng<'gc>(Gc<'gc, RngState>);
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct RngState(RefCell<SmallRng>);
+
+impl<'gc> Singleton<'gc> for MathRng<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        MathRng(Gc::new(
+            &ctx,
+            RngState(RefCell::new(SmallRng::from_entropy())),
+        ))
+    }
+}
+
+impl<'gc> MathRng<'gc> {
+    fn get(ctx: Context<'gc>) -> &'gc RefCell<SmallRng> {
+        let rng = ctx.state.registry.singleton::<Rootable![MathRng<'_>]>(ctx);
+        &Gc::as_ref(rng.0).0
+    }
+}
+
+/// Draws a float uniformly from `[0, 1)` using 53 random bits, the full precision of an `f64`
+/// mantissa, as Lua 5.4 does.
+fn random_float(rng: &mut impl Rng) -> f64 {
+    (rng.gen::<u64>() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
 pub fn load_math<'gc>(ctx: Context<'gc>) {
     fn callback<'gc, F, A, R>(name: &'static str, mc: &Mutation<'gc>, f: F) -> AnyCallback<'gc>
     where
@@ -16,36 +54,43 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
         R: IntoMultiValue<'gc>,
     {
         AnyCallback::from_fn(mc, move |ctx, _, stack| {
-            if let Some(res) = f(ctx, stack.consume(ctx)?) {
+            let args = parse_args(ctx, name, stack)?;
+            if let Some(res) = f(ctx, args) {
                 stack.replace(ctx, res);
                 Ok(CallbackReturn::Return)
             } else {
-                Err(format!("Bad argument to {name}").into_value(ctx).into())
+                Err(format!("bad argument to '{name}'").into_value(ctx).into())
             }
         })
     }
 
-    fn to_int(v: Value) -> Value {
-        if let Some(i) = v.to_integer() {
-            Value::Integer(i)
-        } else {
-            v
-        }
+    // Converts numbers and numeric strings to a number, keeping integers as integers.
+    fn to_numeric(v: Value) -> Option<Value> {
+        v.to_constant()?.to_numeric().map(Value::from)
     }
 
-    let math = Table::new(&ctx);
-    let seeded_rng: Rc<RefCell<SmallRng>> = Rc::new(RefCell::new(SmallRng::from_entropy()));
+    // Rounds every element of a sequence into a new table in one call, for numeric code that would
+    // otherwise call `math.floor` or `math.ceil` once per element.
+    fn round_all<'gc>(
+        name: &'static str,
+        mc: &Mutation<'gc>,
+        round: for<'a> fn(Value<'a>) -> Option<Value<'a>>,
+    ) -> AnyCallback<'gc> {
+        callback(name, mc, move |ctx, t: Table| {
+            let rounded = Table::with_instance_hashing(ctx);
+            for (i, v) in t.iter_array() {
+                rounded.set(ctx, i, round(v)?).ok()?;
+            }
+            Some(rounded)
+        })
+    }
+
+    let math = Table::with_instance_hashing(ctx);
 
     math.set(
         ctx,
         "abs",
-        callback("abs", &ctx, |_, v: Value| {
-            Some(if let Value::Integer(i) = v {
-                Value::Integer(i.abs())
-            } else {
-                v.to_number()?.abs().into()
-            })
-        }),
+        callback("abs", &ctx, |_, v: Value| raw_ops::abs(v)),
     )
     .unwrap();
 
@@ -79,10 +124,15 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "ceil",
-        callback("ceil", &ctx, |_, v: f64| Some(to_int(v.ceil().into()))),
+        callback("ceil", &ctx, |_, v: Value| raw_ops::ceil(v)),
     )
     .unwrap();
 
+    // `ceil_all` and `floor_all` are piccolo extensions, which return a new table holding the
+    // rounded elements of the sequence `1..#t` of the given table.
+    math.set(ctx, "ceil_all", round_all("ceil_all", &ctx, raw_ops::ceil))
+        .unwrap();
+
     math.set(ctx, "cos", callback("cos", &ctx, |_, v: f64| Some(v.cos())))
         .unwrap();
 
@@ -103,16 +153,30 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "floor",
-        callback("floor", &ctx, |_, v: f64| Some(to_int(v.floor().into()))),
+        callback("floor", &ctx, |_, v: Value| raw_ops::floor(v)),
+    )
+    .unwrap();
+
+    math.set(
+        ctx,
+        "floor_all",
+        round_all("floor_all", &ctx, raw_ops::floor),
     )
     .unwrap();
 
     math.set(
         ctx,
         "fmod",
-        callback("fmod", &ctx, |_, (f, g): (f64, f64)| {
-            let result = (f % g).abs();
-            Some(if f < 0.0 { -result } else { result })
+        callback("fmod", &ctx, |_, (f, g): (Value, Value)| {
+            Some(match (to_numeric(f)?, to_numeric(g)?) {
+                // Integer `fmod` truncates like Rust's `%`, and `wrapping_rem` gives 0 for the
+                // one overflowing case of `mininteger % -1`.
+                (Value::Integer(f), Value::Integer(g)) if g != 0 => {
+                    Value::Integer(f.wrapping_rem(g))
+                }
+                (Value::Integer(_), Value::Integer(_)) => return None,
+                (f, g) => (f.to_number()? % g.to_number()?).into(),
+            })
         }),
     )
     .unwrap();
@@ -136,9 +200,11 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
             if v.is_empty() {
                 None
             } else {
+                // NaN arguments are skipped, so the result is only NaN if every argument is.
                 v.into_iter()
-                    .try_fold(Value::Number(-f64::INFINITY), |max, entry| {
-                        Some(if raw_ops::less_than(max, entry)? {
+                    .try_fold(Value::Number(f64::NAN), |max, entry| {
+                        let is_nan = matches!(max, Value::Number(n) if n.is_nan());
+                        Some(if raw_ops::less_than(max, entry)? || is_nan {
                             entry
                         } else {
                             max
@@ -159,12 +225,14 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
             if v.is_empty() {
                 None
             } else {
+                // NaN arguments are skipped, so the result is only NaN if every argument is.
                 v.into_iter()
-                    .try_fold(Value::Number(f64::INFINITY), |max, entry| {
-                        Some(if raw_ops::less_than(entry, max)? {
+                    .try_fold(Value::Number(f64::NAN), |min, entry| {
+                        let is_nan = matches!(min, Value::Number(n) if n.is_nan());
+                        Some(if raw_ops::less_than(entry, min)? || is_nan {
                             entry
                         } else {
-                            max
+                            min
                         })
                     })
             }
@@ -178,12 +246,33 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "modf",
-        callback("modf", &ctx, |_, f: f64| Some((f as i64, f % 1.0))),
+        callback("modf", &ctx, |_, v: Value| {
+            Some(match to_numeric(v)? {
+                Value::Integer(i) => (Value::Integer(i), Value::Number(0.0)),
+                v => {
+                    let f = v.to_number()?;
+                    let int = f.trunc();
+                    // The fraction of an infinity is 0 rather than NaN.
+                    let frac = if f.is_infinite() { 0.0 } else { f - int };
+                    (Value::Number(int), Value::Number(frac))
+                }
+            })
+        }),
     )
     .unwrap();
 
     math.set(ctx, "pi", Value::Number(f64::consts::PI)).unwrap();
 
+    #[cfg(feature = "compat51")]
+    math.set(
+        ctx,
+        "pow",
+        callback("pow", &ctx, |_, (x, y): (Value, Value)| {
+            raw_ops::exponentiate(x, y)
+        }),
+    )
+    .unwrap();
+
     math.set(
         ctx,
         "rad",
@@ -191,17 +280,16 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
-    let random_rng = seeded_rng.clone();
     math.set(
         ctx,
         "random",
         callback(
             "random",
             &ctx,
-            move |_, (a, b): (Option<i64>, Option<i64>)| -> Option<Value> {
-                let rng = &random_rng;
+            |ctx, (a, b): (Option<i64>, Option<i64>)| -> Option<Value> {
+                let rng = MathRng::get(ctx);
                 match (a, b) {
-                    (None, None) => Some(rng.borrow_mut().gen::<f64>().into()),
+                    (None, None) => Some(random_float(&mut *rng.borrow_mut()).into()),
                     (Some(a), None) => Some(rng.borrow_mut().gen_range(1..a + 1).into()),
                     (Some(a), Some(b)) => Some(rng.borrow_mut().gen_range(a..b + 1).into()),
                     _ => None,
@@ -211,13 +299,11 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
-    let randomseed_rng = seeded_rng.clone();
     math.set(
         ctx,
         "randomseed",
-        callback("randomseed", &ctx, move |_, f: i64| {
-            let rng = &randomseed_rng;
-            *(rng.borrow_mut().deref_mut()) = SmallRng::seed_from_u64(f as u64);
+        callback("randomseed", &ctx, |ctx, f: i64| {
+            *MathRng::get(ctx).borrow_mut() = SmallRng::seed_from_u64(f as u64);
             Some(())
         }),
     )
@@ -1,11 +1,32 @@
 mod base;
+mod config;
 mod coroutine;
+mod debug;
+pub(crate) mod format;
 mod io;
 mod math;
+#[cfg(feature = "std")]
+mod os;
+mod pack;
+mod package;
+mod pattern;
 mod string;
 mod table;
+mod util;
+mod warn;
 
 pub use self::{
-    base::load_base, coroutine::load_coroutine, io::load_io, math::load_math, string::load_string,
+    base::load_base,
+    config::{load_stdlib, StdlibConfig},
+    coroutine::load_coroutine,
+    debug::{format_traceback, load_debug},
+    io::{load_io, load_io_with, PrintConfig},
+    math::load_math,
+    package::load_package,
+    string::load_string,
     table::load_table,
+    warn::Warnings,
 };
+
+#[cfg(feature = "std")]
+pub use self::os::load_os;
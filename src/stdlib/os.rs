@@ -0,0 +1,145 @@
+use std::{
+    env, fs, io,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{AnyCallback, CallbackReturn, Context, IntoValue, Stack, String, Table, Value};
+
+use super::util::parse_args;
+
+/// Loads the parts of the `os` library that work with files.
+///
+/// Failures are reported with Lua's convention of returning `nil`, an error message, and the OS
+/// error code, rather than by raising an error.
+pub fn load_os<'gc>(ctx: Context<'gc>) {
+    let os = Table::with_instance_hashing(ctx);
+
+    os.set(
+        ctx,
+        "tmpname",
+        AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let Ok(path) = temp_file() else {
+                return Err("unable to generate a unique filename"
+                    .into_value(ctx)
+                    .into());
+            };
+            stack.replace(ctx, path.to_string_lossy().as_ref());
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "remove",
+        AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let name: String = parse_args(ctx, "remove", stack)?;
+            // Like C's `remove`, this also removes empty directories.
+            let result = to_path(name).and_then(|path| match fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() => fs::remove_dir(&path),
+                _ => fs::remove_file(&path),
+            });
+            push_result(ctx, stack, name, result);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "rename",
+        AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let (from, to): (String, String) = parse_args(ctx, "rename", stack)?;
+            let result = match (to_path(from), to_path(to)) {
+                (Ok(from), Ok(to)) => fs::rename(from, to),
+                (Err(err), _) | (_, Err(err)) => Err(err),
+            };
+            push_result(ctx, stack, from, result);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    ctx.state.globals.set(ctx, "os", os).unwrap();
+}
+
+// File names are passed to the OS as the raw bytes of the string where the platform allows it.
+#[cfg(unix)]
+fn to_path(name: String) -> io::Result<PathBuf> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    Ok(PathBuf::from(OsStr::from_bytes(name.as_bytes())))
+}
+
+// Elsewhere file names must be valid UTF-8, rather than being silently changed into a different
+// name.
+#[cfg(not(unix))]
+fn to_path(name: String) -> io::Result<PathBuf> {
+    match name.to_str() {
+        Ok(name) => Ok(PathBuf::from(name)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "file name is not valid UTF-8",
+        )),
+    }
+}
+
+// Replaces the stack with `true` on success, or `nil`, a message, and an error code on failure.
+fn push_result<'gc>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc>,
+    name: String<'gc>,
+    result: io::Result<()>,
+) {
+    match result {
+        Ok(()) => stack.replace(ctx, true),
+        Err(err) => {
+            let code = err.raw_os_error().map(i64::from);
+            // Strip the " (os error N)" suffix that Rust adds, since the code is returned
+            // separately.
+            let message = err.to_string();
+            let message = match message.find(" (os error ") {
+                Some(i) => &message[..i],
+                None => &message,
+            };
+            stack.replace(
+                ctx,
+                (
+                    Value::Nil,
+                    format!("{}: {}", name.to_str_lossy(), message),
+                    code,
+                ),
+            );
+        }
+    }
+}
+
+// Creates a new empty file in the system temporary directory, returning its path.
+fn temp_file() -> io::Result<PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = env::temp_dir();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    for _ in 0..100 {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("lua_{}_{:x}_{}", process::id(), nanos, n));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::ErrorKind::AlreadyExists.into())
+}
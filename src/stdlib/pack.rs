@@ -0,0 +1,484 @@
+//! The binary codec behind `string.pack`, `string.unpack`, and `string.packsize`.
+
+use crate::{Context, Error, IntoValue, Stack, String, Value};
+
+use super::{
+    string::{relative_index, string_arg},
+    util::{argument_error, bad_argument},
+};
+
+// The largest size allowed for `i[n]`, `I[n]`, `s[n]`, and `![n]`.
+const MAX_INT_SIZE: usize = 16;
+// The size of a Lua integer, which is also used for `size_t` and the native maximum alignment.
+const INTEGER_SIZE: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Int,
+    Uint,
+    Float,
+    Double,
+    // A fixed size string.
+    Char,
+    // A string prefixed by its length.
+    String,
+    // A zero terminated string.
+    ZString,
+    Padding,
+    PaddingAlign,
+    Nop,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Item {
+    kind: Kind,
+    size: usize,
+    // The number of padding bytes that must precede this item to align it.
+    padding: usize,
+}
+
+struct Format<'gc, 'a> {
+    ctx: Context<'gc>,
+    function: &'static str,
+    fmt: &'a [u8],
+    pos: usize,
+    little: bool,
+    max_align: usize,
+}
+
+impl<'gc, 'a> Format<'gc, 'a> {
+    fn new(ctx: Context<'gc>, function: &'static str, fmt: &'a [u8]) -> Self {
+        Format {
+            ctx,
+            function,
+            fmt,
+            pos: 0,
+            little: cfg!(target_endian = "little"),
+            max_align: 1,
+        }
+    }
+
+    // Reads the next item, computing the padding needed to align it when `total` bytes precede it.
+    fn next(&mut self, total: usize) -> Result<Option<Item>, Error<'gc>> {
+        let Some((kind, size)) = self.read_option()? else {
+            return Ok(None);
+        };
+
+        let mut align = size;
+        if kind == Kind::PaddingAlign {
+            // `X` takes its alignment from the following option, which is otherwise ignored.
+            match self.read_option()? {
+                Some((next, next_size)) if next != Kind::Char && next_size != 0 => {
+                    align = next_size
+                }
+                _ => {
+                    return Err(argument_error(
+                        self.ctx,
+                        1,
+                        self.function,
+                        "invalid next option for option 'X'",
+                    ))
+                }
+            }
+        }
+
+        let padding = if align <= 1 || kind == Kind::Char {
+            0
+        } else {
+            let align = align.min(self.max_align);
+            if !align.is_power_of_two() {
+                return Err(argument_error(
+                    self.ctx,
+                    1,
+                    self.function,
+                    "format asks for alignment not power of 2",
+                ));
+            }
+            (align - (total & (align - 1))) & (align - 1)
+        };
+
+        Ok(Some(Item {
+            kind,
+            size,
+            padding,
+        }))
+    }
+
+    fn read_option(&mut self) -> Result<Option<(Kind, usize)>, Error<'gc>> {
+        let Some(&c) = self.fmt.get(self.pos) else {
+            return Ok(None);
+        };
+        self.pos += 1;
+
+        Ok(Some(match c {
+            b'b' => (Kind::Int, 1),
+            b'B' => (Kind::Uint, 1),
+            b'h' => (Kind::Int, 2),
+            b'H' => (Kind::Uint, 2),
+            b'l' | b'j' => (Kind::Int, INTEGER_SIZE),
+            b'L' | b'J' | b'T' => (Kind::Uint, INTEGER_SIZE),
+            b'i' => (Kind::Int, self.read_size(4)?),
+            b'I' => (Kind::Uint, self.read_size(4)?),
+            b'f' => (Kind::Float, 4),
+            b'd' | b'n' => (Kind::Double, 8),
+            b's' => (Kind::String, self.read_size(INTEGER_SIZE)?),
+            b'c' => match self.read_number() {
+                Some(size) => (Kind::Char, size),
+                None => {
+                    return Err("missing size for format option 'c'"
+                        .into_value(self.ctx)
+                        .into())
+                }
+            },
+            b'z' => (Kind::ZString, 0),
+            b'x' => (Kind::Padding, 1),
+            b'X' => (Kind::PaddingAlign, 0),
+            b' ' => (Kind::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (Kind::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (Kind::Nop, 0)
+            }
+            b'=' => {
+                self.little = cfg!(target_endian = "little");
+                (Kind::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.read_size(INTEGER_SIZE)?;
+                (Kind::Nop, 0)
+            }
+            c => {
+                return Err(format!("invalid format option '{}'", c as char)
+                    .into_value(self.ctx)
+                    .into())
+            }
+        }))
+    }
+
+    fn read_number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        let mut n: usize = 0;
+        while let Some(&c) = self.fmt.get(self.pos) {
+            if !c.is_ascii_digit() || n > (usize::MAX / 2 - 9) / 10 {
+                break;
+            }
+            n = n * 10 + usize::from(c - b'0');
+            self.pos += 1;
+        }
+        (self.pos > start).then_some(n)
+    }
+
+    // Reads an optional size for an integer or alignment option.
+    fn read_size(&mut self, default: usize) -> Result<usize, Error<'gc>> {
+        let size = self.read_number().unwrap_or(default);
+        if size == 0 || size > MAX_INT_SIZE {
+            Err(
+                format!("integral size ({size}) out of limits [1,{MAX_INT_SIZE}]")
+                    .into_value(self.ctx)
+                    .into(),
+            )
+        } else {
+            Ok(size)
+        }
+    }
+}
+
+/// `string.pack(fmt, v1, v2, ...)`
+pub(crate) fn pack<'gc>(ctx: Context<'gc>, stack: &mut Stack<'gc>) -> Result<(), Error<'gc>> {
+    let fmt = string_arg(ctx, 1, "pack", stack.get(0))?;
+    let mut format = Format::new(ctx, "pack", fmt.as_bytes());
+    let mut out = Vec::new();
+    let mut arg = 1;
+
+    while let Some(item) = format.next(out.len())? {
+        out.resize(out.len() + item.padding, 0);
+        let size = item.size;
+        let position = arg + 1;
+
+        match item.kind {
+            Kind::Int => {
+                let n = integer_arg(ctx, stack.get(arg), position, "pack")?;
+                if size < INTEGER_SIZE {
+                    let limit = 1i64 << (size * 8 - 1);
+                    if !(-limit..limit).contains(&n) {
+                        return Err(argument_error(ctx, position, "pack", "integer overflow"));
+                    }
+                }
+                pack_int(&mut out, n as u64, format.little, size, n < 0);
+                arg += 1;
+            }
+            Kind::Uint => {
+                let n = integer_arg(ctx, stack.get(arg), position, "pack")?;
+                if size < INTEGER_SIZE && (n as u64) >= 1 << (size * 8) {
+                    return Err(argument_error(ctx, position, "pack", "unsigned overflow"));
+                }
+                pack_int(&mut out, n as u64, format.little, size, false);
+                arg += 1;
+            }
+            Kind::Float => {
+                let n = number_arg(ctx, stack.get(arg), position, "pack")? as f32;
+                out.extend(if format.little {
+                    n.to_le_bytes()
+                } else {
+                    n.to_be_bytes()
+                });
+                arg += 1;
+            }
+            Kind::Double => {
+                let n = number_arg(ctx, stack.get(arg), position, "pack")?;
+                out.extend(if format.little {
+                    n.to_le_bytes()
+                } else {
+                    n.to_be_bytes()
+                });
+                arg += 1;
+            }
+            Kind::Char => {
+                let s = string_arg(ctx, position, "pack", stack.get(arg))?;
+                if s.as_bytes().len() > size {
+                    return Err(argument_error(
+                        ctx,
+                        position,
+                        "pack",
+                        "string longer than given size",
+                    ));
+                }
+                out.extend(s.as_bytes());
+                out.resize(out.len() + size - s.as_bytes().len(), 0);
+                arg += 1;
+            }
+            Kind::String => {
+                let s = string_arg(ctx, position, "pack", stack.get(arg))?;
+                let len = s.as_bytes().len();
+                if size < INTEGER_SIZE && len as u64 >= 1 << (size * 8) {
+                    return Err(argument_error(
+                        ctx,
+                        position,
+                        "pack",
+                        "string length does not fit in given size",
+                    ));
+                }
+                pack_int(&mut out, len as u64, format.little, size, false);
+                out.extend(s.as_bytes());
+                arg += 1;
+            }
+            Kind::ZString => {
+                let s = string_arg(ctx, position, "pack", stack.get(arg))?;
+                if s.as_bytes().contains(&0) {
+                    return Err(argument_error(
+                        ctx,
+                        position,
+                        "pack",
+                        "string contains zeros",
+                    ));
+                }
+                out.extend(s.as_bytes());
+                out.push(0);
+                arg += 1;
+            }
+            Kind::Padding => out.push(0),
+            Kind::PaddingAlign | Kind::Nop => {}
+        }
+    }
+
+    stack.replace(ctx, String::from_slice(&ctx, &out));
+    Ok(())
+}
+
+/// `string.unpack(fmt, s [, pos])`
+pub(crate) fn unpack<'gc>(ctx: Context<'gc>, stack: &mut Stack<'gc>) -> Result<(), Error<'gc>> {
+    let fmt = string_arg(ctx, 1, "unpack", stack.get(0))?;
+    let data = string_arg(ctx, 2, "unpack", stack.get(1))?;
+    let data = data.as_bytes();
+    let len = data.len();
+
+    let mut pos = match stack.get(2) {
+        Value::Nil => 0,
+        v => relative_index(integer_arg(ctx, v, 3, "unpack")?, len).max(1) - 1,
+    };
+    if pos > len {
+        return Err(argument_error(
+            ctx,
+            3,
+            "unpack",
+            "initial position out of string",
+        ));
+    }
+
+    let too_short = || argument_error(ctx, 2, "unpack", "data string too short");
+
+    let mut format = Format::new(ctx, "unpack", fmt.as_bytes());
+    let mut results = Vec::new();
+    while let Some(item) = format.next(pos)? {
+        if item.padding + item.size > len - pos {
+            return Err(too_short());
+        }
+        pos += item.padding;
+        let bytes = &data[pos..pos + item.size];
+
+        match item.kind {
+            Kind::Int | Kind::Uint => {
+                let n = unpack_int(ctx, bytes, format.little, item.kind == Kind::Int)?;
+                results.push(Value::Integer(n));
+            }
+            Kind::Float => {
+                let bytes = bytes.try_into().unwrap();
+                let n = if format.little {
+                    f32::from_le_bytes(bytes)
+                } else {
+                    f32::from_be_bytes(bytes)
+                };
+                results.push(Value::Number(n.into()));
+            }
+            Kind::Double => {
+                let bytes = bytes.try_into().unwrap();
+                let n = if format.little {
+                    f64::from_le_bytes(bytes)
+                } else {
+                    f64::from_be_bytes(bytes)
+                };
+                results.push(Value::Number(n));
+            }
+            Kind::Char => results.push(Value::String(String::from_slice(&ctx, bytes))),
+            Kind::String => {
+                let str_len = unpack_int(ctx, bytes, format.little, false)? as u64;
+                let start = pos + item.size;
+                match usize::try_from(str_len) {
+                    Ok(str_len) if str_len <= len - start => {
+                        results.push(Value::String(String::from_slice(
+                            &ctx,
+                            &data[start..start + str_len],
+                        )));
+                        pos += str_len;
+                    }
+                    _ => return Err(too_short()),
+                }
+            }
+            Kind::ZString => {
+                let Some(str_len) = data[pos..].iter().position(|&b| b == 0) else {
+                    return Err(argument_error(
+                        ctx,
+                        2,
+                        "unpack",
+                        "unfinished string for format 'z'",
+                    ));
+                };
+                results.push(Value::String(String::from_slice(
+                    &ctx,
+                    &data[pos..pos + str_len],
+                )));
+                pos += str_len + 1;
+            }
+            Kind::Padding | Kind::PaddingAlign | Kind::Nop => {}
+        }
+
+        pos += item.size;
+    }
+
+    results.push(Value::Integer(pos as i64 + 1));
+    stack.clear();
+    stack.extend(results);
+    Ok(())
+}
+
+/// `string.packsize(fmt)`
+pub(crate) fn packsize<'gc>(ctx: Context<'gc>, stack: &mut Stack<'gc>) -> Result<(), Error<'gc>> {
+    let fmt = string_arg(ctx, 1, "packsize", stack.get(0))?;
+    let mut format = Format::new(ctx, "packsize", fmt.as_bytes());
+    let mut total: usize = 0;
+
+    while let Some(item) = format.next(total)? {
+        if matches!(item.kind, Kind::String | Kind::ZString) {
+            return Err(argument_error(ctx, 1, "packsize", "variable-length format"));
+        }
+        total = total
+            .checked_add(item.padding + item.size)
+            .filter(|&t| t <= i64::MAX as usize)
+            .ok_or_else(|| argument_error(ctx, 1, "packsize", "format result too large"))?;
+    }
+
+    stack.replace(ctx, total as i64);
+    Ok(())
+}
+
+// Appends the low `size` bytes of `n`, sign extending negative numbers past 8 bytes.
+fn pack_int(out: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+    let mut bytes = vec![if negative { 0xff } else { 0 }; size];
+    for (i, b) in bytes.iter_mut().take(INTEGER_SIZE).enumerate() {
+        *b = (n >> (i * 8)) as u8;
+    }
+    if !little {
+        bytes.reverse();
+    }
+    out.extend(bytes);
+}
+
+fn unpack_int<'gc>(
+    ctx: Context<'gc>,
+    bytes: &[u8],
+    little: bool,
+    signed: bool,
+) -> Result<i64, Error<'gc>> {
+    let size = bytes.len();
+    // The `i`th least significant byte.
+    let byte = |i: usize| {
+        if little {
+            bytes[i]
+        } else {
+            bytes[size - 1 - i]
+        }
+    };
+
+    let mut n: u64 = 0;
+    for i in (0..size.min(INTEGER_SIZE)).rev() {
+        n = (n << 8) | u64::from(byte(i));
+    }
+
+    if size < INTEGER_SIZE {
+        if signed {
+            let shift = 64 - size * 8;
+            n = (((n << shift) as i64) >> shift) as u64;
+        }
+    } else if size > INTEGER_SIZE {
+        // The extra bytes must only hold the sign extension of the value.
+        let extension = if !signed || (n as i64) >= 0 { 0 } else { 0xff };
+        if (INTEGER_SIZE..size).any(|i| byte(i) != extension) {
+            return Err(format!("{size}-byte integer does not fit into Lua Integer")
+                .into_value(ctx)
+                .into());
+        }
+    }
+
+    Ok(n as i64)
+}
+
+fn integer_arg<'gc>(
+    ctx: Context<'gc>,
+    value: Value<'gc>,
+    position: usize,
+    function: &str,
+) -> Result<i64, Error<'gc>> {
+    value.to_integer().ok_or_else(|| match value {
+        Value::Number(_) => argument_error(
+            ctx,
+            position,
+            function,
+            "number has no integer representation",
+        ),
+        value => bad_argument(ctx, position, function, "number", value.type_name()),
+    })
+}
+
+fn number_arg<'gc>(
+    ctx: Context<'gc>,
+    value: Value<'gc>,
+    position: usize,
+    function: &str,
+) -> Result<f64, Error<'gc>> {
+    value
+        .to_number()
+        .ok_or_else(|| bad_argument(ctx, position, function, "number", value.type_name()))
+}
@@ -0,0 +1,179 @@
+use gc_arena::Collect;
+
+use crate::{
+    meta_ops, AnyCallback, AnySequence, CallbackReturn, Context, Error, Fuel, IntoValue, Sequence,
+    SequencePoll, Stack, String, Table, Value,
+};
+
+use super::util::parse_args;
+
+/// Loads the `package` table and the `require` function.
+///
+/// `require` first checks `package.loaded`, and otherwise calls each function in
+/// `package.searchers` in turn with the module name until one returns a loader. Only a searcher
+/// for `package.preload` is installed by default, embedders may add their own searchers to find
+/// modules elsewhere.
+///
+/// `package.path` and `package.cpath` are set to the conventional defaults for scripts that
+/// inspect them, but no searcher loads modules from the filesystem.
+pub fn load_package<'gc>(ctx: Context<'gc>) {
+    let package = Table::with_instance_hashing(ctx);
+    let loaded = Table::with_instance_hashing(ctx);
+    let preload = Table::with_instance_hashing(ctx);
+    let searchers = Table::with_instance_hashing(ctx);
+
+    searchers
+        .set(
+            ctx,
+            1,
+            AnyCallback::from_fn_with(&ctx, preload, |preload, ctx, _, stack| {
+                let name: String = parse_args(ctx, "searcher", stack)?;
+                let loader = preload.get(ctx, name);
+                if loader.is_nil() {
+                    stack.replace(
+                        ctx,
+                        format!("\n\tno field package.preload['{}']", name.to_str_lossy()),
+                    );
+                } else {
+                    stack.replace(ctx, (loader, ":preload:"));
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    package.set(ctx, "loaded", loaded).unwrap();
+    package.set(ctx, "preload", preload).unwrap();
+    package.set(ctx, "searchers", searchers).unwrap();
+    package.set(ctx, "path", DEFAULT_PATH).unwrap();
+    package.set(ctx, "cpath", DEFAULT_CPATH).unwrap();
+    package.set(ctx, "config", "/\n;\n?\n!\n-\n").unwrap();
+
+    loaded.set(ctx, "_G", ctx.state.globals).unwrap();
+    loaded.set(ctx, "package", package).unwrap();
+
+    ctx.state.globals.set(ctx, "package", package).unwrap();
+
+    ctx.state
+        .globals
+        .set(
+            ctx,
+            "require",
+            AnyCallback::from_fn_with(
+                &ctx,
+                (package, loaded),
+                |&(package, loaded), ctx, _, stack| {
+                    let name: String = parse_args(ctx, "require", stack)?;
+
+                    let module = loaded.get(ctx, name);
+                    if module.to_bool() {
+                        stack.replace(ctx, module);
+                        return Ok(CallbackReturn::Return);
+                    }
+
+                    let Value::Table(searchers) = package.get(ctx, "searchers") else {
+                        return Err("'package.searchers' must be a table".into_value(ctx).into());
+                    };
+
+                    Ok(CallbackReturn::Sequence(AnySequence::new(
+                        &ctx,
+                        Require {
+                            name,
+                            loaded,
+                            searchers,
+                            state: RequireState::Search(0),
+                            messages: Vec::new(),
+                        },
+                    )))
+                },
+            ),
+        )
+        .unwrap();
+}
+
+const DEFAULT_PATH: &str = "./?.lua;./?/init.lua";
+const DEFAULT_CPATH: &str = "./?.so";
+
+#[derive(Collect)]
+#[collect(no_drop)]
+enum RequireState<'gc> {
+    // Waiting for the results of the searcher with the given index, or about to call the first
+    // searcher if the index is 0.
+    Search(i64),
+    // Waiting for the results of the module loader, along with the extra value returned by the
+    // searcher which found it.
+    Load(Value<'gc>),
+}
+
+// The state of a `require` call for a module that is not loaded yet, which must call searchers and
+// the module loader.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Require<'gc> {
+    name: String<'gc>,
+    loaded: Table<'gc>,
+    searchers: Table<'gc>,
+    state: RequireState<'gc>,
+    // The messages returned by searchers which did not find the module, to be reported if no
+    // searcher does.
+    messages: Vec<u8>,
+}
+
+impl<'gc> Sequence<'gc> for Require<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        match self.state {
+            RequireState::Search(i) => {
+                if i > 0 {
+                    match stack.get(0) {
+                        Value::Function(loader) => {
+                            let extra = stack.get(1);
+                            stack.replace(ctx, (self.name, extra));
+                            self.state = RequireState::Load(extra);
+                            return Ok(SequencePoll::Call {
+                                function: loader,
+                                is_tail: false,
+                            });
+                        }
+                        Value::String(message) => {
+                            self.messages.extend_from_slice(message.as_bytes())
+                        }
+                        _ => {}
+                    }
+                }
+
+                let searcher = self.searchers.get(ctx, i + 1);
+                if searcher.is_nil() {
+                    let mut message =
+                        format!("module '{}' not found:", self.name.to_str_lossy()).into_bytes();
+                    message.extend_from_slice(&self.messages);
+                    return Err(String::from_slice(&ctx, message).into_value(ctx).into());
+                }
+
+                stack.replace(ctx, self.name);
+                self.state = RequireState::Search(i + 1);
+                Ok(SequencePoll::Call {
+                    function: meta_ops::call(ctx, searcher)?,
+                    is_tail: false,
+                })
+            }
+            RequireState::Load(extra) => {
+                let module = stack.get(0);
+                if !module.is_nil() {
+                    self.loaded.set(ctx, self.name, module)?;
+                }
+                // A loader which returns nothing and does not set `package.loaded` itself still
+                // marks the module as loaded.
+                if self.loaded.get(ctx, self.name).is_nil() {
+                    self.loaded.set(ctx, self.name, true)?;
+                }
+                stack.replace(ctx, (self.loaded.get(ctx, self.name), extra));
+                Ok(SequencePoll::Return)
+            }
+        }
+    }
+}
@@ -0,0 +1,527 @@
+//! Lua pattern matching, as used by `string.gsub` and the other pattern functions of the string
+//! library.
+//!
+//! This is a direct port of the backtracking matcher in PUC-Rio Lua's `lstrlib.c`, and it matches
+//! bytes rather than characters, with character classes following the "C" locale.
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+// The maximum number of captures in a single pattern, the same as `LUA_MAXCAPTURES`.
+const MAX_CAPTURES: usize = 32;
+// The maximum recursion depth of the matcher, the same as `MAXCCALLS` in `lstrlib.c`.
+const MAX_MATCH_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, Error)]
+pub(crate) enum PatternError {
+    #[error("malformed pattern (ends with '%')")]
+    EndsWithEscape,
+    #[error("malformed pattern (missing ']')")]
+    MissingBracket,
+    #[error("malformed pattern (missing arguments to '%b')")]
+    MissingBalanceArguments,
+    #[error("missing '[' after '%f' in pattern")]
+    MissingFrontierBracket,
+    #[error("invalid capture index %{0}")]
+    InvalidCaptureIndex(usize),
+    #[error("invalid pattern capture")]
+    InvalidPatternCapture,
+    #[error("unfinished capture")]
+    UnfinishedCapture,
+    #[error("too many captures")]
+    TooManyCaptures,
+    #[error("pattern too complex")]
+    TooComplex,
+}
+
+/// The value of a single capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Capture {
+    /// A position capture `()`, holding the 1-based position in the source.
+    Position(usize),
+    /// A range of the source string.
+    Span(Range<usize>),
+}
+
+#[derive(Debug, Copy, Clone)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Len(usize),
+}
+
+/// A successful match of a pattern against part of a source string.
+#[derive(Debug, Clone)]
+pub(crate) struct Match {
+    pub(crate) range: Range<usize>,
+    captures: Vec<(usize, CaptureLen)>,
+}
+
+impl Match {
+    /// The number of values the match produces, which is the number of captures, or 1 if there
+    /// are no captures and the whole match is used instead.
+    pub(crate) fn value_count(&self) -> usize {
+        self.captures.len().max(1)
+    }
+
+    /// The number of captures in the pattern, not counting the whole match.
+    pub(crate) fn capture_count(&self) -> usize {
+        self.captures.len()
+    }
+
+    /// Returns the capture at the 0-based `index`, or the whole match if `index` is 0 and the
+    /// pattern has no captures.
+    pub(crate) fn capture(&self, index: usize) -> Result<Capture, PatternError> {
+        match self.captures.get(index) {
+            None if index == 0 => Ok(Capture::Span(self.range.clone())),
+            None => Err(PatternError::InvalidCaptureIndex(index + 1)),
+            Some(&(_, CaptureLen::Unfinished)) => Err(PatternError::UnfinishedCapture),
+            Some(&(start, CaptureLen::Position)) => Ok(Capture::Position(start + 1)),
+            Some(&(start, CaptureLen::Len(len))) => Ok(Capture::Span(start..start + len)),
+        }
+    }
+}
+
+/// Whether `pattern` contains any characters with special meaning in a pattern. A pattern without
+/// them only matches itself, so it can be searched for as a plain substring.
+pub(crate) fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
+
+/// A pattern with its leading `^` anchor, if any, removed.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Pattern<'a> {
+    pattern: &'a [u8],
+    anchored: bool,
+}
+
+impl<'a> Pattern<'a> {
+    pub(crate) fn new(pattern: &'a [u8]) -> Self {
+        match pattern.strip_prefix(b"^") {
+            Some(pattern) => Pattern {
+                pattern,
+                anchored: true,
+            },
+            None => Pattern {
+                pattern,
+                anchored: false,
+            },
+        }
+    }
+
+    /// Whether the pattern may only match at the start of the subject (or at the initial
+    /// position of a search).
+    pub(crate) fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    /// Tries to match the pattern starting at exactly `start` in `source`.
+    pub(crate) fn match_at(
+        &self,
+        source: &[u8],
+        start: usize,
+    ) -> Result<Option<Match>, PatternError> {
+        let mut state = MatchState {
+            source,
+            pattern: self.pattern,
+            depth: 0,
+            captures: Vec::new(),
+        };
+        Ok(state.do_match(start, 0)?.map(|end| Match {
+            range: start..end,
+            captures: state.captures,
+        }))
+    }
+
+    /// Finds the first match of the pattern starting at or after `init`.
+    pub(crate) fn find(&self, source: &[u8], init: usize) -> Result<Option<Match>, PatternError> {
+        let mut start = init;
+        loop {
+            if let Some(m) = self.match_at(source, start)? {
+                return Ok(Some(m));
+            }
+            start += 1;
+            if self.anchored || start > source.len() {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+struct MatchState<'a> {
+    source: &'a [u8],
+    pattern: &'a [u8],
+    depth: usize,
+    // The start of each capture and its length once it has been closed.
+    captures: Vec<(usize, CaptureLen)>,
+}
+
+impl<'a> MatchState<'a> {
+    // Returns the end of the match of `pattern[p..]` against `source[s..]`, if there is one.
+    fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        self.depth += 1;
+        if self.depth > MAX_MATCH_DEPTH {
+            return Err(PatternError::TooComplex);
+        }
+        let res = self.do_match_inner(s, p);
+        self.depth -= 1;
+        res
+    }
+
+    fn do_match_inner(
+        &mut self,
+        mut s: usize,
+        mut p: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        let pat = self.pattern;
+        let src = self.source;
+
+        loop {
+            if p == pat.len() {
+                return Ok(Some(s));
+            }
+
+            match (pat[p], pat.get(p + 1).copied()) {
+                (b'(', Some(b')')) => return self.start_capture(s, p + 2, CaptureLen::Position),
+                (b'(', _) => return self.start_capture(s, p + 1, CaptureLen::Unfinished),
+                (b')', _) => return self.end_capture(s, p + 1),
+                (b'$', None) => return Ok((s == src.len()).then_some(s)),
+                (b'%', Some(b'b')) => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                (b'%', Some(b'f')) => {
+                    p += 2;
+                    if pat.get(p) != Some(&b'[') {
+                        return Err(PatternError::MissingFrontierBracket);
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { src[s - 1] };
+                    let current = src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(previous, p, ep - 1)
+                        && self.match_bracket_class(current, p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                (b'%', Some(d)) if d.is_ascii_digit() => match self.match_capture(s, d)? {
+                    Some(end) => {
+                        s = end;
+                        p += 2;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                _ => {}
+            }
+
+            let ep = self.class_end(p)?;
+            let matched = s < src.len() && self.single_match(src[s], p, ep);
+            match pat.get(ep) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                            return Ok(Some(end));
+                        }
+                    }
+                    p = ep + 1;
+                }
+                Some(b'+') => {
+                    return if matched {
+                        self.max_expand(s + 1, p, ep)
+                    } else {
+                        Ok(None)
+                    };
+                }
+                Some(b'*') => return self.max_expand(s, p, ep),
+                Some(b'-') => return self.min_expand(s, p, ep),
+                _ => {
+                    if !matched {
+                        return Ok(None);
+                    }
+                    s += 1;
+                    p = ep;
+                }
+            }
+        }
+    }
+
+    // Returns the index just past the single character class starting at `p`.
+    fn class_end(&self, mut p: usize) -> Result<usize, PatternError> {
+        let pat = self.pattern;
+        let c = pat[p];
+        p += 1;
+        if c == b'%' {
+            if p >= pat.len() {
+                return Err(PatternError::EndsWithEscape);
+            }
+            Ok(p + 1)
+        } else if c == b'[' {
+            if pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // The first character of a set is never its closing bracket, so `[]]` is a set
+            // containing `]`.
+            loop {
+                if p >= pat.len() {
+                    return Err(PatternError::MissingBracket);
+                }
+                let c = pat[p];
+                p += 1;
+                if c == b'%' && p < pat.len() {
+                    p += 1;
+                }
+                match pat.get(p) {
+                    None => return Err(PatternError::MissingBracket),
+                    Some(b']') => return Ok(p + 1),
+                    Some(_) => {}
+                }
+            }
+        } else {
+            Ok(p)
+        }
+    }
+
+    // Whether the character `c` matches the single character class `pattern[p..ep]`.
+    fn single_match(&self, c: u8, p: usize, ep: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // Whether `c` matches the set `pattern[p..=ec]`, where `p` is the opening bracket and `ec` is
+    // the closing one.
+    fn match_bracket_class(&self, c: u8, p: usize, ec: usize) -> bool {
+        let pat = self.pattern;
+        let mut p = p + 1;
+        let mut sig = true;
+        if pat[p] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        while p < ec {
+            if pat[p] == b'%' {
+                p += 1;
+                if match_class(c, pat[p]) {
+                    return sig;
+                }
+                p += 1;
+            } else if pat[p + 1] == b'-' && p + 2 < ec {
+                if pat[p] <= c && c <= pat[p + 2] {
+                    return sig;
+                }
+                p += 3;
+            } else {
+                if pat[p] == c {
+                    return sig;
+                }
+                p += 1;
+            }
+        }
+        !sig
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, PatternError> {
+        let src = self.source;
+        let mut i = 0;
+        while s + i < src.len() && self.single_match(src[s + i], p, ep) {
+            i += 1;
+        }
+        // Try to match the rest of the pattern with the longest possible expansion, then back off.
+        loop {
+            if let Some(end) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(
+        &mut self,
+        mut s: usize,
+        p: usize,
+        ep: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if s < self.source.len() && self.single_match(self.source[s], p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, PatternError> {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err(PatternError::TooManyCaptures);
+        }
+        self.captures.push((s, len));
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures.pop();
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        let l = self
+            .captures
+            .iter()
+            .rposition(|(_, len)| matches!(len, CaptureLen::Unfinished))
+            .ok_or(PatternError::InvalidPatternCapture)?;
+        self.captures[l].1 = CaptureLen::Len(s - self.captures[l].0);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.captures[l].1 = CaptureLen::Unfinished;
+        }
+        Ok(res)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        let (Some(&open), Some(&close)) = (self.pattern.get(p), self.pattern.get(p + 1)) else {
+            return Err(PatternError::MissingBalanceArguments);
+        };
+        let src = self.source;
+        if src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+
+        let mut depth = 1;
+        for (i, &c) in src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // Matches a back reference to a previous capture, such as `%1`.
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, PatternError> {
+        let index = (digit - b'0') as usize;
+        let len = match index.checked_sub(1).and_then(|i| self.captures.get(i)) {
+            Some(&(start, CaptureLen::Len(len))) => Some((start, len)),
+            Some(&(_, CaptureLen::Position)) => None,
+            _ => return Err(PatternError::InvalidCaptureIndex(index)),
+        };
+
+        let src = self.source;
+        Ok(match len {
+            Some((start, len))
+                if src.len() - s >= len && src[start..start + len] == src[s..s + len] =>
+            {
+                Some(s + len)
+            }
+            _ => None,
+        })
+    }
+}
+
+// Whether `c` is in the character class named by `class`, such as `a` for `%a`. Upper case class
+// names match the complement of their lower case class, and any other character matches itself.
+fn match_class(c: u8, class: u8) -> bool {
+    let res = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        // Unlike `u8::is_ascii_whitespace`, C's `isspace` includes vertical tab.
+        b's' => matches!(c, b' ' | b'\t'..=b'\r'),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !res
+    } else {
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(source: &str, pattern: &str) -> Option<Range<usize>> {
+        Pattern::new(pattern.as_bytes())
+            .find(source.as_bytes(), 0)
+            .unwrap()
+            .map(|m| m.range)
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("hello world", "o w"), Some(4..7));
+        assert_eq!(find("hello world", "^world"), None);
+        assert_eq!(find("hello world", "world$"), Some(6..11));
+        assert_eq!(find("  abc123", "%a+%d*"), Some(2..8));
+        assert_eq!(find("x = (a(b)c)", "%b()"), Some(4..11));
+        assert_eq!(find("THE (quick) fox", "%f[%a]%a+"), Some(0..3));
+        assert_eq!(find("aaab", "a-b"), Some(0..4));
+        assert_eq!(find("a]b", "[]]"), Some(1..2));
+        assert_eq!(find("a-z", "[%-]"), Some(1..2));
+        assert_eq!(find("\x0b", "%s"), Some(0..1));
+        assert_eq!(find("", ""), Some(0..0));
+    }
+
+    #[test]
+    fn test_captures() {
+        let m = Pattern::new(b"(%w+)=()(%w*)")
+            .find(b"key=value", 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(m.value_count(), 3);
+        assert_eq!(m.capture(0).unwrap(), Capture::Span(0..3));
+        assert_eq!(m.capture(1).unwrap(), Capture::Position(5));
+        assert_eq!(m.capture(2).unwrap(), Capture::Span(4..9));
+        assert!(matches!(
+            m.capture(3),
+            Err(PatternError::InvalidCaptureIndex(4))
+        ));
+
+        assert_eq!(find("say 'hi' now", "(['\"]).-%1"), Some(4..8));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |pattern: &str| {
+            Pattern::new(pattern.as_bytes())
+                .find(b"abc", 0)
+                .unwrap_err()
+        };
+        assert!(matches!(err("%"), PatternError::EndsWithEscape));
+        assert!(matches!(err("[a"), PatternError::MissingBracket));
+        assert!(matches!(err("%b"), PatternError::MissingBalanceArguments));
+        assert!(matches!(err("%fa"), PatternError::MissingFrontierBracket));
+        assert!(matches!(err("a)"), PatternError::InvalidPatternCapture));
+        assert!(matches!(err("%1"), PatternError::InvalidCaptureIndex(1)));
+    }
+}
@@ -1,7 +1,184 @@
-use crate::{AnyCallback, CallbackReturn, Context, IntoValue, Table, Value};
+use gc_arena::Collect;
+
+use crate::{
+    bytecode,
+    meta_ops::{self, MetaResult, PrimitiveType},
+    AnyCallback, AnySequence, CallbackReturn, Context, Error, Fuel, Function, IntoValue,
+    MemoryLimit, MetaMethod, Sequence, SequencePoll, SizeLimits, Stack, String, Table, Value,
+    Variadic,
+};
+
+use super::{
+    format::{format_float, format_integer, format_string, quote_value, Spec},
+    pack::{pack, packsize, unpack},
+    pattern::{has_specials, Capture, Match, Pattern, PatternError},
+    util::{argument_error, bad_argument, parse_args},
+};
 
 pub fn load_string<'gc>(ctx: Context<'gc>) {
-    let string = Table::new(&ctx);
+    let string = Table::with_instance_hashing(ctx);
+
+    string
+        .set(
+            ctx,
+            "byte",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (s, i, j): (Value, Option<i64>, Option<i64>) = parse_args(ctx, "byte", stack)?;
+                let s = string_arg(ctx, 1, "byte", s)?;
+                let i = i.unwrap_or(1);
+                let start = relative_index(i, s.len() as usize).max(1);
+                let end = relative_index(j.unwrap_or(i), s.len() as usize).min(s.len() as usize);
+                if start <= end {
+                    stack.extend(
+                        s.as_bytes()[start - 1..end]
+                            .iter()
+                            .map(|&b| Value::Integer(b.into())),
+                    );
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "dump",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (f, strip): (Value, Value) = parse_args(ctx, "dump", stack)?;
+                match f {
+                    Value::Function(Function::Closure(closure)) => {
+                        let bytes = bytecode::dump(&closure.0.proto, strip.to_bool());
+                        stack.replace(ctx, String::from_slice(&ctx, bytes));
+                        Ok(CallbackReturn::Return)
+                    }
+                    Value::Function(Function::Callback(_)) => {
+                        Err("unable to dump given function".into_value(ctx).into())
+                    }
+                    f => Err(bad_argument(ctx, 1, "dump", "function", f.type_name())),
+                }
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "find",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (s, pattern, init, plain): (Value, Value, Option<i64>, Value) =
+                    parse_args(ctx, "find", stack)?;
+                let source = string_arg(ctx, 1, "find", s)?;
+                let pattern = string_arg(ctx, 2, "find", pattern)?;
+                let (source, pattern) = (source.as_bytes(), pattern.as_bytes());
+
+                let init = relative_index(init.unwrap_or(1), source.len()).max(1);
+                if init > source.len() + 1 {
+                    stack.replace(ctx, Value::Nil);
+                    return Ok(CallbackReturn::Return);
+                }
+
+                if plain.to_bool() || !has_specials(pattern) {
+                    match find_plain(&source[init - 1..], pattern) {
+                        Some(i) => {
+                            let start = init + i;
+                            stack.replace(ctx, (start as i64, (start + pattern.len() - 1) as i64));
+                        }
+                        None => stack.replace(ctx, Value::Nil),
+                    }
+                    return Ok(CallbackReturn::Return);
+                }
+
+                match Pattern::new(pattern)
+                    .find(source, init - 1)
+                    .map_err(|e| pattern_error(ctx, e))?
+                {
+                    Some(m) => {
+                        stack.replace(ctx, (m.range.start as i64 + 1, m.range.end as i64));
+                        for i in 0..m.capture_count() {
+                            let capture = m.capture(i).map_err(|e| pattern_error(ctx, e))?;
+                            stack.push_back(capture_value(ctx, source, capture));
+                        }
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "format",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                if stack.is_empty() {
+                    return Err(bad_argument(ctx, 1, "format", "string", "no value"));
+                }
+                let format = string_arg(ctx, 1, "format", stack.pop_front())?;
+                Ok(CallbackReturn::Sequence(AnySequence::new(
+                    &ctx,
+                    Format {
+                        format,
+                        args: stack.drain(..).collect(),
+                        position: 0,
+                        arg: 0,
+                        pending: None,
+                        result: Vec::new(),
+                    },
+                )))
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "gsub",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (s, pattern, replacement, max_replacements): (
+                    Value,
+                    Value,
+                    Value,
+                    Option<i64>,
+                ) = parse_args(ctx, "gsub", stack)?;
+                let source = string_arg(ctx, 1, "gsub", s)?;
+                let pattern = string_arg(ctx, 2, "gsub", pattern)?;
+                let replacement = match replacement {
+                    Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+                        Replacement::String(string_arg(ctx, 3, "gsub", replacement)?)
+                    }
+                    Value::Table(t) => Replacement::Table(t),
+                    Value::Function(f) => Replacement::Function(f),
+                    v => {
+                        return Err(bad_argument(
+                            ctx,
+                            3,
+                            "gsub",
+                            "string/function/table",
+                            v.type_name(),
+                        ))
+                    }
+                };
+
+                Ok(CallbackReturn::Sequence(AnySequence::new(
+                    &ctx,
+                    GSub {
+                        source,
+                        pattern,
+                        replacement,
+                        max_replacements: max_replacements.unwrap_or(i64::MAX),
+                        count: 0,
+                        position: 0,
+                        last_match: None,
+                        finished: false,
+                        pending: None,
+                        result: Vec::new(),
+                    },
+                )))
+            }),
+        )
+        .unwrap();
 
     string
         .set(
@@ -10,8 +187,9 @@ pub fn load_string<'gc>(ctx: Context<'gc>) {
             AnyCallback::from_fn(&ctx, |ctx, _, stack| {
                 let v: Option<Value> = stack.consume(ctx)?;
                 if let Some(len) = v.and_then(|v| match v {
-                    Value::Integer(i) => Some(i.to_string().as_bytes().len().try_into().unwrap()),
-                    Value::Number(n) => Some(n.to_string().as_bytes().len().try_into().unwrap()),
+                    Value::Integer(_) | Value::Number(_) => {
+                        Some(v.to_string().as_bytes().len().try_into().unwrap())
+                    }
                     Value::String(s) => Some(s.len()),
                     _ => None,
                 }) {
@@ -24,5 +202,647 @@ pub fn load_string<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    string
+        .set(
+            ctx,
+            "lower",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let s: Value = parse_args(ctx, "lower", stack)?;
+                let s = string_arg(ctx, 1, "lower", s)?;
+                stack.replace(
+                    ctx,
+                    String::from_slice(&ctx, s.as_bytes().to_ascii_lowercase()),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "pack",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                pack(ctx, stack)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "packsize",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                packsize(ctx, stack)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "rep",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (s, n, sep): (Value, i64, Option<Value>) = parse_args(ctx, "rep", stack)?;
+                let s = string_arg(ctx, 1, "rep", s)?;
+                let sep = match sep {
+                    Some(sep) => Some(string_arg(ctx, 3, "rep", sep)?),
+                    None => None,
+                };
+                let (s, sep) = (
+                    s.as_bytes(),
+                    sep.as_ref().map(|s| s.as_bytes()).unwrap_or(b""),
+                );
+
+                if n <= 0 {
+                    stack.replace(ctx, String::from_slice(&ctx, b""));
+                    return Ok(CallbackReturn::Return);
+                }
+
+                // Check the length of the result before allocating anything, so that an enormous
+                // count fails with an error rather than an allocation failure.
+                let len = usize::try_from(n)
+                    .ok()
+                    .and_then(|n| {
+                        s.len()
+                            .checked_mul(n)?
+                            .checked_add(sep.len().checked_mul(n - 1)?)
+                    })
+                    .unwrap_or(usize::MAX);
+                SizeLimits::check_string_len(ctx, len)?;
+                MemoryLimit::check_allocation(ctx, len)?;
+
+                let mut bytes = Vec::with_capacity(len);
+                for i in 0..n {
+                    if i > 0 {
+                        bytes.extend_from_slice(sep);
+                    }
+                    bytes.extend_from_slice(s);
+                }
+                stack.replace(ctx, String::from_slice(&ctx, bytes));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "reverse",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let s: Value = parse_args(ctx, "reverse", stack)?;
+                let s = string_arg(ctx, 1, "reverse", s)?;
+                let mut bytes = s.as_bytes().to_vec();
+                bytes.reverse();
+                stack.replace(ctx, String::from_slice(&ctx, bytes));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // `split` is a piccolo extension, which returns a new sequence of the parts of `s` between
+    // occurrences of the plain (not pattern) separator `sep`. Consecutive separators produce empty
+    // parts, so there is always one more part than there are separators. An empty separator splits
+    // `s` into its individual bytes instead.
+    string
+        .set(
+            ctx,
+            "split",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (s, sep): (Value, Value) = parse_args(ctx, "split", stack)?;
+                let s = string_arg(ctx, 1, "split", s)?;
+                let sep = string_arg(ctx, 2, "split", sep)?;
+                let (s, sep) = (s.as_bytes(), sep.as_bytes());
+
+                let parts = Table::with_instance_hashing(ctx);
+                if sep.is_empty() {
+                    for (i, &b) in s.iter().enumerate() {
+                        parts.set(ctx, i as i64 + 1, String::from_slice(&ctx, [b]))?;
+                    }
+                } else {
+                    let mut count: i64 = 0;
+                    let mut start = 0;
+                    let mut i = 0;
+                    while i + sep.len() <= s.len() {
+                        if s[i..].starts_with(sep) {
+                            count += 1;
+                            parts.set(ctx, count, String::from_slice(&ctx, &s[start..i]))?;
+                            i += sep.len();
+                            start = i;
+                        } else {
+                            i += 1;
+                        }
+                    }
+                    parts.set(ctx, count + 1, String::from_slice(&ctx, &s[start..]))?;
+                }
+
+                stack.replace(ctx, parts);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "sub",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (s, i, j): (Value, Option<i64>, Option<i64>) = parse_args(ctx, "sub", stack)?;
+                let s = string_arg(ctx, 1, "sub", s)?;
+                let start = relative_index(i.unwrap_or(1), s.len() as usize).max(1);
+                let end = relative_index(j.unwrap_or(-1), s.len() as usize).min(s.len() as usize);
+                if start <= end {
+                    stack.replace(ctx, String::from_slice(&ctx, &s.as_bytes()[start - 1..end]));
+                } else {
+                    stack.replace(ctx, "");
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "unpack",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                unpack(ctx, stack)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "upper",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let s: Value = parse_args(ctx, "upper", stack)?;
+                let s = string_arg(ctx, 1, "upper", s)?;
+                stack.replace(
+                    ctx,
+                    String::from_slice(&ctx, s.as_bytes().to_ascii_uppercase()),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     ctx.state.globals.set(ctx, "string", string).unwrap();
+
+    // Strings share a metatable which makes methods on strings, such as `s:upper()`, look up
+    // functions in the `string` table.
+    let metatable = Table::with_instance_hashing(ctx);
+    metatable.set(ctx, MetaMethod::Index, string).unwrap();
+    meta_ops::set_type_metatable(ctx, PrimitiveType::String, Some(metatable));
+}
+
+// The state of a `string.format` call, which must be able to stop to call a `__tostring`
+// metamethod for `%s`.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Format<'gc> {
+    format: String<'gc>,
+    args: Vec<Value<'gc>>,
+    // The position in `format` to continue from.
+    position: usize,
+    // The number of arguments consumed so far.
+    arg: usize,
+    // The `%s` conversion whose string is being computed by a `__tostring` call, if any.
+    #[collect(require_static)]
+    pending: Option<Spec>,
+    result: Vec<u8>,
+}
+
+impl<'gc> Format<'gc> {
+    // Appends a single conversion of `value`, which is argument number `position`.
+    fn add_conversion(
+        &mut self,
+        ctx: Context<'gc>,
+        spec: &Spec,
+        position: usize,
+        value: Value<'gc>,
+    ) -> Result<(), Error<'gc>> {
+        let integer_arg = |value: Value<'gc>| match value.to_integer() {
+            Some(i) => Ok(i),
+            None if value.to_number().is_some() => Err(argument_error(
+                ctx,
+                position,
+                "format",
+                "number has no integer representation",
+            )),
+            None => Err(bad_argument(
+                ctx,
+                position,
+                "format",
+                "number",
+                value.type_name(),
+            )),
+        };
+
+        match spec.conversion {
+            b'c' => {
+                let c = integer_arg(value)? as u8;
+                format_string(&mut self.result, spec, &[c]);
+            }
+            b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+                format_integer(&mut self.result, spec, integer_arg(value)?);
+            }
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let n = value.to_number().ok_or_else(|| {
+                    bad_argument(ctx, position, "format", "number", value.type_name())
+                })?;
+                format_float(&mut self.result, spec, n);
+            }
+            b'q' => {
+                if spec.has_modifiers() {
+                    return Err("specifier '%q' cannot have modifiers"
+                        .into_value(ctx)
+                        .into());
+                }
+                if !quote_value(&mut self.result, value) {
+                    return Err(argument_error(
+                        ctx,
+                        position,
+                        "format",
+                        "value has no literal form",
+                    ));
+                }
+            }
+            _ => unreachable!("conversion is checked when parsing"),
+        }
+        Ok(())
+    }
+}
+
+impl<'gc> Sequence<'gc> for Format<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(spec) = self.pending.take() {
+            let Value::String(s) = stack.get(0) else {
+                return Err("'__tostring' must return a string".into_value(ctx).into());
+            };
+            stack.clear();
+            format_string(&mut self.result, &spec, s.as_bytes());
+        }
+
+        let format = self.format.as_bytes();
+        while let Some(&c) = format.get(self.position) {
+            self.position += 1;
+            if c != b'%' {
+                self.result.push(c);
+                continue;
+            }
+
+            if format.get(self.position) == Some(&b'%') {
+                self.position += 1;
+                self.result.push(b'%');
+                continue;
+            }
+
+            let spec = match Spec::parse(&format[self.position..]) {
+                Some((spec, len)) if b"cdiuoxXaAeEfFgGqs".contains(&spec.conversion) => {
+                    self.position += len;
+                    spec
+                }
+                _ => {
+                    let rest = &format[self.position..];
+                    let len = rest
+                        .iter()
+                        .position(u8::is_ascii_alphabetic)
+                        .map(|i| i + 1)
+                        .unwrap_or(rest.len());
+                    return Err(format!(
+                        "invalid conversion '%{}' to 'format'",
+                        std::string::String::from_utf8_lossy(&rest[..len])
+                    )
+                    .into_value(ctx)
+                    .into());
+                }
+            };
+
+            // The format string is argument 1.
+            let position = self.arg + 2;
+            let Some(&value) = self.args.get(self.arg) else {
+                return Err(argument_error(ctx, position, "format", "no value"));
+            };
+            self.arg += 1;
+
+            if spec.conversion == b's' {
+                match meta_ops::tostring(ctx, value)? {
+                    MetaResult::Value(Value::String(s)) => {
+                        format_string(&mut self.result, &spec, s.as_bytes())
+                    }
+                    MetaResult::Value(_) => unreachable!("tostring always returns a string"),
+                    MetaResult::Call(call) => {
+                        stack.replace(ctx, Variadic(call.args));
+                        self.pending = Some(spec);
+                        return Ok(SequencePoll::Call {
+                            function: call.function,
+                            is_tail: false,
+                        });
+                    }
+                }
+            } else {
+                self.add_conversion(ctx, &spec, position, value)?;
+            }
+        }
+
+        stack.replace(ctx, String::from_slice(&ctx, &self.result));
+        Ok(SequencePoll::Return)
+    }
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+enum Replacement<'gc> {
+    String(String<'gc>),
+    Table(Table<'gc>),
+    Function(Function<'gc>),
+}
+
+// The state of a `string.gsub` call, which must be able to stop after each match to call a
+// replacement function or `__index` metamethod.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct GSub<'gc> {
+    source: String<'gc>,
+    pattern: String<'gc>,
+    replacement: Replacement<'gc>,
+    max_replacements: i64,
+    count: i64,
+    // The position in `source` to try the next match at.
+    position: usize,
+    // The end of the last match, so that an empty match directly after it is skipped.
+    last_match: Option<usize>,
+    // Set once an anchored pattern has been tried.
+    finished: bool,
+    // The span of the match whose replacement is being computed by a call, if any.
+    pending: Option<(usize, usize)>,
+    result: Vec<u8>,
+}
+
+impl<'gc> GSub<'gc> {
+    // Appends the replacement for `source[start..end]` that was returned by a table lookup or
+    // function call.
+    fn add_value(
+        &mut self,
+        ctx: Context<'gc>,
+        start: usize,
+        end: usize,
+        value: Value<'gc>,
+    ) -> Result<(), Error<'gc>> {
+        match value {
+            Value::Nil | Value::Boolean(false) => {
+                self.result
+                    .extend_from_slice(&self.source.as_bytes()[start..end]);
+            }
+            Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+                self.result
+                    .extend_from_slice(String::concat(ctx, &[value])?.as_bytes());
+            }
+            v => {
+                return Err(format!("invalid replacement value (a {})", v.type_name())
+                    .into_value(ctx)
+                    .into())
+            }
+        }
+        Ok(())
+    }
+
+    // Appends a replacement string, substituting `%0` through `%9` with captures.
+    fn add_string(
+        &mut self,
+        ctx: Context<'gc>,
+        m: &Match,
+        replacement: String<'gc>,
+    ) -> Result<(), Error<'gc>> {
+        let source = self.source.as_bytes();
+        let mut bytes = replacement.as_bytes().iter();
+        while let Some(&c) = bytes.next() {
+            if c != b'%' {
+                self.result.push(c);
+                continue;
+            }
+
+            match bytes.next() {
+                Some(b'%') => self.result.push(b'%'),
+                Some(b'0') => self.result.extend_from_slice(&source[m.range.clone()]),
+                Some(&d) if d.is_ascii_digit() => {
+                    match m
+                        .capture((d - b'1') as usize)
+                        .map_err(|e| pattern_error(ctx, e))?
+                    {
+                        Capture::Span(r) => self.result.extend_from_slice(&source[r]),
+                        Capture::Position(p) => {
+                            self.result.extend_from_slice(p.to_string().as_bytes())
+                        }
+                    }
+                }
+                _ => {
+                    return Err("invalid use of '%' in replacement string"
+                        .into_value(ctx)
+                        .into())
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'gc> Sequence<'gc> for GSub<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some((start, end)) = self.pending.take() {
+            let value = stack.get(0);
+            stack.clear();
+            self.add_value(ctx, start, end, value)?;
+        }
+
+        let source = self.source.as_bytes();
+        let pattern = Pattern::new(self.pattern.as_bytes());
+        while !self.finished && self.count < self.max_replacements {
+            self.finished = pattern.is_anchored();
+
+            let m = pattern
+                .match_at(source, self.position)
+                .map_err(|e| pattern_error(ctx, e))?;
+            match m {
+                Some(m) if Some(m.range.end) != self.last_match => {
+                    self.count += 1;
+                    self.position = m.range.end;
+                    self.last_match = Some(m.range.end);
+
+                    match self.replacement {
+                        Replacement::String(replacement) => {
+                            self.add_string(ctx, &m, replacement)?;
+                        }
+                        Replacement::Table(table) => {
+                            let key = m.capture(0).map_err(|e| pattern_error(ctx, e))?;
+                            match meta_ops::index(
+                                ctx,
+                                table.into(),
+                                capture_value(ctx, source, key),
+                            )? {
+                                MetaResult::Value(v) => {
+                                    self.add_value(ctx, m.range.start, m.range.end, v)?
+                                }
+                                MetaResult::Call(call) => {
+                                    stack.replace(ctx, Variadic(call.args));
+                                    self.pending = Some((m.range.start, m.range.end));
+                                    return Ok(SequencePoll::Call {
+                                        function: call.function,
+                                        is_tail: false,
+                                    });
+                                }
+                            }
+                        }
+                        Replacement::Function(function) => {
+                            stack.clear();
+                            for i in 0..m.value_count() {
+                                let capture = m.capture(i).map_err(|e| pattern_error(ctx, e))?;
+                                stack.push_back(capture_value(ctx, source, capture));
+                            }
+                            self.pending = Some((m.range.start, m.range.end));
+                            return Ok(SequencePoll::Call {
+                                function,
+                                is_tail: false,
+                            });
+                        }
+                    }
+                }
+                _ if self.position < source.len() => {
+                    self.result.push(source[self.position]);
+                    self.position += 1;
+                }
+                _ => break,
+            }
+        }
+
+        self.result.extend_from_slice(&source[self.position..]);
+        stack.replace(ctx, (String::from_slice(&ctx, &self.result), self.count));
+        Ok(SequencePoll::Return)
+    }
+}
+
+// Finds the first occurrence of `needle` in `haystack` without interpreting any pattern
+// characters, returning its 0-based offset.
+fn find_plain(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let Some((&first, rest)) = needle.split_first() else {
+        return Some(0);
+    };
+    let last_start = haystack.len().checked_sub(needle.len())?;
+
+    // Scan for the first byte of the needle, and only then compare the rest of it.
+    let mut start = 0;
+    while let Some(i) = haystack[start..=last_start]
+        .iter()
+        .position(|&c| c == first)
+    {
+        let candidate = start + i;
+        if haystack[candidate + 1..candidate + needle.len()] == *rest {
+            return Some(candidate);
+        }
+        start = candidate + 1;
+        if start > last_start {
+            break;
+        }
+    }
+    None
+}
+
+fn capture_value<'gc>(ctx: Context<'gc>, source: &[u8], capture: Capture) -> Value<'gc> {
+    match capture {
+        Capture::Span(r) => String::from_slice(&ctx, &source[r]).into(),
+        Capture::Position(p) => Value::Integer(p as i64),
+    }
+}
+
+fn pattern_error<'gc>(ctx: Context<'gc>, error: PatternError) -> Error<'gc> {
+    error.to_string().into_value(ctx).into()
+}
+
+/// Converts a Lua string index into a 1-based position in a string of length `len`.
+///
+/// Positive indices are returned as-is, negative indices count back from the end of the string
+/// (so `-1` is the last byte), and negative indices before the start of the string become `0`.
+///
+/// The result may still lie outside of `[1, len]`; as in PUC-Rio Lua, callers clamp start
+/// positions up to `1` and end positions down to `len`.
+pub(crate) fn relative_index(i: i64, len: usize) -> usize {
+    if i >= 0 {
+        usize::try_from(i).unwrap_or(usize::MAX)
+    } else if i.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len - i.unsigned_abs() as usize + 1
+    }
+}
+
+// String functions accept numbers in place of strings, converting them the same way that
+// concatenation does.
+pub(crate) fn string_arg<'gc>(
+    ctx: Context<'gc>,
+    position: usize,
+    function: &str,
+    value: Value<'gc>,
+) -> Result<String<'gc>, Error<'gc>> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Integer(_) | Value::Number(_) => Ok(String::concat(ctx, &[value])?),
+        value => Err(bad_argument(
+            ctx,
+            position,
+            function,
+            "string",
+            value.type_name(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_plain, relative_index};
+
+    #[test]
+    fn test_find_plain() {
+        assert_eq!(find_plain(b"hello", b""), Some(0));
+        assert_eq!(find_plain(b"", b""), Some(0));
+        assert_eq!(find_plain(b"", b"a"), None);
+        assert_eq!(find_plain(b"hello", b"l"), Some(2));
+        assert_eq!(find_plain(b"hello", b"lo"), Some(3));
+        assert_eq!(find_plain(b"hello", b"hello!"), None);
+        assert_eq!(find_plain(b"aaab", b"aab"), Some(1));
+        assert_eq!(find_plain(b"abab", b"bb"), None);
+    }
+
+    #[test]
+    fn test_relative_index() {
+        // Zero is before the first byte, so starting positions must clamp it up to one.
+        assert_eq!(relative_index(0, 5), 0);
+        assert_eq!(relative_index(0, 0), 0);
+
+        assert_eq!(relative_index(1, 5), 1);
+        assert_eq!(relative_index(-1, 5), 5);
+        assert_eq!(relative_index(-5, 5), 1);
+
+        // Negative indices before the start of the string.
+        assert_eq!(relative_index(-6, 5), 0);
+        assert_eq!(relative_index(i64::MIN, 5), 0);
+        assert_eq!(relative_index(-1, 0), 0);
+
+        // Positive indices past the end of the string are left for the caller to clamp.
+        assert_eq!(relative_index(6, 5), 6);
+        assert_eq!(relative_index(i64::MAX, 5).min(5), 5);
+    }
 }
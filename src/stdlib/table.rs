@@ -1,14 +1,27 @@
-use crate::{AnyCallback, CallbackReturn, Context, Table};
+use std::mem;
+
+use gc_arena::Collect;
+
+use crate::{
+    meta_ops::{self, MetaResult},
+    raw_ops,
+    serialize::serialize,
+    thread::BinaryOperatorError,
+    AnyCallback, AnySequence, CallbackReturn, Context, Error, Fuel, Function, IntoValue, Sequence,
+    SequencePoll, Stack, String, Table, Value,
+};
+
+use super::util::{argument_error, parse_args};
 
 pub fn load_table<'gc>(ctx: Context<'gc>) {
-    let table = Table::new(&ctx);
+    let table = Table::with_instance_hashing(ctx);
 
     table
         .set(
             ctx,
             "pack",
             AnyCallback::from_fn(&ctx, |ctx, _, stack| {
-                let t = Table::new(&ctx);
+                let t = Table::with_instance_hashing(ctx);
                 for i in 0..stack.len() {
                     t.set(ctx, i as i64 + 1, stack[i]).unwrap();
                 }
@@ -41,5 +54,434 @@ pub fn load_table<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    table
+        .set(
+            ctx,
+            "serialize",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let value: Value = stack.consume(ctx)?;
+                let source = serialize(value)?;
+                stack.replace(ctx, String::from_slice(&ctx, source));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "insert",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (table, pos, value) = match stack.len() {
+                    2 => {
+                        let (table, value): (Table, Value) = parse_args(ctx, "insert", stack)?;
+                        (table, None, value)
+                    }
+                    3 => {
+                        let (table, pos, value): (Table, i64, Value) =
+                            parse_args(ctx, "insert", stack)?;
+                        (table, Some(pos), value)
+                    }
+                    _ => {
+                        return Err("wrong number of arguments to 'insert'"
+                            .into_value(ctx)
+                            .into())
+                    }
+                };
+
+                if table.metatable().is_some() {
+                    return Ok(CallbackReturn::Sequence(AnySequence::new(
+                        &ctx,
+                        MetaShift::new(table, ShiftKind::Insert { pos, value }),
+                    )));
+                }
+
+                let pos = insert_position(ctx, table.length(), pos)?;
+                table.insert_array(&ctx, pos, value)?;
+                stack.clear();
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "remove",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (table, pos): (Table, Option<i64>) = parse_args(ctx, "remove", stack)?;
+
+                if table.metatable().is_some() {
+                    return Ok(CallbackReturn::Sequence(AnySequence::new(
+                        &ctx,
+                        MetaShift::new(table, ShiftKind::Remove { pos }),
+                    )));
+                }
+
+                let pos = remove_position(ctx, table.length(), pos)?;
+                stack.replace(ctx, table.remove_array(&ctx, pos)?);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "sort",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (table, comparator): (Table<'gc>, Option<Function<'gc>>) =
+                    stack.consume(ctx)?;
+                let values: Vec<Value<'gc>> = (1..=table.length())
+                    .map(|i| table.get_value(i.into()))
+                    .collect();
+                Ok(CallbackReturn::Sequence(AnySequence::new(
+                    &ctx,
+                    Sort::new(table, comparator, values),
+                )))
+            }),
+        )
+        .unwrap();
+
     ctx.state.globals.set(ctx, "table", table).unwrap();
 }
+
+// The position that `table.insert` inserts at in a table of length `len`, which must be within
+// `[1, len + 1]`.
+fn insert_position<'gc>(ctx: Context<'gc>, len: i64, pos: Option<i64>) -> Result<i64, Error<'gc>> {
+    match pos {
+        Some(pos) if (pos as u64).wrapping_sub(1) >= (len as u64).wrapping_add(1) => {
+            Err(argument_error(ctx, 2, "insert", "position out of bounds"))
+        }
+        Some(pos) => Ok(pos),
+        None => Ok(len.wrapping_add(1)),
+    }
+}
+
+// The position that `table.remove` removes from in a table of length `len`. Besides `[1, len]`, the
+// default position `len` and `len + 1` are accepted.
+fn remove_position<'gc>(ctx: Context<'gc>, len: i64, pos: Option<i64>) -> Result<i64, Error<'gc>> {
+    match pos {
+        Some(pos) if pos != len && (pos as u64).wrapping_sub(1) > len as u64 => {
+            Err(argument_error(ctx, 2, "remove", "position out of bounds"))
+        }
+        Some(pos) => Ok(pos),
+        None => Ok(len),
+    }
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+enum ShiftKind<'gc> {
+    Insert { pos: Option<i64>, value: Value<'gc> },
+    Remove { pos: Option<i64> },
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+enum ShiftState<'gc> {
+    // Waiting for the length of the table.
+    Length,
+    // Waiting for the value that `table.remove` removes, `t[cursor]`.
+    Removed,
+    // Waiting for `t[cursor + step]`, which is then moved to `t[cursor]`.
+    Get,
+    // Waiting for `t[cursor] = value`, after which `cursor` moves on by `step`.
+    Set(Value<'gc>),
+    // Waiting for the final `t[cursor] = last`.
+    Last,
+}
+
+// `table.insert` and `table.remove` for a table with a metatable, which read the length and move
+// the entries one at a time through `__len`, `__index`, and `__newindex`, as PUC-Rio Lua does.
+// Entries are moved from `cursor + step` to `cursor` until `cursor` reaches `end`, and then `last`
+// is stored at `cursor`.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct MetaShift<'gc> {
+    table: Table<'gc>,
+    kind: ShiftKind<'gc>,
+    state: ShiftState<'gc>,
+    cursor: i64,
+    end: i64,
+    step: i64,
+    last: Value<'gc>,
+    removed: Value<'gc>,
+    // Whether the sequence is waiting on the results of a metamethod.
+    calling: bool,
+}
+
+impl<'gc> MetaShift<'gc> {
+    fn new(table: Table<'gc>, kind: ShiftKind<'gc>) -> Self {
+        MetaShift {
+            table,
+            kind,
+            state: ShiftState::Length,
+            cursor: 0,
+            end: 0,
+            step: 0,
+            last: Value::Nil,
+            removed: Value::Nil,
+            calling: false,
+        }
+    }
+
+    fn begin(&mut self, ctx: Context<'gc>, len: i64) -> Result<(), Error<'gc>> {
+        match self.kind {
+            ShiftKind::Insert { pos, value } => {
+                self.end = insert_position(ctx, len, pos)?;
+                self.cursor = len.wrapping_add(1);
+                self.step = -1;
+                self.last = value;
+                self.state = self.next_move();
+            }
+            ShiftKind::Remove { pos } => {
+                self.cursor = remove_position(ctx, len, pos)?;
+                self.end = len;
+                self.step = 1;
+                self.last = Value::Nil;
+                self.state = ShiftState::Removed;
+            }
+        }
+        Ok(())
+    }
+
+    fn next_move(&self) -> ShiftState<'gc> {
+        let more = if self.step < 0 {
+            self.cursor > self.end
+        } else {
+            self.cursor < self.end
+        };
+        if more {
+            ShiftState::Get
+        } else {
+            ShiftState::Last
+        }
+    }
+
+    fn call(&mut self, function: Function<'gc>) -> SequencePoll<'gc> {
+        self.calling = true;
+        SequencePoll::Call {
+            function,
+            is_tail: false,
+        }
+    }
+}
+
+impl<'gc> Sequence<'gc> for MetaShift<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        // The first result of the metamethod that was just called, if any.
+        let mut result = mem::take(&mut self.calling).then(|| stack.get(0));
+        stack.clear();
+
+        loop {
+            match self.state {
+                ShiftState::Length => {
+                    let len = match result.take() {
+                        Some(len) => len,
+                        None => match meta_ops::len(ctx, self.table.into())? {
+                            MetaResult::Value(len) => len,
+                            MetaResult::Call(call) => {
+                                stack.extend(call.args);
+                                return Ok(self.call(call.function));
+                            }
+                        },
+                    };
+                    let Some(len) = len.to_integer() else {
+                        return Err("object length is not an integer".into_value(ctx).into());
+                    };
+                    self.begin(ctx, len)?;
+                }
+                ShiftState::Removed | ShiftState::Get => {
+                    let key = match self.state {
+                        ShiftState::Get => self.cursor + self.step,
+                        _ => self.cursor,
+                    };
+                    let value = match result.take() {
+                        Some(value) => value,
+                        None => match meta_ops::index(ctx, self.table.into(), key.into())? {
+                            MetaResult::Value(value) => value,
+                            MetaResult::Call(call) => {
+                                stack.extend(call.args);
+                                return Ok(self.call(call.function));
+                            }
+                        },
+                    };
+                    self.state = match self.state {
+                        ShiftState::Get => ShiftState::Set(value),
+                        _ => {
+                            self.removed = value;
+                            self.next_move()
+                        }
+                    };
+                }
+                ShiftState::Set(value) => {
+                    if result.take().is_none() {
+                        if let Some(call) =
+                            meta_ops::new_index(ctx, self.table.into(), self.cursor.into(), value)?
+                        {
+                            stack.extend(call.args);
+                            return Ok(self.call(call.function));
+                        }
+                    }
+                    self.cursor += self.step;
+                    self.state = self.next_move();
+                }
+                ShiftState::Last => {
+                    if result.take().is_none() {
+                        if let Some(call) = meta_ops::new_index(
+                            ctx,
+                            self.table.into(),
+                            self.cursor.into(),
+                            self.last,
+                        )? {
+                            stack.extend(call.args);
+                            return Ok(self.call(call.function));
+                        }
+                    }
+                    if let ShiftKind::Remove { .. } = self.kind {
+                        stack.replace(ctx, self.removed);
+                    }
+                    return Ok(SequencePoll::Return);
+                }
+            }
+        }
+    }
+}
+
+// A bottom-up merge sort, written as a state machine so that it can call a Lua comparison function
+// between steps.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Sort<'gc> {
+    table: Table<'gc>,
+    comparator: Option<Function<'gc>>,
+    values: Vec<Value<'gc>>,
+    buffer: Vec<Value<'gc>>,
+    // The length of the already sorted runs being merged.
+    width: usize,
+    // The merge in progress is of `values[start..start + width]` and the following run. `left` and
+    // `right` are the next unmerged positions in each run.
+    start: usize,
+    left: usize,
+    right: usize,
+    // Whether we are waiting on the result of the comparison function.
+    comparing: bool,
+}
+
+impl<'gc> Sort<'gc> {
+    fn new(table: Table<'gc>, comparator: Option<Function<'gc>>, values: Vec<Value<'gc>>) -> Self {
+        let len = values.len();
+        Sort {
+            table,
+            comparator,
+            buffer: values.clone(),
+            values,
+            width: 1,
+            start: 0,
+            left: 0,
+            right: len.min(1),
+            comparing: false,
+        }
+    }
+
+    fn run_end(&self) -> (usize, usize) {
+        let len = self.values.len();
+        (
+            (self.start + self.width).min(len),
+            (self.start + 2 * self.width).min(len),
+        )
+    }
+
+    // Merges as far as possible, returning `(a, b)` when the result of `a < b` is needed to
+    // continue, or `None` once the values are sorted.
+    fn next_comparison(&mut self) -> Option<(Value<'gc>, Value<'gc>)> {
+        let len = self.values.len();
+        loop {
+            if self.width >= len {
+                return None;
+            }
+
+            if self.start >= len {
+                mem::swap(&mut self.values, &mut self.buffer);
+                self.width *= 2;
+                self.start = 0;
+                self.left = 0;
+                self.right = self.width.min(len);
+                continue;
+            }
+
+            let (mid, end) = self.run_end();
+            let out = self.left + self.right - mid;
+            if self.left < mid && self.right < end {
+                return Some((self.values[self.right], self.values[self.left]));
+            } else if self.left < mid {
+                self.buffer[out] = self.values[self.left];
+                self.left += 1;
+            } else if self.right < end {
+                self.buffer[out] = self.values[self.right];
+                self.right += 1;
+            } else {
+                self.start = end;
+                self.left = end;
+                self.right = (end + self.width).min(len);
+            }
+        }
+    }
+
+    // Takes the next value from the right run if it was found to be less than the next value from
+    // the left run, otherwise from the left, which keeps the sort stable.
+    fn merge_next(&mut self, right_less: bool) {
+        let (mid, _) = self.run_end();
+        let out = self.left + self.right - mid;
+        if right_less {
+            self.buffer[out] = self.values[self.right];
+            self.right += 1;
+        } else {
+            self.buffer[out] = self.values[self.left];
+            self.left += 1;
+        }
+    }
+}
+
+impl<'gc> Sequence<'gc> for Sort<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if self.comparing {
+            self.comparing = false;
+            self.merge_next(stack.get(0).to_bool());
+        }
+
+        while let Some((a, b)) = self.next_comparison() {
+            if let Some(comparator) = self.comparator {
+                stack.replace(ctx, (a, b));
+                self.comparing = true;
+                return Ok(SequencePoll::Call {
+                    function: comparator,
+                    is_tail: false,
+                });
+            } else {
+                // Without a comparison function, NaN sorts after every other number. A comparison
+                // function which is not a consistent ordering never causes an error, since this is
+                // a merge sort, but the resulting order is unspecified.
+                let less = raw_ops::sort_less_than(a, b).ok_or(BinaryOperatorError::LessThan)?;
+                self.merge_next(less);
+            }
+        }
+
+        for (i, &v) in self.values.iter().enumerate() {
+            self.table.set(ctx, i as i64 + 1, v)?;
+        }
+        stack.clear();
+        Ok(SequencePoll::Return)
+    }
+}
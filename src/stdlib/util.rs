@@ -0,0 +1,98 @@
+use crate::{Context, Error, FromMultiValue, IntoValue, Stack, TypeError};
+
+/// Produces a Lua style argument error, such as
+/// `bad argument #2 to 'random' (number expected, got string)`.
+pub(crate) fn bad_argument<'gc>(
+    ctx: Context<'gc>,
+    position: usize,
+    function: &str,
+    expected: &str,
+    found: &str,
+) -> Error<'gc> {
+    argument_error(
+        ctx,
+        position,
+        function,
+        &format!("{expected} expected, got {found}"),
+    )
+}
+
+/// Produces a Lua style argument error with a custom message, such as
+/// `bad argument #2 to 'unpack' (data string too short)`.
+pub(crate) fn argument_error<'gc>(
+    ctx: Context<'gc>,
+    position: usize,
+    function: &str,
+    message: &str,
+) -> Error<'gc> {
+    format!("bad argument #{position} to '{function}' ({message})")
+        .into_value(ctx)
+        .into()
+}
+
+/// Consumes all of the arguments in the stack and converts them to the given type.
+///
+/// If conversion fails, returns a `bad_argument` error naming the position of the offending
+/// argument. Arguments past the end of the stack are reported as "no value".
+pub(crate) fn parse_args<'gc, A: FromMultiValue<'gc>>(
+    ctx: Context<'gc>,
+    function: &str,
+    stack: &mut Stack<'gc>,
+) -> Result<A, Error<'gc>> {
+    // Counts every request for a value, including requests past the end of the arguments, so that
+    // when a conversion fails we know which argument was being converted.
+    struct Counted<'a, I> {
+        iter: I,
+        count: &'a mut usize,
+    }
+
+    impl<'a, I: Iterator> Iterator for Counted<'a, I> {
+        type Item = I::Item;
+
+        fn next(&mut self) -> Option<I::Item> {
+            *self.count += 1;
+            self.iter.next()
+        }
+    }
+
+    let len = stack.len();
+    let mut position = 0;
+    let res = A::from_multi_value(
+        ctx,
+        Counted {
+            iter: stack.drain(..),
+            count: &mut position,
+        },
+    );
+
+    res.map_err(|TypeError { expected, found }| {
+        let expected = lua_type_name(expected);
+        if position > len {
+            bad_argument(ctx, position, function, expected, "no value")
+        } else if expected == "number" && found == "number" {
+            // The only way for a number to fail to convert to a number is if we need an integer.
+            argument_error(
+                ctx,
+                position,
+                function,
+                "number has no integer representation",
+            )
+        } else {
+            bad_argument(ctx, position, function, expected, found)
+        }
+    })
+}
+
+// Conversion errors name the expected Rust type, translate these into Lua type names.
+fn lua_type_name(name: &'static str) -> &'static str {
+    match name {
+        "i8" | "u8" | "i16" | "u16" | "i32" | "u32" | "i64" | "u64" | "f32" | "f64" => "number",
+        "Boolean" => "boolean",
+        "String" => "string",
+        "Table" => "table",
+        "Function" | "Closure" | "Callback" => "function",
+        "Thread" => "thread",
+        "UserData" => "userdata",
+        name => name,
+    }
+}
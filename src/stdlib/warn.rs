@@ -0,0 +1,83 @@
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Write},
+};
+
+use gc_arena::{Collect, Gc, Rootable};
+
+use crate::{Context, Singleton};
+
+/// Where the messages of Lua's `warn` function go, and whether they are emitted at all.
+///
+/// As in PUC-Rio Lua, warnings start out disabled. Scripts turn them on and off with the control
+/// messages `warn("@on")` and `warn("@off")`, and hosts with [`Warnings::set_enabled`]. While they
+/// are enabled, each call to `warn` concatenates its arguments into one message and passes it to
+/// the handler, which by default writes `Lua warning: <message>` to stderr.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Warnings<'gc>(Gc<'gc, WarningsState>);
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct WarningsState {
+    enabled: Cell<bool>,
+    handler: RefCell<Box<dyn FnMut(&[u8])>>,
+}
+
+impl<'gc> Singleton<'gc> for Warnings<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        Warnings(Gc::new(
+            &ctx,
+            WarningsState {
+                enabled: Cell::new(false),
+                handler: RefCell::new(Box::new(write_to_stderr)),
+            },
+        ))
+    }
+}
+
+impl<'gc> Warnings<'gc> {
+    /// Returns the warning settings of this Lua instance.
+    pub fn get(ctx: Context<'gc>) -> Self {
+        *ctx.state.registry.singleton::<Rootable![Warnings<'_>]>(ctx)
+    }
+
+    pub fn is_enabled(self) -> bool {
+        self.0.enabled.get()
+    }
+
+    pub fn set_enabled(self, enabled: bool) {
+        self.0.enabled.set(enabled);
+    }
+
+    /// Replaces the handler which receives every emitted warning message.
+    pub fn set_handler(self, handler: impl FnMut(&[u8]) + 'static) {
+        *self.0.handler.borrow_mut() = Box::new(handler);
+    }
+
+    /// Emits a warning message, unless warnings are disabled.
+    ///
+    /// Unlike the `warn` function, this never treats the message as a control message.
+    pub fn warn(self, message: &[u8]) {
+        if self.is_enabled() {
+            (self.0.handler.borrow_mut())(message);
+        }
+    }
+
+    /// Handles a control message, the part of a `warn` message after the leading `@`. Unknown
+    /// control messages are ignored.
+    pub(crate) fn control(self, message: &[u8]) {
+        match message {
+            b"on" => self.set_enabled(true),
+            b"off" => self.set_enabled(false),
+            _ => {}
+        }
+    }
+}
+
+fn write_to_stderr(message: &[u8]) {
+    let mut stderr = io::stderr().lock();
+    let _ = stderr.write_all(b"Lua warning: ");
+    let _ = stderr.write_all(message);
+    let _ = stderr.write_all(b"\n");
+}
@@ -4,7 +4,7 @@ use std::{
     fmt,
     hash::{BuildHasherDefault, Hash, Hasher},
     io::Write,
-    ops, slice,
+    ops, ptr, slice,
     str::{self, Utf8Error},
     string::String as StdString,
 };
@@ -17,7 +17,7 @@ use hashbrown::{hash_map, raw::RawTable, HashMap};
 use rustc_hash::FxHasher;
 use thiserror::Error;
 
-use crate::{Context, Value};
+use crate::{value::write_float, Context, Value};
 
 // Represents `String` as either a pointer to an external / owned slice pointer or a size prefixed
 // inline array.
@@ -128,6 +128,13 @@ impl<'gc> String<'gc> {
         ))
     }
 
+    /// Returns true if `a` and `b` are the same allocation. Equal strings which were not both
+    /// created through the interner may be distinct allocations, so this is not the same as
+    /// equality.
+    pub fn ptr_eq(a: String<'gc>, b: String<'gc>) -> bool {
+        Gc::ptr_eq(a.0, b.0)
+    }
+
     pub fn stored_hash(&self) -> u64 {
         self.0.hash
     }
@@ -194,7 +201,7 @@ impl<'gc> String<'gc> {
                 Value::Nil => write!(&mut bytes, "nil").unwrap(),
                 Value::Boolean(b) => write!(&mut bytes, "{}", b).unwrap(),
                 Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
+                Value::Number(n) => write_float(&mut bytes, *n).unwrap(),
                 Value::String(s) => bytes.extend(s.as_bytes()),
                 Value::Table(_) => return Err(StringError::Concat { bad_type: "table" }),
                 Value::Function(_) => {
@@ -205,7 +212,7 @@ impl<'gc> String<'gc> {
                 Value::Thread(_) => {
                     return Err(StringError::Concat { bad_type: "thread" });
                 }
-                Value::UserData(_) => {
+                Value::UserData(_) | Value::LightUserData(_) => {
                     return Err(StringError::Concat {
                         bad_type: "userdata",
                     });
@@ -239,7 +246,11 @@ where
     T: ?Sized + AsRef<[u8]>,
 {
     fn eq(&self, other: &T) -> bool {
-        self.as_bytes() == other.as_ref()
+        let (a, b) = (self.as_bytes(), other.as_ref());
+        // Strings created through the interner are shared, so comparing a string with itself is
+        // common (such as when looking up a field by a constant key), and is cheap to check before
+        // falling back to comparing bytes.
+        ptr::eq(a, b) || a == b
     }
 }
 
@@ -427,4 +438,20 @@ mod tests {
             assert_eq!(test6.as_bytes(), b"test 666666");
         });
     }
+
+    #[test]
+    fn test_string_eq() {
+        rootless_arena(|mc| {
+            let a = String::from_slice(mc, b"field");
+            let b = String::from_buffer(mc, Box::from(b"field".as_slice()));
+            let c = String::from_static(mc, b"field");
+            let d = String::from_slice(mc, b"fiel");
+
+            assert!(a == a && String::ptr_eq(a, a));
+            assert!(a == b && !String::ptr_eq(a, b));
+            assert!(b == c && a == c);
+            assert!(a != d);
+            assert!(a == *b"field" && a != *b"field!");
+        });
+    }
 }
@@ -1,29 +1,80 @@
 use std::{
+    cmp::Ordering,
+    collections::hash_map::RandomState,
     fmt,
-    hash::{Hash, Hasher},
-    i64, mem,
+    hash::{BuildHasher, Hash, Hasher},
+    i64, iter, mem, ptr,
 };
 
 use allocator_api2::vec;
-use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
+use gc_arena::{
+    allocator_api::MetricsAlloc, lock::RefLock, Collect, Collection, Finalization, Gc, Mutation,
+};
 use hashbrown::{hash_map, HashMap};
 use rustc_hash::FxHasher;
 use thiserror::Error;
 
-use crate::{Context, IntoValue, Value};
+use crate::{
+    constant::float_to_int, Context, Function, IntoValue, MemoryLimit, SizeLimits, String, Value,
+    VmConfig,
+};
 
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct Table<'gc>(pub Gc<'gc, RefLock<TableState<'gc>>>);
 
+/// The error from setting an invalid key in a table.
+///
+/// When this is raised as an error inside Lua (such as by `t[nil] = 1`), scripts which catch it
+/// receive its message as a plain string, as they would in PUC-Rio Lua.
 #[derive(Debug, Copy, Clone, Error)]
 pub enum InvalidTableKey {
-    #[error("table key is NaN")]
+    #[error("table index is NaN")]
     IsNaN,
-    #[error("table key is Nil")]
+    #[error("table index is nil")]
     IsNil,
+    #[error("attempt to modify a read-only table")]
+    ReadOnly,
+    /// Setting the key would grow the table past its maximum length, see [`Table::set_max_len`].
+    #[error("table overflow")]
+    Overflow,
+    /// Setting the key would grow the table past the [`MemoryLimit`] of the Lua instance.
+    #[error("not enough memory")]
+    NotEnoughMemory,
+}
+
+/// The error from changing the metatable or default value of a table that has been made read-only
+/// with [`Table::freeze`].
+#[derive(Debug, Copy, Clone, Error)]
+#[error("attempt to modify a read-only table")]
+pub struct ReadOnlyTable;
+
+/// The error from [`Table::insert_array`] and [`Table::remove_array`].
+#[derive(Debug, Copy, Clone, Error)]
+pub enum ArrayShiftError {
+    #[error(transparent)]
+    ReadOnly(#[from] ReadOnlyTable),
+    #[error(transparent)]
+    InvalidKey(#[from] InvalidTableKey),
+}
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum SetPathError {
+    #[error("table path is empty")]
+    EmptyPath,
+    /// The value at `path[..=index]` exists but is not a table.
+    #[error("value at path element {index} is not a table")]
+    NotATable { index: usize },
+    #[error(transparent)]
+    InvalidKey(#[from] InvalidTableKey),
 }
 
+/// The error from [`Table::next_checked`] when the table changed in a way that makes continuing
+/// the iteration unreliable.
+#[derive(Debug, Copy, Clone, Error)]
+#[error("table modified during iteration (a new key was added or the table was resized)")]
+pub struct ModifiedDuringIteration;
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub enum NextValue<'gc> {
@@ -42,7 +93,7 @@ impl<'gc> Eq for Table<'gc> {}
 
 impl<'gc> Hash for Table<'gc> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.0.as_ptr().hash(state);
+        self.as_ptr().hash(state);
     }
 }
 
@@ -51,18 +102,76 @@ impl<'gc> Table<'gc> {
         Self::from_parts(mc, TableEntries::new(mc), None)
     }
 
+    /// Creates an empty table which hashes its keys with the [`KeyHashing`] setting of the Lua
+    /// instance, like tables created by Lua table constructors do.
+    ///
+    /// The stdlib creates all of its tables this way. Prefer this over [`Table::new`] for tables
+    /// that scripts may add keys to.
+    pub fn with_instance_hashing(ctx: Context<'gc>) -> Table<'gc> {
+        Self::from_parts(&ctx, TableEntries::with_instance_hashing(ctx), None)
+    }
+
+    /// Returns the address of this table, which identifies it for as long as it is alive.
+    ///
+    /// Two handles return the same pointer exactly when they refer to the same table, which is
+    /// also when they are equal with `==` in Lua and with `PartialEq`, so the pointer can key a
+    /// host-side map of tables. An address may be reused once its table has been collected, so
+    /// such a map should only hold tables that are kept alive, for example by stashing them in the
+    /// registry.
+    pub fn as_ptr(&self) -> *const () {
+        Gc::as_ptr(self.0) as *const ()
+    }
+
+    /// Creates an empty table which hashes its keys with the given [`KeyHashing`].
+    ///
+    /// [`Table::new`] always uses [`KeyHashing::Fx`], while [`Table::with_instance_hashing`] uses
+    /// the hashing set for the whole Lua instance.
+    pub fn with_key_hashing(mc: &Mutation<'gc>, hashing: KeyHashing) -> Table<'gc> {
+        Self::from_parts(mc, TableEntries::with_key_hashing(mc, hashing), None)
+    }
+
     pub fn from_parts(
         mc: &Mutation<'gc>,
         entries: TableEntries<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Table<'gc> {
-        Self(Gc::new(mc, RefLock::new(TableState { entries, metatable })))
+        Self(Gc::new(
+            mc,
+            RefLock::new(TableState {
+                entries,
+                metatable,
+                frozen: false,
+                default: Value::Nil,
+                iteration_generation: None,
+            }),
+        ))
     }
 
     pub fn get<K: IntoValue<'gc>>(&self, ctx: Context<'gc>, key: K) -> Value<'gc> {
         self.get_value(key.into_value(ctx))
     }
 
+    /// Looks up several keys at once, borrowing the table only once for all of the lookups.
+    ///
+    /// This is a raw lookup like [`Table::get`], returning the values in the same order as the
+    /// keys, which is convenient for reading a fixed set of fields such as when deserializing a
+    /// struct.
+    pub fn get_many<K: IntoValue<'gc>, const N: usize>(
+        &self,
+        ctx: Context<'gc>,
+        keys: [K; N],
+    ) -> [Value<'gc>; N] {
+        let keys = keys.map(|key| key.into_value(ctx));
+        let state = self.0.borrow();
+        keys.map(|key| state.entries.get(key))
+    }
+
+    /// Looks up a key without collapsing a missing key and an array hole into Nil, see
+    /// [`TableEntries::get_raw`].
+    pub fn get_raw(&self, key: Value<'gc>) -> Option<Value<'gc>> {
+        self.0.borrow().entries.get_raw(key)
+    }
+
     pub fn set<K: IntoValue<'gc>, V: IntoValue<'gc>>(
         &self,
         ctx: Context<'gc>,
@@ -72,6 +181,63 @@ impl<'gc> Table<'gc> {
         self.set_value(&ctx, key.into_value(ctx), value.into_value(ctx))
     }
 
+    /// Looks up a value through a chain of nested tables, so `get_path(ctx, &["a", "b"])` returns
+    /// the value of `self.a.b`, or `Nil` as soon as an intermediate value is not a table.
+    ///
+    /// This is a raw lookup intended for reading plain data such as configuration. It does not call
+    /// `__index` metamethods.
+    pub fn get_path(&self, ctx: Context<'gc>, path: &[&str]) -> Value<'gc> {
+        let mut value = Value::Table(*self);
+        for key in path {
+            match value {
+                Value::Table(t) => value = t.get_value(ctx.intern(key)),
+                _ => return Value::Nil,
+            }
+        }
+        value
+    }
+
+    /// Sets a value through a chain of nested tables, so `set_path(ctx, &["a", "b"], v)` sets
+    /// `self.a.b = v`, creating any missing intermediate tables along the way.
+    ///
+    /// Returns an error without changing anything if an intermediate value is present but is not a
+    /// table. Like [`Table::get_path`], this does not call metamethods.
+    pub fn set_path<V: IntoValue<'gc>>(
+        &self,
+        ctx: Context<'gc>,
+        path: &[&str],
+        value: V,
+    ) -> Result<(), SetPathError> {
+        let Some((leaf, parents)) = path.split_last() else {
+            return Err(SetPathError::EmptyPath);
+        };
+
+        // Check the whole path first, so that nothing is created if it fails part way.
+        let mut table = Some(*self);
+        for (index, key) in parents.iter().enumerate() {
+            table = match table.map(|t| t.get_value(ctx.intern(key))) {
+                Some(Value::Table(t)) => Some(t),
+                Some(Value::Nil) | None => None,
+                Some(_) => return Err(SetPathError::NotATable { index }),
+            };
+        }
+
+        let mut table = *self;
+        for key in parents {
+            let key = ctx.intern(key);
+            table = match table.get_value(key) {
+                Value::Table(t) => t,
+                _ => {
+                    let t = Table::with_instance_hashing(ctx);
+                    table.set_value(&ctx, key, t.into())?;
+                    t
+                }
+            };
+        }
+        table.set_value(&ctx, ctx.intern(leaf), value.into_value(ctx))?;
+        Ok(())
+    }
+
     pub fn get_value(&self, key: Value<'gc>) -> Value<'gc> {
         self.0.borrow().entries.get(key)
     }
@@ -82,7 +248,11 @@ impl<'gc> Table<'gc> {
         key: Value<'gc>,
         value: Value<'gc>,
     ) -> Result<Value<'gc>, InvalidTableKey> {
-        self.0.borrow_mut(&mc).entries.set(key, value)
+        let mut state = self.0.borrow_mut(&mc);
+        if state.frozen {
+            return Err(InvalidTableKey::ReadOnly);
+        }
+        state.entries.set(key, value)
     }
 
     /// Returns a 'border' for this table.
@@ -96,6 +266,42 @@ impl<'gc> Table<'gc> {
         self.0.borrow().entries.length()
     }
 
+    /// Inserts `value` at position `pos` of this table's sequence, shifting the entries from `pos`
+    /// up to [`Table::length`] up by one, as `table.insert(t, pos, value)` does.
+    ///
+    /// This is a raw operation, it does not call metamethods. When the whole sequence is inside the
+    /// array part of the table, the entries are shifted with a single move rather than one `set`
+    /// per entry. Checking that `pos` is within `1..=length + 1` is left to the caller.
+    pub fn insert_array(
+        &self,
+        mc: &Mutation<'gc>,
+        pos: i64,
+        value: Value<'gc>,
+    ) -> Result<(), ArrayShiftError> {
+        let mut state = self.0.borrow_mut(&mc);
+        if state.frozen {
+            return Err(ReadOnlyTable.into());
+        }
+        Ok(state.entries.array_insert(pos, value)?)
+    }
+
+    /// Removes and returns the value at position `pos` of this table's sequence, shifting the
+    /// entries after it down by one, as `table.remove(t, pos)` does.
+    ///
+    /// Like [`Table::insert_array`], this is a raw operation which shifts the entries with a single
+    /// move when the sequence is inside the array part.
+    pub fn remove_array(
+        &self,
+        mc: &Mutation<'gc>,
+        pos: i64,
+    ) -> Result<Value<'gc>, ArrayShiftError> {
+        let mut state = self.0.borrow_mut(&mc);
+        if state.frozen {
+            return Err(ReadOnlyTable.into());
+        }
+        Ok(state.entries.array_remove(pos)?)
+    }
+
     /// Returns the next value after this key in the table order.
     ///
     /// The table order in the map portion of the table is defined by the incidental order of the
@@ -110,31 +316,570 @@ impl<'gc> Table<'gc> {
         self.0.borrow().entries.next(key)
     }
 
+    /// Like [`Table::next`], but detects a table being changed during an iteration in a way that
+    /// may make the iteration skip or repeat keys.
+    ///
+    /// Calling this with Nil starts an iteration. Every later call fails with
+    /// [`ModifiedDuringIteration`] if the table's [generation](TableEntries::generation) changed
+    /// since the iteration started, which happens when a new key is added to its map part or the
+    /// table is resized. Assigning to or clearing existing keys is allowed, as it is in Lua. Only
+    /// the most recently started iteration of a table is tracked.
+    ///
+    /// This is meant for debugging `pairs` loops, and is what `next` uses when the
+    /// [`IterationMode`] is `Checked`.
+    pub fn next_checked(
+        &self,
+        mc: &Mutation<'gc>,
+        key: Value<'gc>,
+    ) -> Result<NextValue<'gc>, ModifiedDuringIteration> {
+        let mut state = self.0.borrow_mut(mc);
+        let generation = state.entries.generation();
+        if key.is_nil() {
+            state.iteration_generation = Some(generation);
+        } else if matches!(state.iteration_generation, Some(start) if start != generation) {
+            return Err(ModifiedDuringIteration);
+        }
+        Ok(state.entries.next(key))
+    }
+
+    /// Returns a copy of every key-value pair in this table, in the same order as `next` would
+    /// produce them.
+    ///
+    /// This takes O(n) time and space in the number of entries. The result is a point-in-time copy
+    /// which does not borrow the table, so the table may be freely modified (such as by Lua
+    /// callbacks) while the copy is being processed, without affecting it.
+    pub fn entries_snapshot(&self) -> Vec<(Value<'gc>, Value<'gc>)> {
+        let state = self.0.borrow();
+        let entries = &state.entries;
+        entries
+            .array
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !v.is_nil())
+            .map(|(i, &v)| (from_array_index(i), v))
+            .chain(entries.map.iter().map(|(&k, &v)| (k, v)))
+            .collect()
+    }
+
+    /// Copies every entry of `other` into this table.
+    ///
+    /// If `overwrite` is true, entries in `other` replace any existing entries with the same key,
+    /// otherwise only keys which are absent from this table are filled in. If `deep` is true and a
+    /// key holds a table in both this table and `other`, the nested tables are merged recursively
+    /// with the same flags instead of the value being replaced. Nested tables which are copied
+    /// over are shared, not cloned.
+    ///
+    /// Like `rawset`, this does not call metamethods. Returns an error if this table, or a nested
+    /// table being merged into, is frozen, in which case the merge may be partially applied.
+    pub fn merge(
+        &self,
+        mc: &Mutation<'gc>,
+        other: Table<'gc>,
+        overwrite: bool,
+        deep: bool,
+    ) -> Result<(), InvalidTableKey> {
+        self.merge_from(mc, other, overwrite, deep, &mut Vec::new())
+    }
+
+    // Tracks every pair of tables already being merged, so that cyclic tables do not recurse
+    // forever.
+    fn merge_from(
+        &self,
+        mc: &Mutation<'gc>,
+        other: Table<'gc>,
+        overwrite: bool,
+        deep: bool,
+        merging: &mut Vec<(Table<'gc>, Table<'gc>)>,
+    ) -> Result<(), InvalidTableKey> {
+        if *self == other || merging.contains(&(*self, other)) {
+            return Ok(());
+        }
+        merging.push((*self, other));
+
+        for (key, value) in other.entries_snapshot() {
+            match (self.get_value(key), value) {
+                (Value::Table(dest), Value::Table(src)) if deep => {
+                    dest.merge_from(mc, src, overwrite, deep, merging)?;
+                }
+                (existing, value) => {
+                    if overwrite || existing.is_nil() {
+                        self.set_value(mc, key, value)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every key-value pair in this table in a deterministic order, independent of the
+    /// order in which the keys were inserted.
+    ///
+    /// Integer keys come first in ascending order, followed by float keys in ascending order, then
+    /// `false` and `true`, then strings ordered by their bytes. Keys which are tables, functions,
+    /// threads, or userdata come last, ordered by type and then by address, so their order is only
+    /// stable within a single run.
+    ///
+    /// Unlike `next`, this must copy and sort every entry, so it takes O(n log n) time and O(n)
+    /// space in the number of entries. As with [`Table::entries_snapshot`], the table may be
+    /// modified during iteration without affecting the result.
+    pub fn iter_sorted(&self) -> std::vec::IntoIter<(Value<'gc>, Value<'gc>)> {
+        let mut entries = self.entries_snapshot();
+        entries.sort_unstable_by(|(a, _), (b, _)| key_order(*a, *b));
+        entries.into_iter()
+    }
+
+    /// Iterates over the sequence `1..=n` of this table, stopping at the first Nil value, in the
+    /// same way that `ipairs` does.
+    ///
+    /// Indexes in the array part of the table are read directly, only indexes past the end of the
+    /// array part require a map lookup. The table is only borrowed while advancing the iterator,
+    /// so it is safe to modify the table during iteration and the iterator will observe the
+    /// changes.
+    pub fn iter_array(&self) -> ArrayIter<'gc> {
+        ArrayIter {
+            table: *self,
+            index: Some(0),
+        }
+    }
+
+    /// Returns a copy of every key-value pair in this table which [`Table::iter_array`] does not
+    /// visit, in the same order as `next` would produce them.
+    ///
+    /// Together the two iterators visit every entry of the table exactly once, which suits
+    /// serializers that write the sequence of a table differently from its other keys. The split
+    /// follows the sequence `1..=n` rather than the internal array part, so integer keys in the
+    /// sequence are never returned here even if they are stored in the map part, and integer keys
+    /// past the first Nil always are. As with [`Table::entries_snapshot`], this takes O(n) time and
+    /// space, and the table may be modified during iteration without affecting the result.
+    pub fn iter_map(&self) -> std::vec::IntoIter<(Value<'gc>, Value<'gc>)> {
+        let state = self.0.borrow();
+        let entries = &state.entries;
+
+        // The length of the sequence that `iter_array` visits.
+        let mut len = 0;
+        while let Some(next) = len.checked_add(1) {
+            if entries.get(Value::Integer(next)).is_nil() {
+                break;
+            }
+            len = next;
+        }
+
+        entries
+            .array
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !v.is_nil())
+            .map(|(i, &v)| (from_array_index(i), v))
+            .chain(entries.map.iter().map(|(&k, &v)| (k, v)))
+            .filter(|(k, _)| !matches!(*k, Value::Integer(i) if (1..=len).contains(&i)))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Moves integer keys from the map part of this table into the array part, if doing so would
+    /// make the array part at least half full.
+    ///
+    /// The table is normally only rebalanced when a new key does not fit, so this is useful after
+    /// filling a table in an order that left integer keys in the map part, such as in reverse.
+    pub fn optimize(&self, mc: &Mutation<'gc>) {
+        self.0.borrow_mut(mc).entries.optimize();
+    }
+
+    /// The length of the array part of this table, including any Nil entries.
+    ///
+    /// This and the other `_part_` methods expose how the table is laid out internally, and are
+    /// only meant for diagnostics and profiling. The split between the array and map parts is an
+    /// implementation detail and may change between versions.
+    pub fn array_part_len(&self) -> usize {
+        self.0.borrow().entries.array_len()
+    }
+
+    /// The number of entries the array part of this table can hold before it is reallocated.
+    pub fn array_part_capacity(&self) -> usize {
+        self.0.borrow().entries.array_capacity()
+    }
+
+    /// The number of entries stored in the map part of this table.
+    pub fn map_part_len(&self) -> usize {
+        self.0.borrow().entries.map_len()
+    }
+
+    /// The number of entries the map part of this table can hold before it is reallocated.
+    ///
+    /// The map part may grow before it is full, depending on the table's [`TableGrowth`].
+    pub fn map_part_capacity(&self) -> usize {
+        self.0.borrow().entries.map_capacity()
+    }
+
+    pub fn growth(&self) -> TableGrowth {
+        self.0.borrow().entries.growth()
+    }
+
+    /// Sets how the map part of this table grows when a new key does not fit, see [`TableGrowth`].
+    ///
+    /// This only affects future growth, the table's current capacity is left unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy is not valid, see [`TableGrowth::is_valid`].
+    pub fn set_growth(&self, mc: &Mutation<'gc>, growth: TableGrowth) {
+        self.0.borrow_mut(mc).entries.set_growth(growth);
+    }
+
+    /// Makes this table read-only.
+    ///
+    /// Every later attempt to set an entry in the table, including through `rawset`, fails with
+    /// `InvalidTableKey::ReadOnly`, and changing its metatable or default value fails with
+    /// [`ReadOnlyTable`]. Reading from and iterating over the table is unaffected. There is no way
+    /// to unfreeze a table.
+    pub fn freeze(&self, mc: &Mutation<'gc>) {
+        self.0.borrow_mut(mc).frozen = true;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.0.borrow().frozen
+    }
+
+    pub fn key_hashing(&self) -> KeyHashing {
+        self.0.borrow().entries.key_hashing()
+    }
+
+    /// Rehashes every key in the map part of this table with `hashing`.
+    pub fn set_key_hashing(&self, mc: &Mutation<'gc>, hashing: KeyHashing) {
+        self.0.borrow_mut(mc).entries.set_key_hashing(hashing);
+    }
+
+    /// Returns which parts of this table's entries are weak, see [`Weakness`].
+    pub fn weakness(&self) -> Weakness {
+        self.0.borrow().entries.weakness()
+    }
+
+    pub(crate) fn set_weakness(&self, mc: &Mutation<'gc>, weakness: Weakness) {
+        self.0.borrow_mut(mc).entries.set_weakness(weakness);
+    }
+
+    pub(crate) fn clear_dead(&self, fc: &Finalization<'gc>) {
+        self.0.borrow_mut(fc).entries.clear_dead(fc);
+    }
+
+    /// Returns the number of entries past which this table refuses to grow.
+    pub fn max_len(&self) -> usize {
+        self.0.borrow().entries.max_len()
+    }
+
+    /// Sets the number of entries past which this table refuses to grow.
+    ///
+    /// Once a table holds `max_len` entries, setting a new key which does not fit in the storage
+    /// the table has already allocated fails with [`InvalidTableKey::Overflow`] ("table overflow"),
+    /// rather than growing the table. Since tables allocate storage ahead of time, a table may hold
+    /// somewhat more than `max_len` entries before this happens. Replacing the value of a key which
+    /// is already in the table always succeeds.
+    ///
+    /// Tables created by Lua table constructors take their limit from
+    /// [`SizeLimits::max_table_len`], other tables default to [`SizeLimits::DEFAULT`].
+    pub fn set_max_len(&self, mc: &Mutation<'gc>, max_len: usize) {
+        self.0.borrow_mut(mc).entries.set_max_len(max_len);
+    }
+
     pub fn metatable(&self) -> Option<Table<'gc>> {
         self.0.borrow().metatable
     }
 
-    pub fn set_metatable(
+    /// Returns the value that indexing this table produces for missing keys, set with
+    /// [`Table::set_default`], or Nil if there is none.
+    pub fn default_value(&self) -> Value<'gc> {
+        self.0.borrow().default
+    }
+
+    /// Sets a value that indexing this table (`t[k]` in Lua, or [`meta_ops::index`]) produces for
+    /// keys that have no entry, returning the previous default. Setting Nil removes the default.
+    ///
+    /// This is a piccolo extension, which behaves like an `__index` function that always returns
+    /// `value`, but without the cost of calling one. It is layered under the normal `__index`
+    /// semantics: the default is only used when the table's metatable has no `__index` field, and
+    /// when this table is reached through the `__index` of another table. Raw accesses like
+    /// [`Table::get`] and `rawget`, as well as `next` and the length operator, ignore it.
+    ///
+    /// Fails with [`ReadOnlyTable`] if the table is frozen.
+    ///
+    /// [`meta_ops::index`]: crate::meta_ops::index
+    pub fn set_default(
         &self,
         mc: &Mutation<'gc>,
+        value: Value<'gc>,
+    ) -> Result<Value<'gc>, ReadOnlyTable> {
+        let mut state = self.0.borrow_mut(mc);
+        if state.frozen {
+            return Err(ReadOnlyTable);
+        }
+        Ok(mem::replace(&mut state.default, value))
+    }
+
+    /// Sets the metatable for this table, returning the previous one.
+    ///
+    /// The table's weakness is set from the `__mode` field of the new metatable, see
+    /// [`WeakTables`](crate::WeakTables). As in PUC-Rio Lua, the mode should be set in the
+    /// metatable before it is assigned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table is frozen, see [`Table::try_set_metatable`].
+    pub fn set_metatable(
+        &self,
+        ctx: Context<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Option<Table<'gc>> {
-        mem::replace(&mut self.0.borrow_mut(mc).metatable, metatable)
+        self.try_set_metatable(ctx, metatable)
+            .expect("cannot set the metatable of a frozen table")
     }
+
+    /// Sets the metatable for this table like [`Table::set_metatable`], but fails with
+    /// [`ReadOnlyTable`] if the table is frozen.
+    pub fn try_set_metatable(
+        &self,
+        ctx: Context<'gc>,
+        metatable: Option<Table<'gc>>,
+    ) -> Result<Option<Table<'gc>>, ReadOnlyTable> {
+        let old = {
+            let mut state = self.0.borrow_mut(&ctx);
+            if state.frozen {
+                return Err(ReadOnlyTable);
+            }
+            mem::replace(&mut state.metatable, metatable)
+        };
+        ctx.state.weak_tables.register(ctx, *self);
+        Ok(old)
+    }
+}
+
+/// Iterator over the sequence part of a table, returned by `Table::iter_array`.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ArrayIter<'gc> {
+    table: Table<'gc>,
+    // The index of the last entry returned, or None once the iterator has finished.
+    index: Option<i64>,
 }
 
+impl<'gc> Iterator for ArrayIter<'gc> {
+    type Item = (i64, Value<'gc>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.index?.checked_add(1).and_then(|i| {
+            let value = self.table.0.borrow().entries.get(Value::Integer(i));
+            (!value.is_nil()).then_some((i, value))
+        });
+        self.index = entry.map(|(i, _)| i);
+        entry
+    }
+}
+
+impl<'gc> iter::FusedIterator for ArrayIter<'gc> {}
+
 #[derive(Debug, Collect)]
 #[collect(no_drop)]
 pub struct TableState<'gc> {
-    pub entries: TableEntries<'gc>,
-    pub metatable: Option<Table<'gc>>,
+    entries: TableEntries<'gc>,
+    metatable: Option<Table<'gc>>,
+    frozen: bool,
+    default: Value<'gc>,
+    // The generation of the entries when the latest `Table::next_checked` iteration started.
+    iteration_generation: Option<u64>,
+}
+
+/// Whether Lua's `next` function, and so `pairs`, checks that the table being iterated over does
+/// not gain new keys during the iteration.
+///
+/// Lua leaves the behavior of `next` unspecified if a new key is assigned during the traversal, so
+/// a `pairs` loop which adds keys to its table may silently skip or repeat keys. With `Checked`,
+/// `next` raises an error instead, see [`Table::next_checked`]. This costs a little time per call,
+/// and is meant for debugging. The mode is a [`Setting`](crate::Setting) of the Lua instance.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub enum IterationMode {
+    #[default]
+    Unchecked,
+    Checked,
+}
+
+/// How a table hashes the keys in its map part.
+///
+/// The default FxHasher is fast, but it is not collision-resistant: a script which chooses its keys
+/// adversarially can make every key land in the same bucket, turning each table access into a
+/// linear scan. When running untrusted scripts that may build large tables, use
+/// [`KeyHashing::random`] so that every Lua instance hashes keys differently and collisions can't
+/// be precomputed.
+///
+/// The hashing only affects performance and the order in which `next` and `pairs` visit the map
+/// part, never which keys are equal.
+///
+/// Tables created by Lua table constructors and by [`Table::with_instance_hashing`] use the hashing
+/// in the [`VmConfig`](crate::VmConfig) of the Lua instance at the time they are created. Changing
+/// the hashing does not affect tables which already exist, except for those that
+/// `Lua::set_key_hashing` rehashes.
+#[derive(Debug, Clone, Default, Collect)]
+#[collect(require_static)]
+pub enum KeyHashing {
+    /// Hash keys with FxHasher, suitable when the scripts are trusted.
+    #[default]
+    Fx,
+    /// Hash keys with the keyed SipHash of the given [`RandomState`]. String keys are hashed by
+    /// their contents rather than by the FxHasher hash cached in every string.
+    Random(RandomState),
+}
+
+impl KeyHashing {
+    /// Returns a [`KeyHashing::Random`] with keys drawn from the operating system's entropy.
+    pub fn random() -> KeyHashing {
+        KeyHashing::Random(RandomState::new())
+    }
+
+    /// Hashes a canonical table key.
+    pub fn hash_key<'gc>(&self, key: Value<'gc>) -> u64 {
+        match self {
+            KeyHashing::Fx => {
+                let mut state = FxHasher::default();
+                write_key(key, &mut state);
+                state.finish()
+            }
+            KeyHashing::Random(random) => {
+                let mut state = random.build_hasher();
+                if let Value::String(s) = key {
+                    Hash::hash(&4, &mut state);
+                    state.write(s.as_bytes());
+                } else {
+                    write_key(key, &mut state);
+                }
+                state.finish()
+            }
+        }
+    }
+}
+
+/// Controls when and by how much the map part of a table grows.
+///
+/// A new key which does not fit in the array part goes in the map part. If the map part already
+/// holds `load_factor` times its capacity, it is first reallocated with room for `growth_factor`
+/// times as many entries as it currently holds, while staying under the load factor. The array
+/// part grows independently of this, whenever doing so would leave it at least half full.
+///
+/// The default policy fills the map part completely and then doubles it. A lower load factor
+/// trades memory for fewer collisions, and a larger growth factor trades memory for fewer
+/// reallocations, which can help when a table's final size is roughly known but cannot be
+/// reserved up front.
+#[derive(Debug, Copy, Clone, PartialEq, Collect)]
+#[collect(require_static)]
+pub struct TableGrowth {
+    /// The fraction of the map part's capacity which is filled before it grows, in `(0, 1]`.
+    pub load_factor: f64,
+    /// How many entries the map part grows by, as a multiple of its current length. Must be
+    /// positive, and the map part always grows by at least one entry.
+    pub growth_factor: f64,
+}
+
+impl TableGrowth {
+    pub const DEFAULT: TableGrowth = TableGrowth {
+        load_factor: 1.0,
+        growth_factor: 1.0,
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.load_factor > 0.0 && self.load_factor <= 1.0 && self.growth_factor > 0.0
+    }
+
+    // The number of entries the map part can hold before it must grow.
+    fn threshold(&self, capacity: usize) -> usize {
+        (capacity as f64 * self.load_factor) as usize
+    }
+
+    // The number of entries to reserve in a map part of length `len` which must grow, with at most
+    // `limit` new entries.
+    fn additional(&self, len: usize, limit: usize) -> usize {
+        let grow = ((len as f64 * self.growth_factor).ceil() as usize)
+            .min(limit)
+            .max(1);
+        ((len + grow) as f64 / self.load_factor).ceil() as usize - len
+    }
+}
+
+impl Default for TableGrowth {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Which parts of a table's entries are weak references, as set by the `__mode` field of its
+/// metatable.
+///
+/// A weak reference does not keep a table, function, thread, or userdata alive, and once the
+/// garbage collector finds that such an object is otherwise unreachable, every entry which refers
+/// to it weakly is removed. As in PUC-Rio Lua, strings are never removed from weak tables.
+///
+/// A table only becomes weak once it is registered with [`WeakTables::register`], which is what
+/// [`Table::set_metatable`] does, see there for the details.
+///
+/// [`WeakTables::register`]: crate::WeakTables::register
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct Weakness {
+    pub keys: bool,
+    pub values: bool,
+}
+
+impl Weakness {
+    /// Parses a `__mode` string, which makes keys weak if it contains a 'k' and values weak if it
+    /// contains a 'v'.
+    pub fn from_mode(mode: &[u8]) -> Weakness {
+        Weakness {
+            keys: mode.contains(&b'k'),
+            values: mode.contains(&b'v'),
+        }
+    }
+
+    pub fn is_weak(self) -> bool {
+        self.keys || self.values
+    }
 }
 
-#[derive(Collect)]
-#[collect(no_drop)]
 pub struct TableEntries<'gc> {
     array: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     map: HashMap<Value<'gc>, Value<'gc>, (), MetricsAlloc<'gc>>,
+    hashing: KeyHashing,
+    max_len: usize,
+    growth: TableGrowth,
+    // Incremented whenever a key is added to the map part, the map part is reallocated, or the
+    // array part grows.
+    generation: u64,
+    weakness: Weakness,
+    // The config of the Lua instance whose memory limit is checked before growing, if any.
+    config: Option<Gc<'gc, VmConfig>>,
+}
+
+// SAFETY: Weak keys and values are not traced, so once marking is done they may refer to objects
+// which are about to be freed. Weakness can only be set by `WeakTables`, which removes every such
+// entry with `TableEntries::clear_dead` before the arena is swept.
+//
+// This relies on `State::prepare_sweep` running every time the arena finishes marking and before
+// it starts sweeping. The arena is private to `Lua`, and every collection goes through
+// `Lua::collect_debt` or `Lua::collect_all`, which always do this.
+unsafe impl<'gc> Collect for TableEntries<'gc> {
+    fn trace(&self, cc: &Collection) {
+        // Strings are always traced, since weak tables never remove them.
+        let trace = |value: &Value<'gc>, weak: bool| {
+            if !weak || matches!(value, Value::String(_)) {
+                value.trace(cc);
+            }
+        };
+
+        for value in self.array.iter() {
+            trace(value, self.weakness.values);
+        }
+        for (key, value) in self.map.iter() {
+            trace(key, self.weakness.keys);
+            trace(value, self.weakness.values);
+        }
+        self.config.trace(cc);
+    }
 }
 
 impl<'gc> fmt::Debug for TableEntries<'gc> {
@@ -144,7 +889,7 @@ impl<'gc> fmt::Debug for TableEntries<'gc> {
                 self.array
                     .iter()
                     .enumerate()
-                    .map(|(i, v)| (Value::Integer(i.try_into().unwrap()), *v))
+                    .map(|(i, v)| (from_array_index(i), *v))
                     .chain(self.map.iter().map(|(&k, &v)| (k, v))),
             )
             .finish()
@@ -153,9 +898,104 @@ impl<'gc> fmt::Debug for TableEntries<'gc> {
 
 impl<'gc> TableEntries<'gc> {
     pub fn new(mc: &Mutation<'gc>) -> Self {
+        Self::with_key_hashing(mc, KeyHashing::Fx)
+    }
+
+    /// Creates empty entries which hash their keys with the [`KeyHashing`] setting of the Lua
+    /// instance, see [`Table::with_instance_hashing`].
+    ///
+    /// These entries also check the [`MemoryLimit`] of the Lua instance before they grow.
+    pub fn with_instance_hashing(ctx: Context<'gc>) -> Self {
+        let mut entries = Self::with_key_hashing(&ctx, ctx.state.config.key_hashing());
+        entries.config = Some(ctx.state.config);
+        entries
+    }
+
+    pub fn with_key_hashing(mc: &Mutation<'gc>, hashing: KeyHashing) -> Self {
         Self {
             array: vec::Vec::new_in(MetricsAlloc::new(mc)),
             map: hash_map::HashMap::with_hasher_in((), MetricsAlloc::new(mc)),
+            hashing,
+            max_len: SizeLimits::DEFAULT.max_table_len,
+            growth: TableGrowth::DEFAULT,
+            generation: 0,
+            weakness: Weakness::default(),
+            config: None,
+        }
+    }
+
+    pub fn key_hashing(&self) -> KeyHashing {
+        self.hashing.clone()
+    }
+
+    /// Rehashes every key in the map part with `hashing`, which may change the order in which
+    /// `next` visits them.
+    pub fn set_key_hashing(&mut self, hashing: KeyHashing) {
+        self.generation += 1;
+        let mut map = HashMap::with_capacity_and_hasher_in(
+            self.map.capacity(),
+            (),
+            self.map.allocator().clone(),
+        );
+        for (key, value) in self.map.drain() {
+            map.raw_table_mut()
+                .insert(hashing.hash_key(key), (key, value), |(k, _)| {
+                    hashing.hash_key(*k)
+                });
+        }
+        self.map = map;
+        self.hashing = hashing;
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    pub fn growth(&self) -> TableGrowth {
+        self.growth
+    }
+
+    /// A counter which changes whenever a key is added to the map part, the map part is
+    /// reallocated, or the array part grows, which are the changes that can reorder the keys
+    /// visited by `next`.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn set_growth(&mut self, growth: TableGrowth) {
+        assert!(growth.is_valid(), "invalid table growth policy {growth:?}");
+        self.growth = growth;
+    }
+
+    pub fn weakness(&self) -> Weakness {
+        self.weakness
+    }
+
+    // Only `WeakTables` may make entries weak, since it is what clears their dead entries.
+    pub(crate) fn set_weakness(&mut self, weakness: Weakness) {
+        self.weakness = weakness;
+    }
+
+    // Removes every entry which refers weakly to an object that the collector has found to be
+    // unreachable. Must be called on every weak table once marking is done, before the arena is
+    // swept.
+    pub(crate) fn clear_dead(&mut self, fc: &Finalization<'gc>) {
+        let weakness = self.weakness;
+        if weakness.values {
+            for value in self.array.iter_mut() {
+                if value.is_dead(fc) {
+                    *value = Value::Nil;
+                }
+            }
+        }
+        if weakness.is_weak() {
+            self.map.retain(|key, value| {
+                !((weakness.keys && key.is_dead(fc)) || (weakness.values && value.is_dead(fc)))
+            });
         }
     }
 
@@ -170,7 +1010,7 @@ impl<'gc> TableEntries<'gc> {
             if let Some((_, value)) = self
                 .map
                 .raw_entry()
-                .from_hash(key_hash(key), |k| key_eq(*k, key))
+                .from_hash(self.hashing.hash_key(key), |k| key_eq(*k, key))
             {
                 *value
             } else {
@@ -181,6 +1021,26 @@ impl<'gc> TableEntries<'gc> {
         }
     }
 
+    /// Like `TableEntries::get`, but distinguishes keys that have no slot in the table at all from
+    /// holes in the array part.
+    ///
+    /// Returns `None` if the key is outside of the array part and not present in the map part, and
+    /// `Some(Value::Nil)` if the key indexes a slot of the array part which holds Nil. Entries in
+    /// the map part are removed when set to Nil, so a present map entry is never Nil.
+    pub fn get_raw(&self, key: Value<'gc>) -> Option<Value<'gc>> {
+        if let Some(index) = to_array_index(key) {
+            if index < self.array.len() {
+                return Some(self.array[index]);
+            }
+        }
+
+        let key = canonical_key(key).ok()?;
+        self.map
+            .raw_entry()
+            .from_hash(self.hashing.hash_key(key), |k| key_eq(*k, key))
+            .map(|(_, &value)| value)
+    }
+
     pub fn set(
         &mut self,
         key: Value<'gc>,
@@ -194,7 +1054,8 @@ impl<'gc> TableEntries<'gc> {
         }
 
         let table_key = canonical_key(key)?;
-        let hash = key_hash(table_key);
+        let hashing = self.hashing.clone();
+        let hash = hashing.hash_key(table_key);
         Ok(if value.is_nil() {
             if let hash_map::RawEntryMut::Occupied(occupied) = self
                 .map
@@ -205,7 +1066,7 @@ impl<'gc> TableEntries<'gc> {
             } else {
                 Value::Nil
             }
-        } else if self.map.len() < self.map.capacity() {
+        } else if self.map.len() < self.growth.threshold(self.map.capacity()) {
             match self
                 .map
                 .raw_entry_mut()
@@ -215,85 +1076,48 @@ impl<'gc> TableEntries<'gc> {
                     mem::replace(occupied.into_mut(), value)
                 }
                 hash_map::RawEntryMut::Vacant(vacant) => {
-                    vacant.insert_with_hasher(hash, table_key, value, |k| key_hash(*k));
+                    vacant.insert_with_hasher(hash, table_key, value, |k| hashing.hash_key(*k));
+                    self.generation += 1;
                     Value::Nil
                 }
             }
         } else {
-            // If a new element does not fit in either the array or map part of the table, we need
-            // to grow. First, we find the total count of array candidate elements across the array
-            // part, the map part, and the newly inserted key.
-
-            const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
-
-            // Count of array-candidate elements based on the highest bit in the index
-            let mut array_counts = [0; USIZE_BITS];
-            // Total count of all array-candidate elements
-            let mut array_total = 0;
-
-            for (i, e) in self.array.iter().enumerate() {
-                if !e.is_nil() {
-                    array_counts[highest_bit(i)] += 1;
-                    array_total += 1;
-                }
-            }
-
-            for &key in self.map.keys() {
-                if let Some(i) = to_array_index(key) {
-                    array_counts[highest_bit(i)] += 1;
-                    array_total += 1;
-                }
+            if let hash_map::RawEntryMut::Occupied(occupied) = self
+                .map
+                .raw_entry_mut()
+                .from_hash(hash, |k| key_eq(*k, table_key))
+            {
+                return Ok(mem::replace(occupied.into_mut(), value));
             }
 
-            if let Some(i) = index_key {
-                array_counts[highest_bit(i)] += 1;
-                array_total += 1;
+            // A new key which does not fit in the table's current storage makes it grow, which is
+            // where the length limit is enforced.
+            let len = self.array.iter().filter(|v| !v.is_nil()).count() + self.map.len();
+            if len >= self.max_len {
+                return Err(InvalidTableKey::Overflow);
             }
 
-            // Then, we compute the new optimal size for the array by finding the largest array size
-            // such that at least half of the elements in the array would be in use.
-
-            let mut optimal_size = 0;
-            let mut total = 0;
-            for i in 0..USIZE_BITS {
-                if (1 << i) / 2 >= array_total {
-                    break;
-                }
-
-                if array_counts[i] > 0 {
-                    total += array_counts[i];
-                    if total > (1 << i) / 2 {
-                        optimal_size = 1 << i;
-                    }
-                }
-            }
+            // If a new element does not fit in either the array or map part of the table, we need
+            // to grow. First, we find the optimal array size counting the array part, the map part,
+            // and the newly inserted key.
+            let optimal_size = self.optimal_array_size(index_key);
 
-            let old_array_size = self.array.len();
             let old_map_size = self.map.len();
-            if optimal_size > old_array_size {
+            if optimal_size > self.array.len() && optimal_size <= self.max_len {
                 // If we're growing the array part, we need to grow the array and take any newly
                 // valid array keys from the map part.
-
-                self.array.reserve(optimal_size - old_array_size);
-                let capacity = self.array.capacity();
-                self.array.resize(capacity, Value::Nil);
-
-                let array = &mut self.array;
-                self.map.retain(|&key, &mut value| {
-                    if let Some(i) = to_array_index(key) {
-                        if i < array.len() {
-                            array[i] = value;
-                            return false;
-                        }
-                    }
-                    true
-                });
+                self.check_growth::<Value<'gc>>(optimal_size - self.array.len())?;
+                self.grow_array(optimal_size);
             } else {
                 // If we aren't growing the array, we're adding a new element to the map that won't
-                // fit in the advertised capacity. We explicitly double the map size here.
+                // fit under its load factor. We explicitly grow the map here, by default doubling
+                // it, unless that would take the table past its length limit.
+                let additional = self.growth.additional(old_map_size, self.max_len - len);
+                self.check_growth::<(Value<'gc>, Value<'gc>)>(additional)?;
+                self.generation += 1;
                 self.map
                     .raw_table_mut()
-                    .reserve(old_map_size, |(key, _)| key_hash(*key));
+                    .reserve(additional, |(key, _)| hashing.hash_key(*key));
             }
 
             // Now we can insert the new key value pair
@@ -311,13 +1135,26 @@ impl<'gc> TableEntries<'gc> {
                     mem::replace(occupied.into_mut(), value)
                 }
                 hash_map::RawEntryMut::Vacant(vacant) => {
-                    vacant.insert_with_hasher(hash, table_key, value, |k| key_hash(*k));
+                    vacant.insert_with_hasher(hash, table_key, value, |k| hashing.hash_key(*k));
+                    self.generation += 1;
                     Value::Nil
                 }
             }
         })
     }
 
+    /// Grows the array part to the optimal size for the keys currently in the table, moving any
+    /// array-candidate keys out of the map part.
+    ///
+    /// This is normally only done when inserting a key that does not fit in the table, so a table
+    /// whose keys were inserted in an unfavorable order may keep integer keys in the map part.
+    pub fn optimize(&mut self) {
+        let optimal_size = self.optimal_array_size(None);
+        if optimal_size > self.array.len() {
+            self.grow_array(optimal_size);
+        }
+    }
+
     pub fn length(&self) -> i64 {
         // Binary search for a border. Entry at max must be Nil, min must be 0 or entry at min must
         // be != Nil.
@@ -350,7 +1187,9 @@ impl<'gc> TableEntries<'gc> {
             while self
                 .map
                 .raw_entry()
-                .from_hash(key_hash(max.into()), |k| key_eq(*k, max.into()))
+                .from_hash(self.hashing.hash_key(max.into()), |k| {
+                    key_eq(*k, max.into())
+                })
                 .is_some()
             {
                 if max == i64::MAX {
@@ -370,13 +1209,58 @@ impl<'gc> TableEntries<'gc> {
             binary_search(min, max, |i| {
                 self.map
                     .raw_entry()
-                    .from_hash(key_hash(i.into()), |k| key_eq(*k, i.into()))
+                    .from_hash(self.hashing.hash_key(i.into()), |k| key_eq(*k, i.into()))
                     .is_none()
             })
         }
     }
 
+    pub fn array_insert(&mut self, pos: i64, value: Value<'gc>) -> Result<(), InvalidTableKey> {
+        let len = self.length();
+        if let Some(start) = to_array_index(pos.into()) {
+            // If the border is inside the array part, the slot after it is a free Nil which the
+            // entries can be shifted into.
+            let end = len as usize;
+            if start <= end && end < self.array.len() {
+                self.array[start..=end].rotate_right(1);
+                self.array[start] = value;
+                return Ok(());
+            }
+        }
+
+        for i in (pos..=len).rev() {
+            let v = self.get(i.into());
+            self.set((i + 1).into(), v)?;
+        }
+        self.set(pos.into(), value)?;
+        Ok(())
+    }
+
+    pub fn array_remove(&mut self, pos: i64) -> Result<Value<'gc>, InvalidTableKey> {
+        let len = self.length();
+        if let Some(start) = to_array_index(pos.into()) {
+            let end = len as usize;
+            if start < end && end <= self.array.len() {
+                let value = mem::replace(&mut self.array[start], Value::Nil);
+                self.array[start..end].rotate_left(1);
+                return Ok(value);
+            }
+        }
+
+        let value = self.get(pos.into());
+        let mut i = pos;
+        while i < len {
+            let next = self.get((i + 1).into());
+            self.set(i.into(), next)?;
+            i += 1;
+        }
+        self.set(i.into(), Value::Nil)?;
+        Ok(value)
+    }
+
     pub fn next(&self, key: Value<'gc>) -> NextValue<'gc> {
+        // Floats with an integral value are found as the equal integer key in both the array and
+        // the map part, and the returned key is always the integer.
         let array_result = if let Some(index_key) = to_array_index(key) {
             if index_key < self.array.len() {
                 Some((index_key + 1, self.array[index_key].is_nil()))
@@ -396,7 +1280,7 @@ impl<'gc> TableEntries<'gc> {
             for i in start_index..self.array.len() {
                 if !self.array[i].is_nil() {
                     return NextValue::Found {
-                        key: Value::Integer((i + 1).try_into().unwrap()),
+                        key: from_array_index(i),
                         value: self.array[i],
                     };
                 }
@@ -419,9 +1303,9 @@ impl<'gc> TableEntries<'gc> {
         }
 
         if let Ok(table_key) = canonical_key(key) {
-            if let Some(bucket) =
-                raw_table.find(key_hash(table_key), |(k, _)| key_eq(*k, table_key))
-            {
+            if let Some(bucket) = raw_table.find(self.hashing.hash_key(table_key), |(k, _)| {
+                key_eq(*k, table_key)
+            }) {
                 unsafe {
                     let bucket_index = raw_table.bucket_index(&bucket);
                     for i in bucket_index + 1..raw_table.buckets() {
@@ -438,18 +1322,118 @@ impl<'gc> TableEntries<'gc> {
         NextValue::NotFound
     }
 
+    // Finds the optimal size for the array part, counting the array-candidate entries across the
+    // array part, the map part, and the given new key (if any).
+    //
+    // This is the largest power of two size such that more than half of the array would be in use.
+    fn optimal_array_size(&self, new_key: Option<usize>) -> usize {
+        const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
+
+        // Count of array-candidate elements based on the highest bit in the index
+        let mut array_counts = [0; USIZE_BITS];
+        // Total count of all array-candidate elements
+        let mut array_total = 0;
+
+        for (i, e) in self.array.iter().enumerate() {
+            if !e.is_nil() {
+                array_counts[highest_bit(i)] += 1;
+                array_total += 1;
+            }
+        }
+
+        for &key in self.map.keys() {
+            if let Some(i) = to_array_index(key) {
+                array_counts[highest_bit(i)] += 1;
+                array_total += 1;
+            }
+        }
+
+        if let Some(i) = new_key {
+            array_counts[highest_bit(i)] += 1;
+            array_total += 1;
+        }
+
+        let mut optimal_size = 0;
+        let mut total = 0;
+        for i in 0..USIZE_BITS {
+            if (1 << i) / 2 >= array_total {
+                break;
+            }
+
+            if array_counts[i] > 0 {
+                total += array_counts[i];
+                if total > (1 << i) / 2 {
+                    optimal_size = 1 << i;
+                }
+            }
+        }
+
+        optimal_size
+    }
+
+    // Returns an error if allocating `additional` more elements of type `T` would take the Lua
+    // instance past its memory limit.
+    fn check_growth<T>(&self, additional: usize) -> Result<(), InvalidTableKey> {
+        match self.config {
+            Some(config) => MemoryLimit::check_allocation_in(
+                &config,
+                additional.saturating_mul(mem::size_of::<T>()),
+            )
+            .map_err(|_| InvalidTableKey::NotEnoughMemory),
+            None => Ok(()),
+        }
+    }
+
+    // Grows the array part to at least the given size and moves every entry in the map part that
+    // now fits into the array part.
+    fn grow_array(&mut self, size: usize) {
+        self.generation += 1;
+        self.array.reserve(size.saturating_sub(self.array.len()));
+        let capacity = self.array.capacity();
+        self.array.resize(capacity, Value::Nil);
+
+        let array = &mut self.array;
+        self.map.retain(|&key, &mut value| {
+            if let Some(i) = to_array_index(key) {
+                if i < array.len() {
+                    array[i] = value;
+                    return false;
+                }
+            }
+            true
+        });
+    }
+
+    pub fn array_len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn array_capacity(&self) -> usize {
+        self.array.capacity()
+    }
+
+    pub fn map_len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn map_capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
     pub fn reserve_array(&mut self, additional: usize) {
         self.array.reserve(additional);
     }
 
     pub fn reserve_map(&mut self, additional: usize) {
+        self.generation += 1;
+        let hashing = self.hashing.clone();
         self.map
             .raw_table_mut()
-            .reserve(additional, |(k, _)| key_hash(*k));
+            .reserve(additional, |(k, _)| hashing.hash_key(*k));
     }
 }
 
-fn canonical_key<'gc>(value: Value<'gc>) -> Result<Value<'gc>, InvalidTableKey> {
+pub(crate) fn canonical_key<'gc>(value: Value<'gc>) -> Result<Value<'gc>, InvalidTableKey> {
     match value {
         Value::Nil => Err(InvalidTableKey::IsNil),
         Value::Number(n) => {
@@ -457,7 +1441,7 @@ fn canonical_key<'gc>(value: Value<'gc>) -> Result<Value<'gc>, InvalidTableKey>
             // to themselves when cast back to f64 are considered integer keys.
             if n.is_nan() {
                 Err(InvalidTableKey::IsNaN)
-            } else if let Some(i) = f64_to_i64(n) {
+            } else if let Some(i) = float_to_int(n) {
                 Ok(Value::Integer(i))
             } else {
                 Ok(Value::Number(n))
@@ -473,63 +1457,99 @@ fn key_eq<'gc>(a: Value<'gc>, b: Value<'gc>) -> bool {
         (Value::Boolean(a), Value::Boolean(b)) => a == b,
         (Value::Integer(a), Value::Integer(b)) => a == b,
         (Value::Number(a), Value::Number(b)) => a == b,
-        (Value::String(a), Value::String(b)) => a == b,
+        // Distinct strings almost always have distinct hashes, which are stored in the string and
+        // so are cheaper to compare than the contents.
+        (Value::String(a), Value::String(b)) => {
+            String::ptr_eq(a, b) || (a.stored_hash() == b.stored_hash() && a == b)
+        }
         (Value::Table(a), Value::Table(b)) => a == b,
         (Value::Function(a), Value::Function(b)) => a == b,
         (Value::Thread(a), Value::Thread(b)) => a == b,
         (Value::UserData(a), Value::UserData(b)) => a == b,
+        (Value::LightUserData(a), Value::LightUserData(b)) => a == b,
         _ => false,
     }
 }
 
-fn key_hash<'gc>(value: Value<'gc>) -> u64 {
-    let mut state = FxHasher::default();
+// A total order over canonical table keys, used by `Table::iter_sorted`.
+fn key_order<'gc>(a: Value<'gc>, b: Value<'gc>) -> Ordering {
+    fn rank(value: Value) -> u8 {
+        match value {
+            Value::Nil => 0,
+            Value::Integer(_) => 1,
+            Value::Number(_) => 2,
+            Value::Boolean(_) => 3,
+            Value::String(_) => 4,
+            Value::Table(_) => 5,
+            Value::Function(_) => 6,
+            Value::Thread(_) => 7,
+            Value::UserData(_) => 8,
+            Value::LightUserData(_) => 9,
+        }
+    }
+
+    fn address(value: Value) -> *const () {
+        match value {
+            Value::Table(t) => Gc::as_ptr(t.0) as *const (),
+            Value::Function(Function::Closure(c)) => Gc::as_ptr(c.0) as *const (),
+            Value::Function(Function::Callback(c)) => c.as_ptr(),
+            Value::Thread(t) => Gc::as_ptr(t.0) as *const (),
+            Value::UserData(u) => u.as_ptr(),
+            Value::LightUserData(p) => p as *const (),
+            _ => ptr::null(),
+        }
+    }
+
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(&b),
+        (Value::Number(a), Value::Number(b)) => a.total_cmp(&b),
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(&b),
+        (Value::String(a), Value::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (a, b) => rank(a)
+            .cmp(&rank(b))
+            .then_with(|| address(a).cmp(&address(b))),
+    }
+}
+
+fn write_key<'gc, H: Hasher>(value: Value<'gc>, state: &mut H) {
     match value {
-        Value::Nil => Hash::hash(&0, &mut state),
+        Value::Nil => Hash::hash(&0, state),
         Value::Boolean(b) => {
-            Hash::hash(&1, &mut state);
-            b.hash(&mut state);
+            Hash::hash(&1, state);
+            b.hash(state);
         }
         Value::Integer(i) => {
-            Hash::hash(&2, &mut state);
-            i.hash(&mut state);
+            Hash::hash(&2, state);
+            i.hash(state);
         }
         Value::Number(n) => {
-            Hash::hash(&3, &mut state);
-            canonical_float_bytes(n).hash(&mut state);
+            Hash::hash(&3, state);
+            canonical_float_bytes(n).hash(state);
         }
         Value::String(s) => {
-            Hash::hash(&4, &mut state);
-            s.hash(&mut state);
+            Hash::hash(&4, state);
+            s.hash(state);
         }
         Value::Table(t) => {
-            Hash::hash(&5, &mut state);
-            t.hash(&mut state);
+            Hash::hash(&5, state);
+            t.hash(state);
         }
         Value::Function(c) => {
-            Hash::hash(&6, &mut state);
-            c.hash(&mut state);
+            Hash::hash(&6, state);
+            c.hash(state);
         }
         Value::Thread(t) => {
-            Hash::hash(&7, &mut state);
-            t.hash(&mut state);
+            Hash::hash(&7, state);
+            t.hash(state);
         }
         Value::UserData(u) => {
-            Hash::hash(&8, &mut state);
-            u.hash(&mut state);
+            Hash::hash(&8, state);
+            u.hash(state);
+        }
+        Value::LightUserData(p) => {
+            Hash::hash(&9, state);
+            p.hash(state);
         }
-    }
-    state.finish()
-}
-
-// Returns the closest i64 to a given f64 such that casting the i64 back to an f64 results in an
-// equal value, if such an integer exists.
-fn f64_to_i64(n: f64) -> Option<i64> {
-    let i = n as i64;
-    if i as f64 == n {
-        Some(i)
-    } else {
-        None
     }
 }
 
@@ -549,7 +1569,7 @@ fn canonical_float_bytes(f: f64) -> u64 {
 fn to_array_index<'gc>(key: Value<'gc>) -> Option<usize> {
     let i = match key {
         Value::Integer(i) => i,
-        Value::Number(f) => f64_to_i64(f)?,
+        Value::Number(f) => float_to_int(f)?,
         _ => return None,
     };
 
@@ -560,6 +1580,14 @@ fn to_array_index<'gc>(key: Value<'gc>) -> Option<usize> {
     }
 }
 
+// Returns the integer key for the given index into the array part of a table.
+//
+// The array part is a `Vec` of values, which can never hold more than `isize::MAX` bytes, so the
+// index is always far below `i64::MAX` and `index + 1` can neither overflow nor be truncated.
+fn from_array_index<'gc>(index: usize) -> Value<'gc> {
+    Value::Integer(index as i64 + 1)
+}
+
 // Returns the place of the highest set bit in the given i, i = 0 returns 0, i = 1 returns 1, i = 2
 // returns 2, i = 3 returns 2, and so on.
 fn highest_bit(mut i: usize) -> usize {
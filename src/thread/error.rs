@@ -36,8 +36,26 @@ pub enum BinaryOperatorError {
     LessThan,
     #[error("cannot compare values with <=")]
     LessEqual,
+    #[error("attempt to perform arithmetic on a string value")]
+    ArithmeticOnString,
+    #[error("attempt to perform 'n//0'")]
+    IntegerDivideByZero,
+    #[error("attempt to perform 'n%0'")]
+    IntegerModuloByZero,
+    #[error("attempt to divide by zero")]
+    FloatDivideByZero,
+    #[error("integer overflow")]
+    IntegerOverflow,
 }
 
+/// The error raised in a thread whose call stack grows past [`CallDepthLimit`] frames, usually
+/// because of unbounded recursion.
+///
+/// [`CallDepthLimit`]: crate::CallDepthLimit
+#[derive(Debug, Copy, Clone, Error)]
+#[error("stack overflow")]
+pub struct StackOverflow;
+
 #[derive(Debug, Copy, Clone, Error)]
 #[error("bad thread mode: {found:?}{}", if let Some(expected) = *.expected {
         format!(", expected {:?}", expected)
@@ -61,4 +79,8 @@ pub enum VMError {
     BadType(#[from] TypeError),
     #[error("_ENV upvalue is only allowed on top-level closure")]
     BadEnvUpValue,
+    #[error("'for' step is zero")]
+    ForStepZero,
+    #[error("'for' {0} must be a number")]
+    ForNotNumber(&'static str),
 }
@@ -0,0 +1,171 @@
+use gc_arena::Collect;
+
+use crate::{compiler::LineNumber, opcode::Operation, Closure, Function};
+
+/// The set of events that a [`Hook`] is called for.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct HookMask {
+    /// Call the hook when a Lua function is entered.
+    pub call: bool,
+    /// Call the hook when a Lua function is about to return.
+    pub ret: bool,
+    /// Call the hook when a Lua function is about to execute a new line of code, or jumps
+    /// backwards (even to the same line).
+    pub line: bool,
+}
+
+/// A debug hook installed on a thread with [`Thread::set_hook`](crate::Thread::set_hook).
+///
+/// The hook function is called with the event name (`"call"`, `"return"`, `"line"`, or `"count"`)
+/// and, for line events, the new line number. Hooks are only called for Lua functions, and are
+/// not called while a hook function is itself running.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Hook<'gc> {
+    pub function: Function<'gc>,
+    pub mask: HookMask,
+    /// If non-zero, the hook is also called with a `"count"` event once every `count` instructions.
+    pub count: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum HookEvent {
+    Call,
+    Return,
+    Line(LineNumber),
+    Count,
+}
+
+impl HookEvent {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            HookEvent::Call => "call",
+            HookEvent::Return => "return",
+            HookEvent::Line(_) => "line",
+            HookEvent::Count => "count",
+        }
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+pub(crate) struct HookState<'gc> {
+    pub(crate) hook: Hook<'gc>,
+    // The number of instructions run since the last count event.
+    counter: u32,
+    // The frame index, closure, and pc of the last instruction that ran with hooks enabled.
+    last: Option<(usize, Closure<'gc>, usize)>,
+    // The number of events for the upcoming instruction that have already been delivered. Each
+    // event is delivered by calling the hook and then re-entering the VM at the same instruction,
+    // so this is how we know which event comes next.
+    delivered: usize,
+    // While a hook function is running, the index of the frame that it was called from.
+    calling_frame: Option<usize>,
+    // The frame index and pc of an instruction which has already run and will be run again, such
+    // as a `Return` that first closes a to-be-closed variable.
+    rerun: Option<(usize, usize)>,
+}
+
+impl<'gc> HookState<'gc> {
+    pub(crate) fn new(hook: Hook<'gc>) -> Self {
+        HookState {
+            hook,
+            counter: 0,
+            last: None,
+            delivered: 0,
+            calling_frame: None,
+            rerun: None,
+        }
+    }
+
+    // Called when the instruction at `pc` in the Lua frame at `frame_index` will be run again. Its
+    // events have already been delivered, so none are delivered when it runs again.
+    pub(crate) fn rerun(&mut self, frame_index: usize, pc: usize) {
+        self.rerun = Some((frame_index, pc));
+    }
+
+    // Called before the instruction at `pc` in the Lua frame at `frame_index` is executed. Returns
+    // the next event that the hook should be called for, or `None` if the instruction should now
+    // run.
+    pub(crate) fn next_event(
+        &mut self,
+        frame_index: usize,
+        closure: Closure<'gc>,
+        pc: usize,
+        is_variable: bool,
+    ) -> Option<HookEvent> {
+        if let Some(calling_frame) = self.calling_frame {
+            if frame_index > calling_frame {
+                return None;
+            }
+            self.calling_frame = None;
+        }
+
+        if self.rerun == Some((frame_index, pc)) {
+            self.rerun = None;
+            self.last = Some((frame_index, closure, pc));
+            return None;
+        }
+
+        let proto = &closure.0.proto;
+        let mask = self.hook.mask;
+
+        // The previous instruction run in this frame. If we have returned to this frame from
+        // another, this is the calling instruction.
+        let prev = match self.last {
+            Some((f, c, last_pc)) if f == frame_index && c == closure => Some(last_pc),
+            _ => pc.checked_sub(1),
+        };
+
+        let events = [
+            (mask.call && prev.is_none()).then_some(HookEvent::Call),
+            (self.hook.count != 0 && self.counter + 1 >= self.hook.count)
+                .then_some(HookEvent::Count),
+            match (mask.line, prev) {
+                (false, _) => None,
+                (true, Some(prev))
+                    if pc > prev && proto.line_number(pc) == proto.line_number(prev) =>
+                {
+                    None
+                }
+                (true, _) => proto.line_number(pc).map(HookEvent::Line),
+            },
+            (mask.ret && matches!(proto.opcodes[pc].decode(), Operation::Return { .. }))
+                .then_some(HookEvent::Return),
+        ];
+
+        // A hook cannot be called while the frame holds a variable number of values on the top of
+        // the stack, so any events for such an instruction are dropped.
+        let event = if is_variable {
+            None
+        } else {
+            events.into_iter().flatten().nth(self.delivered)
+        };
+
+        if let Some(event) = event {
+            self.delivered += 1;
+            self.calling_frame = Some(frame_index);
+            Some(event)
+        } else {
+            self.delivered = 0;
+            self.last = Some((frame_index, closure, pc));
+            if self.hook.count != 0 {
+                self.counter = (self.counter + 1) % self.hook.count;
+            }
+            None
+        }
+    }
+
+    // Called when the thread unwinds to `frame_count` frames after an error, which abandons any
+    // hook that was running above that point.
+    pub(crate) fn unwound(&mut self, frame_count: usize) {
+        if self.calling_frame.is_some_and(|f| f >= frame_count) {
+            self.calling_frame = None;
+            self.delivered = 0;
+        }
+        if self.rerun.is_some_and(|(f, _)| f >= frame_count) {
+            self.rerun = None;
+        }
+    }
+}
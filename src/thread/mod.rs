@@ -1,10 +1,12 @@
 mod error;
+mod hook;
 mod thread;
 mod vm;
 
 pub use self::{
-    error::{BadThreadMode, BinaryOperatorError, VMError},
-    thread::{Thread, ThreadMode},
+    error::{BadThreadMode, BinaryOperatorError, StackOverflow, VMError},
+    hook::{Hook, HookMask},
+    thread::{BacktraceFrame, CallDepthLimit, Thread, ThreadMode, DEFAULT_CALL_DEPTH_LIMIT},
 };
 
-pub(crate) use self::{thread::LuaFrame, vm::run_vm};
+pub(crate) use self::{hook::HookEvent, thread::LuaFrame, vm::run_vm};
@@ -1,4 +1,5 @@
 use std::{
+    cell::{Cell, RefCell},
     fmt::{self, Debug},
     hash::{Hash, Hasher},
     mem,
@@ -8,7 +9,7 @@ use allocator_api2::vec;
 use gc_arena::{
     allocator_api::MetricsAlloc,
     lock::{Lock, RefLock},
-    Collect, Gc, Mutation,
+    Collect, Gc, Mutation, Rootable,
 };
 
 use crate::{
@@ -16,10 +17,14 @@ use crate::{
     meta_ops,
     types::{RegisterIndex, VarCount},
     AnyCallback, AnySequence, BadThreadMode, CallbackReturn, Closure, Context, Error,
-    FromMultiValue, Fuel, Function, IntoMultiValue, SequencePoll, Stack, TypeError, VMError, Value,
+    FromMultiValue, Fuel, Function, IntoMultiValue, MemoryLimit, Sequence, SequencePoll, Singleton,
+    Stack, StackOverflow, String, TypeError, VMError, Value,
 };
 
-use super::run_vm;
+use super::{
+    hook::{Hook, HookEvent, HookState},
+    run_vm,
+};
 
 #[derive(Clone, Copy, Collect)]
 #[collect(no_drop)]
@@ -62,6 +67,115 @@ pub enum ThreadMode {
     Suspended,
 }
 
+/// A single frame of a thread's call stack, as returned by [`Thread::backtrace`].
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub enum BacktraceFrame<'gc> {
+    /// A Lua function, along with the index of the opcode that it is currently executing.
+    Lua { closure: Closure<'gc>, pc: usize },
+    /// A Rust callback or sequence.
+    Callback,
+}
+
+// The stack of threads that are currently inside `Thread::step`, innermost last.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct RunningThreads<'gc>(Gc<'gc, RefLock<vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>>>);
+
+impl<'gc> Singleton<'gc> for RunningThreads<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        RunningThreads(Gc::new(
+            &ctx,
+            RefLock::new(vec::Vec::new_in(MetricsAlloc::new(&ctx))),
+        ))
+    }
+}
+
+impl<'gc> RunningThreads<'gc> {
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.state
+            .registry
+            .singleton::<Rootable![RunningThreads<'_>]>(ctx)
+    }
+}
+
+/// The default limit on the number of frames in a thread's call stack.
+pub const DEFAULT_CALL_DEPTH_LIMIT: usize = 200_000;
+
+/// The maximum number of frames that the call stack of any thread in this Lua instance may hold.
+/// It is held in the [`VmConfig`](crate::VmConfig) and defaults to [`DEFAULT_CALL_DEPTH_LIMIT`].
+///
+/// Calls do not recurse on the native stack, so deep recursion in Lua cannot crash the process,
+/// but without a limit unbounded recursion would grow the call stack until memory runs out.
+/// Instead, `Thread::step` raises a [`StackOverflow`] error in a thread once a new frame takes its
+/// call stack past this limit, which can be caught with `pcall`. Every Lua function, callback, and
+/// pending `Sequence` counts as one frame, tail calls do not add a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[collect(require_static)]
+pub struct CallDepthLimit(pub usize);
+
+impl Default for CallDepthLimit {
+    fn default() -> Self {
+        CallDepthLimit(DEFAULT_CALL_DEPTH_LIMIT)
+    }
+}
+
+type UncaughtErrorFn = Box<dyn for<'gc> FnMut(Context<'gc>, &Error<'gc>, &[BacktraceFrame<'gc>])>;
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct UncaughtErrorHandler<'gc>(Gc<'gc, UncaughtErrorHandlerState>);
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct UncaughtErrorHandlerState {
+    handler: RefCell<Option<UncaughtErrorFn>>,
+    // Set whenever the handler is set or cleared, so that a handler which replaces itself is not
+    // put back once it returns.
+    replaced: Cell<bool>,
+}
+
+impl<'gc> Singleton<'gc> for UncaughtErrorHandler<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        UncaughtErrorHandler(Gc::new(
+            &ctx,
+            UncaughtErrorHandlerState {
+                handler: RefCell::new(None),
+                replaced: Cell::new(false),
+            },
+        ))
+    }
+}
+
+impl<'gc> UncaughtErrorHandler<'gc> {
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.state
+            .registry
+            .singleton::<Rootable![UncaughtErrorHandler<'_>]>(ctx)
+    }
+
+    fn is_set(self) -> bool {
+        self.0.handler.borrow().is_some()
+    }
+
+    fn set(self, handler: Option<UncaughtErrorFn>) {
+        *self.0.handler.borrow_mut() = handler;
+        self.0.replaced.set(true);
+    }
+
+    // The handler is taken out of its cell while it runs, so that it may replace or clear itself.
+    fn call(self, ctx: Context<'gc>, error: &Error<'gc>, backtrace: &[BacktraceFrame<'gc>]) {
+        let Some(mut handler) = self.0.handler.borrow_mut().take() else {
+            return;
+        };
+        self.0.replaced.set(false);
+        handler(ctx, error, backtrace);
+        if !self.0.replaced.get() {
+            *self.0.handler.borrow_mut() = Some(handler);
+        }
+    }
+}
+
 impl<'gc> Thread<'gc> {
     pub fn new(mc: &Mutation<'gc>) -> Thread<'gc> {
         Thread(Gc::new(
@@ -70,8 +184,12 @@ impl<'gc> Thread<'gc> {
                 stack: vec::Vec::new_in(MetricsAlloc::new(mc)),
                 frames: vec::Vec::new_in(MetricsAlloc::new(mc)),
                 open_upvalues: vec::Vec::new_in(MetricsAlloc::new(mc)),
+                to_be_closed: vec::Vec::new_in(MetricsAlloc::new(mc)),
                 external_stack: Stack::new(mc),
                 error: None,
+                error_backtrace: None,
+                closing: vec::Vec::new_in(MetricsAlloc::new(mc)),
+                hook: None,
             }),
         ))
     }
@@ -80,6 +198,95 @@ impl<'gc> Thread<'gc> {
         self.0.borrow().mode()
     }
 
+    /// Returns the name and value of a local variable in an active Lua frame of this thread.
+    ///
+    /// `level` is an index into the frames returned by [`Thread::backtrace`], and `index` counts
+    /// the local variables in scope at the frame's current instruction, in declaration order.
+    /// Returns `None` if there is no such frame or variable.
+    pub fn get_local(self, level: usize, index: usize) -> Option<(String<'gc>, Value<'gc>)> {
+        let state = self.0.borrow();
+        let (closure, pc, base) = state.lua_frame_at(level)?;
+        let local = closure.0.proto.active_locals(pc).nth(index)?;
+        Some((local.name, state.stack[base + local.register.0 as usize]))
+    }
+
+    /// Sets the value of a local variable in an active Lua frame of this thread, returning the
+    /// variable's name.
+    ///
+    /// Frames and variables are found in the same way as [`Thread::get_local`].
+    pub fn set_local(
+        self,
+        mc: &Mutation<'gc>,
+        level: usize,
+        index: usize,
+        value: Value<'gc>,
+    ) -> Option<String<'gc>> {
+        let mut state = self.0.borrow_mut(mc);
+        let (closure, pc, base) = state.lua_frame_at(level)?;
+        let local = closure.0.proto.active_locals(pc).nth(index)?;
+        state.stack[base + local.register.0 as usize] = value;
+        Some(local.name)
+    }
+
+    // Access a value on this thread's stack by absolute index, used by open upvalues.
+    pub(crate) fn stack_value(self, index: usize) -> Value<'gc> {
+        self.0.borrow().stack[index]
+    }
+
+    pub(crate) fn set_stack_value(self, mc: &Mutation<'gc>, index: usize, value: Value<'gc>) {
+        self.0.borrow_mut(mc).stack[index] = value;
+    }
+
+    /// Installs a debug hook on this thread, replacing any previous hook, or removes the hook if
+    /// `hook` is `None`.
+    pub fn set_hook(self, mc: &Mutation<'gc>, hook: Option<Hook<'gc>>) {
+        self.0.borrow_mut(mc).hook = hook.map(HookState::new);
+    }
+
+    /// Returns the debug hook installed on this thread, if any.
+    pub fn hook(self) -> Option<Hook<'gc>> {
+        self.0.borrow().hook.as_ref().map(|h| h.hook)
+    }
+
+    /// Sets a handler which is called with every error that is raised in a top-level thread and
+    /// which nothing will catch, replacing any previous handler.
+    ///
+    /// A top-level thread is one that is stepped directly, rather than from within another thread
+    /// as a coroutine is. An error is uncaught once there is no pending `Sequence` (such as the one
+    /// `pcall` uses) left in the thread's call stack to handle it. The handler is called once the
+    /// error has unwound the whole call stack, including any `__close` metamethods, with the final
+    /// error and the thread's call stack at the point where the error was first raised, innermost
+    /// first, which [`format_traceback`](crate::stdlib::format_traceback) turns into a Lua-style
+    /// traceback. The error then propagates out of the thread as usual.
+    ///
+    /// The thread is borrowed while the handler runs, so the handler must not access it. The
+    /// handler may replace or clear itself.
+    pub fn set_uncaught_error_handler(
+        ctx: Context<'gc>,
+        handler: impl for<'a> FnMut(Context<'a>, &Error<'a>, &[BacktraceFrame<'a>]) + 'static,
+    ) {
+        UncaughtErrorHandler::get(ctx).set(Some(Box::new(handler)));
+    }
+
+    /// Removes the handler set by [`Thread::set_uncaught_error_handler`].
+    pub fn clear_uncaught_error_handler(ctx: Context<'gc>) {
+        UncaughtErrorHandler::get(ctx).set(None);
+    }
+
+    /// Returns the innermost thread that is currently being stepped, if any.
+    ///
+    /// When called from within a callback, this is the thread that called the callback.
+    pub fn current(ctx: Context<'gc>) -> Option<Thread<'gc>> {
+        RunningThreads::get(ctx).0.borrow().last().copied()
+    }
+
+    /// Returns the active frames of this thread's call stack, innermost first.
+    ///
+    /// When called from within a callback, the first frame is the running callback itself.
+    pub fn backtrace(self) -> Vec<BacktraceFrame<'gc>> {
+        self.0.borrow().backtrace()
+    }
+
     /// If this thread is `Stopped`, start a new function with the given arguments.
     pub fn start(
         self,
@@ -191,13 +398,17 @@ impl<'gc> Thread<'gc> {
         let mut state = self.0.borrow_mut(&ctx);
         state.check_mode(ThreadMode::Normal)?;
 
+        let running = RunningThreads::get(ctx);
+        running.0.borrow_mut(&ctx).push(self);
+
         while state.mode() == ThreadMode::Normal {
+            let depth = state.frames.len();
             match state.frames.pop().expect("no frame to step") {
                 Frame::Callback(callback) => {
                     let mut rfuel = match fuel.recurse() {
                         Ok(r) => r,
                         Err(err) => {
-                            state.unwind(&ctx, err.into());
+                            state.raise(ctx, err.into());
                             continue;
                         }
                     };
@@ -220,23 +431,25 @@ impl<'gc> Thread<'gc> {
 
                     match seq {
                         Ok(ret) => state.return_ext(fuel, ret),
-                        Err(error) => state.unwind(&ctx, error),
+                        Err(error) => state.raise(ctx, error),
                     }
                 }
                 Frame::Sequence(mut sequence) => {
                     let mut rfuel = match fuel.recurse() {
                         Ok(r) => r,
                         Err(err) => {
-                            state.unwind(&ctx, err.into());
+                            state.raise(ctx, err.into());
                             continue;
                         }
                     };
 
                     rfuel.consume_fuel(FUEL_PER_SEQ_STEP);
+                    let is_closing = state.closing.last() == Some(&state.frames.len());
                     state.frames.push(Frame::Calling);
 
                     let mut stack = mem::replace(&mut state.external_stack, Stack::new(&ctx));
                     let error = state.error.take();
+                    let had_error = error.is_some();
                     drop(state);
                     let fin = if let Some(error) = error {
                         assert!(stack.is_empty());
@@ -253,6 +466,17 @@ impl<'gc> Thread<'gc> {
                         "thread state has changed while callback was run"
                     );
 
+                    // The sequence caught the error, so it will not reach the uncaught error
+                    // handler. A sequence closing to-be-closed variables only passes the error on
+                    // once it has finished, so the original call stack is kept until then.
+                    if is_closing {
+                        if fin.is_err() {
+                            state.closing.pop();
+                        }
+                    } else if had_error && fin.is_ok() {
+                        state.error_backtrace = None;
+                    }
+
                     match fin {
                         Ok(SequencePoll::Pending) => {
                             state.return_ext(fuel, CallbackReturn::Sequence(sequence))
@@ -272,7 +496,7 @@ impl<'gc> Thread<'gc> {
                                 if tail { None } else { Some(sequence) },
                             ),
                         ),
-                        Err(error) => state.unwind(&ctx, error),
+                        Err(error) => state.raise(ctx, error),
                     }
                 }
                 frame @ Frame::Lua { .. } => {
@@ -289,7 +513,7 @@ impl<'gc> Thread<'gc> {
                     };
                     match run_vm(ctx, lua_frame, VM_GRANULARITY) {
                         Err(err) => {
-                            state.unwind(&ctx, err.into());
+                            state.raise(ctx, err.into());
                         }
                         Ok(instructions_run) => {
                             fuel.consume_fuel(instructions_run.try_into().unwrap());
@@ -299,11 +523,22 @@ impl<'gc> Thread<'gc> {
                 _ => panic!("tried to step invalid frame type"),
             }
 
+            if state.mode() == ThreadMode::Normal {
+                if let Err(err) = MemoryLimit::check(ctx) {
+                    state.raise(ctx, err.into());
+                } else if state.frames.len() > depth
+                    && state.frames.len() > ctx.state.config.call_depth_limit().0
+                {
+                    state.raise(ctx, StackOverflow.into());
+                }
+            }
+
             if !fuel.should_continue() {
                 break;
             }
         }
 
+        running.0.borrow_mut(&ctx).pop();
         Ok(())
     }
 
@@ -321,10 +556,13 @@ impl<'gc> Thread<'gc> {
         state.close_upvalues(mc, 0);
         assert!(state.open_upvalues.is_empty());
 
+        state.to_be_closed.clear();
         state.stack.clear();
         state.frames.clear();
         state.external_stack.clear();
         state.error = None;
+        state.error_backtrace = None;
+        state.closing.clear();
         Ok(())
     }
 }
@@ -335,8 +573,18 @@ pub(crate) struct ThreadState<'gc> {
     stack: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     frames: vec::Vec<Frame<'gc>, MetricsAlloc<'gc>>,
     open_upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    // The stack indexes of every to-be-closed variable that has not yet been closed, in the order
+    // they were declared.
+    to_be_closed: vec::Vec<usize, MetricsAlloc<'gc>>,
     external_stack: Stack<'gc>,
     error: Option<Error<'gc>>,
+    // The call stack at the point where the error being unwound was first raised, recorded for the
+    // uncaught error handler.
+    error_backtrace: Option<Vec<BacktraceFrame<'gc>>>,
+    // The frame indexes of every sequence closing to-be-closed variables while an error unwinds,
+    // which pass the error on rather than catching it.
+    closing: vec::Vec<usize, MetricsAlloc<'gc>>,
+    hook: Option<HookState<'gc>>,
 }
 
 pub(crate) struct LuaFrame<'gc, 'a> {
@@ -351,7 +599,11 @@ pub(crate) struct LuaRegisters<'gc, 'a> {
     upper_stack: &'a mut [Value<'gc>],
     base: usize,
     open_upvalues: &'a mut vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    to_be_closed: &'a [usize],
     thread: Thread<'gc>,
+    hook: Option<&'a mut HookState<'gc>>,
+    frame_index: usize,
+    is_variable: bool,
 }
 
 #[derive(Collect)]
@@ -363,6 +615,9 @@ enum LuaReturn {
     // Synthetic metamethod call, place an optional single return value at an index relative to the
     // returned to function's bottom.
     Meta(Option<RegisterIndex>),
+    // Call to a `__close` metamethod, discard all return values and leave the returned to frame
+    // exactly as it was before the call.
+    Close,
 }
 
 #[derive(Collect)]
@@ -414,8 +669,14 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
     // returns a view of the Lua frame's registers
     pub(crate) fn registers<'b>(&'b mut self) -> LuaRegisters<'gc, 'b> {
+        let frame_index = self.state.frames.len() - 1;
         match self.state.frames.last_mut() {
-            Some(Frame::Lua { base, pc, .. }) => {
+            Some(Frame::Lua {
+                base,
+                pc,
+                is_variable,
+                ..
+            }) => {
                 let (upper_stack, stack_frame) = self.state.stack.split_at_mut(*base);
                 LuaRegisters {
                     pc,
@@ -423,7 +684,11 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                     upper_stack,
                     base: *base,
                     open_upvalues: &mut self.state.open_upvalues,
+                    to_be_closed: &self.state.to_be_closed,
                     thread: self.thread,
+                    hook: self.state.hook.as_mut(),
+                    frame_index,
+                    is_variable: *is_variable,
                 }
             }
             _ => panic!("top frame is not lua frame"),
@@ -603,13 +868,15 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
     }
 
     // Calls the function at the given index with a constant number of arguments without
-    // invalidating the function or its arguments. Returns are placed *after* the function and its
-    // aruments, and all registers past this are invalidated as normal.
+    // invalidating the function or its arguments. Returns are placed starting at `dest`, which must
+    // be *after* the function and its arguments, and all registers past this are invalidated as
+    // normal. Registers between the arguments and `dest` are left untouched.
     pub(crate) fn call_function_keep(
         self,
         ctx: Context<'gc>,
         func: RegisterIndex,
         arg_count: u8,
+        dest: RegisterIndex,
         returns: VarCount,
     ) -> Result<(), VMError> {
         match self.state.frames.last_mut() {
@@ -628,7 +895,8 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 let arg_count = arg_count as usize;
                 *expected_return = Some(LuaReturn::Normal(returns));
                 let function_index = *base + func.0 as usize;
-                let top = function_index + 1 + arg_count;
+                let top = *base + dest.0 as usize;
+                assert!(top >= function_index + 1 + arg_count);
 
                 match meta_ops::call(ctx, self.state.stack[function_index])? {
                     Function::Closure(closure) => {
@@ -662,9 +930,9 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                     }
                     Function::Callback(callback) => {
                         assert!(self.state.external_stack.is_empty());
-                        self.state
-                            .external_stack
-                            .extend(&self.state.stack[function_index + 1..top]);
+                        self.state.external_stack.extend(
+                            &self.state.stack[function_index + 1..function_index + 1 + arg_count],
+                        );
                         self.state.stack.resize(top, Value::Nil);
                         self.state.frames.push(Frame::Callback(callback));
                         Ok(())
@@ -703,42 +971,59 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 *expected_return = Some(LuaReturn::Meta(ret_index));
                 let top = *base + *stack_size;
 
-                match meta_ops::call(ctx, func.into())? {
-                    Function::Closure(closure) => {
-                        self.state.stack.resize(top + 1 + args.len(), Value::Nil);
-                        self.state.stack[top] = closure.into();
-                        self.state.stack[top + 1..top + 1 + args.len()].copy_from_slice(args);
-
-                        let fixed_params = closure.0.proto.fixed_params as usize;
-                        let stack_size = closure.0.proto.stack_size as usize;
+                let func = meta_ops::call(ctx, func.into())?;
+                self.state.call_above(func, args, top);
+                Ok(())
+            }
+            _ => panic!("top frame is not lua frame"),
+        }
+    }
 
-                        let base = if args.len() > fixed_params {
-                            self.state.stack[top + 1..].rotate_left(fixed_params);
-                            top + 1 + (args.len() - fixed_params)
-                        } else {
-                            top + 1
-                        };
+    // Marks the value in the given register as a to-be-closed variable, checking that it can be
+    // closed.
+    pub(crate) fn to_be_closed(
+        &mut self,
+        ctx: Context<'gc>,
+        register: RegisterIndex,
+    ) -> Result<(), VMError> {
+        match self.state.frames.last() {
+            Some(&Frame::Lua { base, .. }) => {
+                let index = base + register.0 as usize;
+                if meta_ops::close(ctx, self.state.stack[index])?.is_some() {
+                    self.state.to_be_closed.push(index);
+                }
+                Ok(())
+            }
+            _ => panic!("top frame is not lua frame"),
+        }
+    }
 
-                        self.state.stack.resize(base + stack_size, Value::Nil);
+    // Closes the most recently declared to-be-closed variable of this frame by calling its
+    // `__close` metamethod in a new frame.
+    //
+    // Like `call_meta_function`, nothing in the current frame is invalidated, and this is allowed
+    // even when the stack is variable, so that it can be used while results are being returned.
+    pub(crate) fn close_variable(self, ctx: Context<'gc>) -> Result<(), VMError> {
+        let index = self
+            .state
+            .to_be_closed
+            .pop()
+            .expect("no to-be-closed variable to close");
+        let value = self.state.stack[index];
 
-                        self.state.frames.push(Frame::Lua {
-                            bottom: top,
-                            base,
-                            is_variable: false,
-                            pc: 0,
-                            stack_size,
-                            expected_return: None,
-                        });
-                        Ok(())
-                    }
-                    Function::Callback(callback) => {
-                        assert!(self.state.external_stack.is_empty());
-                        self.state.external_stack.extend(args);
-                        self.state.stack.resize(top, Value::Nil);
-                        self.state.frames.push(Frame::Callback(callback));
-                        Ok(())
-                    }
+        match self.state.frames.last_mut() {
+            Some(Frame::Lua {
+                expected_return, ..
+            }) => {
+                // The variable may have been reassigned since it was declared, in which case its
+                // new value is closed instead.
+                if let Some(close) = meta_ops::close(ctx, value)? {
+                    consume_call_fuel(self.fuel, 2);
+                    *expected_return = Some(LuaReturn::Close);
+                    let top = self.state.stack.len();
+                    self.state.call_above(close, &[value, Value::Nil], top);
                 }
+                Ok(())
             }
             _ => panic!("top frame is not lua frame"),
         }
@@ -897,6 +1182,9 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                                 self.state.stack[*base + meta_ind.0 as usize] = meta_ret;
                             }
                         }
+                        Some(LuaReturn::Close) => {
+                            self.state.stack.truncate(bottom);
+                        }
                         None => {
                             panic!("no expected returns set for returned to lua frame")
                         }
@@ -919,6 +1207,26 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 }
 
 impl<'gc, 'a> LuaRegisters<'gc, 'a> {
+    // Returns the hook function and the event it must be called with before the instruction at
+    // the current pc is run, if there is one.
+    pub(crate) fn hook_event(
+        &mut self,
+        closure: Closure<'gc>,
+    ) -> Option<(Function<'gc>, HookEvent)> {
+        let hook = self.hook.as_deref_mut()?;
+        let event = hook.next_event(self.frame_index, closure, *self.pc, self.is_variable)?;
+        Some((hook.hook.function, event))
+    }
+
+    // Moves the pc back so that the instruction that just ran is run again, once the frame is next
+    // entered. The hook has already been called for that instruction, so it is not called again.
+    pub(crate) fn rerun_instruction(&mut self) {
+        *self.pc -= 1;
+        if let Some(hook) = self.hook.as_deref_mut() {
+            hook.rerun(self.frame_index, *self.pc);
+        }
+    }
+
     pub(crate) fn open_upvalue(&mut self, mc: &Mutation<'gc>, reg: RegisterIndex) -> UpValue<'gc> {
         let ind = self.base + reg.0 as usize;
         match self
@@ -975,6 +1283,15 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
         }
     }
 
+    // Returns true if there are any to-be-closed variables at or above the given register that have
+    // not yet been closed.
+    pub(crate) fn has_to_be_closed(&self, bottom_register: RegisterIndex) -> bool {
+        matches!(
+            self.to_be_closed.last(),
+            Some(&index) if index >= self.base + bottom_register.0 as usize
+        )
+    }
+
     pub(crate) fn close_upvalues(&mut self, mc: &Mutation<'gc>, bottom_register: RegisterIndex) {
         let bottom = self.base + bottom_register.0 as usize;
         let start = match self
@@ -1007,6 +1324,33 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
 }
 
 impl<'gc> ThreadState<'gc> {
+    // Finds the Lua frame at the given level of `Thread::backtrace`, returning its closure, the
+    // index of the opcode it is executing, and its base.
+    fn lua_frame_at(&self, level: usize) -> Option<(Closure<'gc>, usize, usize)> {
+        let frame = self
+            .frames
+            .iter()
+            .rev()
+            .filter(|frame| {
+                !matches!(
+                    frame,
+                    Frame::StartCoroutine(_) | Frame::ResumeCoroutine | Frame::HasResult
+                )
+            })
+            .nth(level)?;
+        match *frame {
+            Frame::Lua {
+                bottom, base, pc, ..
+            } => match self.stack[bottom] {
+                Value::Function(Function::Closure(closure)) => {
+                    Some((closure, pc.saturating_sub(1), base))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     fn mode(&self) -> ThreadMode {
         match self.frames.last() {
             None => {
@@ -1077,6 +1421,45 @@ impl<'gc> ThreadState<'gc> {
         }
     }
 
+    // Calls the given function in a new frame starting at the stack index `top`, which must be at
+    // or above the end of the current frame. Nothing in the stack below `top` is disturbed.
+    fn call_above(&mut self, function: Function<'gc>, args: &[Value<'gc>], top: usize) {
+        match function {
+            Function::Closure(closure) => {
+                self.stack.resize(top + 1 + args.len(), Value::Nil);
+                self.stack[top] = closure.into();
+                self.stack[top + 1..top + 1 + args.len()].copy_from_slice(args);
+
+                let fixed_params = closure.0.proto.fixed_params as usize;
+                let stack_size = closure.0.proto.stack_size as usize;
+
+                let base = if args.len() > fixed_params {
+                    self.stack[top + 1..].rotate_left(fixed_params);
+                    top + 1 + (args.len() - fixed_params)
+                } else {
+                    top + 1
+                };
+
+                self.stack.resize(base + stack_size, Value::Nil);
+
+                self.frames.push(Frame::Lua {
+                    bottom: top,
+                    base,
+                    is_variable: false,
+                    pc: 0,
+                    stack_size,
+                    expected_return: None,
+                });
+            }
+            Function::Callback(callback) => {
+                assert!(self.external_stack.is_empty());
+                self.external_stack.extend(args);
+                self.stack.resize(top, Value::Nil);
+                self.frames.push(Frame::Callback(callback));
+            }
+        }
+    }
+
     fn return_to_lua(&mut self) {
         match self.frames.last_mut() {
             Some(Frame::Lua {
@@ -1115,12 +1498,58 @@ impl<'gc> ThreadState<'gc> {
                         self.stack[*base + meta_ind.0 as usize] = meta_ret;
                     }
                 }
+                Some(LuaReturn::Close) => {
+                    self.external_stack.clear();
+                }
                 None => panic!("no expected return set for returned to lua frame"),
             },
             _ => panic!("no lua frame to return to"),
         };
     }
 
+    fn backtrace(&self) -> Vec<BacktraceFrame<'gc>> {
+        self.frames
+            .iter()
+            .rev()
+            .filter_map(|frame| match *frame {
+                Frame::Lua { bottom, pc, .. } => match self.stack[bottom] {
+                    Value::Function(Function::Closure(closure)) => Some(BacktraceFrame::Lua {
+                        closure,
+                        pc: pc.saturating_sub(1),
+                    }),
+                    _ => None,
+                },
+                Frame::Callback(_) | Frame::Sequence(_) | Frame::Calling => {
+                    Some(BacktraceFrame::Callback)
+                }
+                Frame::StartCoroutine(_) | Frame::ResumeCoroutine | Frame::HasResult => None,
+            })
+            .collect()
+    }
+
+    // Unwinds an error raised while stepping the thread. If this is a top-level thread, the call
+    // stack is recorded when the error is first raised, and once nothing is left that could catch
+    // the error, it is passed to the uncaught error handler along with that call stack.
+    fn raise(&mut self, ctx: Context<'gc>, error: Error<'gc>) {
+        let handler = UncaughtErrorHandler::get(ctx);
+        if self.error_backtrace.is_none()
+            && RunningThreads::get(ctx).0.borrow().len() == 1
+            && handler.is_set()
+        {
+            self.error_backtrace = Some(self.backtrace());
+        }
+
+        self.unwind(&ctx, error);
+
+        // `unwind` leaves only a `HasResult` frame once the error has left the whole call stack.
+        if matches!(self.frames[..], [Frame::HasResult]) {
+            if let Some(backtrace) = self.error_backtrace.take() {
+                let error = self.error.as_ref().expect("no error after unwinding");
+                handler.call(ctx, error, &backtrace);
+            }
+        }
+    }
+
     fn unwind(&mut self, mc: &Mutation<'gc>, error: Error<'gc>) {
         self.external_stack.clear();
         self.error = Some(error);
@@ -1128,18 +1557,50 @@ impl<'gc> ThreadState<'gc> {
         while let Some(frame) = self.frames.pop() {
             match frame {
                 Frame::Lua { bottom, .. } => {
+                    let mut to_close = Vec::new();
+                    while let Some(&index) = self.to_be_closed.last() {
+                        if index < bottom {
+                            break;
+                        }
+                        self.to_be_closed.pop();
+                        to_close.push(self.stack[index]);
+                    }
+
                     self.close_upvalues(mc, bottom);
                     self.stack.truncate(bottom);
+
+                    // The error is passed through every pending `__close` metamethod in this frame
+                    // before unwinding continues. The sequence takes the error rather than being
+                    // given it with `Sequence::error`, since it does not catch the error.
+                    if !to_close.is_empty() {
+                        to_close.reverse();
+                        self.closing.push(self.frames.len());
+                        self.frames.push(Frame::Sequence(AnySequence::new(
+                            mc,
+                            CloseVariables {
+                                values: to_close,
+                                error: self.error.take(),
+                            },
+                        )));
+                        break;
+                    }
                 }
                 Frame::Sequence(sequence) => {
                     self.frames.push(Frame::Sequence(sequence));
-                    return;
+                    break;
                 }
                 _ => {}
             }
         }
-        assert!(self.stack.is_empty());
-        self.frames.push(Frame::HasResult);
+
+        if let Some(hook) = &mut self.hook {
+            hook.unwound(self.frames.len());
+        }
+
+        if self.frames.is_empty() {
+            assert!(self.stack.is_empty());
+            self.frames.push(Frame::HasResult);
+        }
     }
 
     fn return_ext(&mut self, fuel: &mut Fuel, ret: CallbackReturn<'gc>) {
@@ -1203,6 +1664,65 @@ impl<'gc> ThreadState<'gc> {
     }
 }
 
+// Calls the `__close` metamethods of the to-be-closed variables of a frame that was unwound by an
+// error, passing each the error, then raises the error again. An error raised by a `__close`
+// metamethod replaces the original error for the remaining variables and the frames below.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct CloseVariables<'gc> {
+    // The values to close, which are closed starting from the back.
+    values: Vec<Value<'gc>>,
+    error: Option<Error<'gc>>,
+}
+
+impl<'gc> CloseVariables<'gc> {
+    fn close_next(
+        &mut self,
+        ctx: Context<'gc>,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let mut error = self.error.take().expect("no error to close variables with");
+        while let Some(value) = self.values.pop() {
+            match meta_ops::close(ctx, value) {
+                Ok(Some(close)) => {
+                    stack.replace(ctx, (value, error.to_value(ctx)));
+                    self.error = Some(error);
+                    return Ok(SequencePoll::Call {
+                        function: close,
+                        is_tail: false,
+                    });
+                }
+                Ok(None) => {}
+                Err(err) => error = err.into(),
+            }
+        }
+        Err(error)
+    }
+}
+
+impl<'gc> Sequence<'gc> for CloseVariables<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.clear();
+        self.close_next(ctx, stack)
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        _fuel: &mut Fuel,
+        error: Error<'gc>,
+        stack: &mut Stack<'gc>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.error = Some(error);
+        self.close_next(ctx, stack)
+    }
+}
+
 fn open_upvalue_ind<'gc>(u: UpValue<'gc>) -> usize {
     match u.0.get() {
         UpValueState::Open(_, ind) => ind,
@@ -3,15 +3,16 @@ use gc_arena::{allocator_api::MetricsAlloc, Gc};
 
 use crate::{
     closure::ClosureState,
+    constant::float_to_int,
     meta_ops::{self, MetaResult},
     opcode::{Operation, RCIndex},
-    raw_ops,
+    raw_ops::{self, ArithmeticMode, FloatDivideByZero},
     table::TableEntries,
     types::{RegisterIndex, UpValueDescriptor, VarCount},
     Closure, Constant, Context, Function, RuntimeError, String, Table, Value,
 };
 
-use super::{BinaryOperatorError, LuaFrame, VMError};
+use super::{BinaryOperatorError, HookEvent, LuaFrame, VMError};
 
 // Runs the VM for the given number of instructions or until the current LuaFrame may have been
 // changed.
@@ -29,6 +30,8 @@ pub(crate) fn run_vm<'gc>(
     let current_function = lua_frame.closure();
     let mut registers = lua_frame.registers();
     let mut instructions_run = 0;
+    let checked = ctx.state.config.arithmetic_mode() == ArithmeticMode::Checked;
+    let strict_division = ctx.state.config.float_divide_by_zero() == FloatDivideByZero::Error;
 
     fn get_rc<'gc>(
         stack_frame: &[Value<'gc>],
@@ -42,6 +45,24 @@ pub(crate) fn run_vm<'gc>(
     }
 
     loop {
+        if let Some((hook, event)) = registers.hook_event(current_function) {
+            let name: Value = ctx
+                .state
+                .strings
+                .intern_static(&ctx, event.name().as_bytes())
+                .into();
+            match event {
+                HookEvent::Line(line) => lua_frame.call_meta_function(
+                    ctx,
+                    hook,
+                    &[name, Value::Integer(line.0 as i64 + 1)],
+                    None,
+                )?,
+                _ => lua_frame.call_meta_function(ctx, hook, &[name], None)?,
+            }
+            break;
+        }
+
         let op = current_function.0.proto.opcodes[*registers.pc].decode();
         *registers.pc += 1;
 
@@ -77,7 +98,8 @@ pub(crate) fn run_vm<'gc>(
                 array_size,
                 map_size,
             } => {
-                let mut entries = TableEntries::new(&ctx);
+                let mut entries = TableEntries::with_instance_hashing(ctx);
+                entries.set_max_len(ctx.state.config.size_limits().max_table_len);
                 entries.reserve_array(array_size as usize);
                 entries.reserve_map(map_size as usize);
                 let table = Table::from_parts(&ctx, entries, None);
@@ -176,7 +198,14 @@ pub(crate) fn run_vm<'gc>(
             }
 
             Operation::Return { start, count } => {
-                lua_frame.return_upper(&ctx, start, count)?;
+                if registers.has_to_be_closed(RegisterIndex(0)) {
+                    // Close the next variable and then run this instruction again, until every
+                    // to-be-closed variable in the frame is closed.
+                    registers.rerun_instruction();
+                    lua_frame.close_variable(ctx)?;
+                } else {
+                    lua_frame.return_upper(&ctx, start, count)?;
+                }
                 break;
             }
 
@@ -189,10 +218,21 @@ pub(crate) fn run_vm<'gc>(
                 offset,
                 close_upvalues,
             } => {
-                *registers.pc = add_offset(*registers.pc, offset);
                 if let Some(r) = close_upvalues.to_u8() {
+                    if registers.has_to_be_closed(RegisterIndex(r)) {
+                        // As with `Return`, this jump is run again once the variable is closed.
+                        registers.rerun_instruction();
+                        lua_frame.close_variable(ctx)?;
+                        break;
+                    }
                     registers.close_upvalues(&ctx, RegisterIndex(r));
                 }
+                *registers.pc = add_offset(*registers.pc, offset);
+            }
+
+            Operation::ToBeClosed { value } => {
+                lua_frame.to_be_closed(ctx, value)?;
+                registers = lua_frame.registers();
             }
 
             Operation::Test { value, is_true } => {
@@ -238,32 +278,71 @@ pub(crate) fn run_vm<'gc>(
             }
 
             Operation::NumericForPrep { base, jump } => {
-                registers.stack_frame[base.0 as usize] = raw_ops::subtract(
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 2],
-                )
-                .ok_or(BinaryOperatorError::Subtract)?;
-                *registers.pc = add_offset(*registers.pc, jump);
-            }
-
-            Operation::NumericForLoop { base, jump } => {
+                let base = base.0 as usize;
                 match (
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 1],
-                    registers.stack_frame[base.0 as usize + 2],
+                    registers.stack_frame[base],
+                    registers.stack_frame[base + 1],
+                    registers.stack_frame[base + 2],
                 ) {
-                    (Value::Integer(index), Value::Integer(limit), Value::Integer(step)) => {
-                        let index = index + step;
-                        registers.stack_frame[base.0 as usize] = Value::Integer(index);
+                    // Loops with an integer initial value and step count over integers, even if the
+                    // limit is a float.
+                    (Value::Integer(start), limit, Value::Integer(step)) => {
+                        if step == 0 {
+                            return Err(VMError::ForStepZero.into());
+                        }
 
-                        let past_end = if step < 0 {
-                            index < limit
+                        if let Some(count) = for_integer_limit(limit, step)?
+                            .and_then(|limit| for_loop_count(start, limit, step))
+                        {
+                            registers.stack_frame[base + 1] = Value::Integer(count as i64);
+                            registers.stack_frame[base + 3] = Value::Integer(start);
                         } else {
-                            limit < index
+                            *registers.pc = add_offset(*registers.pc, jump);
+                        }
+                    }
+                    (start, limit, step) => {
+                        let start = start
+                            .to_number()
+                            .ok_or(VMError::ForNotNumber("initial value"))?;
+                        let limit = limit.to_number().ok_or(VMError::ForNotNumber("limit"))?;
+                        let step = step.to_number().ok_or(VMError::ForNotNumber("step"))?;
+                        if step == 0.0 {
+                            return Err(VMError::ForStepZero.into());
+                        }
+
+                        registers.stack_frame[base] = Value::Number(start);
+                        registers.stack_frame[base + 1] = Value::Number(limit);
+                        registers.stack_frame[base + 2] = Value::Number(step);
+
+                        let past_end = if step < 0.0 {
+                            start < limit
+                        } else {
+                            limit < start
                         };
-                        if !past_end {
+                        if past_end {
+                            *registers.pc = add_offset(*registers.pc, jump);
+                        } else {
+                            registers.stack_frame[base + 3] = Value::Number(start);
+                        }
+                    }
+                }
+            }
+
+            Operation::NumericForLoop { base, jump } => {
+                let base = base.0 as usize;
+                match (
+                    registers.stack_frame[base],
+                    registers.stack_frame[base + 1],
+                    registers.stack_frame[base + 2],
+                ) {
+                    (Value::Integer(index), Value::Integer(count), Value::Integer(step)) => {
+                        let count = count as u64;
+                        if count > 0 {
+                            let index = index.wrapping_add(step);
+                            registers.stack_frame[base] = Value::Integer(index);
+                            registers.stack_frame[base + 1] = Value::Integer((count - 1) as i64);
+                            registers.stack_frame[base + 3] = Value::Integer(index);
                             *registers.pc = add_offset(*registers.pc, jump);
-                            registers.stack_frame[base.0 as usize + 3] = Value::Integer(index);
                         }
                     }
                     (index, limit, step) => {
@@ -271,7 +350,7 @@ pub(crate) fn run_vm<'gc>(
                             (index.to_number(), limit.to_number(), step.to_number())
                         {
                             let index = index + step;
-                            registers.stack_frame[base.0 as usize] = Value::Number(index);
+                            registers.stack_frame[base] = Value::Number(index);
 
                             let past_end = if step < 0.0 {
                                 index < limit
@@ -280,7 +359,7 @@ pub(crate) fn run_vm<'gc>(
                             };
                             if !past_end {
                                 *registers.pc = add_offset(*registers.pc, jump);
-                                registers.stack_frame[base.0 as usize + 3] = Value::Number(index);
+                                registers.stack_frame[base + 3] = Value::Number(index);
                             }
                         } else {
                             return Err(BinaryOperatorError::Add.into());
@@ -290,14 +369,20 @@ pub(crate) fn run_vm<'gc>(
             }
 
             Operation::GenericForCall { base, var_count } => {
-                lua_frame.call_function_keep(ctx, base, 2, VarCount::constant(var_count))?;
+                lua_frame.call_function_keep(
+                    ctx,
+                    base,
+                    2,
+                    RegisterIndex(base.0 + 4),
+                    VarCount::constant(var_count),
+                )?;
                 break;
             }
 
             Operation::GenericForLoop { base, jump } => {
-                if registers.stack_frame[base.0 as usize + 1].to_bool() {
+                if registers.stack_frame[base.0 as usize + 2].to_bool() {
                     registers.stack_frame[base.0 as usize] =
-                        registers.stack_frame[base.0 as usize + 1];
+                        registers.stack_frame[base.0 as usize + 2];
                     *registers.pc = add_offset(*registers.pc, jump);
                 }
             }
@@ -432,8 +517,8 @@ pub(crate) fn run_vm<'gc>(
 
             Operation::Minus { dest, source } => {
                 let value = registers.stack_frame[source.0 as usize];
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::negate(value).ok_or(BinaryOperatorError::UnaryNegate)?;
+                registers.stack_frame[dest.0 as usize] = raw_ops::negate(value)
+                    .ok_or_else(|| arithmetic_error(BinaryOperatorError::UnaryNegate, &[value]))?;
             }
 
             Operation::BitNot { dest, source } => {
@@ -453,8 +538,13 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::add(left, right).ok_or(BinaryOperatorError::Add)?;
+                let result = if checked {
+                    raw_ops::checked_add(left, right)
+                } else {
+                    raw_ops::add(left, right)
+                };
+                registers.stack_frame[dest.0 as usize] = result
+                    .ok_or_else(|| arithmetic_error(BinaryOperatorError::Add, &[left, right]))?;
             }
 
             Operation::Sub { dest, left, right } => {
@@ -468,8 +558,14 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::subtract(left, right).ok_or(BinaryOperatorError::Add)?;
+                let result = if checked {
+                    raw_ops::checked_subtract(left, right)
+                } else {
+                    raw_ops::subtract(left, right)
+                };
+                registers.stack_frame[dest.0 as usize] = result.ok_or_else(|| {
+                    arithmetic_error(BinaryOperatorError::Subtract, &[left, right])
+                })?;
             }
 
             Operation::Mul { dest, left, right } => {
@@ -483,8 +579,14 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::multiply(left, right).ok_or(BinaryOperatorError::Multiply)?;
+                let result = if checked {
+                    raw_ops::checked_multiply(left, right)
+                } else {
+                    raw_ops::multiply(left, right)
+                };
+                registers.stack_frame[dest.0 as usize] = result.ok_or_else(|| {
+                    arithmetic_error(BinaryOperatorError::Multiply, &[left, right])
+                })?;
             }
 
             Operation::Div { dest, left, right } => {
@@ -498,8 +600,14 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::float_divide(left, right).ok_or(BinaryOperatorError::FloatDivide)?;
+                let result = if strict_division && divides_by_zero(left, right) {
+                    None
+                } else {
+                    raw_ops::float_divide(left, right)
+                };
+                registers.stack_frame[dest.0 as usize] = result.ok_or_else(|| {
+                    arithmetic_error(BinaryOperatorError::FloatDivide, &[left, right])
+                })?;
             }
 
             Operation::IDiv { dest, left, right } => {
@@ -513,8 +621,14 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::floor_divide(left, right).ok_or(BinaryOperatorError::FloorDivide)?;
+                let result = if strict_division && divides_by_zero(left, right) {
+                    None
+                } else {
+                    raw_ops::idiv(left, right)
+                };
+                registers.stack_frame[dest.0 as usize] = result.ok_or_else(|| {
+                    arithmetic_error(BinaryOperatorError::FloorDivide, &[left, right])
+                })?;
             }
 
             Operation::Mod { dest, left, right } => {
@@ -528,8 +642,13 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::modulo(left, right).ok_or(BinaryOperatorError::Modulo)?;
+                let result = if strict_division && divides_by_zero(left, right) {
+                    None
+                } else {
+                    raw_ops::modulo(left, right)
+                };
+                registers.stack_frame[dest.0 as usize] = result
+                    .ok_or_else(|| arithmetic_error(BinaryOperatorError::Modulo, &[left, right]))?;
             }
 
             Operation::Pow { dest, left, right } => {
@@ -543,8 +662,10 @@ pub(crate) fn run_vm<'gc>(
                     &current_function.0.proto.constants,
                     right,
                 );
-                registers.stack_frame[dest.0 as usize] =
-                    raw_ops::exponentiate(left, right).ok_or(BinaryOperatorError::Exponentiate)?;
+                registers.stack_frame[dest.0 as usize] = raw_ops::exponentiate(left, right)
+                    .ok_or_else(|| {
+                        arithmetic_error(BinaryOperatorError::Exponentiate, &[left, right])
+                    })?;
             }
 
             Operation::BitAnd { dest, left, right } => {
@@ -632,6 +753,46 @@ pub(crate) fn run_vm<'gc>(
     Ok(instructions_run)
 }
 
+// Converts the limit of an integer numeric for loop to an integer, rounding float limits towards
+// the initial value.
+//
+// Returns `None` if the limit is a float beyond the integer range in the direction that the loop
+// steps, in which case the loop does not run at all. Float limits beyond the range in the other
+// direction are clamped to the range.
+fn for_integer_limit(limit: Value, step: i64) -> Result<Option<i64>, VMError> {
+    if let Value::Integer(limit) = limit {
+        return Ok(Some(limit));
+    }
+
+    let limit = limit.to_number().ok_or(VMError::ForNotNumber("limit"))?;
+    let rounded = if step < 0 {
+        limit.ceil()
+    } else {
+        limit.floor()
+    };
+    Ok(if let Some(limit) = float_to_int(rounded) {
+        Some(limit)
+    } else if limit > 0.0 {
+        (step > 0).then_some(i64::MAX)
+    } else {
+        (step < 0).then_some(i64::MIN)
+    })
+}
+
+// Returns the number of times an integer numeric for loop runs its body after the first iteration,
+// or `None` if the body is not run at all.
+//
+// Counting the iterations up front means that the loop index is never stepped past the limit, so
+// loops that end near the edges of the integer range cannot overflow.
+fn for_loop_count(start: i64, limit: i64, step: i64) -> Option<u64> {
+    if step > 0 {
+        (start <= limit).then(|| (limit as u64).wrapping_sub(start as u64) / step as u64)
+    } else {
+        (start >= limit)
+            .then(|| (start as u64).wrapping_sub(limit as u64) / (step as u64).wrapping_neg())
+    }
+}
+
 fn add_offset(pc: usize, offset: i16) -> usize {
     if offset > 0 {
         pc.checked_add(offset as usize).unwrap()
@@ -641,3 +802,54 @@ fn add_offset(pc: usize, offset: i16) -> usize {
         pc
     }
 }
+
+// True if a division of `left` by `right` is a division of a number by zero, which only fails
+// with `FloatDivideByZero::Error` (or for integers with `//` and `%`).
+fn divides_by_zero(left: Value<'_>, right: Value<'_>) -> bool {
+    left.to_number().is_some() && right.to_number() == Some(0.0)
+}
+
+// Numeric strings are coerced in arithmetic, so a failed operation on a string that is *not*
+// numeric gets its own error, as does integer division by zero.
+fn arithmetic_error(error: BinaryOperatorError, operands: &[Value<'_>]) -> BinaryOperatorError {
+    if operands
+        .iter()
+        .any(|v| matches!(v, Value::String(_)) && v.to_number().is_none())
+    {
+        return BinaryOperatorError::ArithmeticOnString;
+    }
+
+    let is_integer = |v: &Value<'_>| {
+        matches!(
+            v.to_constant().and_then(|c| c.to_numeric()),
+            Some(Constant::Integer(_))
+        )
+    };
+    match (error, operands) {
+        (BinaryOperatorError::FloorDivide, [l, r])
+            if is_integer(l) && r.to_integer() == Some(0) && is_integer(r) =>
+        {
+            BinaryOperatorError::IntegerDivideByZero
+        }
+        (BinaryOperatorError::Modulo, [l, r])
+            if is_integer(l) && r.to_integer() == Some(0) && is_integer(r) =>
+        {
+            BinaryOperatorError::IntegerModuloByZero
+        }
+        // Float division only fails on division by zero with `FloatDivideByZero::Error`.
+        (
+            BinaryOperatorError::FloatDivide
+            | BinaryOperatorError::FloorDivide
+            | BinaryOperatorError::Modulo,
+            [l, r],
+        ) if divides_by_zero(*l, *r) => BinaryOperatorError::FloatDivideByZero,
+        // Integer arithmetic only fails on overflow in `ArithmeticMode::Checked`.
+        (
+            BinaryOperatorError::Add
+            | BinaryOperatorError::Subtract
+            | BinaryOperatorError::Multiply,
+            [l, r],
+        ) if is_integer(l) && is_integer(r) => BinaryOperatorError::IntegerOverflow,
+        (error, _) => error,
+    }
+}
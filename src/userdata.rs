@@ -1,14 +1,26 @@
 use std::hash::{Hash, Hasher};
 
-use gc_arena::{barrier, lock, Collect, Mutation, Root, Rootable};
+use gc_arena::{barrier, lock, Collect, Finalization, Mutation, Root, Rootable};
 use thiserror::Error;
 
-use crate::{any::AnyValue, Table};
+use crate::{
+    any::{AnyValue, AnyValueWeak},
+    Table,
+};
 
 #[derive(Debug, Copy, Clone, Error)]
 #[error("UserData type mismatch")]
 pub struct BadUserDataType;
 
+/// A garbage collected Lua userdata value, which can hold any Rust type and an optional metatable.
+///
+/// Types that hold `'gc` pointers are stored with [`AnyUserData::new`] and read back with
+/// [`AnyUserData::downcast`], naming the type through the `Rootable!` macro. Plain `'static` types
+/// can use the simpler [`AnyUserData::new_static`] and [`AnyUserData::downcast_static`].
+///
+/// Methods are given to userdata the same way as to tables, with an `__index` field in a metatable
+/// set with [`AnyUserData::set_metatable`]. Two userdata values are equal only if they are the same
+/// object.
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct AnyUserData<'gc>(AnyValue<'gc, lock::Lock<Option<Table<'gc>>>>);
@@ -38,6 +50,7 @@ impl<'a, R: 'static> Rootable<'a> for StaticRoot<R> {
 }
 
 impl<'gc> AnyUserData<'gc> {
+    /// Creates a new userdata holding `val`, with no metatable.
     pub fn new<R>(mc: &Mutation<'gc>, val: Root<'gc, R>) -> Self
     where
         R: for<'a> Rootable<'a>,
@@ -45,6 +58,8 @@ impl<'gc> AnyUserData<'gc> {
         AnyUserData(AnyValue::new::<R>(mc, None.into(), val))
     }
 
+    /// Creates a new userdata holding a `'static` value, which can only be read back with the
+    /// `_static` methods.
     pub fn new_static<R: 'static>(mc: &Mutation<'gc>, val: R) -> Self {
         Self::new::<StaticRoot<R>>(mc, StaticRoot { root: val })
     }
@@ -60,6 +75,8 @@ impl<'gc> AnyUserData<'gc> {
         self.is::<StaticRoot<R>>()
     }
 
+    /// Returns a reference to the held value if it is of the type `R`, or `BadUserDataType` if it
+    /// is some other type.
     pub fn downcast<'a, R>(&'a self) -> Result<&'gc Root<'gc, R>, BadUserDataType>
     where
         R: for<'b> Rootable<'b>,
@@ -67,6 +84,8 @@ impl<'gc> AnyUserData<'gc> {
         self.0.downcast::<R>().ok_or(BadUserDataType)
     }
 
+    /// Like [`AnyUserData::downcast`], but returns a reference that allows mutating the held value
+    /// through `gc_arena` locks.
     pub fn downcast_write<'a, R>(
         &'a self,
         mc: &Mutation<'gc>,
@@ -88,6 +107,11 @@ impl<'gc> AnyUserData<'gc> {
         self.0.metadata().get()
     }
 
+    /// Sets the metatable for this userdata, returning the previous one.
+    ///
+    /// As in PUC-Rio Lua, a `__gc` field in the metatable only takes effect if the userdata is also
+    /// registered with [`Finalizers::register`](crate::Finalizers::register), which the
+    /// `setmetatable` function does automatically for tables.
     pub fn set_metatable(
         &self,
         mc: &Mutation<'gc>,
@@ -99,4 +123,27 @@ impl<'gc> AnyUserData<'gc> {
     pub fn as_ptr(&self) -> *const () {
         self.0.as_ptr()
     }
+
+    pub(crate) fn downgrade(self) -> WeakUserData<'gc> {
+        WeakUserData(self.0.downgrade())
+    }
+}
+
+/// A weak reference to an `AnyUserData`, used to find userdata which need finalizing.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub(crate) struct WeakUserData<'gc>(AnyValueWeak<'gc, lock::Lock<Option<Table<'gc>>>>);
+
+impl<'gc> WeakUserData<'gc> {
+    pub(crate) fn is_dead(self, fc: &Finalization<'gc>) -> bool {
+        self.0.is_dead(fc)
+    }
+
+    pub(crate) fn resurrect(self, fc: &Finalization<'gc>) -> Option<AnyUserData<'gc>> {
+        self.0.resurrect(fc).map(AnyUserData)
+    }
+
+    pub(crate) fn as_ptr(self) -> *const () {
+        self.0.as_ptr()
+    }
 }
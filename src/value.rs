@@ -1,8 +1,11 @@
 use std::{f64, fmt, i64, io, string::String as StdString};
 
-use gc_arena::Collect;
+use gc_arena::{Collect, Finalization, Gc};
 
-use crate::{AnyCallback, AnyUserData, Closure, Constant, Function, String, Table, Thread};
+use crate::{
+    stdlib::format::format_general, table, AnyCallback, AnyUserData, Closure, Constant, Function,
+    InvalidTableKey, KeyHashing, String, Table, Thread,
+};
 
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -16,6 +19,12 @@ pub enum Value<'gc> {
     Function(Function<'gc>),
     Thread(Thread<'gc>),
     UserData(AnyUserData<'gc>),
+    /// An opaque host value like a pointer or an index, the equivalent of C Lua's light userdata.
+    ///
+    /// Unlike `UserData`, this is not garbage collected and cannot have its own metatable. Two
+    /// light userdata values are equal if they hold the same number, so they can be used as table
+    /// keys that are looked up by value. Scripts see them as the type `"userdata"`.
+    LightUserData(usize),
 }
 
 impl<'gc> Default for Value<'gc> {
@@ -34,7 +43,7 @@ impl<'gc> Value<'gc> {
             Value::Table(_) => "table",
             Value::Function(_) => "function",
             Value::Thread(_) => "thread",
-            Value::UserData(_) => "userdata",
+            Value::UserData(_) | Value::LightUserData(_) => "userdata",
         }
     }
 
@@ -43,13 +52,14 @@ impl<'gc> Value<'gc> {
             Value::Nil => write!(w, "nil"),
             Value::Boolean(b) => write!(w, "{}", b),
             Value::Integer(i) => write!(w, "{}", i),
-            Value::Number(f) => write!(w, "{}", f),
+            Value::Number(f) => write_float(w, f),
             Value::String(s) => w.write_all(s.as_bytes()),
             Value::Table(t) => write!(w, "<table {:p}>", t.0),
             Value::Function(Function::Closure(c)) => write!(w, "<function {:p}>", c.0),
             Value::Function(Function::Callback(c)) => write!(w, "<function {:p}>", c.as_ptr()),
             Value::Thread(t) => write!(w, "<thread {:p}>", t.0),
             Value::UserData(t) => write!(w, "<userdata {:p}>", t.as_ptr()),
+            Value::LightUserData(p) => write!(w, "<userdata {:#x}>", p),
         }
     }
 
@@ -57,6 +67,63 @@ impl<'gc> Value<'gc> {
         matches!(self, Value::Nil)
     }
 
+    pub fn is_boolean(self) -> bool {
+        matches!(self, Value::Boolean(_))
+    }
+
+    pub fn is_integer(self) -> bool {
+        matches!(self, Value::Integer(_))
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Value::Number(_))
+    }
+
+    /// True for both integers and floats. Strings are not numbers, even if they can be converted
+    /// to one with [`Value::to_number`].
+    pub fn is_number(self) -> bool {
+        matches!(self, Value::Integer(_) | Value::Number(_))
+    }
+
+    pub fn is_string(self) -> bool {
+        matches!(self, Value::String(_))
+    }
+
+    pub fn is_table(self) -> bool {
+        matches!(self, Value::Table(_))
+    }
+
+    pub fn is_function(self) -> bool {
+        matches!(self, Value::Function(_))
+    }
+
+    pub fn is_thread(self) -> bool {
+        matches!(self, Value::Thread(_))
+    }
+
+    /// True only for full userdata, see [`Value::is_light_userdata`].
+    pub fn is_userdata(self) -> bool {
+        matches!(self, Value::UserData(_))
+    }
+
+    pub fn is_light_userdata(self) -> bool {
+        matches!(self, Value::LightUserData(_))
+    }
+
+    // Whether this is a table, function, thread, or userdata which the collector has found to be
+    // unreachable. Only meaningful during finalization, and always false for strings, which weak
+    // tables never remove.
+    pub(crate) fn is_dead(self, fc: &Finalization<'gc>) -> bool {
+        match self {
+            Value::Table(t) => Gc::downgrade(t.0).is_dead(fc),
+            Value::Function(Function::Closure(c)) => Gc::downgrade(c.0).is_dead(fc),
+            Value::Function(Function::Callback(c)) => c.is_dead(fc),
+            Value::Thread(t) => Gc::downgrade(t.0).is_dead(fc),
+            Value::UserData(u) => u.downgrade().is_dead(fc),
+            _ => false,
+        }
+    }
+
     /// Lua `nil` and `false` are false, anything else is true.
     pub fn to_bool(self) -> bool {
         match self {
@@ -70,6 +137,18 @@ impl<'gc> Value<'gc> {
         Value::Boolean(!self.to_bool())
     }
 
+    /// Returns an Integer or Number as an `f64`, or `None` for any other value.
+    ///
+    /// Unlike [`Value::to_number`], strings are not converted, even if they are numeric. Integers
+    /// with a magnitude above 2^53 are rounded to the nearest float.
+    pub fn as_f64(self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(i as f64),
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
     /// Interprets Numbers, Integers, and Strings as a Number, if possible.
     pub fn to_number(self) -> Option<f64> {
         self.to_constant().and_then(|c| c.to_number())
@@ -80,6 +159,19 @@ impl<'gc> Value<'gc> {
         self.to_constant().and_then(|c| c.to_integer())
     }
 
+    /// Hashes this value the same way that a `Table` with the given [`KeyHashing`] hashes its keys,
+    /// see [`Table::key_hashing`].
+    ///
+    /// Floats with an exact integer representation are normalized to integers first, so `1` and
+    /// `1.0` (which are the same table key) produce the same hash. All other values hash by their
+    /// type and contents (strings) or identity (tables, functions, threads, and userdata).
+    ///
+    /// Values which cannot be table keys cannot be hashed, so this returns an error for `nil` and
+    /// for NaN.
+    pub fn table_hash(self, hashing: &KeyHashing) -> Result<u64, InvalidTableKey> {
+        Ok(hashing.hash_key(table::canonical_key(self)?))
+    }
+
     pub fn to_constant(self) -> Option<Constant<String<'gc>>> {
         match self {
             Value::Nil => Some(Constant::Nil),
@@ -92,6 +184,26 @@ impl<'gc> Value<'gc> {
     }
 }
 
+/// Writes a float the way that Lua's `tostring` does.
+///
+/// This uses `%.14g` formatting, and adds `.0` to any float that would otherwise look like an
+/// integer so that `1.0` and `1` remain distinguishable.
+pub(crate) fn write_float<W: io::Write>(mut w: W, n: f64) -> Result<(), io::Error> {
+    let sign = if n.is_sign_negative() { "-" } else { "" };
+    if n.is_nan() {
+        return write!(w, "{sign}nan");
+    } else if n.is_infinite() {
+        return write!(w, "{sign}inf");
+    }
+
+    let digits = format_general(n.abs(), 14, false);
+    if digits.bytes().all(|c| c.is_ascii_digit()) {
+        write!(w, "{sign}{digits}.0")
+    } else {
+        write!(w, "{sign}{digits}")
+    }
+}
+
 impl<'gc> fmt::Display for Value<'gc> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         let mut buf = Vec::new();
@@ -1,7 +1,9 @@
+use std::{cell::Cell, rc::Rc};
+
 use gc_arena::Collect;
 use piccolo::{
-    AnyCallback, AnySequence, CallbackReturn, Closure, Error, Function, IntoValue, Lua, Sequence,
-    SequencePoll, StaticError, String, Thread, Value,
+    AnyCallback, AnySequence, CallbackReturn, Closure, DefaultArg, Error, Function, IntoValue, Lua,
+    Sequence, SequencePoll, StaticError, String, Thread, Value, Variadic,
 };
 
 #[test]
@@ -68,6 +70,50 @@ fn tail_call_trivial_callback() -> Result<(), StaticError> {
     Ok(())
 }
 
+#[test]
+fn deep_tail_call_recursion() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    let max_depth = Rc::new(Cell::new(0));
+
+    let thread = lua.try_run(|ctx| {
+        let depth = max_depth.clone();
+        let bounce = AnyCallback::from_fn(&ctx, move |ctx, _, stack| {
+            let frames = Thread::current(ctx).unwrap().backtrace().len();
+            depth.set(depth.get().max(frames));
+
+            let (f, n): (Function, i64) = stack.consume(ctx)?;
+            if n == 0 {
+                stack.replace(ctx, "done");
+                Ok(CallbackReturn::Return)
+            } else {
+                stack.replace(ctx, n - 1);
+                Ok(CallbackReturn::TailCall(f, None))
+            }
+        });
+        ctx.state.globals.set(ctx, "bounce", bounce)?;
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local function step(n)
+                    return bounce(step, n)
+                end
+                return step(100000) == "done"
+            "#[..],
+        )?;
+
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+    // Neither the Lua tail call to the callback nor the callback's tail call back into Lua adds a
+    // frame, so the call stack stays the same depth however long the recursion runs.
+    assert!(max_depth.get() <= 3);
+    Ok(())
+}
+
 #[test]
 fn loopy_callback() -> Result<(), StaticError> {
     let mut lua = Lua::core();
@@ -223,6 +269,46 @@ fn yield_sequence() -> Result<(), StaticError> {
     lua.run_thread(&thread)
 }
 
+#[test]
+fn yield_from_callback() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let thread = lua.try_run(|ctx| {
+        // Yields a request to the code driving the coroutine, which resumes it with the result.
+        let sleep = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let millis: i64 = stack.consume(ctx)?;
+            stack.replace(ctx, ("sleep", millis));
+            Ok(CallbackReturn::Yield(None))
+        });
+        ctx.state.globals.set(ctx, "sleep", sleep)?;
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local co = coroutine.create(function(n)
+                    local slept, extra = sleep(n * 10)
+                    return slept + extra
+                end)
+
+                local ok1, request, millis = coroutine.resume(co, 5)
+                local status = coroutine.status(co)
+                local ok2, total = coroutine.resume(co, millis, 1)
+
+                return ok1 and request == "sleep" and millis == 50 and
+                    status == "suspended" and ok2 and total == 51 and
+                    coroutine.status(co) == "dead"
+            "#[..],
+        )?;
+
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+    Ok(())
+}
+
 #[test]
 fn resume_with_err() {
     let mut lua = Lua::core();
@@ -292,3 +378,197 @@ fn resume_with_err() {
         }
     });
 }
+
+#[test]
+fn variadic_stack_sum() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        // Reads arguments directly out of the stack slots without collecting them anywhere.
+        let callback = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let mut total: i64 = 0;
+            for value in &*stack {
+                match value {
+                    Value::Integer(i) => total += i,
+                    v => {
+                        return Err(format!("cannot sum {}", v.type_name())
+                            .into_value(ctx)
+                            .into())
+                    }
+                }
+            }
+            stack.clear();
+            stack.push_back(Value::Integer(total));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.state.globals.set(ctx, "sum", callback)?;
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                assert(sum() == 0)
+                assert(sum(7) == 7)
+                return sum(1, 2, 3, 4, 5, 6, 7, 8, 9, 10)
+            "#[..],
+        )?;
+
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert_eq!(lua.run_thread::<i64>(&thread)?, 55);
+    Ok(())
+}
+
+#[test]
+fn dynamic_return_count() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        // Returns every character of a string as a separate value.
+        let chars = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let s: String = stack.consume(ctx)?;
+            let chars: Vec<Value> = s
+                .as_bytes()
+                .iter()
+                .map(|&c| ctx.intern_bytes(&[c]))
+                .collect();
+            stack.replace(ctx, Variadic(chars));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.state.globals.set(ctx, "chars", chars)?;
+
+        // Returns the integers from `1` to `n`, pushed onto the stack one at a time.
+        let range = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let n: i64 = stack.consume(ctx)?;
+            stack.extend((1..=n).map(Value::Integer));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.state.globals.set(ctx, "range", range)?;
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br##"
+                assert(select("#", chars("")) == 0)
+                local a, b, c, d = chars("abc")
+                assert(a == "a" and b == "b" and c == "c" and d == nil)
+                assert(select("#", range(0)) == 0)
+                local t = {range(100)}
+                assert(#t == 100 and t[1] == 1 and t[100] == 100)
+                return select("#", chars("hello")) + select("#", range(37))
+            "##[..],
+        )?;
+
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert_eq!(lua.run_thread::<i64>(&thread)?, 42);
+    Ok(())
+}
+
+#[test]
+fn callback_with_state() -> Result<(), StaticError> {
+    #[derive(Collect)]
+    #[collect(no_drop)]
+    struct Counter<'gc> {
+        count: i64,
+        last: Value<'gc>,
+    }
+
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        let counter = AnyCallback::from_fn_with_state(
+            &ctx,
+            Counter {
+                count: 0,
+                last: Value::Nil,
+            },
+            |counter, ctx, _, stack| {
+                let value = stack.get(0);
+                stack.replace(ctx, (counter.count, counter.last));
+                counter.count += 1;
+                counter.last = value;
+                Ok(CallbackReturn::Return)
+            },
+        );
+        ctx.state.globals.set(ctx, "counter", counter)?;
+        Ok(())
+    })?;
+
+    let call = |lua: &mut Lua, source: &'static str| -> Result<(), StaticError> {
+        let thread = lua.try_run(|ctx| {
+            let closure = Closure::load(ctx, source.as_bytes())?;
+            let thread = Thread::new(&ctx);
+            thread.start(ctx, closure.into(), ())?;
+            Ok(ctx.state.registry.stash(&ctx, thread))
+        })?;
+        lua.run_thread::<()>(&thread)
+    };
+
+    call(
+        &mut lua,
+        r#"
+            local count, last = counter({})
+            assert(count == 0 and last == nil)
+            count, last = counter("a" .. "b")
+            assert(count == 1 and type(last) == "table")
+        "#,
+    )?;
+
+    // The state keeps the last value alive across collections.
+    lua.gc_collect();
+
+    call(
+        &mut lua,
+        r#"
+            local count, last = counter()
+            assert(count == 2 and last == "ab")
+        "#,
+    )
+}
+
+#[test]
+fn default_args() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        let callback = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let (a, b, c): (i64, DefaultArg<i64>, DefaultArg<bool>) = stack.consume(ctx)?;
+            stack.replace(ctx, (a, *b, *c));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.state.globals.set(ctx, "callback", callback)?;
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local a, b, c = callback(1)
+                assert(a == 1 and b == 0 and c == false)
+                a, b, c = callback(1, 2)
+                assert(a == 1 and b == 2 and c == false)
+                a, b, c = callback(1, nil, true)
+                assert(a == 1 and b == 0 and c == true)
+                assert(not pcall(callback, 1, "x"))
+            "#[..],
+        )?;
+
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    lua.run_thread::<()>(&thread)
+}
@@ -0,0 +1,137 @@
+#![cfg(feature = "continue")]
+
+use piccolo::{compiler::CompilerError, Closure, Lua, ProtoCompileError, StaticError, Thread};
+
+fn run(source: &'static str) -> Result<bool, StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, source.as_bytes())?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    lua.run_thread::<bool>(&thread)
+}
+
+fn compile_error(source: &'static str) -> Option<CompilerError> {
+    let mut lua = Lua::core();
+    lua.run(|ctx| match Closure::load(ctx, source.as_bytes()) {
+        Err(ProtoCompileError::Compiler(err)) => Some(err),
+        _ => None,
+    })
+}
+
+#[test]
+fn continue_loops() -> Result<(), StaticError> {
+    assert!(run(r#"
+        local odd = {}
+        for i = 1, 10 do
+            if i % 2 == 0 then
+                continue
+            end
+            odd[#odd + 1] = i
+        end
+        assert(#odd == 5 and odd[5] == 9)
+
+        local i, count = 0, 0
+        while i < 10 do
+            i = i + 1
+            if i > 3 then
+                continue
+            end
+            count = count + 1
+        end
+        assert(i == 10 and count == 3)
+
+        local j, skipped = 0, 0
+        repeat
+            local k = j
+            j = j + 1
+            if k % 3 ~= 0 then
+                skipped = skipped + 1
+                continue
+            end
+        until j == 9
+        assert(skipped == 6)
+
+        local keys = 0
+        for k, v in pairs({a = 1, b = 2, c = 3}) do
+            if v == 2 then
+                continue
+            end
+            keys = keys + 1
+        end
+        assert(keys == 2)
+
+        -- `continue` only affects the innermost loop.
+        local pairs_seen = 0
+        for a = 1, 3 do
+            for b = 1, 3 do
+                if a == b then
+                    continue
+                end
+                pairs_seen = pairs_seen + 1
+            end
+        end
+        assert(pairs_seen == 6)
+
+        -- Each iteration still gets a fresh loop variable for closures to capture.
+        local fns = {}
+        for i = 1, 3 do
+            fns[i] = function() return i end
+            if i == 2 then
+                continue
+            end
+        end
+        return fns[1]() == 1 and fns[2]() == 2 and fns[3]() == 3
+    "#)?);
+
+    Ok(())
+}
+
+#[test]
+fn continue_closes_variables() -> Result<(), StaticError> {
+    assert!(run(r#"
+        local closed = {}
+        local function closer(name)
+            return setmetatable({}, {
+                __close = function()
+                    closed[#closed + 1] = name
+                end
+            })
+        end
+
+        for i = 1, 3 do
+            local c <close> = closer(i)
+            if i ~= 2 then
+                continue
+            end
+            closed[#closed + 1] = "body"
+        end
+
+        return #closed == 4 and closed[1] == 1 and closed[2] == "body" and closed[3] == 2
+            and closed[4] == 3
+    "#)?);
+
+    Ok(())
+}
+
+#[test]
+fn continue_errors() {
+    assert!(matches!(
+        compile_error("continue"),
+        Some(CompilerError::ContinueOutsideLoop)
+    ));
+    assert!(matches!(
+        compile_error("for i = 1, 2 do local f = function() continue end end"),
+        Some(CompilerError::ContinueOutsideLoop)
+    ));
+    // The `until` condition of a `repeat` loop can see the locals of its body, so `continue` must
+    // not skip over their declarations.
+    assert!(matches!(
+        compile_error("repeat if true then continue end local x = 1 until x"),
+        Some(CompilerError::JumpLocal(local)) if local == "x"
+    ));
+}
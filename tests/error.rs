@@ -1,6 +1,11 @@
 mod sizes;
 
-use piccolo::{error::LuaError, AnyCallback, Closure, Error, Lua, StaticError, Thread, Value};
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    error::LuaError, stdlib::format_traceback, AnyCallback, Closure, Error, Lua, StaticError,
+    Thread, Value,
+};
 use thiserror::Error;
 
 #[test]
@@ -61,3 +66,279 @@ fn error_tostring() -> Result<(), StaticError> {
 
     lua.run_thread(&thread)
 }
+
+#[test]
+fn assign_to_const() {
+    let mut lua = Lua::core();
+
+    for source in [
+        "local x <const> = 1; x = 2",
+        "local x <const> = 1; local function f() x = 2 end",
+        "local x <close> = nil; x = 2",
+        "local x <const>, y = 1, 2; y, x = 3, 4",
+    ] {
+        lua.run(|ctx| match Closure::load(ctx, source.as_bytes()) {
+            Err(err) => assert_eq!(err.to_string(), "attempt to assign to const variable 'x'"),
+            Ok(_) => panic!("assignment to const variable compiled"),
+        });
+    }
+}
+
+#[test]
+fn illegal_jumps() {
+    let mut lua = Lua::core();
+
+    for (source, message) in [
+        (
+            "goto skip; local x = 1; ::skip:: print(x)",
+            "jumps into the scope of local 'x'",
+        ),
+        ("goto missing", "no visible label 'missing' for goto"),
+        (
+            "do ::inner:: end goto inner",
+            "no visible label 'inner' for goto",
+        ),
+        ("::twice:: ::twice::", "label 'twice' already defined"),
+        ("break", "break outside a loop"),
+    ] {
+        lua.run(|ctx| match Closure::load(ctx, source.as_bytes()) {
+            Err(err) => assert_eq!(err.to_string(), message),
+            Ok(_) => panic!("illegal jump compiled"),
+        });
+    }
+}
+
+#[test]
+fn stack_overflow() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let run = |lua: &mut Lua| -> Result<bool, StaticError> {
+        let thread = lua.try_run(|ctx| {
+            let closure = Closure::load(
+                ctx,
+                &br#"
+                    local function recurse(n)
+                        return 1 + recurse(n + 1)
+                    end
+                    local ok, err = pcall(recurse, 1)
+
+                    -- Tail calls do not grow the stack, and are never limited.
+                    local function loop(n)
+                        if n == 0 then return "done" end
+                        return loop(n - 1)
+                    end
+
+                    return not ok and err == "stack overflow" and loop(1000000) == "done"
+                "#[..],
+            )?;
+            let thread = Thread::new(&ctx);
+            thread.start(ctx, closure.into(), ())?;
+            Ok(ctx.state.registry.stash(&ctx, thread))
+        })?;
+        lua.run_thread::<bool>(&thread)
+    };
+
+    assert!(run(&mut lua)?);
+    lua.set_call_depth_limit(100);
+    assert!(run(&mut lua)?);
+    Ok(())
+}
+
+#[test]
+fn uncaught_error_handler() -> Result<(), StaticError> {
+    let captured = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::core();
+    lua.set_uncaught_error_handler({
+        let captured = captured.clone();
+        move |ctx, error, frames| {
+            let message = match error.to_value(ctx) {
+                Value::String(s) => s.to_str_lossy().into_owned(),
+                v => v.type_name().to_owned(),
+            };
+            captured
+                .borrow_mut()
+                .push((message, format_traceback(frames.iter().copied())));
+        }
+    });
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                -- Errors caught by `pcall`, including ones raised inside of coroutines, are not
+                -- passed to the handler.
+                assert(not pcall(error, "caught"))
+                assert(not pcall(coroutine.wrap(function() error("in coroutine") end)))
+                assert(not coroutine.resume(coroutine.create(function() error("resumed") end)))
+
+                local function inner()
+                    error("uncaught")
+                end
+                local function outer()
+                    inner()
+                end
+                outer()
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    // The error still propagates out of the thread after the handler sees it.
+    let err = lua.run_thread::<()>(&thread).unwrap_err();
+    assert!(err.to_string().contains("uncaught"));
+
+    let captured = captured.borrow();
+    assert_eq!(captured.len(), 1);
+    let (message, traceback) = &captured[0];
+    assert_eq!(message, "uncaught");
+    let lines = traceback.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "stack traceback:");
+    assert_eq!(lines.len(), 4);
+    assert!(lines[3].ends_with("in main chunk"));
+
+    Ok(())
+}
+
+#[test]
+fn uncaught_error_handler_with_close() -> Result<(), StaticError> {
+    let captured = Rc::new(RefCell::new(Vec::new()));
+    let replacement_calls = Rc::new(RefCell::new(0));
+
+    let mut lua = Lua::core();
+    lua.set_uncaught_error_handler({
+        let captured = captured.clone();
+        let replacement_calls = replacement_calls.clone();
+        move |ctx, error, frames| {
+            let message = match error.to_value(ctx) {
+                Value::String(s) => s.to_str_lossy().into_owned(),
+                v => v.type_name().to_owned(),
+            };
+            captured
+                .borrow_mut()
+                .push((message, format_traceback(frames.iter().copied())));
+
+            // The handler may replace itself while it runs.
+            let replacement_calls = replacement_calls.clone();
+            Thread::set_uncaught_error_handler(ctx, move |_, _, _| {
+                *replacement_calls.borrow_mut() += 1;
+            });
+        }
+    });
+
+    let mut run = |source: &'static str| -> Result<(), StaticError> {
+        let thread = lua.try_run(|ctx| {
+            let closure = Closure::load(ctx, source.as_bytes())?;
+            let thread = Thread::new(&ctx);
+            thread.start(ctx, closure.into(), ())?;
+            Ok(ctx.state.registry.stash(&ctx, thread))
+        })?;
+        let err = lua.run_thread::<()>(&thread).unwrap_err();
+        assert!(err.to_string().contains("uncaught"));
+        Ok(())
+    };
+
+    // Unwinding through the `__close` metamethod of a to-be-closed variable passes the error through
+    // a sequence, but the handler still sees it only once, with the call stack where it was raised.
+    run(r#"
+        closed = false
+        local function inner()
+            local guard <close> = setmetatable({}, { __close = function() closed = true end })
+            error("uncaught")
+        end
+        inner()
+    "#)?;
+
+    {
+        let captured = captured.borrow();
+        assert_eq!(captured.len(), 1);
+        let (message, traceback) = &captured[0];
+        assert_eq!(message, "uncaught");
+        let lines = traceback.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("in function 'inner'"));
+        assert!(lines[2].ends_with("in main chunk"));
+    }
+    assert_eq!(*replacement_calls.borrow(), 0);
+
+    run(r#"error("uncaught again")"#)?;
+    assert_eq!(captured.borrow().len(), 1);
+    assert_eq!(*replacement_calls.borrow(), 1);
+
+    lua.run(|ctx| {
+        assert!(matches!(
+            ctx.state.globals.get(ctx, "closed"),
+            Value::Boolean(true)
+        ));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn uncaught_error_handler_with_two_closes() -> Result<(), StaticError> {
+    let captured = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::core();
+    lua.set_uncaught_error_handler({
+        let captured = captured.clone();
+        move |ctx, error, frames| {
+            let message = match error.to_value(ctx) {
+                Value::String(s) => s.to_str_lossy().into_owned(),
+                v => v.type_name().to_owned(),
+            };
+            captured
+                .borrow_mut()
+                .push((message, format_traceback(frames.iter().copied())));
+        }
+    });
+
+    // The first `__close` metamethod to run raises a new error, which the second receives. The
+    // error is still uncaught, and the handler sees the call stack where the original error was
+    // raised rather than none at all.
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                received = nil
+                local function inner()
+                    local outer <close> = setmetatable({}, {
+                        __close = function(_, err) received = err end
+                    })
+                    local guard <close> = setmetatable({}, {
+                        __close = function() error("in close") end
+                    })
+                    error("uncaught")
+                end
+                inner()
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    let err = lua.run_thread::<()>(&thread).unwrap_err();
+    assert!(err.to_string().contains("in close"));
+
+    {
+        let captured = captured.borrow();
+        assert_eq!(captured.len(), 1);
+        let (message, traceback) = &captured[0];
+        assert_eq!(message, "in close");
+        let lines = traceback.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("in function 'inner'"));
+        assert!(lines[2].ends_with("in main chunk"));
+    }
+
+    lua.run(|ctx| {
+        assert!(matches!(
+            ctx.state.globals.get(ctx, "received"),
+            Value::String(s) if s == "in close"
+        ));
+    });
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use piccolo::{AnyCallback, CallbackReturn, Closure, Lua, StaticError, Thread, ThreadMode};
+use piccolo::{AnyCallback, CallbackReturn, Closure, Fuel, Lua, StaticError, Thread, ThreadMode};
 
 #[test]
 fn test_interrupt() -> Result<(), StaticError> {
@@ -28,3 +28,40 @@ fn test_interrupt() -> Result<(), StaticError> {
 
     Ok(())
 }
+
+#[test]
+fn test_fuel_limit() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, &b"local i = 0 while true do i = i + 1 end"[..])?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    let mut fuel = Fuel::with_fuel(10_000);
+    assert!(!lua.finish_thread_with_fuel(&thread, &mut fuel));
+    assert!(fuel.remaining_fuel() <= 0);
+
+    // The paused thread can be resumed with more fuel, and still never completes.
+    fuel.refill(10_000, 10_000);
+    assert!(!lua.finish_thread_with_fuel(&thread, &mut fuel));
+
+    lua.run(|ctx| {
+        assert!(ctx.state.registry.fetch(&thread).mode() == ThreadMode::Normal);
+    });
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, &b"return 1 + 1"[..])?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    let mut fuel = Fuel::with_fuel(10_000);
+    assert!(lua.finish_thread_with_fuel(&thread, &mut fuel));
+    assert_eq!(lua.run_thread::<i64>(&thread)?, 2);
+
+    Ok(())
+}
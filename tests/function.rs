@@ -1,4 +1,8 @@
-use piccolo::{AnyCallback, CallbackReturn, Function, Lua, StaticError, Thread, Variadic};
+use piccolo::{
+    bytecode::{self, UndumpError},
+    AnyCallback, CallbackReturn, Closure, ClosureError, Function, FunctionProto, Lua, StaticError,
+    Table, Thread, Value, Variadic,
+};
 
 #[test]
 fn function_compose_bind() -> Result<(), StaticError> {
@@ -44,3 +48,194 @@ fn function_compose_bind() -> Result<(), StaticError> {
     assert_eq!(lua.run_thread::<i64>(&thread)?, 33);
     Ok(())
 }
+
+#[test]
+fn call_function() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let add = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            "
+                function add(a, b)
+                    return a + b
+                end
+
+                function fail()
+                    error('failed')
+                end
+            "
+            .as_bytes(),
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&add)?;
+
+    let (add, fail) = lua.run(|ctx| {
+        let (Value::Function(add), Value::Function(fail)) = (
+            ctx.state.globals.get(ctx, "add"),
+            ctx.state.globals.get(ctx, "fail"),
+        ) else {
+            panic!("functions not defined");
+        };
+        (
+            ctx.state.registry.stash(&ctx, add),
+            ctx.state.registry.stash(&ctx, fail),
+        )
+    });
+
+    assert_eq!(lua.call_function::<_, i64>(&add, (2, 3))?, 5);
+    assert_eq!(lua.call_function::<_, f64>(&add, (2, 0.5))?, 2.5);
+    assert!(lua.call_function::<_, ()>(&fail, ()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn protected_call() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            "
+                function divide(a, b)
+                    if b == 0 then
+                        error({ code = 42 })
+                    end
+                    return a / b
+                end
+            "
+            .as_bytes(),
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    let divide = lua.run(|ctx| {
+        let Value::Function(divide) = ctx.state.globals.get(ctx, "divide") else {
+            panic!("function not defined");
+        };
+        ctx.state.registry.stash(&ctx, divide)
+    });
+
+    assert_eq!(lua.protected_call::<_, f64>(&divide, (3, 2)).unwrap(), 1.5);
+
+    let error = lua.protected_call::<_, f64>(&divide, (3, 0)).unwrap_err();
+    lua.run(|ctx| {
+        let Value::Table(error) = ctx.state.registry.fetch(&error) else {
+            panic!("error value was not the table passed to error");
+        };
+        assert_eq!(error.get(ctx, "code").to_integer(), Some(42));
+    });
+
+    // Later calls are unaffected by the error.
+    assert_eq!(lua.protected_call::<_, f64>(&divide, (1, 4)).unwrap(), 0.25);
+
+    Ok(())
+}
+
+#[test]
+fn dump_and_load() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let bytes = lua.try_run(|ctx| {
+        let proto = FunctionProto::compile(
+            ctx,
+            &br#"
+                local t = {}
+                for i = 1, 10 do
+                    t[#t + 1] = i * i
+                end
+                return t[3], t[10], #t
+            "#[..],
+        )?;
+        Ok(bytecode::dump(&proto, false))
+    })?;
+
+    let mut lua = Lua::core();
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load_binary(ctx, &bytes, ctx.state.globals)?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert_eq!(lua.run_thread::<(i64, i64, i64)>(&thread)?, (9, 100, 10));
+
+    lua.run(|ctx| {
+        let mut mismatched = bytes.clone();
+        mismatched[bytecode::SIGNATURE.len()] += 1;
+        assert!(matches!(
+            bytecode::undump(ctx, &mismatched),
+            Err(UndumpError::VersionMismatch)
+        ));
+
+        let mut mismatched = bytes.clone();
+        mismatched[bytecode::SIGNATURE.len() + 1] = 1;
+        assert!(matches!(
+            bytecode::undump(ctx, &mismatched),
+            Err(UndumpError::ByteOrderMismatch)
+        ));
+
+        assert!(matches!(
+            bytecode::undump(ctx, &bytes[..bytes.len() - 1]),
+            Err(UndumpError::Truncated)
+        ));
+        assert!(matches!(
+            bytecode::undump(ctx, b"return 1"),
+            Err(UndumpError::NotBinary)
+        ));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn restricted_env() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let os = Table::new(&ctx);
+        os.set(ctx, "name", "host")?;
+        ctx.state.globals.set(ctx, "os", os)?;
+
+        let env = Table::new(&ctx);
+        env.set(ctx, "pcall", ctx.state.globals.get(ctx, "pcall"))?;
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                counter = 1
+                local function bump()
+                    counter = counter + 1
+                end
+                bump()
+                local ok = pcall(function() return os.name end)
+                return ok, counter
+            "#[..],
+        )?;
+        closure.set_env(&ctx, env)?;
+        assert!(matches!(closure.env(), Some(Value::Table(t)) if t == env));
+
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert_eq!(lua.run_thread::<(bool, i64)>(&thread)?, (false, 2));
+
+    lua.run(|ctx| {
+        assert!(ctx.state.globals.get(ctx, "counter").is_nil());
+
+        let closure = Closure::load(ctx, &b"local x = 1 return x"[..]).unwrap();
+        assert!(closure.env().is_none());
+        assert!(matches!(
+            closure.set_env(&ctx, Table::new(&ctx)),
+            Err(ClosureError::NoEnv)
+        ));
+    });
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "json")]
+
+use piccolo::{
+    json::{json_to_table, table_to_json, JsonError},
+    Closure, Lua, StaticError, Table, Thread, Value,
+};
+
+fn round_trip(lua: &mut Lua, json: &'static str) -> String {
+    lua.run(|ctx| table_to_json(json_to_table(ctx, json).unwrap()).unwrap())
+}
+
+#[test]
+fn nested_round_trip() {
+    let mut lua = Lua::core();
+
+    for json in [
+        r#"[1,2.5,"three",true,false]"#,
+        r#"{"a":{"b":[1,{"c":[]}]},"d":"e"}"#,
+        r#"{"escapes":"quote \" backslash \\ newline \n tab \t bell \u0007 snow ☃"}"#,
+        r#"[[[[1]]],{"x":-0.0,"y":1e300,"z":-9223372036854775808}]"#,
+    ] {
+        // An empty array is an empty table, which is written back as an object.
+        let expected = json.replace("[]", "{}");
+        assert_eq!(round_trip(&mut lua, json), expected);
+    }
+
+    assert_eq!(
+        round_trip(&mut lua, " { \"b\" : 2 , \"a\" : [ 1 , 2 ] } "),
+        r#"{"a":[1,2],"b":2}"#
+    );
+    assert_eq!(round_trip(&mut lua, r#"["\ud83d\ude00"]"#), "[\"😀\"]");
+}
+
+#[test]
+fn lua_tables_to_json() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local cycle = {}
+                cycle.self = cycle
+                return {
+                    list = {10, 20, {name = "nested"}},
+                    empty = {},
+                    float = 2.0,
+                }, {1, 2, nil, 4}, {[1.5] = true}, cycle, {f = type}
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.finish_thread(&thread);
+
+    lua.try_run(|ctx| {
+        let (config, holes, float_key, cycle, function): (Table, Table, Table, Table, Table) =
+            ctx.state.registry.fetch(&thread).take_return(ctx)??;
+
+        let json = table_to_json(config)?;
+        assert_eq!(
+            json,
+            r#"{"empty":{},"float":2.0,"list":[10,20,{"name":"nested"}]}"#
+        );
+
+        // Converting back gives an equal table.
+        let restored = json_to_table(ctx, &json)?;
+        assert_eq!(table_to_json(restored)?, json);
+        let Value::Table(list) = restored.get(ctx, "list") else {
+            panic!("list is not a table");
+        };
+        assert_eq!(list.length(), 3);
+        assert!(matches!(restored.get(ctx, "float"), Value::Number(f) if f == 2.0));
+
+        assert!(matches!(
+            table_to_json(holes),
+            Err(JsonError::BadKey("number"))
+        ));
+        assert!(matches!(
+            table_to_json(float_key),
+            Err(JsonError::BadKey("number"))
+        ));
+        assert!(matches!(table_to_json(cycle), Err(JsonError::Cycle)));
+        assert!(matches!(
+            table_to_json(function),
+            Err(JsonError::Unsupported("function"))
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+fn invalid_json() {
+    let mut lua = Lua::core();
+
+    lua.run(|ctx| {
+        assert!(matches!(json_to_table(ctx, "1"), Err(JsonError::NotATable)));
+        assert!(matches!(
+            json_to_table(ctx, "[1,]"),
+            Err(JsonError::Syntax(3))
+        ));
+        assert!(matches!(
+            json_to_table(ctx, "{\"a\" 1}"),
+            Err(JsonError::Syntax(5))
+        ));
+        assert!(matches!(
+            json_to_table(ctx, "[1] x"),
+            Err(JsonError::Syntax(4))
+        ));
+        assert!(matches!(
+            json_to_table(ctx, "[\"\\ud800\"]"),
+            Err(JsonError::Syntax(_))
+        ));
+
+        let deep = "[".repeat(1000) + &"]".repeat(1000);
+        assert!(matches!(json_to_table(ctx, &deep), Err(JsonError::TooDeep)));
+    });
+}
@@ -0,0 +1,296 @@
+use piccolo::{
+    raw_ops, ArithmeticMode, Closure, Constant, FloatDivideByZero, Lua, StaticError, Table, Thread,
+    Value,
+};
+
+fn run_lua(lua: &mut Lua, source: &'static str) -> Result<Vec<i64>, StaticError> {
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, source.as_bytes())?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<Vec<i64>>(&thread)
+}
+
+#[test]
+fn independent_rng() -> Result<(), StaticError> {
+    const SEED: &str = "math.randomseed(1234); return {}";
+    const DRAW: &str = r#"
+        local t = {}
+        for i = 1, 16 do
+            t[i] = math.random(1, 1000000)
+        end
+        return t
+    "#;
+
+    let mut lua_a = Lua::core();
+    let mut lua_b = Lua::core();
+
+    run_lua(&mut lua_a, SEED)?;
+    run_lua(&mut lua_b, SEED)?;
+
+    // Drawing from one interpreter must not advance the generator of the other.
+    let a1 = run_lua(&mut lua_a, DRAW)?;
+    let a2 = run_lua(&mut lua_a, DRAW)?;
+    let b1 = run_lua(&mut lua_b, DRAW)?;
+    let b2 = run_lua(&mut lua_b, DRAW)?;
+
+    assert_eq!(a1, b1);
+    assert_eq!(a2, b2);
+    assert_ne!(a1, a2);
+    Ok(())
+}
+
+#[test]
+fn interleaved_rng() -> Result<(), StaticError> {
+    const DRAW: &str = "return { math.random(1, 1000000), math.random(1, 1000000) }";
+
+    let mut lua_a = Lua::core();
+    let mut lua_b = Lua::core();
+    run_lua(&mut lua_a, "math.randomseed(1); return {}")?;
+    run_lua(&mut lua_b, "math.randomseed(2); return {}")?;
+
+    // Reseeding `lua_b` between draws from `lua_a` must not disturb the sequence of `lua_a`.
+    let a1 = run_lua(&mut lua_a, DRAW)?;
+    run_lua(&mut lua_b, "math.randomseed(1); return {}")?;
+    let a2 = run_lua(&mut lua_a, DRAW)?;
+    let b1 = run_lua(&mut lua_b, DRAW)?;
+    let b2 = run_lua(&mut lua_b, DRAW)?;
+
+    assert_eq!((a1, a2), (b1, b2));
+    Ok(())
+}
+
+#[cfg(feature = "compat51")]
+#[test]
+fn pow_alias() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    let r = run_lua(
+        &mut lua,
+        "return { math.type(math.pow(2, 3)) == 'float' and 1 or 0, math.pow(2, 10) }",
+    )?;
+    assert_eq!(r, vec![1, 1024]);
+    Ok(())
+}
+
+#[test]
+fn exact_powers() {
+    fn pow(a: f64, b: f64) -> f64 {
+        match Constant::<&[u8]>::Number(a).exponentiate(&Constant::Number(b)) {
+            Some(Constant::Number(n)) => n,
+            r => panic!("unexpected result {r:?}"),
+        }
+    }
+
+    assert_eq!(pow(10.0, 3.0), 1000.0);
+    assert_eq!(pow(10.0, 22.0), 1e22);
+    assert_eq!(pow(-3.0, 3.0), -27.0);
+    assert_eq!(pow(2.0, 64.0), 18446744073709551616.0);
+    assert!(pow(-0.0, 3.0).is_sign_negative());
+
+    // Results which are not exactly representable, or whose base is not an integer, are left to
+    // `powf` rather than accumulating rounding errors over repeated multiplications.
+    assert_eq!(pow(1.1, 50.0), 1.1f64.powf(50.0));
+    assert_eq!(pow(3.0, 40.0), 3.0f64.powf(40.0));
+    assert_eq!(pow(2.0, 0.5), 2.0f64.powf(0.5));
+}
+
+#[test]
+fn arithmetic_modes() -> Result<(), StaticError> {
+    const OVERFLOW: &str = "return { math.maxinteger + 1 }";
+
+    let mut lua = Lua::core();
+    assert_eq!(run_lua(&mut lua, OVERFLOW)?, vec![i64::MIN]);
+
+    lua.set_arithmetic_mode(ArithmeticMode::Checked);
+    assert_eq!(
+        run_lua(&mut lua, OVERFLOW).unwrap_err().to_string(),
+        "runtime error: integer overflow"
+    );
+    assert_eq!(
+        run_lua(
+            &mut lua,
+            r#"
+                local ok, err = pcall(function() return math.mininteger - 1 end)
+                assert(not ok and tostring(err) == "integer overflow")
+                assert(not pcall(function() return math.maxinteger * 2 end))
+                -- Constant expressions that overflow are not folded away at compile time.
+                assert(not pcall(function() return 9223372036854775807 + 1 end))
+                assert(math.maxinteger + 1.0 == 2^63)
+                return { math.maxinteger - 1 + 1, 3 * -4 }
+            "#
+        )?,
+        vec![i64::MAX, -12]
+    );
+
+    lua.set_arithmetic_mode(ArithmeticMode::Wrapping);
+    assert_eq!(run_lua(&mut lua, OVERFLOW)?, vec![i64::MIN]);
+
+    Ok(())
+}
+
+#[test]
+fn division_by_zero() -> Result<(), StaticError> {
+    // Integer `//` and `%` by zero always raise, whatever the float policy is.
+    const INTEGER: &str = r#"
+        local ok, err = pcall(function() return 1 // 0 end)
+        assert(not ok and tostring(err) == "attempt to perform 'n//0'")
+        ok, err = pcall(function() local z = 0; return 1 % z end)
+        assert(not ok and tostring(err) == "attempt to perform 'n%0'")
+        return {}
+    "#;
+
+    let mut lua = Lua::core();
+    run_lua(&mut lua, INTEGER)?;
+    assert_eq!(
+        run_lua(
+            &mut lua,
+            r#"
+                local zero = 0.0
+                assert(1 / 0 == math.huge and -1 / 0 == -math.huge)
+                assert(1.0 // zero == math.huge)
+                local nan = 1.5 % zero
+                assert(nan ~= nan)
+                return { math.type(1 / 0) == "float" and 1 or 0 }
+            "#
+        )?,
+        vec![1]
+    );
+
+    lua.set_float_divide_by_zero(FloatDivideByZero::Error);
+    run_lua(&mut lua, INTEGER)?;
+    assert_eq!(
+        run_lua(
+            &mut lua,
+            r#"
+                local zero = 0.0
+                local ok, err = pcall(function() return 1.0 / zero end)
+                assert(not ok and tostring(err) == "attempt to divide by zero")
+                -- Constant expressions are not folded away at compile time.
+                assert(not pcall(function() return 1 / 0 end))
+                assert(not pcall(function() return 1.0 // 0 end))
+                assert(not pcall(function() return 2.5 % -0.0 end))
+                assert(not pcall(function() return "1" / "0" end))
+                return { 7 // 2, 7.0 / 2 == 3.5 and 1 or 0 }
+            "#
+        )?,
+        vec![3, 1]
+    );
+
+    lua.set_float_divide_by_zero(FloatDivideByZero::Ieee);
+    assert_eq!(
+        run_lua(&mut lua, "return { 1 / 0 == math.huge and 1 or 0 }")?,
+        vec![1]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn random_float() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    let r = run_lua(
+        &mut lua,
+        r#"
+            local in_range, low_bits = 1, {}
+            for i = 1, 4096 do
+                local x = math.random()
+                if math.type(x) ~= "float" or x < 0 or x >= 1 then
+                    in_range = 0
+                end
+                -- Every draw is a multiple of 2^-53, so this is the lowest random bit.
+                low_bits[(x * 2^53) % 2] = true
+            end
+            return { in_range, low_bits[0] and 1 or 0, low_bits[1] and 1 or 0 }
+        "#,
+    )?;
+    assert_eq!(r, vec![1, 1, 1]);
+    Ok(())
+}
+
+#[test]
+fn library_matches_operators() -> Result<(), StaticError> {
+    // True if both values have the same type and value, telling apart 0.0 and -0.0 and treating
+    // NaN as equal to itself.
+    fn same(a: Value, b: Value) -> bool {
+        match (a, b) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => {
+                (a.is_nan() && b.is_nan())
+                    || (a == b && a.is_sign_negative() == b.is_sign_negative())
+            }
+            _ => false,
+        }
+    }
+
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local values = {
+                    0, -0.0, 7, -7, 2.5, -2.5, math.maxinteger, math.mininteger, 1e300, 1 / 0,
+                    0 / 0, "3", "-1.5",
+                }
+                local function try(f)
+                    local ok, r = pcall(f)
+                    if ok then return r end
+                end
+
+                local results = {}
+                for _, a in ipairs(values) do
+                    for _, b in ipairs(values) do
+                        results[#results + 1] = {
+                            a, b, a + b, a - b, a * b, a / b, a ^ b, -a, math.abs(a),
+                            math.floor(a), math.ceil(a),
+                            try(function() return a // b end),
+                            try(function() return a % b end),
+                        }
+                    end
+                end
+                return results
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    lua.finish_thread(&thread);
+    lua.try_run(|ctx| {
+        let results: Table = ctx.state.registry.fetch(&thread).take_return(ctx)??;
+        assert_eq!(results.length(), 13 * 13);
+
+        for (_, row) in results.iter_array() {
+            let Value::Table(row) = row else {
+                panic!("result row is not a table");
+            };
+            let [a, b] = row.get_many(ctx, [1, 2]);
+            let expected = [
+                raw_ops::add(a, b),
+                raw_ops::subtract(a, b),
+                raw_ops::multiply(a, b),
+                raw_ops::float_divide(a, b),
+                raw_ops::exponentiate(a, b),
+                raw_ops::negate(a),
+                raw_ops::abs(a),
+                raw_ops::floor(a),
+                raw_ops::ceil(a),
+                raw_ops::idiv(a, b),
+                raw_ops::modulo(a, b),
+            ];
+            for (i, expected) in expected.into_iter().enumerate() {
+                let actual = row.get(ctx, i as i64 + 3);
+                assert!(
+                    same(actual, expected.unwrap_or(Value::Nil)),
+                    "result {} for {a} and {b} was {actual}, expected {expected:?}",
+                    i + 3
+                );
+            }
+        }
+        Ok(())
+    })
+}
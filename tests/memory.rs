@@ -0,0 +1,137 @@
+use piccolo::{Closure, InvalidTableKey, Lua, SizeLimits, StaticError, Table, Thread};
+
+#[test]
+fn memory_limit() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.set_memory_limit(Some(lua.total_memory() + 1024 * 1024));
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local ok, err = pcall(function()
+                    local t = {}
+                    local i = 1
+                    while true do
+                        t[i] = {}
+                        i = i + 1
+                    end
+                end)
+                assert(not ok and tostring(err) == "not enough memory")
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    lua.gc_collect();
+
+    // Without a `pcall`, the error stops the script.
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local t = {}
+                local i = 1
+                while true do
+                    t[i] = {}
+                    i = i + 1
+                end
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    let err = lua.run_thread::<()>(&thread).unwrap_err();
+    assert_eq!(err.to_string(), "runtime error: not enough memory");
+
+    Ok(())
+}
+
+#[test]
+fn memory_limit_on_allocation() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+    let limit = lua.total_memory() + 1024 * 1024;
+    lua.set_memory_limit(Some(limit));
+
+    // A single large string is refused before it is built.
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local ok, err = pcall(string.rep, "x", 64 * 1024 * 1024)
+                assert(not ok and tostring(err) == "not enough memory")
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+    assert!(lua.total_memory() < limit);
+
+    // Tables refuse to grow past the limit even when no thread is running to check it.
+    lua.run(|ctx| {
+        let table = Table::with_instance_hashing(ctx);
+        let mut i = 1i64;
+        let err = loop {
+            if let Err(err) = table.set(ctx, i, i) {
+                break err;
+            }
+            i += 1;
+        };
+        assert!(matches!(err, InvalidTableKey::NotEnoughMemory));
+        assert!(ctx.metrics().total_allocation() <= limit);
+    });
+
+    Ok(())
+}
+
+#[test]
+fn size_limits() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+    lua.set_size_limits(SizeLimits {
+        max_string_len: 1000,
+        max_table_len: 100,
+    });
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                assert(#string.rep("ab", 500) == 1000)
+                assert(#string.rep("a", 334, "bb") == 1000)
+                assert(string.rep("abc", 0) == "")
+
+                local ok, err = pcall(string.rep, "ab", 501)
+                assert(not ok and string.find(tostring(err), "string length overflow", 1, true))
+                ok, err = pcall(string.rep, "x", math.maxinteger)
+                assert(not ok and string.find(tostring(err), "string length overflow", 1, true))
+
+                local t = {}
+                local n = 0
+                ok, err = pcall(function()
+                    while true do
+                        t[n + 1] = n
+                        n = n + 1
+                    end
+                end)
+                assert(not ok and tostring(err) == "table overflow")
+                assert(n >= 100 and n <= 200)
+
+                -- Keys already in the table can still be updated.
+                t[1] = "one"
+                assert(t[1] == "one")
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use piccolo::{AnyCallback, CallbackReturn, Closure, Lua, StaticError, Table, Thread, Value};
+
+#[test]
+fn preload_from_rust() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let Value::Table(package) = ctx.globals().get(ctx, "package") else {
+            panic!("package is not a table");
+        };
+        let Value::Table(preload) = package.get(ctx, "preload") else {
+            panic!("package.preload is not a table");
+        };
+        preload.set(
+            ctx,
+            "host",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let module = Table::new(&ctx);
+                module.set(ctx, "answer", 42)?;
+                stack.replace(ctx, module);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local host = require("host")
+                return host.answer, package.loaded.host == host
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert_eq!(lua.run_thread::<(i64, bool)>(&thread)?, (42, true));
+
+    Ok(())
+}
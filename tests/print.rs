@@ -0,0 +1,46 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+use piccolo::{stdlib::PrintConfig, Closure, Lua, StaticError, Thread};
+
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn print_config() -> Result<(), StaticError> {
+    let captured = Captured::default();
+
+    let mut lua = Lua::core();
+    lua.load_io_with(PrintConfig {
+        output: Box::new(captured.clone()),
+        separator: b",".to_vec(),
+        terminator: Vec::new(),
+    });
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                print(1, "two", nil, setmetatable({}, { __tostring = function() return "t" end }))
+                print()
+                print(true)
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    assert_eq!(&captured.0.borrow()[..], b"1,two,nil,ttrue");
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    AnyCallback, CallbackReturn, Closure, Lua, StaticError, StaticTable, StaticValue, Table,
+    Thread, Value,
+};
+
+#[test]
+fn stash_across_collection() -> Result<(), StaticError> {
+    let kept: Rc<RefCell<Option<StaticTable>>> = Rc::default();
+
+    let mut lua = Lua::core();
+    let thread = lua.try_run(|ctx| {
+        let kept = kept.clone();
+        let keep = AnyCallback::from_fn(&ctx, move |ctx, _, stack| {
+            let table: Table = stack.consume(ctx)?;
+            *kept.borrow_mut() = Some(ctx.stash(table));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.globals().set(ctx, "keep", keep)?;
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                keep({ name = "kept", 1, 2, 3, nested = { value = 42 } })
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    let value = lua.run(|ctx| ctx.stash(ctx.intern("stashed value")));
+    assert!(matches!(value, StaticValue::String(_)));
+
+    // Nothing in Lua refers to the table any more, only the stashed handle keeps it alive.
+    lua.run(|ctx| {
+        ctx.globals().set(ctx, "keep", Value::Nil).unwrap();
+    });
+    lua.gc_collect();
+    lua.gc_collect();
+
+    lua.run(|ctx| {
+        let table = ctx.fetch(kept.borrow().as_ref().unwrap());
+        assert!(matches!(table.get(ctx, "name"), Value::String(s) if s == "kept"));
+        assert_eq!(table.length(), 3);
+        assert_eq!(
+            table.get_path(ctx, &["nested", "value"]).to_integer(),
+            Some(42)
+        );
+
+        assert!(matches!(ctx.fetch(&value), Value::String(s) if s == "stashed value"));
+    });
+
+    Ok(())
+}
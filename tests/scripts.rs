@@ -18,12 +18,18 @@ fn test_scripts() {
         let file = io::buffered_read(File::open(&path).unwrap()).unwrap();
         if let Some(ext) = path.extension() {
             if ext == "lua" {
+                // The `os` library touches the filesystem and is only loaded with `std`.
+                if cfg!(not(feature = "std")) && path.file_name() == Some("os.lua".as_ref()) {
+                    let _ = writeln!(stdout(), "skipping file {:?}", path);
+                    continue;
+                }
+
                 let _ = writeln!(stdout(), "running {:?}", path);
                 let mut lua = Lua::full();
 
                 if let Err(err) = lua
                     .try_run(|ctx| {
-                        let closure = Closure::load(ctx, file)?;
+                        let closure = Closure::load_with_name(ctx, &path.to_string_lossy(), file)?;
                         let thread = Thread::new(&ctx);
                         thread.start(ctx, closure.into(), ())?;
                         Ok(ctx.state.registry.stash(&ctx, thread))
@@ -0,0 +1,167 @@
+local function closer(log, name)
+    return setmetatable({}, {
+        __close = function(value, err)
+            log[#log + 1] = name
+            if err ~= nil then
+                log[#log + 1] = err
+            end
+        end,
+    })
+end
+
+local function test1()
+    local log = {}
+    do
+        local a <close> = closer(log, "a")
+        local b <close> = closer(log, "b")
+        log[#log + 1] = "body"
+    end
+    return #log == 3 and log[1] == "body" and log[2] == "b" and log[3] == "a"
+end
+
+local function test2()
+    local log = {}
+    for i = 1, 3 do
+        local x <close> = closer(log, i)
+        if i == 2 then
+            break
+        end
+    end
+    return #log == 2 and log[1] == 1 and log[2] == 2
+end
+
+local function test3()
+    local log = {}
+    local function f()
+        local x <close> = closer(log, "x")
+        local y <close> = closer(log, "y")
+        return "r1", "r2", "r3"
+    end
+    local a, b, c = f()
+    return a == "r1" and b == "r2" and c == "r3" and #log == 2 and log[1] == "y" and log[2] == "x"
+end
+
+local function test4()
+    local log = {}
+    local function g(...)
+        return ...
+    end
+    local function f()
+        local x <close> = closer(log, "x")
+        return g(1, 2, 3)
+    end
+    local a, b, c = f()
+    return a == 1 and b == 2 and c == 3 and #log == 1 and log[1] == "x"
+end
+
+local function test5()
+    local log = {}
+    local ok, err = pcall(function()
+        local x <close> = closer(log, "x")
+        local y <close> = closer(log, "y")
+        error("oops", 0)
+    end)
+    return not ok and err == "oops" and #log == 4 and
+        log[1] == "y" and log[2] == "oops" and log[3] == "x" and log[4] == "oops"
+end
+
+local function test6()
+    local log = {}
+    local ok, err = pcall(function()
+        local x <close> = closer(log, "x")
+        local y <close> = setmetatable({}, {
+            __close = function()
+                error("replaced", 0)
+            end,
+        })
+        error("original", 0)
+    end)
+    return not ok and err == "replaced" and #log == 2 and log[1] == "x" and log[2] == "replaced"
+end
+
+local function test7()
+    local a <close> = nil
+    local b <close> = false
+    local ok = pcall(function()
+        local c <close> = {}
+    end)
+    return not ok
+end
+
+local function test8()
+    local log = {}
+    local i = 0
+    ::top::
+    do
+        local x <close> = closer(log, i)
+        i = i + 1
+        if i < 3 then
+            goto top
+        end
+    end
+    return #log == 3 and log[1] == 0 and log[2] == 1 and log[3] == 2
+end
+
+local function test9()
+    local log = {}
+    local co = coroutine.wrap(function()
+        local x <close> = closer(log, "x")
+        coroutine.yield(1)
+        return 2
+    end)
+    return co() == 1 and #log == 0 and co() == 2 and #log == 1 and log[1] == "x"
+end
+
+local function test10()
+    local log = {}
+    local function range(n)
+        local i = 0
+        local function next_value()
+            i = i + 1
+            if i <= n then
+                return i
+            end
+        end
+        return next_value, nil, nil, closer(log, "range")
+    end
+
+    local sum = 0
+    for i in range(5) do
+        sum = sum + i
+        if i == 3 then
+            break
+        end
+    end
+    if sum ~= 6 or #log ~= 1 or log[1] ~= "range" then
+        return false
+    end
+
+    for i in range(2) do
+        log[#log + 1] = i
+    end
+    if #log ~= 4 or log[2] ~= 1 or log[3] ~= 2 or log[4] ~= "range" then
+        return false
+    end
+
+    local function find(n)
+        for i in range(10) do
+            if i == n then
+                return i
+            end
+        end
+    end
+    return find(4) == 4 and #log == 5 and log[5] == "range"
+end
+
+assert(
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6() and
+    test7() and
+    test8() and
+    test9() and
+    test10()
+)
@@ -0,0 +1,41 @@
+local function test1()
+    local x <const> = 10
+    local y <const>, z = 20, 30
+    z = x + y
+    return z == 30
+end
+
+local function test2()
+    local x <const> = "outer"
+    local function f()
+        local x = "inner"
+        x = "assigned"
+        return x
+    end
+    return f() == "assigned" and x == "outer"
+end
+
+local function test3()
+    local x <const> = 1
+    do
+        local x = 2
+        x = 3
+        if x ~= 3 then
+            return false
+        end
+    end
+    return x == 1
+end
+
+local function test4()
+    local t <const> = {}
+    t.field = 1
+    return t.field == 1
+end
+
+assert(
+    test1() and
+    test2() and
+    test3() and
+    test4()
+)
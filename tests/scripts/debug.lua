@@ -0,0 +1,175 @@
+local function find(s, part)
+    for i = 1, #s - #part + 1 do
+        if string.sub(s, i, i + #part - 1) == part then
+            return i
+        end
+    end
+    return nil
+end
+
+do
+    local trace
+    local function inner()
+        trace = debug.traceback("message")
+    end
+    local function middle()
+        inner()
+    end
+    function outer()
+        middle()
+    end
+    outer()
+
+    assert(string.sub(trace, 1, 24) == "message\nstack traceback:")
+    local inner_pos = find(trace, "in function 'inner'")
+    local middle_pos = find(trace, "in function 'middle'")
+    local outer_pos = find(trace, "in function 'outer'")
+    local main_pos = find(trace, "in main chunk")
+    assert(inner_pos and middle_pos and outer_pos and main_pos)
+    assert(inner_pos < middle_pos and middle_pos < outer_pos and outer_pos < main_pos)
+    assert(find(trace, "debug.lua:") ~= nil)
+end
+
+do
+    local t = {}
+    assert(debug.traceback(t) == t)
+    assert(find(debug.traceback(), "stack traceback:") == 1)
+end
+
+do
+    local up = 1
+    local function named()
+        local first = debug.getinfo(1)
+        local second = debug.getinfo(1)
+        return first, second, up
+    end
+    local first, second = named()
+    assert(first.what == "Lua")
+    assert(first.name == "named")
+    assert(first.nups == 1)
+    assert(second.currentline == first.currentline + 1)
+    assert(find(first.source, "debug.lua") ~= nil)
+
+    local main = debug.getinfo(1)
+    assert(main.what == "main")
+
+    local anonymous = debug.getinfo(function() end)
+    assert(anonymous.what == "Lua" and anonymous.name == nil and anonymous.currentline == -1)
+
+    assert(debug.getinfo(print).what == "C")
+    assert(debug.getinfo(0).what == "C")
+    assert(debug.getinfo(100) == nil)
+end
+
+do
+    local lines = {}
+    local function f()
+        local a = 1
+        local b = 2
+        return a + b
+    end
+    local defined = debug.getinfo(f).linedefined
+    local start = debug.getinfo(1).currentline
+    debug.sethook(function(event, line)
+        assert(event == "line")
+        lines[#lines + 1] = line
+    end, "l")
+    f()
+    debug.sethook()
+
+    assert(#lines == 5)
+    assert(lines[1] == start + 5)
+    assert(lines[2] == defined + 1)
+    assert(lines[3] == defined + 2)
+    assert(lines[4] == defined + 3)
+    assert(lines[5] == start + 6)
+end
+
+do
+    local events = {}
+    local function g() end
+    debug.sethook(function(event) events[#events + 1] = event end, "cr")
+    g()
+    debug.sethook()
+    assert(#events == 2 and events[1] == "call" and events[2] == "return")
+end
+
+do
+    -- A return that first closes to-be-closed variables reports its line and return events once.
+    local events = {}
+    local mt = { __close = rawget }
+    local function f()
+        local a <close> = setmetatable({}, mt)
+        local b <close> = setmetatable({}, mt)
+        return
+    end
+    local defined = debug.getinfo(f).linedefined
+    debug.sethook(function(event, line)
+        events[#events + 1] = line and line - defined or event
+    end, "lr")
+    f()
+    debug.sethook()
+
+    assert(#events == 6)
+    assert(events[1] == 9)
+    assert(events[2] == 1 and events[3] == 2 and events[4] == 3)
+    assert(events[5] == "return")
+    assert(events[6] == 10)
+end
+
+do
+    local count = 0
+    local function hook(event)
+        assert(event == "count")
+        count = count + 1
+    end
+    debug.sethook(hook, "", 1)
+    local h, mask, n = debug.gethook()
+    debug.sethook()
+    assert(count > 0)
+    assert(h == hook and mask == "" and n == 1)
+    assert(debug.gethook() == nil)
+end
+
+do
+    local function inspect()
+        local name, value = debug.getlocal(2, 1)
+        return name, value
+    end
+    local function target(a)
+        local b = a * 2
+        local n1, v1 = debug.getlocal(1, 1)
+        local n2, v2 = debug.getlocal(1, 2)
+        assert(n1 == "a" and v1 == 21)
+        assert(n2 == "b" and v2 == 42)
+        assert(debug.setlocal(1, 2, 7) == "b")
+        assert(b == 7)
+        local n, v = inspect()
+        assert(n == "a" and v == 21)
+        return true
+    end
+    assert(target(21))
+    assert(debug.getlocal(target, 1) == "a")
+    assert(debug.getlocal(target, 2) == nil)
+    assert(not pcall(debug.getlocal, 100, 1))
+end
+
+do
+    local x = 10
+    local function f() return x end
+    local name, value = debug.getupvalue(f, 1)
+    assert(name == "x" and value == 10)
+    assert(debug.setupvalue(f, 1, 20) == "x")
+    assert(f() == 20 and x == 20)
+    assert(debug.getupvalue(f, 2) == nil)
+
+    local function make()
+        local c = 5
+        return function() return c end
+    end
+    local g = make()
+    local _, v = debug.getupvalue(g, 1)
+    assert(v == 5)
+    assert(debug.setupvalue(g, 1, 6) == "c")
+    assert(g() == 6)
+end
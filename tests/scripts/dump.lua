@@ -0,0 +1,70 @@
+local function test1()
+    local function f(n, ...)
+        local t = { ... }
+        local sum = 0
+        for i = 1, n do
+            sum = sum + (t[i] or 0) * 2.5
+        end
+        local function inner(x)
+            return x .. ":" .. math.floor(sum)
+        end
+        return inner("sum"), select("#", ...)
+    end
+
+    local g = load(string.dump(f), "f", "b")
+    local a1, b1 = f(3, 1, 2, 3, 4)
+    local a2, b2 = g(3, 1, 2, 3, 4)
+    return type(g) == "function" and g ~= f and a1 == a2 and b1 == b2 and a2 == "sum:15"
+end
+
+local function test2()
+    local chunk = load("local a, b = ... return a * b, 'chunk'")
+    local stripped = load(string.dump(chunk, true), "stripped", "b")
+    local x, y = stripped(6, 7)
+    return x == 42 and y == "chunk"
+end
+
+local function test3()
+    local dumped = string.dump(function() return 1 end)
+    local f1, err1 = load(dumped, "binary", "t")
+    local f2, err2 = load("return 1", "text", "b")
+    local f3, err3 = load(string.sub(dumped, 1, -2), "truncated", "bt")
+    local f4, err4 = load("return +")
+    local f5, err5 = load(dumped)
+    return f1 == nil and err1 == "attempt to load a binary chunk (mode is 't')" and
+        f2 == nil and err2 == "attempt to load a text chunk (mode is 'b')" and
+        f3 == nil and err3 == "truncated precompiled chunk" and
+        f4 == nil and type(err4) == "string" and
+        f5 == nil and err5 == "attempt to load a binary chunk (mode is 't')" and
+        not pcall(string.dump, print)
+end
+
+local function test4()
+    local env = { value = 10 }
+    local f = load(string.dump(function() return value end), "env", "b", env)
+    local g = load("value = value + 1 return value", "env", "t", env)
+    return f() == 10 and g() == 11 and env.value == 11 and value == nil
+end
+
+local function test5()
+    local x = 1
+    local function f(a, b)
+        return a + b + x
+    end
+    local stripped = load(string.dump(f, true), "stripped", "b")
+    local name, value = debug.getupvalue(stripped, 1)
+    local set_name = debug.setupvalue(stripped, 1, 5)
+    return debug.getlocal(f, 1) == "a" and
+        debug.getlocal(stripped, 1) == "?" and
+        debug.getlocal(stripped, 3) == nil and
+        name == "?" and value == _G and set_name == "?" and
+        stripped(1, 2) == 8
+end
+
+assert(
+    test1() and
+    test2() and
+    test3() and
+    test4() and
+    test5()
+)
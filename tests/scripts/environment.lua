@@ -14,7 +14,23 @@ local function test2()
     return _ENV.i == 3 and i == 3
 end
 
+local function test3()
+    local env = { tostring = tostring }
+    local f = load("x = 5 return tostring(x), pcall ~= nil", "chunk", "t", env)
+    local s, has_pcall = f()
+    return s == "5" and not has_pcall and env.x == 5 and x == nil
+end
+
+local function test4()
+    _G.test4var = 1
+    test4var2 = 2
+    return test4var == 1 and _G.test4var2 == 2 and _G._G == _G and _G.print == print and
+        _ENV == _G
+end
+
 assert(
     test1() and
-    test2()
+    test2() and
+    test3() and
+    test4()
 )
\ No newline at end of file
@@ -81,10 +81,120 @@ function test_break_scope()
     return true
 end
 
+function test_numeric_max_integer()
+    local count = 0
+    local last
+    for i = math.maxinteger - 2, math.maxinteger do
+        count = count + 1
+        last = i
+    end
+    if count ~= 3 or last ~= math.maxinteger then
+        return false
+    end
+
+    count = 0
+    for i = math.mininteger + 2, math.mininteger, -1 do
+        count = count + 1
+        last = i
+    end
+    if count ~= 3 or last ~= math.mininteger then
+        return false
+    end
+
+    count = 0
+    for i = math.maxinteger - 5, math.maxinteger, 4 do
+        count = count + 1
+        last = i
+    end
+    if count ~= 2 or last ~= math.maxinteger - 1 then
+        return false
+    end
+
+    count = 0
+    for i = math.mininteger, math.maxinteger, math.maxinteger do
+        count = count + 1
+        last = i
+    end
+    return count == 3 and last == math.maxinteger - 1
+end
+
+function test_numeric_mixed()
+    local values = {}
+    for i = 1, 3, 0.5 do
+        values[#values + 1] = i
+    end
+    if #values ~= 5 or values[5] ~= 3 or math.type(values[1]) ~= "float" then
+        return false
+    end
+
+    local sum = 0
+    for i = 1, 3 do
+        if math.type(i) ~= "integer" then
+            return false
+        end
+        sum = sum + i
+    end
+    if sum ~= 6 then
+        return false
+    end
+
+    -- An integer initial value and step loop over integers, even with a float limit.
+    local last
+    for i = 1, 3.5 do
+        last = i
+    end
+    if last ~= 3 or math.type(last) ~= "integer" then
+        return false
+    end
+    for i = 3, 1.5, -1 do
+        last = i
+    end
+    if last ~= 2 or math.type(last) ~= "integer" then
+        return false
+    end
+    for i = 1, 1e100 do
+        last = i
+        break
+    end
+    if last ~= 1 then
+        return false
+    end
+
+    local ran = false
+    for i = 1, -1e100 do
+        ran = true
+    end
+    return not ran
+end
+
+function test_numeric_errors()
+    local ok, err = pcall(function()
+        for i = 1, 10, 0 do end
+    end)
+    if ok or tostring(err) ~= "'for' step is zero" then
+        return false
+    end
+
+    ok, err = pcall(function()
+        for i = 1.0, 10, 0.0 do end
+    end)
+    if ok or tostring(err) ~= "'for' step is zero" then
+        return false
+    end
+
+    ok, err = pcall(function()
+        for i = 1, {} do end
+    end)
+    return not ok and tostring(err) == "'for' limit must be a number"
+end
+
 assert(
     test_generic() and
     test_numeric() and
     test_numeric_closure() and
     test_generic_closure() and
-    test_break_scope()
+    test_break_scope() and
+    test_numeric_max_integer() and
+    test_numeric_mixed() and
+    test_numeric_errors()
 )
@@ -30,7 +30,21 @@ function test2()
     goto start
 end
 
+function test3()
+    local odd = 0
+    for i = 1, 10 do
+        if i % 2 == 0 then
+            goto next
+        end
+        local doubled = i * 2
+        odd = odd + doubled
+        ::next::
+    end
+    return odd == 50
+end
+
 assert(
     test1() and
-    test2()
+    test2() and
+    test3()
 )
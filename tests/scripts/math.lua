@@ -148,7 +148,6 @@ function test15()
            math.min(5, 4, 3, 2, 1, 0, -10, -9, -8, -7, -6, -5, -4, -3, -2, -1) == -10
     -- Tests we do not currently pass due to incompatibility with PUC-Rio Lua
     --     is_err(math.min(1, "2", 1))
-    --     is_nan(math.min(0.0 % 0.0, 1, 2))
 end
 
 function test16()
@@ -282,6 +281,65 @@ function test24()
                math.ult(1, 2)
 end
 
+function test25()
+    local function message(f, ...)
+        local ok, err = pcall(f, ...)
+        return not ok and err
+    end
+
+    return message(math.sin, "x") == "bad argument #1 to 'sin' (number expected, got string)" and
+           message(math.atan, 1, {}) == "bad argument #2 to 'atan' (number expected, got table)" and
+           message(math.ult, 1) == "bad argument #2 to 'ult' (number expected, got no value)" and
+           message(math.ult, 1.5, 1) == "bad argument #1 to 'ult' (number has no integer representation)"
+end
+
+function test26()
+    local two = 2
+    return math.type(2^2) == "float" and 2^2 == 4.0 and
+           math.type(two^two) == "float" and two^two == 4.0 and
+           math.type(two^-1) == "float" and two^-1 == 0.5 and
+           math.type("2"^2) == "float"
+end
+
+function test27()
+    local a, b = 7, 2
+    local ip, fp = math.modf(3.5)
+    local mi, mf = math.modf(-3)
+    return math.type(a + b) == "integer" and a + b == 9 and
+           math.type(a - b) == "integer" and
+           math.type(a * b) == "integer" and a * b == 14 and
+           math.type(a // b) == "integer" and a // b == 3 and
+           math.type(a % b) == "integer" and a % b == 1 and
+           math.type(a / b) == "float" and a / b == 3.5 and
+           math.type(4 / 2) == "float" and
+           math.type(a + 1.0) == "float" and
+           math.type(7.0 // 2) == "float" and
+           math.type("3" + 4) == "integer" and
+           math.type("3.0" + 4) == "float" and
+           math.type(math.abs(-3)) == "integer" and
+           math.abs(math.mininteger) == math.mininteger and
+           math.type(math.abs("-3")) == "integer" and
+           math.floor(math.maxinteger) == math.maxinteger and
+           math.ceil(math.mininteger + 1) == math.mininteger + 1 and
+           math.type(math.floor(2.5)) == "integer" and
+           math.type(math.floor(1e300)) == "float" and
+           math.type(math.fmod(7, 3)) == "integer" and math.fmod(-7, 3) == -1 and
+           math.type(math.fmod(7.0, 3)) == "float" and math.fmod(-7.5, 2) == -1.5 and
+           not pcall(math.fmod, 1, 0) and
+           math.type(ip) == "float" and ip == 3.0 and fp == 0.5 and
+           math.type(mi) == "integer" and mi == -3 and math.type(mf) == "float" and
+           math.type(math.max(1, 2)) == "integer" and math.type(math.min(1.0, 2)) == "float"
+end
+
+function test28()
+    local ten, half = 10, 0.5
+    return 10^3 == 1000.0 and math.type(10^3) == "float" and ten^3 == 1000.0 and
+           2^10 == 1024.0 and 2.0^10.0 == 1024.0 and 10^15 == 1e15 and ten^22 == 1e22 and
+           (-3)^3 == -27.0 and 0^0 == 1.0 and 5^0 == 1.0 and
+           math.abs(2^0.5 - 1.4142135623730951) < 1e-15 and 4^half == 2.0 and
+           2^-2 == 0.25 and math.type(2^0.5) == "float"
+end
+
 assert(
     test1() and
     test2() and
@@ -306,5 +364,52 @@ assert(
     test21() and
     test22() and
     test23() and
-    test24()
+    test24() and
+    test25() and
+    test26() and
+    test27() and
+    test28()
 )
+
+do
+    local values = {1.5, -1.5, 3, 2^53, -0.25}
+    local floored = math.floor_all(values)
+    local ceiled = math.ceil_all(values)
+    assert(#floored == 5 and #ceiled == 5)
+    assert(floored[1] == 1 and floored[2] == -2 and floored[3] == 3 and floored[5] == -1)
+    assert(ceiled[1] == 2 and ceiled[2] == -1 and ceiled[3] == 3 and ceiled[5] == 0)
+    assert(math.type(floored[1]) == "integer" and math.type(floored[4]) == "integer")
+    -- The input is left unchanged.
+    assert(values[1] == 1.5)
+    assert(next(math.floor_all({})) == nil)
+    assert(not pcall(math.floor_all, {1, "x"}))
+    assert(not pcall(math.floor_all, 1))
+end
+
+do
+    -- Rounding to zero gives the integer 0, never a float -0.0.
+    for _, v in ipairs({math.floor(-0.0), math.ceil(-0.0), math.ceil(-0.5), math.floor(5e-324),
+            math.ceil(-5e-324)}) do
+        assert(math.type(v) == "integer" and v == 0)
+        assert(1 / v == math.huge and tostring(v) == "0")
+    end
+
+    -- Subnormals round away from zero in the direction of the rounding function.
+    assert(math.ceil(5e-324) == 1 and math.type(math.ceil(5e-324)) == "integer")
+    assert(math.floor(-5e-324) == -1 and math.type(math.floor(-5e-324)) == "integer")
+    assert(math.floor(2.2250738585072014e-308) == 0 and math.ceil(-2.2250738585072014e-308) == 0)
+
+    local floored = math.floor_all({-0.0, 5e-324, -5e-324})
+    assert(floored[1] == 0 and floored[2] == 0 and floored[3] == -1)
+    assert(math.type(floored[1]) == "integer" and 1 / floored[1] == math.huge)
+end
+
+do
+    -- NaN arguments are skipped, unless every argument is NaN.
+    local nan = 0/0
+    assert(math.max(nan, 1, 2) == 2 and math.max(1, nan, 2) == 2 and math.max(1, 2, nan) == 2)
+    assert(math.min(nan, 1, 2) == 1 and math.min(1, nan, 2) == 1 and math.min(1, 2, nan) == 1)
+    assert(is_nan(math.max(nan)) and is_nan(math.min(nan, nan)))
+    assert(math.max(math.mininteger) == math.mininteger and is_integer(math.max(-1)))
+    assert(not pcall(math.max, nan, {}) and not pcall(math.min, {}, nan))
+end
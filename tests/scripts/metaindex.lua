@@ -65,3 +65,55 @@ do
     t.foo = 4
     assert(idx.foo == 4)
 end
+
+do
+    local a = {}
+    setmetatable(a, { __index = a, __newindex = a })
+
+    local ok, err = pcall(function() return a.missing end)
+    assert(not ok and tostring(err) == "'__index' chain too long; possible loop")
+
+    ok, err = pcall(function() a.missing = 1 end)
+    assert(not ok and tostring(err) == "'__newindex' chain too long; possible loop")
+
+    local b = setmetatable({}, { __index = a })
+    ok, err = pcall(function() return b.missing end)
+    assert(not ok and tostring(err) == "'__index' chain too long; possible loop")
+end
+
+do
+    local t = { value = 1 }
+    for i = 1, 100 do
+        t = setmetatable({}, { __index = t })
+    end
+    assert(t.value == 1)
+end
+
+do
+    local calls = 0
+    local proxy
+    proxy = setmetatable({}, {
+        __index = function(t, k)
+            calls = calls + 1
+            assert(t == proxy)
+            if k == "double" then
+                return 2, "ignored"
+            elseif type(k) == "number" then
+                return k * 10
+            end
+        end,
+    })
+
+    assert(proxy.double == 2)
+    assert(select("#", proxy.double) == 1)
+    local a, b = proxy.double
+    assert(a == 2 and b == nil)
+    assert(proxy[4] == 40)
+    assert(proxy.other == nil)
+    assert(calls == 5)
+
+    -- A function reached through an `__index` table is called with that table, not the original.
+    local child = setmetatable({}, { __index = proxy })
+    assert(child[7] == 70)
+    assert(calls == 6)
+end
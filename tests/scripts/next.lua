@@ -10,3 +10,22 @@ local k = next(t, k)
 assert(k == nil, "next after last key is not nil")
 
 assert(select(1, pcall(function() next(t, "d") end)) == false, "next with missing key did not error")
+
+-- A float key with an integral value continues iteration from the equal integer key.
+local t = {}
+t[1], t[2], t[3] = "a", "b", "c"
+local k, v = next(t, 2.0)
+assert(k == 3 and math.type(k) == "integer" and v == "c")
+assert(next(t, 3.0) == nil)
+
+-- The same holds for integer keys in the map part.
+local t = {}
+t[30], t[20], t[10] = "c", "b", "a"
+local k = next(t)
+while k ~= nil do
+    local int_next, int_value = next(t, k)
+    local float_next, float_value = next(t, k + 0.0)
+    assert(int_next == float_next and int_value == float_value)
+    k = int_next
+end
+assert(not pcall(next, t, 20.5))
@@ -142,6 +142,54 @@ function test17()
         "0x10" + "4" == 20
 end
 
+function test18()
+    local three, four, hex = "3", "4", " 0x10 "
+    local ok, err = pcall(function() return three + "abc" end)
+    return
+        "3" * "4" == 12 and math.type("3" * "4") == "integer" and
+        three * four == 12 and math.type(three * four) == "integer" and
+        "0x10" + 0 == 16 and math.type("0x10" + 0) == "integer" and
+        hex + 0 == 16 and
+        three / 2 == 1.5 and
+        -three == -3 and math.type(-three) == "integer" and
+        "1e1" + 0 == 10.0 and math.type("1e1" + 0) == "float" and
+        (three + four) .. "" == "7" and
+        not ok and tostring(err) == "attempt to perform arithmetic on a string value"
+end
+
+function test19()
+    local zero, fzero = 0, 0.0
+    local ok1, err1 = pcall(function() return 1 // zero end)
+    local ok2, err2 = pcall(function() return 1 % zero end)
+    local nan = 1 % fzero
+    return
+        -5 % 3 == 1 and 5 % -3 == -1 and -5 % -3 == -2 and 6 % -3 == 0 and
+        5 // -2 == -3 and -5 // 2 == -3 and -5 // -2 == 2 and 4 // -2 == -2 and
+        math.mininteger // -1 == math.mininteger and math.mininteger % -1 == 0 and
+        -5.5 % 2 == 0.5 and 5.5 % -2 == -0.5 and -5.5 // 2 == -3.0 and
+        1 // fzero == math.huge and -1 // fzero == -math.huge and nan ~= nan and
+        not ok1 and tostring(err1) == "attempt to perform 'n//0'" and
+        not ok2 and tostring(err2) == "attempt to perform 'n%0'"
+end
+
+function test20()
+    local max, min = math.maxinteger, math.mininteger
+    local big = 2.0^63
+    local t = {}
+    t[max] = "max"
+    t[big] = "big"
+    return
+        max < big and max <= big and not (max == big) and max ~= big and
+        not (big < max) and not (big <= max) and big > max and
+        min == -big and min <= -big and not (min < -big) and
+        -big <= min and not (-big < min) and
+        max - 1 < max + 0.0 and not (max + 0.0 < max - 1) and
+        9007199254740993 > 2^53 and not (9007199254740993 <= 2^53) and 9007199254740993 ~= 2^53 and
+        max < math.huge and min > -math.huge and not (max < 0/0) and not (max >= 0/0) and
+        math.tointeger(big) == nil and math.tointeger(-big) == min and
+        t[max] == "max" and t[big] == "big"
+end
+
 assert(
     test1() and
     test2() and
@@ -159,5 +207,8 @@ assert(
     test14() and
     test15() and
     test16() and
-    test17()
+    test17() and
+    test18() and
+    test19() and
+    test20()
 )
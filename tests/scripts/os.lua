@@ -0,0 +1,38 @@
+local function test_files()
+    local name = os.tmpname()
+    local renamed = name .. ".renamed"
+
+    local ok1 = os.rename(name, renamed)
+    local nil1, err1, code1 = os.remove(name)
+    local ok2 = os.remove(renamed)
+    local nil2, err2 = os.rename(name, renamed)
+
+    local other = os.tmpname()
+    local ok3 = os.remove(other)
+
+    return type(name) == "string" and name ~= other and ok3 == true and
+        ok1 == true and
+        nil1 == nil and type(err1) == "string" and err1:sub(1, #name + 2) == name .. ": " and
+        math.type(code1) == "integer" and
+        ok2 == true and
+        nil2 == nil and type(err2) == "string"
+end
+
+assert(test_files())
+
+local function test_byte_names()
+    local name = os.tmpname()
+    local bytes = name .. ".\255"
+
+    -- File names which are not valid UTF-8 are either used unchanged, or rejected where the
+    -- platform cannot represent them, but never replaced with a different name.
+    if os.rename(name, bytes) then
+        local nil1 = os.remove(name .. ".\239\191\189")
+        local ok1 = os.remove(bytes)
+        return nil1 == nil and ok1 == true
+    else
+        return os.remove(name) == true
+    end
+end
+
+assert(test_byte_names())
@@ -0,0 +1,47 @@
+local function test_preload()
+    local loads = 0
+    package.preload["counter"] = function(name, extra)
+        loads = loads + 1
+        return { name = name, extra = extra }
+    end
+
+    local m1, extra = require("counter")
+    local m2 = require("counter")
+    return loads == 1 and m1 == m2 and m1.name == "counter" and m1.extra == ":preload:" and
+        extra == ":preload:" and package.loaded["counter"] == m1
+end
+
+local function test_no_value()
+    package.preload["empty"] = function() end
+    return require("empty") == true and package.loaded["empty"] == true
+end
+
+local function test_searcher()
+    local searched = {}
+    package.searchers[#package.searchers + 1] = function(name)
+        searched[#searched + 1] = name
+        if name == "host" then
+            return function() return "from host" end
+        end
+        return "\n\tno host module '" .. name .. "'"
+    end
+
+    local ok, err = pcall(require, "missing")
+    return require("host") == "from host" and require("host") == "from host" and
+        #searched == 2 and not ok and
+        err == "module 'missing' not found:\n\tno field package.preload['missing']\n\tno host module 'missing'"
+end
+
+local function test_package()
+    local module = {}
+    package.loaded["manual"] = module
+    return type(package.path) == "string" and type(package.cpath) == "string" and
+        require("manual") == module and require("_G") == _G and require("package") == package
+end
+
+assert(
+    test_preload() and
+    test_no_value() and
+    test_searcher() and
+    test_package()
+)
@@ -0,0 +1,81 @@
+local function equal(a, b)
+    if type(a) ~= "table" or type(b) ~= "table" then
+        if a ~= a and b ~= b then
+            return true
+        end
+        return a == b and math.type(a) == math.type(b)
+    end
+    for k, v in pairs(a) do
+        if not equal(v, b[k]) then
+            return false
+        end
+    end
+    for k in pairs(b) do
+        if a[k] == nil then
+            return false
+        end
+    end
+    return true
+end
+
+local function round_trip(v)
+    local source = table.serialize(v)
+    local restored = load("return " .. source)()
+    assert(equal(v, restored), source)
+    -- The output only depends on the contents, so serializing again gives the same source.
+    assert(table.serialize(restored) == source)
+    return source
+end
+
+do
+    assert(round_trip({}) == "{}")
+    assert(round_trip({1, 2, 3}) == "{1, 2, 3}")
+    assert(round_trip({1, 2, [4] = 4, x = 1}) == "{1, 2, [4] = 4, x = 1}")
+    assert(round_trip({["end"] = 1, ["a b"] = 2, _ok = 3}) == '{_ok = 3, ["a b"] = 2, ["end"] = 1}')
+    assert(round_trip({[1.5] = "float", [2.0] = "int"}) == '{[2] = "int", [1.5] = "float"}')
+    assert(round_trip({1.0, -0.0, 2^53}) == "{1.0, -0.0, 9007199254740992.0}")
+end
+
+do
+    round_trip({
+        nested = {deep = {deeper = {"value", true, false}}},
+        list = {{1}, {2}, {3, x = {}}},
+        numbers = {math.maxinteger, math.mininteger, 1 / 0, -1 / 0, 0 / 0, 0.1},
+        [true] = "boolean key",
+        [-1] = "negative",
+        [0] = "zero",
+    })
+end
+
+do
+    round_trip({"quote \" backslash \\ newline \n return \r nul \0 tab \t", "\1\0012\255"})
+    round_trip("plain string")
+    round_trip(42)
+end
+
+do
+    local shared = {1, 2}
+    local source = round_trip({a = shared, b = shared})
+    assert(source == "{a = {1, 2}, b = {1, 2}}")
+end
+
+do
+    local cycle = {}
+    cycle.self = cycle
+    assert(not pcall(table.serialize, cycle))
+    assert(not pcall(table.serialize, {f = print}))
+    assert(not pcall(table.serialize, {[{}] = {{}}, nested = {cycle}}))
+end
+
+do
+    local function nested(depth)
+        local t = {}
+        for _ = 2, depth do
+            t = {t}
+        end
+        return t
+    end
+    assert(pcall(table.serialize, nested(256)))
+    local ok, err = pcall(table.serialize, nested(100000))
+    assert(not ok and string.find(tostring(err), "nested more than 256 levels deep"))
+end
@@ -35,7 +35,239 @@ function test_len()
         string.len(-2147483648) == 11
 end
 
+function test_sub()
+    return
+        string.sub("hello", 2, 4) == "ell" and
+        string.sub("hello", 2) == "ello" and
+        string.sub("hello", -3) == "llo" and
+        string.sub("hello", -3, -2) == "ll" and
+        string.sub("hello", 0) == "hello" and
+        string.sub("hello", -100, 2) == "he" and
+        string.sub("hello", 3, 100) == "llo" and
+        string.sub("hello", 10) == "" and
+        string.sub("hello", 4, 2) == "" and
+        string.sub("hello", 2, 0) == "" and
+        string.sub("", 1, 1) == "" and
+        string.sub(12345, 2, 3) == "23" and
+        is_err(function() return string.sub({}, 1) end)
+end
+
+function test_byte()
+    local a, b, c = string.byte("abc", 1, -1)
+    return
+        string.byte("abc") == 97 and
+        string.byte("abc", -1) == 99 and
+        a == 97 and b == 98 and c == 99 and
+        select("#", string.byte("abc", 10)) == 0 and
+        select("#", string.byte("abc", 0)) == 0 and
+        select("#", string.byte("abc", -100, 100)) == 3
+end
+
+function test_pack()
+    local fmt = "<i4 I2 b B f d s1 z c5 j"
+    local packed = string.pack(fmt, -123456, 65535, -128, 255, 1.5, 3.25, "hi", "zero", "ab", math.mininteger)
+    local a, b, c, d, e, f, g, h, i, j, pos = string.unpack(fmt, packed)
+
+    local big = string.pack(">i3 !4 b i4 x h", 70000, -1, 1000, -2)
+    local k, l, m, n, big_pos = string.unpack(">i3 !4 b i4 x h", big)
+
+    local x, y = string.unpack("B", string.pack("BB", 5, 6), 2)
+
+    return
+        #packed == 41 and string.packsize("<i4 I2 b B f d c5 j") == 33 and
+        a == -123456 and b == 65535 and c == -128 and d == 255 and
+        e == 1.5 and f == 3.25 and g == "hi" and h == "zero" and i == "ab\0\0\0" and
+        j == math.mininteger and pos == #packed + 1 and
+        #big == 12 and k == 70000 and l == -1 and m == 1000 and n == -2 and big_pos == 13 and
+        x == 6 and y == 3 and
+        string.byte(string.pack(">I2", 258), 1) == 1 and
+        string.byte(string.pack("<I2", 258), 1) == 2 and
+        string.unpack("<i16", string.pack("<i16", -2)) == -2 and
+        string.unpack(">I16", string.pack(">I16", math.maxinteger)) == math.maxinteger and
+        string.packsize("b d") == 9 and
+        string.packsize("!8 b d") == 16 and
+        string.packsize("!4 b i8") == 12 and
+        string.packsize("! b Xi4") == 4 and
+        is_err(function() return string.pack("i1", 128) end) and
+        is_err(function() return string.pack("I1", -1) end) and
+        is_err(function() return string.pack("z", "a\0b") end) and
+        is_err(function() return string.pack("c2", "abc") end) and
+        is_err(function() return string.pack("i17", 1) end) and
+        is_err(function() return string.pack("q", 1) end) and
+        is_err(function() return string.unpack("i4", "abc") end) and
+        is_err(function() return string.unpack("z", "abc") end) and
+        is_err(function() return string.packsize("s") end) and
+        is_err(function() return string.packsize("!3 i3") end)
+end
+
+function test_gsub()
+    local s1, n1 = string.gsub("hello world", "o", "0")
+    local s2, n2 = string.gsub("hello world", "(%w+) (%w+)", "%2 %1 %0 %%")
+    local s3, n3 = string.gsub("$name is $age", "%$(%w+)", { name = "bob", age = 42 })
+    local s4, n4 = string.gsub("$name $missing", "%$(%w+)", { name = false })
+    local s5, n5 = string.gsub("a,b,c", "%a", function(c) return c .. "!" end)
+    local s6, n6 = string.gsub("abc", "%w", function(c) if c == "b" then return nil end return c .. c end)
+    local s7, n7 = string.gsub("a b c d", " ", "_", 2)
+    local s8, n8 = string.gsub("abc", "", "-")
+    local s9, n9 = string.gsub("hello", "^h", "j")
+    local s10, n10 = string.gsub("x = 1", "()=()", "%1%2")
+    local s11 = string.gsub("key=val", "(%w+)=(%w+)", function(k, v) return v .. "=" .. k end)
+    local lookup = setmetatable({}, { __index = function(_, k) return "<" .. k .. ">" end })
+    local s12 = string.gsub("ab", ".", lookup)
+    local s13, n13 = string.gsub("abc", "b", "x", 0)
+
+    return
+        s1 == "hell0 w0rld" and n1 == 2 and
+        s2 == "world hello hello world %" and n2 == 1 and
+        s3 == "bob is 42" and n3 == 2 and
+        s4 == "$name $missing" and n4 == 2 and
+        s5 == "a!,b!,c!" and n5 == 3 and
+        s6 == "aabcc" and n6 == 3 and
+        s7 == "a_b_c d" and n7 == 2 and
+        s8 == "-a-b-c-" and n8 == 4 and
+        s9 == "jello" and n9 == 1 and
+        s10 == "x 34 1" and n10 == 1 and
+        s11 == "val=key" and
+        s12 == "<a><b>" and
+        s13 == "abc" and n13 == 0 and
+        is_err(function() return string.gsub("abc", "(", "x") end) and
+        is_err(function() return string.gsub("abc", "b", "%2") end) and
+        is_err(function() return string.gsub("abc", "b", "%z") end) and
+        is_err(function() return string.gsub("abc", "b", {b = {}}) end) and
+        is_err(function() return string.gsub("abc", "b") end)
+end
+
+function test_format()
+    local t = setmetatable({}, { __tostring = function() return "custom" end })
+    return
+        string.format("%g", 0.1) == "0.1" and
+        string.format("%g", 3.14) == "3.14" and
+        string.format("%g", 1e20) == "1e+20" and
+        string.format("%g", -1e20) == "-1e+20" and
+        string.format("%g", 1e-5) == "1e-05" and
+        string.format("%g", 0.0001) == "0.0001" and
+        string.format("%g", 100000) == "100000" and
+        string.format("%g", 1e6) == "1e+06" and
+        string.format("%g", 2^53) == "9.0072e+15" and
+        string.format("%.14g", 2^53) == "9.007199254741e+15" and
+        string.format("%.3f", 1.5) == "1.500" and
+        string.format("%e", 12345.678) == "1.234568e+04" and
+        string.format("%5.1f|%-5d|%05d", 3.14159, 42, -42) == "  3.1|42   |-0042" and
+        string.format("%x %X %#x %o", 255, 255, 255, 8) == "ff FF 0xff 10" and
+        string.format("%d %i", 3.0, "7") == "3 7" and
+        string.format("%s %s %s %.2s", 1, true, t, "abc") == "1 true custom ab" and
+        string.format("%c%c", 104, 105) == "hi" and
+        string.format("%q", "a\"b\n\0") == '"a\\"b\\\n\\0"' and
+        string.format("%q %q", 10, 0.5) == "10 0.5" and
+        string.format("100%%") == "100%" and
+        is_err(function() return string.format("%d", 1.5) end) and
+        is_err(function() return string.format("%d") end) and
+        is_err(function() return string.format("%y", 1) end) and
+        is_err(function() return string.format("%100d", 1) end) and
+        is_err(function() return string.format("%10q", "a") end)
+end
+
+function test_find()
+    local a1, b1 = string.find("100% sure", "0%", 1, true)
+    local a2, b2 = string.find("a.b.c", ".", 3, true)
+    local a3, b3 = string.find("a+b", "+", 1, true)
+    local a4, b4 = string.find("hello world", "o w")
+    local a5, b5, c5 = string.find("key=value", "(%w+)=")
+    local a6, b6 = string.find("abc", "", 10)
+    local a7, b7 = string.find("abc", "", 4)
+    local a8, b8 = string.find("abc", "c", -1)
+    return
+        a1 == 3 and b1 == 4 and
+        a2 == 4 and b2 == 4 and
+        a3 == 2 and b3 == 2 and
+        a4 == 5 and b4 == 7 and
+        a5 == 1 and b5 == 4 and c5 == "key" and
+        a6 == nil and b6 == nil and
+        a7 == 4 and b7 == 3 and
+        a8 == 3 and b8 == 3 and
+        string.find("a.b", ".", 1) == 1 and
+        string.find("abc", "[", 1, true) == nil and
+        string.find("a[b", "[", 1, true) == 2 and
+        is_err(function() return string.find("abc", "[") end)
+end
+
+function test_methods()
+    local s = "Hello"
+    return ("abc"):upper() == "ABC" and
+        s:lower() == "hello" and
+        s:sub(2, 3) == "el" and
+        s:len() == 5 and
+        getmetatable("").__index == string and
+        getmetatable({}) == nil and getmetatable(1) == nil and
+        not pcall(function() return (1):upper() end)
+end
+
+function test_method_chain()
+    local s = "hello"
+    local t = { name = "piccolo" }
+    local first = s:upper():reverse():byte()
+    return s:upper():reverse() == "OLLEH" and
+        s:reverse():sub(1, 2):upper() == "OL" and
+        first == 79 and
+        t.name:upper():lower() == "piccolo" and
+        ("%d-%s"):format(1, "x"):upper() == "1-X" and
+        ("a,b"):gsub(",", ";"):upper() == "A;B" and
+        string.reverse("") == "" and
+        s == "hello"
+end
+
+function test_split()
+    local function joined(t)
+        return #t .. ":" .. table.concat(t, "|")
+    end
+
+    return joined(string.split("a,b,c", ",")) == "3:a|b|c" and
+        joined(("key = value = more"):split(" = ")) == "3:key|value|more" and
+        -- Consecutive, leading, and trailing separators produce empty parts.
+        joined(string.split(",a,,b,", ",")) == "5:|a||b|" and
+        joined(string.split("abc", ",")) == "1:abc" and
+        joined(string.split("", ",")) == "1:" and
+        joined(string.split("aaaa", "aa")) == "3:||" and
+        -- The separator is plain text, not a pattern.
+        joined(string.split("1.5.2", ".")) == "3:1|5|2" and
+        -- An empty separator splits into bytes.
+        joined(string.split("abc", "")) == "3:a|b|c" and
+        joined(string.split("", "")) == "0:" and
+        joined(string.split(123, "")) == "3:1|2|3" and
+        not pcall(string.split, "abc") and
+        not pcall(string.split, {}, ",")
+end
+
+function test_hex_format()
+    -- `%a` writes floats exactly, so they read back as the same float.
+    for _, x in ipairs({0.1, 1 / 3, 1e308, -2.5e-300, 5e-324, 2^53, 10.0, 1, -7}) do
+        local y = tonumber(string.format("%a", x))
+        if math.type(y) ~= "float" or y ~= x then
+            return false
+        end
+    end
+    local negative_zero = tonumber(string.format("%a", -0.0))
+    return
+        string.format("%a", 10.0) == "0x1.4p+3" and
+        string.format("%A", 0.1) == "0X1.999999999999AP-4" and
+        string.format("%a", 1) == "0x1p+0" and
+        string.format("%.2a|%8a", 1.0, 0.5) == "0x1.00p+0|  0x1p-1" and
+        string.format("%a %A", 1/0, 0/0):find("^inf %-?NAN$") ~= nil and
+        1 / negative_zero == -math.huge and
+        is_err(function() return string.format("%a", "x") end)
+end
+
 assert(
     test_concat() and
-    test_len()
+    test_len() and
+    test_sub() and
+    test_byte() and
+    test_pack() and
+    test_gsub() and
+    test_format() and
+    test_find() and
+    test_methods() and
+    test_method_chain() and
+    test_split() and
+    test_hex_format()
 )
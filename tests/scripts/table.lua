@@ -122,3 +122,144 @@ do
     assert(table.unpack(t, 4, 4) == nil)
     assert(table.unpack(t, 4, 2) == nil)
 end
+
+do
+    local nan = 0/0
+    assert(nan ~= nan)
+    assert(not (nan == nan))
+    assert(not (0/0 == 0/0))
+
+    local t = {}
+    assert(not pcall(function() t[nan] = 1 end))
+    assert(not pcall(function() rawset(t, nan, 1) end))
+    assert(not pcall(function() return { [nan] = 1 } end))
+    assert(t[nan] == nil)
+    assert(next(t) == nil)
+
+    t[1] = nan
+    assert(t[1] ~= t[1])
+end
+
+do
+    local t = {3, 1.5, 2, 2.5}
+    table.sort(t)
+    assert(t[1] == 1.5 and t[2] == 2 and t[3] == 2.5 and t[4] == 3)
+
+    local t = {1.0, 1, 0.5}
+    table.sort(t)
+    assert(t[1] == 0.5 and t[2] == 1 and t[3] == 1)
+
+    -- Integers are not rounded to floats when compared, so 2^53 + 1 is greater than the float 2^53.
+    local t = {9007199254740993, 2^53, 9007199254740992}
+    table.sort(t)
+    assert(t[1] == 2^53 and t[3] == 9007199254740993)
+
+    local t = {"b", "c", "a"}
+    table.sort(t)
+    assert(t[1] == "a" and t[2] == "b" and t[3] == "c")
+
+    local t = {5, 1, 4, 2, 3}
+    table.sort(t, function(a, b) return a > b end)
+    assert(t[1] == 5 and t[2] == 4 and t[3] == 3 and t[4] == 2 and t[5] == 1)
+
+    local t = {}
+    table.sort(t)
+    assert(#t == 0)
+
+    assert(not pcall(table.sort, {1, "x"}))
+    assert(not pcall(table.sort, {2, 1}, function() error("fail") end))
+
+    -- NaN sorts after every other number.
+    local nan = 0/0
+    local t = {3, nan, 1, nan, 2}
+    table.sort(t)
+    assert(t[1] == 1 and t[2] == 2 and t[3] == 3 and t[4] ~= t[4] and t[5] ~= t[5])
+    assert(not pcall(table.sort, {nan, "x"}))
+
+    -- An inconsistent comparison function leaves the values in some order, but never fails.
+    local t = {3, nan, 1, 2}
+    table.sort(t, function(a, b) return a < b end)
+    assert(#t == 4)
+end
+
+do
+    local ok, err = pcall(function() local t = {} t[nil] = 1 end)
+    assert(ok == false and err == "table index is nil")
+
+    ok, err = pcall(function() local t = {} t[0/0] = 1 end)
+    assert(ok == false and err == "table index is NaN")
+
+    ok, err = pcall(rawset, {}, nil, 1)
+    assert(ok == false and err == "table index is nil")
+
+    ok, err = pcall(function() return { [0/0] = 1 } end)
+    assert(ok == false and err == "table index is NaN")
+end
+
+do
+    local t = {}
+    table.insert(t, "b")
+    table.insert(t, "d")
+    table.insert(t, 1, "a")
+    table.insert(t, 3, "c")
+    table.insert(t, 5, "e")
+    assert(#t == 5 and t[1] == "a" and t[2] == "b" and t[3] == "c" and
+        t[4] == "d" and t[5] == "e")
+
+    assert(table.remove(t, 1) == "a" and #t == 4 and t[1] == "b" and t[4] == "e")
+    assert(table.remove(t) == "e" and #t == 3 and t[3] == "d")
+    assert(table.remove(t, 2) == "c" and #t == 2 and t[1] == "b" and t[2] == "d")
+    assert(table.remove(t, 3) == nil and #t == 2)
+    assert(table.remove({}) == nil)
+
+    assert(not pcall(table.insert, t, 0, "x"))
+    assert(not pcall(table.insert, t, 4, "x"))
+    assert(not pcall(table.insert, t))
+    assert(not pcall(table.insert, t, 1, 2, 3))
+    assert(not pcall(table.remove, t, 4))
+    assert(not pcall(table.remove, t, -1))
+
+    -- Sequences stored in the map part of a table are shifted the same way.
+    local t = {}
+    for i = 5, 1, -1 do
+        t[i] = i
+    end
+    table.insert(t, 1, 0)
+    assert(#t == 6 and t[1] == 0 and t[2] == 1 and t[6] == 5)
+    assert(table.remove(t, 1) == 0 and #t == 5 and t[1] == 1 and t[5] == 5 and t[6] == nil)
+end
+
+do
+    -- `table.insert` and `table.remove` go through the metamethods of a proxy table.
+    local store = {}
+    local reads, writes = 0, 0
+    local proxy = setmetatable({}, {
+        __len = function() return #store end,
+        __index = function(_, k)
+            reads = reads + 1
+            return store[k]
+        end,
+        __newindex = function(_, k, v)
+            writes = writes + 1
+            store[k] = v
+        end,
+    })
+
+    table.insert(proxy, "b")
+    table.insert(proxy, "c")
+    table.insert(proxy, 1, "a")
+    assert(rawget(proxy, 1) == nil and next(proxy) == nil)
+    assert(#store == 3 and store[1] == "a" and store[2] == "b" and store[3] == "c")
+    assert(reads == 2 and writes == 5)
+
+    assert(table.remove(proxy, 1) == "a")
+    assert(#store == 2 and store[1] == "b" and store[2] == "c")
+    assert(table.remove(proxy) == "c" and #store == 1 and store[1] == "b")
+    assert(rawget(proxy, 1) == nil and next(proxy) == nil)
+
+    assert(not pcall(table.insert, proxy, 3, "x"))
+    assert(not pcall(table.remove, proxy, 3))
+
+    local ok, err = pcall(table.insert, setmetatable({}, { __len = function() return "x" end }), 1)
+    assert(not ok and err == "object length is not an integer")
+end
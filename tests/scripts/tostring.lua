@@ -0,0 +1,34 @@
+local function test_numbers()
+    local nan = tostring(0/0)
+    return tostring(1) == "1" and
+        tostring(-7) == "-7" and
+        tostring(1.0) == "1.0" and
+        tostring(-0.0) == "-0.0" and
+        tostring(0.1) == "0.1" and
+        tostring(1.5) == "1.5" and
+        tostring(1e20) == "1e+20" and
+        tostring(1e15) == "1e+15" and
+        tostring(123456789012.0) == "123456789012.0" and
+        tostring(2^63) == "9.2233720368548e+18" and
+        tostring(1/3) == "0.33333333333333" and
+        tostring(1e-5) == "1e-05" and
+        tostring(1/0) == "inf" and
+        tostring(-1/0) == "-inf" and
+        (nan == "nan" or nan == "-nan") and
+        tostring(math.maxinteger) == "9223372036854775807"
+end
+
+local function test_concat()
+    return 1.0 .. "" == "1.0" and
+        "x" .. 2.5 == "x2.5" and
+        10 // 1 .. "" == "10" and
+        10 / 2 .. "" == "5.0" and
+        string.format("%s", 3.0) == "3.0" and
+        #tostring(100.0) == 5 and
+        string.len(100.0) == 5
+end
+
+assert(
+    test_numbers() and
+    test_concat()
+)
@@ -0,0 +1 @@
+assert(_VERSION == "Lua 5.4" and type(_VERSION) == "string")
@@ -0,0 +1,170 @@
+use piccolo::{
+    snapshot::{Snapshot, SnapshotError, SnapshotTable, SnapshotValue},
+    AnyCallback, CallbackReturn, Closure, Lua, StaticError, Table, Thread, Value,
+};
+
+#[test]
+fn round_trip() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local shared = { name = "shared", 1.5, 2, 3 }
+                local mt = { kind = "point" }
+                local point = setmetatable({ x = 1, y = -2 }, mt)
+                state = {
+                    a = shared,
+                    b = { inner = shared },
+                    points = { point, point },
+                    [shared] = true,
+                    flags = { [true] = "yes", [false] = "no" },
+                    bytes = "\0\255",
+                    frozen = { 1, 2 },
+                }
+                state.self = state
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    let bytes = lua.try_run(|ctx| {
+        let Value::Table(state) = ctx.state.globals.get(ctx, "state") else {
+            panic!("state is not a table");
+        };
+        let Value::Table(frozen) = state.get(ctx, "frozen") else {
+            panic!("state.frozen is not a table");
+        };
+        frozen.freeze(&ctx);
+        Ok(Snapshot::capture(state.into())?.to_bytes())
+    })?;
+
+    let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(),
+        snapshot
+    );
+
+    let mut lua = Lua::core();
+    let thread = lua.try_run(|ctx| {
+        let state = snapshot.restore(ctx)?;
+        ctx.state.globals.set(ctx, "state", state)?;
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local shared = state.a
+                local p1, p2 = state.points[1], state.points[2]
+                return shared.name == "shared" and shared[1] == 1.5 and math.type(shared[2]) == "integer" and
+                    #shared == 3 and state.b.inner == shared and state[shared] == true and
+                    state.self == state and
+                    p1 == p2 and p1.x == 1 and p1.y == -2 and getmetatable(p1).kind == "point" and
+                    state.flags[true] == "yes" and state.flags[false] == "no" and
+                    state.bytes == "\0\255" and
+                    #state.frozen == 2 and not pcall(rawset, state.frozen, 3, 3)
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert!(lua.run_thread::<bool>(&thread)?);
+
+    Ok(())
+}
+
+#[test]
+fn unsupported_values() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        let inner = Table::new(&ctx);
+        inner
+            .set(
+                ctx,
+                "f",
+                AnyCallback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return)),
+            )
+            .unwrap();
+        table.set(ctx, "inner", inner).unwrap();
+
+        assert!(matches!(
+            Snapshot::capture(table.into()),
+            Err(SnapshotError::Unsupported("function"))
+        ));
+    });
+}
+
+#[test]
+fn malformed_bytes() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, 1, "one").unwrap();
+        let bytes = Snapshot::capture(table.into()).unwrap().to_bytes();
+
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Malformed)
+        ));
+        assert!(matches!(
+            Snapshot::from_bytes(b"not a snapshot"),
+            Err(SnapshotError::Malformed)
+        ));
+    });
+}
+
+#[test]
+fn restore_registers_finalizers() {
+    let snapshot = Snapshot {
+        root: SnapshotValue::Table(0),
+        tables: vec![
+            SnapshotTable {
+                metatable: Some(1),
+                ..Default::default()
+            },
+            SnapshotTable {
+                entries: vec![(
+                    SnapshotValue::String(b"__gc"[..].into()),
+                    SnapshotValue::Boolean(true),
+                )],
+                ..Default::default()
+            },
+        ],
+    };
+
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let Value::Table(table) = snapshot.restore(ctx).unwrap() else {
+            panic!("restored root is not a table");
+        };
+
+        // The `__gc` field only has to be present when the metatable is restored, and is looked up
+        // again once the table is finalized.
+        table
+            .metatable()
+            .unwrap()
+            .set(
+                ctx,
+                "__gc",
+                AnyCallback::from_fn(&ctx, |ctx, _, _| {
+                    ctx.state.globals.set(ctx, "finalized", true).unwrap();
+                    Ok(CallbackReturn::Return)
+                }),
+            )
+            .unwrap();
+    });
+
+    lua.gc_collect();
+
+    lua.run(|ctx| {
+        assert!(matches!(
+            ctx.state.globals.get(ctx, "finalized"),
+            Value::Boolean(true)
+        ));
+    });
+}
@@ -0,0 +1,34 @@
+use piccolo::{stdlib::StdlibConfig, Lua};
+
+#[test]
+fn load_only_math() {
+    let mut lua = Lua::with_stdlib(StdlibConfig {
+        math: true,
+        ..StdlibConfig::none()
+    });
+
+    lua.run(|ctx| {
+        let globals = ctx.state.globals;
+        assert!(globals.get(ctx, "math").is_table());
+        for name in ["os", "io", "print", "string", "debug", "require", "pcall"] {
+            assert!(
+                globals.get(ctx, name).is_nil(),
+                "{name} should not be loaded"
+            );
+        }
+    });
+}
+
+#[test]
+fn full_config_matches_full() {
+    let mut lua = Lua::with_stdlib(StdlibConfig::full());
+
+    lua.run(|ctx| {
+        let globals = ctx.state.globals;
+        for name in ["math", "string", "table", "coroutine", "debug", "print"] {
+            assert!(!globals.get(ctx, name).is_nil(), "{name} should be loaded");
+        }
+        // The `os` library is only available with the `std` feature.
+        assert_eq!(globals.get(ctx, "os").is_nil(), cfg!(not(feature = "std")));
+    });
+}
@@ -0,0 +1,21 @@
+use piccolo::{Lua, Value};
+
+#[test]
+fn intern_dedups() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let (Value::String(a), Value::String(b), Value::String(c)) = (
+            ctx.intern("hello"),
+            ctx.intern_bytes(b"hello"),
+            ctx.intern("world"),
+        ) else {
+            panic!("interning did not produce strings");
+        };
+        assert!(a == b);
+        assert!(a != c);
+        // Interning the same bytes twice returns the same allocation.
+        assert_eq!(a.as_bytes().as_ptr(), b.as_bytes().as_ptr());
+
+        assert!(matches!(ctx.intern_bytes(b"\xff\x00"), Value::String(s) if s == b"\xff\x00"));
+    });
+}
@@ -0,0 +1,1277 @@
+use piccolo::{
+    meta_ops, raw_ops,
+    table::{NextValue, TableEntries},
+    AnyCallback, ArrayShiftError, CallbackReturn, Closure, IntoValue, InvalidTableKey,
+    IterationMode, KeyHashing, Lua, MetaMethod, ReadOnlyTable, SetPathError, StaticError, Table,
+    TableGrowth, Thread, Value, Weakness,
+};
+
+#[test]
+fn table_hash() {
+    assert_eq!(
+        Value::Integer(1).table_hash(&KeyHashing::Fx).unwrap(),
+        Value::Number(1.0).table_hash(&KeyHashing::Fx).unwrap()
+    );
+    assert_eq!(
+        Value::Number(0.0).table_hash(&KeyHashing::Fx).unwrap(),
+        Value::Number(-0.0).table_hash(&KeyHashing::Fx).unwrap()
+    );
+    assert_ne!(
+        Value::Integer(1).table_hash(&KeyHashing::Fx).unwrap(),
+        Value::Number(1.5).table_hash(&KeyHashing::Fx).unwrap()
+    );
+    assert!(matches!(
+        Value::Number(f64::NAN).table_hash(&KeyHashing::Fx),
+        Err(InvalidTableKey::IsNaN)
+    ));
+    assert!(matches!(
+        Value::Nil.table_hash(&KeyHashing::Fx),
+        Err(InvalidTableKey::IsNil)
+    ));
+
+    let random = KeyHashing::random();
+    assert_eq!(
+        Value::Integer(1).table_hash(&random).unwrap(),
+        Value::Number(1.0).table_hash(&random).unwrap()
+    );
+    assert_eq!(
+        Value::Integer(1).table_hash(&random).unwrap(),
+        random.hash_key(Value::Integer(1))
+    );
+}
+
+#[test]
+fn nan_keys() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        assert!(matches!(
+            table.set(ctx, f64::NAN, 1),
+            Err(InvalidTableKey::IsNaN)
+        ));
+        assert!(matches!(
+            table.set(ctx, f64::NAN, Value::Nil),
+            Err(InvalidTableKey::IsNaN)
+        ));
+        assert!(table.get(ctx, f64::NAN).is_nil());
+        assert!(matches!(table.next(Value::Nil), NextValue::Last));
+
+        // NaN is still allowed as a value.
+        table.set(ctx, 1, f64::NAN).unwrap();
+        assert!(matches!(table.get(ctx, 1), Value::Number(n) if n.is_nan()));
+    });
+}
+
+#[test]
+fn frozen_table() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let config = Table::new(&ctx);
+        config.set(ctx, "name", "piccolo")?;
+        config.set(ctx, 1, 10)?;
+        config.set(ctx, 2, 20)?;
+        config.freeze(&ctx);
+        assert!(config.is_frozen());
+        assert!(matches!(
+            config.set(ctx, "name", "other"),
+            Err(InvalidTableKey::ReadOnly)
+        ));
+        assert!(matches!(
+            config.try_set_metatable(ctx, Some(Table::new(&ctx))),
+            Err(ReadOnlyTable)
+        ));
+        assert!(matches!(
+            config.set_default(&ctx, Value::Integer(0)),
+            Err(ReadOnlyTable)
+        ));
+        assert!(matches!(
+            config.insert_array(&ctx, 1, Value::Integer(0)),
+            Err(ArrayShiftError::ReadOnly(_))
+        ));
+        assert!(config.metatable().is_none() && config.default_value().is_nil());
+        ctx.state.globals.set(ctx, "config", config)?;
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local count = 0
+                for _ in pairs(config) do
+                    count = count + 1
+                end
+                local ok1, err1 = pcall(function() config.name = "other" end)
+                local ok2 = pcall(rawset, config, "new", 1)
+                local ok3 = pcall(function() config[3] = 30 end)
+                local ok4 = pcall(setmetatable, config, {})
+                return config.name == "piccolo" and #config == 2 and count == 3 and
+                    not ok1 and tostring(err1) == "attempt to modify a read-only table" and
+                    not ok2 and not ok3 and config.new == nil and config[3] == nil and
+                    not ok4 and getmetatable(config) == nil
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+    Ok(())
+}
+
+#[test]
+fn array_map_split() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+
+        // Inserted in reverse, none of these keys are dense enough to move into the array part.
+        for i in (2..=8).rev() {
+            table.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(table.array_part_len(), 0);
+        assert_eq!(table.map_part_len(), 7);
+        assert!(table.map_part_capacity() >= 7);
+
+        // Filling the map part forces a rehash, and now every key belongs in the array part.
+        table.set(ctx, 1, 1).unwrap();
+        assert_eq!(table.array_part_len(), 8);
+        assert!(table.array_part_capacity() >= 8);
+        assert_eq!(table.map_part_len(), 0);
+
+        for i in 1..=8 {
+            assert!(matches!(table.get(ctx, i), Value::Integer(v) if v == i));
+        }
+    });
+}
+
+#[test]
+fn optimize_table() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        for i in (1..=7).rev() {
+            table.set(ctx, i, i * 10).unwrap();
+        }
+        assert_eq!(table.array_part_len(), 0);
+        assert_eq!(table.map_part_len(), 7);
+
+        table.optimize(&ctx);
+        assert!(table.array_part_len() >= 7);
+        assert_eq!(table.map_part_len(), 0);
+        assert_eq!(table.length(), 7);
+        for i in 1..=7 {
+            assert!(matches!(table.get(ctx, i), Value::Integer(v) if v == i * 10));
+        }
+
+        // Optimizing an already optimal table does nothing.
+        let array_len = table.array_part_len();
+        table.optimize(&ctx);
+        assert_eq!(table.array_part_len(), array_len);
+    });
+}
+
+#[test]
+fn table_growth() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        // Inserts `count` keys into the map part, returning how many times it was reallocated.
+        let fill = |table: Table, count: usize| {
+            let growth = table.growth();
+            let mut reallocations = 0;
+            for i in 0..count {
+                let (len, capacity) = (table.map_part_len(), table.map_part_capacity());
+                table.set(ctx, i as f64 + 0.5, i as i64).unwrap();
+                if table.map_part_capacity() != capacity {
+                    // The map part only grows once it is filled up to its load factor.
+                    assert!(len >= (capacity as f64 * growth.load_factor) as usize);
+                    reallocations += 1;
+                }
+                assert!(
+                    table.map_part_len() as f64
+                        <= table.map_part_capacity() as f64 * growth.load_factor
+                );
+            }
+            assert_eq!(table.map_part_len(), count);
+            assert_eq!(table.array_part_len(), 0);
+            reallocations
+        };
+
+        let default = Table::new(&ctx);
+        assert_eq!(default.growth(), TableGrowth::DEFAULT);
+        let default_reallocations = fill(default, 1000);
+
+        let sparse = Table::new(&ctx);
+        sparse.set_growth(
+            &ctx,
+            TableGrowth {
+                load_factor: 0.5,
+                growth_factor: 1.0,
+            },
+        );
+        fill(sparse, 1000);
+        assert!(sparse.map_part_capacity() >= 2000);
+
+        let eager = Table::new(&ctx);
+        eager.set_growth(
+            &ctx,
+            TableGrowth {
+                load_factor: 1.0,
+                growth_factor: 7.0,
+            },
+        );
+        assert!(fill(eager, 1000) < default_reallocations);
+
+        // Changing the policy does not reallocate the table.
+        let capacity = eager.map_part_capacity();
+        eager.set_growth(&ctx, TableGrowth::DEFAULT);
+        assert_eq!(eager.map_part_capacity(), capacity);
+        assert!(matches!(eager.get(ctx, 0.5), Value::Integer(0)));
+    });
+}
+
+#[test]
+fn iter_array() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        for i in 1..=5 {
+            table.set(ctx, i, i * 2).unwrap();
+        }
+        // Continues the sequence in the map part.
+        table.set(ctx, 6, 12).unwrap();
+        table.set(ctx, "key", "value").unwrap();
+        // After the first hole, nothing is visited.
+        table.set(ctx, 8, 16).unwrap();
+
+        let mut expected = Vec::new();
+        let mut i = 1;
+        loop {
+            let value = table.get(ctx, i);
+            if value.is_nil() {
+                break;
+            }
+            expected.push((i, value.to_integer().unwrap()));
+            i += 1;
+        }
+
+        let actual: Vec<_> = table
+            .iter_array()
+            .map(|(i, v)| (i, v.to_integer().unwrap()))
+            .collect();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.len(), 6);
+
+        assert_eq!(Table::new(&ctx).iter_array().count(), 0);
+    });
+}
+
+#[test]
+fn iter_array_and_map() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        // Returns the keys visited by `iter_array` and by `iter_map`, checking that together they
+        // visit every entry of the table exactly once.
+        let partition = |table: Table| {
+            let array = table.iter_array().map(|(i, _)| i).collect::<Vec<_>>();
+            let map = table
+                .iter_map()
+                .map(|(k, v)| {
+                    assert!(raw_ops::equal(table.get_value(k), v));
+                    k.to_string()
+                })
+                .collect::<Vec<_>>();
+
+            let mut all = array
+                .iter()
+                .map(|i| i.to_string())
+                .chain(map.iter().cloned())
+                .collect::<Vec<_>>();
+            let mut expected = table
+                .entries_snapshot()
+                .into_iter()
+                .map(|(k, _)| k.to_string())
+                .collect::<Vec<_>>();
+            all.sort();
+            expected.sort();
+            assert_eq!(all, expected);
+
+            let mut map = map;
+            map.sort();
+            (array, map)
+        };
+
+        let table = Table::new(&ctx);
+        for i in 1..=3 {
+            table.set(ctx, i, i * 10).unwrap();
+        }
+        table.set(ctx, 5, 50).unwrap();
+        table.set(ctx, 0, 0).unwrap();
+        table.set(ctx, -1, -10).unwrap();
+        table.set(ctx, 2.5, 25).unwrap();
+        table.set(ctx, "x", "y").unwrap();
+        let (array, map) = partition(table);
+        assert_eq!(array, [1, 2, 3]);
+        assert_eq!(map, ["-1", "0", "2.5", "5", "x"]);
+
+        // Sequence keys stored in the map part are still only visited by `iter_array`. Reserving
+        // room in the map part up front keeps the keys from ever moving to the array part.
+        let mut entries = TableEntries::new(&ctx);
+        entries.reserve_map(16);
+        let table = Table::from_parts(&ctx, entries, None);
+        for i in (1..=4).rev() {
+            table.set(ctx, i, i).unwrap();
+        }
+        table.set(ctx, 4.0, "four").unwrap();
+        table.set(ctx, "n", 4).unwrap();
+        assert_eq!(table.map_part_len(), 5);
+        let (array, map) = partition(table);
+        assert_eq!(array, [1, 2, 3, 4]);
+        assert_eq!(map, ["n"]);
+
+        // Once the sequence is broken, the keys after the gap belong to `iter_map`.
+        table.set(ctx, 2, Value::Nil).unwrap();
+        let (array, map) = partition(table);
+        assert_eq!(array, [1]);
+        assert_eq!(map, ["3", "4", "n"]);
+
+        let (array, map) = partition(Table::new(&ctx));
+        assert!(array.is_empty() && map.is_empty());
+    });
+}
+
+#[test]
+fn get_path() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let server = Table::new(&ctx);
+        server.set(ctx, "port", 8080).unwrap();
+        server.set(ctx, "host", "localhost").unwrap();
+        let config = Table::new(&ctx);
+        config.set(ctx, "server", server).unwrap();
+
+        assert_eq!(
+            config.get_path(ctx, &["server", "port"]).to_integer(),
+            Some(8080)
+        );
+        assert!(matches!(config.get_path(ctx, &["server"]), Value::Table(t) if t == server));
+        assert!(matches!(config.get_path(ctx, &[]), Value::Table(t) if t == config));
+        assert!(config.get_path(ctx, &["client", "port"]).is_nil());
+
+        // Stops at the non-table "localhost" rather than indexing into it.
+        assert!(config.get_path(ctx, &["server", "host", "len"]).is_nil());
+    });
+}
+
+#[test]
+fn set_path() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let root = Table::new(&ctx);
+        root.set_path(ctx, &["a", "b", "c"], 1).unwrap();
+        assert_eq!(root.get_path(ctx, &["a", "b", "c"]).to_integer(), Some(1));
+
+        // Existing intermediate tables are reused.
+        let b = root.get_path(ctx, &["a", "b"]);
+        root.set_path(ctx, &["a", "b", "d"], 2).unwrap();
+        assert!(matches!(
+            (b, root.get_path(ctx, &["a", "b"])),
+            (Value::Table(x), Value::Table(y)) if x == y
+        ));
+        assert_eq!(root.get_path(ctx, &["a", "b", "d"]).to_integer(), Some(2));
+
+        assert!(matches!(
+            root.set_path(ctx, &["a", "b", "c", "e", "f"], 3),
+            Err(SetPathError::NotATable { index: 2 })
+        ));
+        // Nothing was created by the failed assignment.
+        assert_eq!(root.get_path(ctx, &["a", "b", "c"]).to_integer(), Some(1));
+
+        assert!(matches!(
+            root.set_path(ctx, &[], 1),
+            Err(SetPathError::EmptyPath)
+        ));
+    });
+}
+
+#[test]
+fn meta_chain_limit() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.set_meta_chain_limit(3);
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local t1 = { value = 1 }
+                local t2 = setmetatable({}, { __index = t1 })
+                local t3 = setmetatable({}, { __index = t2 })
+                local t4 = setmetatable({}, { __index = t3 })
+                local ok, err = pcall(function() return t4.value end)
+                return t3.value == 1 and not ok and
+                    tostring(err) == "'__index' chain too long; possible loop"
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+
+    lua.set_meta_chain_limit(0);
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local t = { value = 1 }
+                t.value = 2
+                t.other = 3
+                local p = setmetatable({}, { __index = t, __newindex = t })
+                local ok_get = pcall(function() return p.value end)
+                local ok_set = pcall(function() p.value = 4 end)
+                return t.value == 2 and t.other == 3 and not ok_get and not ok_set
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+    Ok(())
+}
+
+#[test]
+fn globals() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        ctx.globals().set(ctx, "from_rust", 1)?;
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                _G.from_lua = from_rust + 1
+                return _G == _ENV
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert!(lua.run_thread::<bool>(&thread)?);
+
+    lua.run(|ctx| {
+        assert!(matches!(
+            ctx.globals().get(ctx, "from_lua"),
+            Value::Integer(2)
+        ));
+        assert!(matches!(ctx.globals().get(ctx, "_G"), Value::Table(t) if t == ctx.globals()));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn distinct_string_keys() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        let interned = ctx.intern("field");
+        let distinct = piccolo::String::from_slice(&ctx, "field");
+        let Value::String(interned_str) = interned else {
+            unreachable!()
+        };
+        assert!(!piccolo::String::ptr_eq(interned_str, distinct));
+
+        table.set(ctx, interned, 1).unwrap();
+        assert!(matches!(table.get(ctx, distinct), Value::Integer(1)));
+        table.set(ctx, distinct, 2).unwrap();
+        assert!(matches!(table.get(ctx, interned), Value::Integer(2)));
+        assert!(matches!(
+            table.get(ctx, piccolo::String::from_slice(&ctx, "fielD")),
+            Value::Nil
+        ));
+    });
+}
+
+#[test]
+fn entries_snapshot() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, 1, "one").unwrap();
+        table.set(ctx, 2, "two").unwrap();
+        table.set(ctx, "key", "value").unwrap();
+
+        let snapshot = table.entries_snapshot();
+
+        table.set(ctx, 1, Value::Nil).unwrap();
+        table.set(ctx, "key", "changed").unwrap();
+        table.set(ctx, "new", true).unwrap();
+
+        assert_eq!(snapshot.len(), 3);
+        let find = |key: Value| {
+            snapshot
+                .iter()
+                .find(|(k, _)| match (*k, key) {
+                    (Value::Integer(a), Value::Integer(b)) => a == b,
+                    (Value::String(a), Value::String(b)) => a == b,
+                    _ => false,
+                })
+                .map(|&(_, v)| v)
+        };
+        assert!(matches!(find(Value::Integer(1)), Some(Value::String(s)) if s == "one"));
+        assert!(matches!(find(Value::Integer(2)), Some(Value::String(s)) if s == "two"));
+        assert!(matches!(find(ctx.intern("key")), Some(Value::String(s)) if s == "value"));
+
+        assert_eq!(table.entries_snapshot().len(), 3);
+    });
+}
+
+#[test]
+fn merge() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let make = |entries: &[(&str, i64)]| {
+            let table = Table::new(&ctx);
+            for &(k, v) in entries {
+                table.set(ctx, k, v).unwrap();
+            }
+            table
+        };
+
+        let base = make(&[("a", 1), ("b", 2)]);
+        base.set(ctx, 1, "first").unwrap();
+        let layer = make(&[("b", 20), ("c", 30)]);
+        layer.set(ctx, 1, "replaced").unwrap();
+        layer.set(ctx, 2, "second").unwrap();
+        base.merge(&ctx, layer, true, false).unwrap();
+        assert!(matches!(base.get(ctx, "a"), Value::Integer(1)));
+        assert!(matches!(base.get(ctx, "b"), Value::Integer(20)));
+        assert!(matches!(base.get(ctx, "c"), Value::Integer(30)));
+        assert!(matches!(base.get(ctx, 1), Value::String(s) if s == "replaced"));
+        assert!(matches!(base.get(ctx, 2), Value::String(s) if s == "second"));
+        assert_eq!(base.length(), 2);
+
+        let defaults = make(&[("a", 10), ("d", 40)]);
+        base.merge(&ctx, defaults, false, false).unwrap();
+        assert!(matches!(base.get(ctx, "a"), Value::Integer(1)));
+        assert!(matches!(base.get(ctx, "d"), Value::Integer(40)));
+    });
+}
+
+#[test]
+fn deep_merge() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let config = Table::new(&ctx);
+        config
+            .set_path(ctx, &["server", "host"], "localhost")
+            .unwrap();
+        config.set_path(ctx, &["server", "port"], 80).unwrap();
+        let server = config.get(ctx, "server");
+
+        let overrides = Table::new(&ctx);
+        overrides.set_path(ctx, &["server", "port"], 8080).unwrap();
+        overrides.set_path(ctx, &["client", "retries"], 3).unwrap();
+        // A cycle in both tables does not recurse forever.
+        overrides.set(ctx, "self", overrides).unwrap();
+        config.set(ctx, "self", config).unwrap();
+
+        // A shallow merge replaces the nested table entirely.
+        let shallow = Table::new(&ctx);
+        shallow.merge(&ctx, config, true, false).unwrap();
+        shallow.merge(&ctx, overrides, true, false).unwrap();
+        assert!(shallow.get_path(ctx, &["server", "host"]).is_nil());
+
+        config.merge(&ctx, overrides, true, true).unwrap();
+        assert!(matches!(
+            (server, config.get(ctx, "server")),
+            (Value::Table(a), Value::Table(b)) if a == b
+        ));
+        assert!(matches!(
+            config.get_path(ctx, &["server", "host"]),
+            Value::String(s) if s == "localhost"
+        ));
+        assert_eq!(
+            config.get_path(ctx, &["server", "port"]).to_integer(),
+            Some(8080)
+        );
+        assert_eq!(
+            config.get_path(ctx, &["client", "retries"]).to_integer(),
+            Some(3)
+        );
+    });
+}
+
+#[test]
+fn iter_sorted() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let keys = [
+            ctx.intern("b"),
+            Value::Integer(3),
+            Value::Number(2.5),
+            Value::Boolean(true),
+            ctx.intern("a"),
+            Value::Integer(-1),
+            Value::Boolean(false),
+            Value::Integer(1),
+        ];
+
+        let forward = Table::new(&ctx);
+        for &key in &keys {
+            forward.set(ctx, key, key).unwrap();
+        }
+        let reverse = Table::new(&ctx);
+        for &key in keys.iter().rev() {
+            reverse.set(ctx, key, key).unwrap();
+        }
+        // Force a rehash in one table only, which changes its internal bucket order.
+        for i in 100..200 {
+            reverse.set(ctx, i, i).unwrap();
+        }
+        for i in 100..200 {
+            reverse.set(ctx, i, Value::Nil).unwrap();
+        }
+
+        let describe = |table: Table| -> Vec<std::string::String> {
+            table
+                .iter_sorted()
+                .map(|(k, v)| {
+                    assert!(matches!(
+                        (k.table_hash(&KeyHashing::Fx), v.table_hash(&KeyHashing::Fx)),
+                        (Ok(a), Ok(b)) if a == b
+                    ));
+                    k.to_string()
+                })
+                .collect()
+        };
+        let expected = ["-1", "1", "3", "2.5", "false", "true", "a", "b"];
+        assert_eq!(describe(forward), expected);
+        assert_eq!(describe(reverse), expected);
+    });
+}
+
+#[test]
+fn next_large_array() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        const LEN: i64 = 100_000;
+        let table = Table::new(&ctx);
+        for i in 1..=LEN {
+            table.set(ctx, i, i).unwrap();
+        }
+        // Keys just past the array part and at the integer limits live in the map part.
+        table.set(ctx, LEN + 1, LEN + 1).unwrap();
+        table.set(ctx, i64::MAX, i64::MAX).unwrap();
+        table.set(ctx, i64::MIN, i64::MIN).unwrap();
+        assert!(table.array_part_len() >= LEN as usize);
+
+        let mut count = 0;
+        let mut sum = 0i128;
+        let mut key = Value::Nil;
+        loop {
+            match table.next(key) {
+                NextValue::Found { key: k, value } => {
+                    let (Value::Integer(k), Value::Integer(v)) = (k, value) else {
+                        panic!("unexpected entry");
+                    };
+                    assert_eq!(k, v);
+                    count += 1;
+                    sum += k as i128;
+                    key = Value::Integer(k);
+                }
+                NextValue::Last => break,
+                NextValue::NotFound => panic!("key not found during iteration"),
+            }
+        }
+        assert_eq!(count, LEN + 3);
+        assert_eq!(
+            sum,
+            (LEN as i128 + 1) * (LEN as i128 + 2) / 2 + i64::MAX as i128 + i64::MIN as i128
+        );
+
+        // Keys past the end of the array part which are not in the table are not found.
+        assert!(matches!(
+            table.next(Value::Integer(LEN + 2)),
+            NextValue::NotFound
+        ));
+        assert!(matches!(
+            table.next(Value::Number(f64::MAX)),
+            NextValue::NotFound
+        ));
+    });
+}
+
+#[test]
+fn get_raw() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(ctx, &b"t = {1, nil, 3}"[..])?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    lua.run(|ctx| {
+        let Value::Table(t) = ctx.globals().get(ctx, "t") else {
+            panic!("expected a table");
+        };
+        assert!(t.array_part_len() >= 3);
+
+        assert!(matches!(
+            t.get_raw(Value::Integer(1)),
+            Some(Value::Integer(1))
+        ));
+        // Index 2 is a hole in the array part, which `get` cannot tell apart from a missing key.
+        assert!(t.get(ctx, 2).is_nil());
+        assert!(matches!(t.get_raw(Value::Integer(2)), Some(Value::Nil)));
+        assert!(matches!(t.get_raw(Value::Number(2.0)), Some(Value::Nil)));
+        assert!(matches!(
+            t.get_raw(Value::Integer(3)),
+            Some(Value::Integer(3))
+        ));
+
+        let past_array = t.array_part_len() as i64 + 1;
+        assert!(t.get_raw(Value::Integer(past_array)).is_none());
+        assert!(t.get_raw(Value::Integer(0)).is_none());
+        assert!(t.get_raw(ctx.intern("key")).is_none());
+        assert!(t.get_raw(Value::Nil).is_none());
+
+        t.set(ctx, "key", "value").unwrap();
+        assert!(matches!(t.get_raw(ctx.intern("key")), Some(Value::String(s)) if s == "value"));
+        t.set(ctx, "key", Value::Nil).unwrap();
+        assert!(t.get_raw(ctx.intern("key")).is_none());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn light_userdata_keys() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let handles = [0x1000usize, 0x2000];
+
+    lua.try_run(|ctx| {
+        let t = Table::new(&ctx);
+        t.set(ctx, Value::LightUserData(handles[0]), "first")?;
+        t.set(ctx, Value::LightUserData(handles[1]), "second")?;
+        ctx.state.globals.set(ctx, "handles", t)?;
+        ctx.state
+            .globals
+            .set(ctx, "first", Value::LightUserData(handles[0]))?;
+
+        // Light userdata are keyed by value, so a new value with the same handle finds the entry.
+        assert!(
+            matches!(t.get(ctx, Value::LightUserData(0x1000)), Value::String(s) if s == "first")
+        );
+        assert!(t.get(ctx, Value::LightUserData(0x3000)).is_nil());
+        assert_eq!(
+            Value::LightUserData(0x2000)
+                .table_hash(&KeyHashing::Fx)
+                .unwrap(),
+            Value::LightUserData(handles[1])
+                .table_hash(&KeyHashing::Fx)
+                .unwrap()
+        );
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local count = 0
+                for k in pairs(handles) do
+                    assert(type(k) == "userdata")
+                    count = count + 1
+                end
+                return count == 2 and handles[first] == "first"
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+    Ok(())
+}
+
+#[test]
+fn default_value() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        let plain = Table::new(&ctx);
+        let counts = Table::new(&ctx);
+        assert!(counts.set_default(&ctx, Value::Integer(0))?.is_nil());
+
+        // An `__index` takes priority over the default of the table itself, but the default of a
+        // table reached through `__index` is used.
+        let with_index = Table::new(&ctx);
+        with_index.set_default(&ctx, "unused".into_value(ctx))?;
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, MetaMethod::Index, counts)?;
+        with_index.set_metatable(ctx, Some(metatable));
+
+        ctx.state.globals.set(ctx, "plain", plain)?;
+        ctx.state.globals.set(ctx, "counts", counts)?;
+        ctx.state.globals.set(ctx, "with_index", with_index)?;
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                for _, word in ipairs({"a", "b", "a", "c", "a"}) do
+                    plain[word] = (plain[word] or 0) + 1
+                    counts[word] = counts[word] + 1
+                end
+                for k, v in pairs(plain) do
+                    assert(counts[k] == v)
+                end
+
+                assert(plain.missing == nil)
+                assert(counts.missing == 0)
+                assert(rawget(counts, "missing") == nil)
+                assert(with_index.missing == 0)
+                return counts.a == 3 and counts.b == 1 and #counts == 0
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+
+    lua.try_run(|ctx| {
+        let Value::Table(counts) = ctx.state.globals.get(ctx, "counts") else {
+            panic!("counts is not a table");
+        };
+        assert!(counts.get(ctx, "missing").is_nil());
+        assert!(matches!(
+            counts.set_default(&ctx, Value::Nil)?,
+            Value::Integer(0)
+        ));
+        assert!(counts.default_value().is_nil());
+        Ok(())
+    })
+}
+
+#[test]
+fn key_hashing() -> Result<(), StaticError> {
+    // Builds a table of string keys with a Lua table constructor and returns the order in which
+    // `next` visits them, which follows the buckets the keys were hashed into.
+    fn key_order(hashing: KeyHashing) -> Result<Vec<String>, StaticError> {
+        let mut lua = Lua::core();
+        lua.set_key_hashing(hashing.clone());
+
+        let thread = lua.try_run(|ctx| {
+            let closure = Closure::load(
+                ctx,
+                &br#"
+                    t = {}
+                    for i = 1, 64 do
+                        t["key" .. i] = i
+                    end
+                    for i = 1, 64 do
+                        assert(t["key" .. i] == i)
+                    end
+                "#[..],
+            )?;
+            let thread = Thread::new(&ctx);
+            thread.start(ctx, closure.into(), ())?;
+            Ok(ctx.state.registry.stash(&ctx, thread))
+        })?;
+        lua.run_thread::<()>(&thread)?;
+
+        lua.try_run(|ctx| {
+            let Value::Table(t) = ctx.state.globals.get(ctx, "t") else {
+                panic!("t is not a table");
+            };
+            let probe = Value::Integer(7);
+            assert_eq!(t.key_hashing().hash_key(probe), hashing.hash_key(probe));
+
+            let mut order = Vec::new();
+            let mut key = Value::Nil;
+            while let NextValue::Found { key: k, .. } = t.next(key) {
+                order.push(k.to_string());
+                key = k;
+            }
+            assert_eq!(order.len(), 64);
+            Ok(order)
+        })
+    }
+
+    let random = KeyHashing::random();
+    let order = key_order(random.clone())?;
+    assert_eq!(order, key_order(random)?);
+    assert_ne!(order, key_order(KeyHashing::random())?);
+    assert_ne!(order, key_order(KeyHashing::Fx)?);
+
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        assert!(matches!(ctx.state.config.key_hashing(), KeyHashing::Fx));
+        assert!(matches!(Table::new(&ctx).key_hashing(), KeyHashing::Fx));
+        let random = KeyHashing::random();
+        let table = Table::with_key_hashing(&ctx, random.clone());
+        let probe = Value::Integer(7);
+        assert_eq!(table.key_hashing().hash_key(probe), random.hash_key(probe));
+        table.set(ctx, "a", 1).unwrap();
+        assert!(matches!(table.get(ctx, "a"), Value::Integer(1)));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn instance_key_hashing() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.set_key_hashing(KeyHashing::random());
+
+    lua.try_run(|ctx| {
+        let globals = ctx.state.globals;
+        let Value::Table(string) = globals.get(ctx, "string") else {
+            panic!("string is not a table");
+        };
+        let Value::Table(loaded) = globals.get_path(ctx, &["package", "loaded"]) else {
+            panic!("package.loaded is not a table");
+        };
+        for table in [globals, string, loaded, Table::with_instance_hashing(ctx)] {
+            assert!(matches!(table.key_hashing(), KeyHashing::Random(_)));
+        }
+        // The rehashed tables still find every key.
+        assert!(matches!(globals.get(ctx, "string"), Value::Table(_)));
+        assert!(matches!(string.get(ctx, "format"), Value::Function(_)));
+        assert!(matches!(Table::new(&ctx).key_hashing(), KeyHashing::Fx));
+        Ok(())
+    })
+}
+
+#[test]
+fn table_identity() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        let table = Table::new(&ctx);
+        let other = Table::new(&ctx);
+        ctx.state.globals.set(ctx, "t", table)?;
+        ctx.state.globals.set(ctx, "other", other)?;
+
+        let Value::Table(handle) = ctx.state.globals.get(ctx, "t") else {
+            panic!("t is not a table");
+        };
+        assert_eq!(handle.as_ptr(), table.as_ptr());
+        assert_ne!(other.as_ptr(), table.as_ptr());
+
+        let mut ids = std::collections::HashMap::new();
+        ids.insert(table.as_ptr(), "t");
+        ids.insert(other.as_ptr(), "other");
+        assert_eq!(ids.get(&handle.as_ptr()), Some(&"t"));
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local alias = t
+                return alias == t and alias ~= other, alias
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    lua.finish_thread(&thread);
+    lua.try_run(|ctx| {
+        let thread = ctx.state.registry.fetch(&thread);
+        let (equal, alias): (bool, Table) = thread.take_return(ctx)??;
+        assert!(equal);
+        let Value::Table(t) = ctx.state.globals.get(ctx, "t") else {
+            panic!("t is not a table");
+        };
+        assert_eq!(alias.as_ptr(), t.as_ptr());
+        Ok(())
+    })
+}
+
+#[test]
+fn get_many() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let config = Table::new(&ctx);
+        config.set(ctx, "name", "server").unwrap();
+        config.set(ctx, "port", 8080).unwrap();
+        config.set(ctx, "ratio", 0.5).unwrap();
+        config.set(ctx, "enabled", true).unwrap();
+        config.set(ctx, 1, "first").unwrap();
+
+        let [name, port, ratio, enabled, missing] =
+            config.get_many(ctx, ["name", "port", "ratio", "enabled", "missing"]);
+        assert!(matches!(name, Value::String(s) if s == "server"));
+        assert!(matches!(port, Value::Integer(8080)));
+        assert!(matches!(ratio, Value::Number(r) if r == 0.5));
+        assert!(matches!(enabled, Value::Boolean(true)));
+        assert!(missing.is_nil());
+
+        let [first, second] = config.get_many(ctx, [Value::Integer(1), Value::Number(2.0)]);
+        assert!(matches!(first, Value::String(s) if s == "first"));
+        assert!(second.is_nil());
+    });
+}
+
+#[test]
+fn named_metatables() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        // Creates a point table which shares the "Point" metatable with every other point.
+        let new_point = AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+            let (x, y): (i64, i64) = stack.consume(ctx)?;
+            let metatable = meta_ops::named_metatable_or_init(ctx, "Point", |metatable| {
+                let methods = Table::new(&ctx);
+                methods
+                    .set(
+                        ctx,
+                        "sum",
+                        AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                            let point: Table = stack.consume(ctx)?;
+                            let [x, y] = point.get_many(ctx, ["x", "y"]);
+                            stack.replace(ctx, raw_ops::add(x, y));
+                            Ok(CallbackReturn::Return)
+                        }),
+                    )
+                    .unwrap();
+                metatable.set(ctx, MetaMethod::Index, methods).unwrap();
+            });
+
+            let point = Table::new(&ctx);
+            point.set(ctx, "x", x)?;
+            point.set(ctx, "y", y)?;
+            point.set_metatable(ctx, Some(metatable));
+            stack.replace(ctx, point);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.state.globals.set(ctx, "new_point", new_point)?;
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                points = {}
+                for i = 1, 1000 do
+                    points[i] = new_point(i, 2 * i)
+                end
+                for i = 1, 1000 do
+                    assert(points[i]:sum() == 3 * i)
+                end
+                return getmetatable(points[1]) == getmetatable(points[1000])
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert!(lua.run_thread::<bool>(&thread)?);
+
+    lua.try_run(|ctx| {
+        let shared = meta_ops::named_metatable(ctx, "Point").unwrap();
+        let Value::Table(points) = ctx.state.globals.get(ctx, "points") else {
+            panic!("points is not a table");
+        };
+        assert_eq!(points.length(), 1000);
+        for (_, point) in points.iter_array() {
+            let Value::Table(point) = point else {
+                panic!("point is not a table");
+            };
+            assert_eq!(point.metatable().unwrap().as_ptr(), shared.as_ptr());
+        }
+
+        // The metatable can be replaced or removed, which only affects objects created later.
+        let replacement = Table::new(&ctx);
+        let previous = meta_ops::set_named_metatable(ctx, "Point", Some(replacement));
+        assert_eq!(previous, Some(shared));
+        assert_eq!(meta_ops::named_metatable(ctx, "Point"), Some(replacement));
+        assert_eq!(
+            meta_ops::set_named_metatable(ctx, "Point", None),
+            Some(replacement)
+        );
+        assert_eq!(meta_ops::named_metatable(ctx, "Point"), None);
+        assert_eq!(meta_ops::named_metatable(ctx, "Other"), None);
+        Ok(())
+    })
+}
+
+#[test]
+fn checked_iteration() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.run(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, "a", 1).unwrap();
+        table.set(ctx, "b", 2).unwrap();
+
+        let NextValue::Found { key, .. } = table.next_checked(&ctx, Value::Nil).unwrap() else {
+            panic!("table is empty");
+        };
+        // Assigning to existing keys does not disturb the iteration.
+        table.set(ctx, "a", 3).unwrap();
+        table.set(ctx, "b", 4).unwrap();
+        assert!(table.next_checked(&ctx, key).is_ok());
+
+        table.set(ctx, "c", 5).unwrap();
+        assert!(table.next_checked(&ctx, key).is_err());
+        // The unchecked `next` is unaffected, and starting a new iteration resets the check.
+        assert!(matches!(table.next(key), NextValue::Found { .. }));
+        assert!(table.next_checked(&ctx, Value::Nil).is_ok());
+        assert!(table.next_checked(&ctx, key).is_ok());
+    });
+
+    lua.set_iteration_mode(IterationMode::Checked);
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local t = {a = 1, b = 2, c = 3, 10, 20, 30}
+
+                -- Assigning to and clearing existing keys is allowed.
+                local count = 0
+                for k, v in pairs(t) do
+                    t[k] = nil
+                    count = count + 1
+                end
+                assert(count == 6 and next(t) == nil)
+
+                local t = {a = 1, b = 2, c = 3}
+                local ok, err = pcall(function()
+                    for k in pairs(t) do
+                        t[k .. "!"] = true
+                    end
+                end)
+                assert(not ok)
+                assert(string.find(tostring(err), "table modified during iteration", 1, true))
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    Ok(())
+}
+
+#[test]
+fn weak_tables() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let run = |lua: &mut Lua, source: &'static str| -> Result<(), StaticError> {
+        let thread = lua.try_run(|ctx| {
+            let closure = Closure::load(ctx, source.as_bytes())?;
+            let thread = Thread::new(&ctx);
+            thread.start(ctx, closure.into(), ())?;
+            Ok(ctx.state.registry.stash(&ctx, thread))
+        })?;
+        lua.run_thread::<()>(&thread)
+    };
+
+    run(
+        &mut lua,
+        r#"
+            keep = {}
+            values = setmetatable({}, {__mode = "v"})
+            keys = setmetatable({}, {__mode = "k"})
+            both = setmetatable({}, {__mode = "kv"})
+
+            values[1] = {}
+            values[2] = keep
+            values[3] = "string"
+            values.f = function() end
+            keys[{}] = 1
+            keys[keep] = 2
+            keys.string = 3
+            both[{}] = keep
+            both[keep] = {}
+
+            -- An unreachable weak table is cleared like any other, and then collected.
+            setmetatable({{}}, {__mode = "v"})
+        "#,
+    )?;
+
+    lua.try_run(|ctx| {
+        let Value::Table(values) = ctx.globals().get(ctx, "values") else {
+            panic!("values is not a table");
+        };
+        assert_eq!(
+            values.weakness(),
+            Weakness {
+                keys: false,
+                values: true
+            }
+        );
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    run(
+        &mut lua,
+        r#"
+            local function count(t)
+                local n = 0
+                for _ in pairs(t) do
+                    n = n + 1
+                end
+                return n
+            end
+
+            assert(values[1] == nil and values.f == nil)
+            assert(values[2] == keep and values[3] == "string" and count(values) == 2)
+            assert(keys[keep] == 2 and keys.string == 3 and count(keys) == 2)
+            assert(next(both) == nil)
+        "#,
+    )
+}
+
+#[test]
+fn weak_table_from_rust() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, "__mode", "v")?;
+        let values = Table::new(&ctx);
+        values.set_metatable(ctx, Some(metatable));
+        assert!(values.weakness().values && !values.weakness().keys);
+        values.set(ctx, 1, Table::new(&ctx))?;
+        values.set(ctx, 2, "string")?;
+        ctx.globals().set(ctx, "values", values)?;
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+
+    lua.run(|ctx| {
+        let Value::Table(values) = ctx.globals().get(ctx, "values") else {
+            panic!("values is not a table");
+        };
+        assert!(values.get(ctx, 1).is_nil());
+        assert!(matches!(values.get(ctx, 2), Value::String(s) if s == "string"));
+
+        values.set_metatable(ctx, None);
+        assert!(!values.weakness().is_weak());
+    });
+
+    Ok(())
+}
@@ -1,5 +1,10 @@
+use std::{cell::Cell, rc::Rc};
+
 use gc_arena::{lock::Lock, Collect, Gc, Rootable};
-use piccolo::{AnyCallback, AnyUserData, CallbackReturn, Closure, Lua, StaticError, Thread, Value};
+use piccolo::{
+    AnyCallback, AnyUserData, CallbackReturn, Closure, Lua, MetaMethod, StaticError, Table, Thread,
+    Value,
+};
 
 #[derive(Collect)]
 #[collect(no_drop)]
@@ -63,3 +68,113 @@ fn userdata() -> Result<(), StaticError> {
         Ok(())
     })
 }
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Counter<'gc> {
+    name: piccolo::String<'gc>,
+    count: Gc<'gc, Lock<i64>>,
+}
+
+#[test]
+fn userdata_methods() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_run(|ctx| {
+        let methods = Table::new(&ctx);
+        methods.set(
+            ctx,
+            "increment",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let (ud, by): (AnyUserData, i64) = stack.consume(ctx)?;
+                let counter = ud.downcast::<Rootable![Counter<'_>]>()?;
+                counter.count.set(&ctx, counter.count.get() + by);
+                stack.replace(ctx, counter.count.get());
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        methods.set(
+            ctx,
+            "name",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let ud: AnyUserData = stack.consume(ctx)?;
+                stack.replace(ctx, ud.downcast::<Rootable![Counter<'_>]>()?.name);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, MetaMethod::Index, methods)?;
+
+        let counter = AnyUserData::new::<Rootable![Counter<'_>]>(
+            &ctx,
+            Counter {
+                name: piccolo::String::from_static(&ctx, "clicks"),
+                count: Gc::new(&ctx, Lock::new(0)),
+            },
+        );
+        counter.set_metatable(&ctx, Some(metatable));
+        ctx.state.globals.set(ctx, "counter", counter)?;
+        Ok(())
+    })?;
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                assert(counter:increment(2) == 2)
+                assert(counter:increment(3) == 5)
+                return counter:name() == "clicks" and not pcall(counter.increment, {}, 1)
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+
+    lua.try_run(|ctx| {
+        let Value::UserData(counter) = ctx.state.globals.get(ctx, "counter") else {
+            panic!("counter is not userdata");
+        };
+        assert_eq!(counter.downcast::<Rootable![Counter<'_>]>()?.count.get(), 5);
+        Ok(())
+    })
+}
+
+#[test]
+fn userdata_finalizer() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let finalized = Rc::new(Cell::new(0));
+
+    lua.try_run(|ctx| {
+        let metatable = Table::new(&ctx);
+        let counter = finalized.clone();
+        metatable.set(
+            ctx,
+            MetaMethod::Gc,
+            AnyCallback::from_fn(&ctx, move |_, _, stack| {
+                assert!(matches!(stack.get(0), Value::UserData(_)));
+                counter.set(counter.get() + 1);
+                stack.clear();
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let userdata = AnyUserData::new_static(&ctx, 5);
+        userdata.set_metatable(&ctx, Some(metatable));
+        ctx.state.finalizers.register(ctx, userdata.into());
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+    assert_eq!(finalized.get(), 1);
+
+    // The userdata was resurrected for its finalizer, but it is only ever finalized once.
+    lua.gc_collect();
+    assert_eq!(finalized.get(), 1);
+
+    Ok(())
+}
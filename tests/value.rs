@@ -0,0 +1,162 @@
+use piccolo::{
+    meta_ops::{self, PrimitiveType},
+    raw_ops, AnyCallback, AnyUserData, CallbackReturn, Closure, Lua, MetaMethod, StaticError,
+    Table, Thread, Value,
+};
+
+#[test]
+fn type_predicates() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        let values = [
+            Value::Nil,
+            Value::Boolean(false),
+            Value::Integer(1),
+            Value::Number(1.5),
+            ctx.intern("string"),
+            Table::new(&ctx).into(),
+            AnyCallback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return)).into(),
+            Thread::new(&ctx).into(),
+            AnyUserData::new_static(&ctx, ()).into(),
+        ];
+
+        let predicates: [fn(Value<'_>) -> bool; 9] = [
+            Value::is_nil,
+            Value::is_boolean,
+            Value::is_integer,
+            Value::is_float,
+            Value::is_string,
+            Value::is_table,
+            Value::is_function,
+            Value::is_thread,
+            Value::is_userdata,
+        ];
+
+        for (i, value) in values.into_iter().enumerate() {
+            for (j, predicate) in predicates.iter().enumerate() {
+                assert_eq!(
+                    predicate(value),
+                    i == j,
+                    "{} predicate {j}",
+                    value.type_name()
+                );
+            }
+        }
+
+        assert!(Value::Integer(1).is_number());
+        assert!(Value::Number(1.5).is_number());
+        assert!(!ctx.intern("1").is_number());
+        assert!(!Value::Nil.is_number());
+    });
+}
+
+#[test]
+fn type_metatables() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        let methods = Table::new(&ctx);
+        methods.set(
+            ctx,
+            "double",
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                let n: i64 = stack.consume(ctx)?;
+                stack.replace(ctx, n * 2);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, MetaMethod::Index, methods)?;
+        assert!(
+            meta_ops::set_type_metatable(ctx, PrimitiveType::Number, Some(metatable)).is_none()
+        );
+        assert!(meta_ops::type_metatable(ctx, PrimitiveType::Number) == Some(metatable));
+        assert!(meta_ops::type_metatable(ctx, PrimitiveType::Boolean).is_none());
+
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local n = 21
+                return n:double(), getmetatable(1.5) == getmetatable(1), ("s"):upper() == "S"
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    assert_eq!(
+        lua.run_thread::<(i64, bool, bool)>(&thread)?,
+        (42, true, true)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn raw_len() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let thread = lua.try_run(|ctx| {
+        assert!(matches!(
+            raw_ops::len(ctx.intern("hello")),
+            Ok(Value::Integer(5))
+        ));
+
+        let table = Table::new(&ctx);
+        for i in 1..=3 {
+            table.set(ctx, i, i)?;
+        }
+        // The raw length ignores `__len`.
+        let mt = Table::new(&ctx);
+        mt.set(
+            ctx,
+            MetaMethod::Len,
+            AnyCallback::from_fn(&ctx, |ctx, _, stack| {
+                stack.replace(ctx, 10);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        table.set_metatable(ctx, Some(mt));
+        assert!(matches!(raw_ops::len(table.into()), Ok(Value::Integer(3))));
+
+        let err = raw_ops::len(Value::Integer(1)).unwrap_err();
+        assert_eq!(err.to_string(), "attempt to get length of a number value");
+
+        ctx.globals().set(ctx, "t", table)?;
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                local ok, err = pcall(function() return #5 end)
+                return #t == 10 and #"abc" == 3 and not ok and
+                    tostring(err) == "attempt to get length of a number value"
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+
+    assert!(lua.run_thread::<bool>(&thread)?);
+    Ok(())
+}
+
+#[test]
+fn as_f64() {
+    let mut lua = Lua::core();
+    lua.run(|ctx| {
+        assert_eq!(Value::Integer(3).as_f64(), Some(3.0));
+        assert_eq!(Value::Integer(-7).as_f64(), Some(-7.0));
+        assert_eq!(Value::Number(2.5).as_f64(), Some(2.5));
+        assert!(Value::Number(f64::NAN).as_f64().unwrap().is_nan());
+
+        // Numeric strings are only converted by `to_number`.
+        let numeric = ctx.intern("1.5");
+        assert_eq!(numeric.as_f64(), None);
+        assert_eq!(numeric.to_number(), Some(1.5));
+
+        assert_eq!(ctx.intern("one").as_f64(), None);
+        assert_eq!(Value::Nil.as_f64(), None);
+        assert_eq!(Value::Boolean(true).as_f64(), None);
+        assert_eq!(Value::Table(Table::new(&ctx)).as_f64(), None);
+    });
+}
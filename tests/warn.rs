@@ -0,0 +1,67 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{stdlib::Warnings, Closure, Lua, StaticError, Thread};
+
+#[test]
+fn warn() -> Result<(), StaticError> {
+    let captured = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::core();
+    lua.set_warn_handler({
+        let captured = captured.clone();
+        move |message| {
+            captured
+                .borrow_mut()
+                .push(String::from_utf8_lossy(message).into_owned())
+        }
+    });
+
+    let thread = lua.try_run(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            &br#"
+                warn("disabled by default")
+                warn("@on")
+                warn("first")
+                warn("multiple ", "parts ", 1, " ", 2.5)
+                warn("@unknown")
+                warn("@off", " is not a control message with more than one argument")
+                warn("@off")
+                warn("disabled again")
+                warn("@on")
+                warn("last")
+
+                assert(not pcall(warn))
+                assert(not pcall(warn, "a", {}))
+                assert(not pcall(warn, nil))
+            "#[..],
+        )?;
+        let thread = Thread::new(&ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.state.registry.stash(&ctx, thread))
+    })?;
+    lua.run_thread::<()>(&thread)?;
+
+    assert_eq!(
+        *captured.borrow(),
+        [
+            "first",
+            "multiple parts 1 2.5",
+            "@off is not a control message with more than one argument",
+            "last",
+        ]
+    );
+
+    lua.run(|ctx| {
+        let warnings = Warnings::get(ctx);
+        assert!(warnings.is_enabled());
+        warnings.warn(b"@off");
+        assert!(warnings.is_enabled());
+        warnings.set_enabled(false);
+        warnings.warn(b"from the host");
+    });
+    assert_eq!(captured.borrow().len(), 5);
+    assert_eq!(captured.borrow()[4], "@off");
+
+    Ok(())
+}